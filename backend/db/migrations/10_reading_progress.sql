@@ -0,0 +1,12 @@
+-- Where the user stopped reading a webpage. The position is synchronized between the apps so the
+-- newest position by the time on the client wins.
+create table reading_progress(
+    webpage_id bigint primary key references webpages(id) on delete cascade,
+    -- How far the webpage has been scrolled from 0 to 100.
+    percentage double precision check (percentage >= 0 and percentage <= 100),
+    -- The id of an element or another client specific anchor to scroll to.
+    anchor text,
+    -- When the position was recorded on the client.
+    client_updated timestamp with time zone not null,
+    updated timestamp with time zone default now() not null
+);
@@ -0,0 +1,13 @@
+-- Highlights are anchored in the readable text with a text quote selector, i.e. the highlighted
+-- text and the text right before and after it.
+create table highlights(
+    id bigserial primary key,
+    webpage_id bigint not null references webpages(id) on delete cascade,
+    exact text not null,
+    prefix text not null default '',
+    suffix text not null default '',
+    note text,
+    created timestamp with time zone default now() not null,
+    updated timestamp with time zone default now() not null
+);
+create index highlights_webpage_id_idx on highlights (webpage_id);
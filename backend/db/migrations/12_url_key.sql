@@ -0,0 +1,5 @@
+-- The canonical url of a webpage, which is the url given in its <link rel=canonical> element if
+-- it has one. It is used to find out if the user has saved the webpage already. Webpages saved
+-- before it was added don't have one and are found by their url instead.
+alter table webpages add column url_key text;
+create unique index webpages_user_id_url_key_idx on webpages(user_id, url_key);
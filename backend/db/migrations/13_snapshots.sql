@@ -0,0 +1,14 @@
+-- Later versions of a saved webpage fetched again from its url. The version saved first stays in
+-- the webpages table.
+create table snapshots(
+    id bigserial primary key,
+    webpage_id bigint not null references webpages(id) on delete cascade,
+    title text not null,
+    -- The readable html, the original html and the readable text like in the webpages table.
+    text text not null,
+    html text not null,
+    plain_text text not null,
+    image_url text,
+    fetched timestamp with time zone default now() not null
+);
+create index snapshots_webpage_idx on snapshots(webpage_id, fetched);
@@ -0,0 +1,25 @@
+-- Webpages posted to /api/fetch are fetched and saved in the background by a pool of workers.
+create table jobs(
+    id bigserial primary key,
+    user_id bigint not null references users(id) on delete cascade,
+    -- The request posted to /api/fetch.
+    url text not null,
+    html text,
+    archive_original boolean not null default false,
+    tags text[] not null default '{}',
+    on_duplicate text not null,
+    -- queued, running, succeeded or failed.
+    status text not null default 'queued',
+    attempts integer not null default 0,
+    -- When the job can be run next. Jobs which fail because of a transient error are retried
+    -- later.
+    run_at timestamp with time zone default now() not null,
+    error text,
+    -- created, updated or duplicate when the job has succeeded.
+    result text,
+    webpage_id bigint references webpages(id) on delete set null,
+    created timestamp with time zone default now() not null,
+    updated timestamp with time zone default now() not null
+);
+create index jobs_run_at_idx on jobs(run_at) where status in ('queued', 'running');
+create index jobs_user_id_idx on jobs(user_id);
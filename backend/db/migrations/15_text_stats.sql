@@ -0,0 +1,8 @@
+-- The number of words in the readable text and the estimated number of minutes it takes to read.
+alter table webpages add column word_count integer not null default 0;
+alter table webpages add column reading_time integer not null default 0;
+-- Webpages saved before are counted by whitespace, which is close to how new webpages are counted
+-- except for languages like Chinese and Japanese which don't separate words by spaces.
+update webpages set word_count = (select count(*) from regexp_matches(plain_text, '\S*\w\S*', 'g'));
+update webpages set reading_time = ceil(word_count / 238.0);
+create index webpages_user_id_reading_time_idx on webpages (user_id, reading_time);
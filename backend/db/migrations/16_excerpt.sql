@@ -0,0 +1,4 @@
+-- A short summary of the webpage shown in lists and search results. Webpages saved before get
+-- their description, if they have one, until they are fetched again.
+alter table webpages add column excerpt text;
+update webpages set excerpt = nullif(btrim(description), '');
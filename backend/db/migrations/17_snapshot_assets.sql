@@ -0,0 +1,4 @@
+-- Assets archived when a snapshot is refetched are linked to the snapshot as well as the webpage
+-- so updating the webpage only replaces the assets of the version saved first.
+alter table assets_to_webpages add column snapshot_id bigint references snapshots(id) on delete cascade;
+create index assets_to_webpages_snapshot_idx on assets_to_webpages(snapshot_id);
@@ -0,0 +1,7 @@
+-- Metadata read from OpenGraph, Twitter card and ordinary meta tags and from JSON-LD.
+alter table webpages add column description text;
+alter table webpages add column site_name text;
+alter table webpages add column author text;
+alter table webpages add column published_time timestamp with time zone;
+alter table webpages add column canonical_url text;
+alter table webpages add column language text;
@@ -0,0 +1,18 @@
+-- Images, stylesheets and fonts downloaded when a webpage is saved. An asset is stored once no
+-- matter how many webpages use it and is identified by the sha256 hash of its content.
+create table assets(
+    id bigserial primary key,
+    hash text unique not null,
+    content_type text not null,
+    data bytea not null,
+    added timestamp with time zone default now() not null
+);
+create table assets_to_webpages(
+    id bigserial primary key,
+    asset_id bigint not null references assets(id),
+    webpage_id bigint not null references webpages(id) on delete cascade,
+    -- The url the asset was downloaded from.
+    url text not null
+);
+create index assets_to_webpages_asset_idx on assets_to_webpages(asset_id);
+create index assets_to_webpages_webpage_idx on assets_to_webpages(webpage_id);
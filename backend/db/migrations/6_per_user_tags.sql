@@ -0,0 +1,29 @@
+-- Tags were shared between all users. Every user now gets their own copy of the tags used on
+-- their webpages.
+alter table tags drop constraint tags_tag_key;
+alter table tags add column user_id bigint references users(id);
+insert into tags(tag, user_id)
+    select distinct tags.tag, webpages.user_id from tags
+    join tags_to_webpages on tags_to_webpages.tag_id = tags.id
+    join webpages on webpages.id = tags_to_webpages.webpage_id;
+update tags_to_webpages set tag_id = user_tags.id
+    from tags as shared_tags, webpages, tags as user_tags
+    where shared_tags.id = tags_to_webpages.tag_id and shared_tags.user_id is null
+    and webpages.id = tags_to_webpages.webpage_id
+    and user_tags.tag = shared_tags.tag and user_tags.user_id = webpages.user_id;
+delete from tags where user_id is null;
+alter table tags alter column user_id set not null;
+alter table tags add constraint tags_user_id_tag_key unique (user_id, tag);
+create index tags_user_idx on tags(user_id);
+
+-- A webpage can only have the same tag once and the tags of a webpage are removed together with
+-- the webpage.
+delete from tags_to_webpages where id not in (
+    select min(id) from tags_to_webpages group by tag_id, webpage_id);
+alter table tags_to_webpages add constraint tags_to_webpages_tag_id_webpage_id_key unique (tag_id, webpage_id);
+alter table tags_to_webpages drop constraint tags_to_webpages_tag_id_fkey;
+alter table tags_to_webpages add constraint tags_to_webpages_tag_id_fkey
+    foreign key (tag_id) references tags(id) on delete cascade;
+alter table tags_to_webpages drop constraint tags_to_webpages_webpage_id_fkey;
+alter table tags_to_webpages add constraint tags_to_webpages_webpage_id_fkey
+    foreign key (webpage_id) references webpages(id) on delete cascade;
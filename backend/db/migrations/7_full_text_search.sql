@@ -0,0 +1,53 @@
+-- The readable text without markup. Searching the readable html directly would make snippets
+-- contain tags and attributes.
+alter table webpages add column plain_text text not null default '';
+update webpages set plain_text = regexp_replace(text, '<[^>]*>', ' ', 'g');
+
+-- Maps the language of a page, e.g. en-GB, to one of the text search configurations which are
+-- built into postgres. Pages in other languages or without a language use the simple
+-- configuration which doesn't do any stemming or stop word removal.
+create function text_search_config(language text) returns regconfig as $$
+    select case lower(split_part(replace(coalesce(language, ''), '_', '-'), '-', 1))
+        when 'ar' then 'arabic'
+        when 'ca' then 'catalan'
+        when 'da' then 'danish'
+        when 'de' then 'german'
+        when 'el' then 'greek'
+        when 'en' then 'english'
+        when 'es' then 'spanish'
+        when 'eu' then 'basque'
+        when 'fi' then 'finnish'
+        when 'fr' then 'french'
+        when 'ga' then 'irish'
+        when 'hi' then 'hindi'
+        when 'hu' then 'hungarian'
+        when 'hy' then 'armenian'
+        when 'id' then 'indonesian'
+        when 'it' then 'italian'
+        when 'lt' then 'lithuanian'
+        when 'nb' then 'norwegian'
+        when 'ne' then 'nepali'
+        when 'nl' then 'dutch'
+        when 'nn' then 'norwegian'
+        when 'no' then 'norwegian'
+        when 'pt' then 'portuguese'
+        when 'ro' then 'romanian'
+        when 'ru' then 'russian'
+        when 'sr' then 'serbian'
+        when 'sv' then 'swedish'
+        when 'ta' then 'tamil'
+        when 'tr' then 'turkish'
+        when 'yi' then 'yiddish'
+        else 'simple'
+    end::regconfig
+$$ language sql immutable;
+
+alter table webpages add column search_config regconfig
+    generated always as (text_search_config(language)) stored;
+-- Matches in the title rank higher than matches in the text.
+alter table webpages add column search_vector tsvector
+    generated always as (
+        setweight(to_tsvector(text_search_config(language), title), 'A') ||
+        setweight(to_tsvector(text_search_config(language), plain_text), 'B')
+    ) stored;
+create index webpages_search_vector_idx on webpages using gin (search_vector);
@@ -0,0 +1,7 @@
+-- When the webpage was read. Unread webpages have no value.
+alter table webpages add column read_at timestamp with time zone;
+-- The host part of the url without a leading www. so webpages can be filtered and sorted by site.
+alter table webpages add column domain text
+    generated always as (regexp_replace(lower(substring(url from '^[A-Za-z][A-Za-z0-9+.-]*://(?:[^@/?#]*@)?(\[[^]]*\]|[^:/?#]+)')), '^www\.', '')) stored;
+create index webpages_user_id_added_idx on webpages (user_id, added, id);
+create index webpages_user_id_domain_idx on webpages (user_id, domain);
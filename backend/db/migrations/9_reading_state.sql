@@ -0,0 +1,4 @@
+-- Archived webpages are moved out of the inbox without being deleted. Starred webpages are the
+-- user's favourites. Both are independent of whether the webpage has been read.
+alter table webpages add column archived boolean not null default false;
+alter table webpages add column starred boolean not null default false;
@@ -0,0 +1,461 @@
+// Downloads the images of a webpage when it is saved so the saved copy keeps working after the
+// original site has changed or gone offline. Stylesheets and the fonts and images they use can
+// be archived for the original html as well. Assets are identified by the sha256 hash of their
+// content so an image used by several webpages is only stored once.
+//
+// kuchiki documents cannot be held across an .await since they aren't Send. The documents are
+// therefore parsed once to find the urls to download and once more to rewrite them afterwards.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use html5ever::tendril::TendrilSink;
+use kuchiki::NodeRef;
+use regex::{Captures,Regex};
+use sha2::{Digest,Sha256};
+use sqlx::PgPool;
+use url::Url;
+use warp::http::{header,Response,StatusCode};
+
+use crate::fetch::{self,FetchError,FetchErrorKind,HttpClient};
+use crate::{errors,politeness,sanitize,urls,Webpage};
+
+pub(crate) const MAX_ASSET_SIZE: usize = 10 * 1024 * 1024;
+// The most assets downloaded for a single webpage.
+const MAX_ASSETS: usize = 100;
+// The most bytes downloaded for a single webpage. The assets are kept in memory until the
+// webpage is saved.
+const MAX_TOTAL_SIZE: usize = 50 * 1024 * 1024;
+const DOWNLOAD_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+// Assets are served from the same origin as the saved html so they must never be able to run
+// scripts, e.g. if an svg image is opened directly.
+const ASSET_CONTENT_SECURITY_POLICY: &str = "default-src 'none'; style-src 'unsafe-inline'; sandbox";
+
+lazy_static::lazy_static! {
+    static ref CSS_URL: Regex = Regex::new(r#"url\(\s*(?:"([^"]*)"|'([^']*)'|([^)"'\s]*))\s*\)"#)
+        .expect("Compiling the regex failed");
+}
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub(crate) enum AssetKind {
+    Image,
+    Stylesheet,
+    // Anything a stylesheet refers to, which is mostly fonts and background images.
+    StylesheetResource,
+}
+
+impl AssetKind {
+    fn accepts(&self, content_type: &str) -> bool {
+        match self {
+            AssetKind::Image => content_type.starts_with("image/"),
+            AssetKind::Stylesheet => content_type == "text/css",
+            // Fonts are frequently served without a font specific content type.
+            AssetKind::StylesheetResource => content_type.starts_with("image/") ||
+                content_type.starts_with("font/") ||
+                content_type.starts_with("application/font") ||
+                content_type.starts_with("application/x-font") ||
+                content_type == "application/vnd.ms-fontobject" ||
+                content_type == "application/octet-stream",
+        }
+    }
+}
+
+#[derive(Debug,Clone)]
+pub struct Asset {
+    pub(crate) url: String,
+    pub(crate) hash: String,
+    pub(crate) content_type: String,
+    pub(crate) data: Vec<u8>,
+}
+
+impl Asset {
+    fn new(url: String, content_type: String, data: Vec<u8>) -> Self {
+        let hash = format!("{:x}", Sha256::digest(&data));
+        Asset {url, hash, content_type, data}
+    }
+
+    fn path(&self) -> String {
+        asset_path(&self.hash)
+    }
+}
+
+/// The path an archived asset is served from.
+pub fn asset_path(hash: &str) -> String {
+    format!("/api/asset/{}", hash)
+}
+
+/// Returns the hash of the asset if the url is the path of an archived asset.
+pub(crate) fn asset_hash(url: &str) -> Option<&str> {
+    url.strip_prefix("/api/asset/").filter(|hash| !hash.is_empty() && !hash.contains(['/', '?', '#']))
+}
+
+pub(crate) async fn download(http_client: &HttpClient, url: &str, kind: AssetKind, max_size: usize) ->
+        Result<Asset, FetchError> {
+    let (_permit, response) = politeness::send(http_client, url, http_client.get(url).timeout(DOWNLOAD_TIMEOUT)).await?;
+    if !response.status().is_success() {
+        return Err(FetchError::new(FetchErrorKind::Status(response.status()),
+            format!("Got status {}", response.status())));
+    }
+    let content_type = response.headers().get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .map(|value| value.trim().to_lowercase())
+        .unwrap_or_default();
+    if !kind.accepts(&content_type) {
+        return Err(FetchError::new(FetchErrorKind::UnexpectedContentType,
+            format!("Unexpected content type {:?}", content_type)));
+    }
+    let data = fetch::read_body(http_client, response, max_size).await?;
+    Ok(Asset::new(url.to_string(), content_type, data))
+}
+
+// Keeps track of what has been downloaded for a webpage so every url is only tried once.
+struct Archiver<'a> {
+    http_client: &'a HttpClient,
+    downloaded: HashMap<String, Option<Asset>>,
+    // The number of bytes downloaded so far.
+    size: usize,
+}
+
+impl<'a> Archiver<'a> {
+    fn new(http_client: &'a HttpClient) -> Self {
+        Archiver {http_client, downloaded: HashMap::new(), size: 0}
+    }
+
+    // Returns the path of the archived asset or None if it couldn't be downloaded.
+    async fn archive(&mut self, url: &str, kind: AssetKind) -> Option<String> {
+        if let Some(asset) = self.downloaded.get(url) {
+            return asset.as_ref().map(Asset::path);
+        }
+        let max_size = MAX_ASSET_SIZE.min(MAX_TOTAL_SIZE - self.size);
+        if self.downloaded.len() >= MAX_ASSETS || max_size == 0 ||
+                !Url::parse(url).is_ok_and(|url| matches!(url.scheme(), "http" | "https")) {
+            return None;
+        }
+        let asset = match download(self.http_client, url, kind, max_size).await {
+            Ok(asset) => {
+                self.size += asset.data.len();
+                Some(asset)
+            },
+            Err(error) => {
+                log::warn!("Unable to archive {}: {}", url, error);
+                None
+            }
+        };
+        self.downloaded.insert(url.to_string(), asset.clone());
+        asset.as_ref().map(Asset::path)
+    }
+
+    // Stylesheets are stored with the urls inside them pointing at archived assets as well.
+    async fn archive_stylesheet(&mut self, url: &str) -> Option<String> {
+        if let Some(asset) = self.downloaded.get(url) {
+            return asset.as_ref().map(Asset::path);
+        }
+        let stylesheet = self.archive(url, AssetKind::Stylesheet).await?;
+        let base = Url::parse(url).ok()?;
+        let css = self.downloaded.get(url).cloned().flatten()
+            .map(|asset| String::from_utf8_lossy(&asset.data).to_string())?;
+        let paths = self.archive_all(css_urls(&css, &base), AssetKind::StylesheetResource).await;
+        if paths.is_empty() {
+            return Some(stylesheet);
+        }
+        let asset = Asset::new(url.to_string(), "text/css".to_string(),
+            replace_css_urls(&css, &base, &paths).into_bytes());
+        let path = asset.path();
+        self.downloaded.insert(url.to_string(), Some(asset));
+        Some(path)
+    }
+
+    // Returns the paths of the assets which were archived keyed by their url.
+    async fn archive_all(&mut self, urls: Vec<String>, kind: AssetKind) -> HashMap<String, String> {
+        let mut paths = HashMap::new();
+        for url in urls {
+            if let Some(path) = self.archive(&url, kind).await {
+                paths.insert(url, path);
+            }
+        }
+        paths
+    }
+
+    fn into_assets(self) -> Vec<Asset> {
+        self.downloaded.into_values().flatten().collect()
+    }
+}
+
+fn css_url_value<'a>(captures: &Captures<'a>) -> &'a str {
+    captures.get(1).or_else(|| captures.get(2)).or_else(|| captures.get(3))
+        .map_or("", |value| value.as_str())
+}
+
+// Archived assets are referred to by their path which must not be resolved against the page.
+fn resolve_css_url(base: &Url, url: &str) -> String {
+    match asset_hash(url) {
+        Some(_) => url.to_string(),
+        None => urls::resolve_url(base, url),
+    }
+}
+
+// Returns the absolute urls of everything referred to with url(...) in the css.
+pub(crate) fn css_urls(css: &str, base: &Url) -> Vec<String> {
+    CSS_URL.captures_iter(css)
+        .map(|captures| resolve_css_url(base, css_url_value(&captures)))
+        .filter(|url| !url.is_empty() && !url.starts_with("data:"))
+        .collect()
+}
+
+pub(crate) fn replace_css_urls(css: &str, base: &Url, paths: &HashMap<String, String>) -> String {
+    CSS_URL.replace_all(css, |captures: &Captures| {
+        match paths.get(&resolve_css_url(base, css_url_value(captures))) {
+            Some(path) => format!("url(\"{}\")", path),
+            None => captures[0].to_string(),
+        }
+    }).to_string()
+}
+
+fn attribute(node: &NodeRef, name: &str) -> Option<String> {
+    node.as_element().and_then(|element| {
+        element.attributes.borrow().get(name).map(|value| value.to_string())
+    })
+}
+
+fn resolve(base: Option<&Url>, url: &str) -> String {
+    match base {
+        Some(base) => urls::resolve_url(base, url),
+        None => url.to_string(),
+    }
+}
+
+fn elements(document: &NodeRef, selector: &str) -> Vec<NodeRef> {
+    document.select(selector).map_or_else(|_| Vec::new(), |elements| {
+        elements.map(|element| element.as_node().clone()).collect()
+    })
+}
+
+fn image_urls(document: &NodeRef, base: Option<&Url>) -> Vec<String> {
+    elements(document, "img[src]").iter()
+        .filter_map(|image| attribute(image, "src"))
+        .map(|src| resolve(base, &src))
+        .collect()
+}
+
+// Points images at the archived copies. The srcset is removed from archived images since the
+// browser would otherwise prefer loading one of its candidates from the original site.
+fn rewrite_images(document: &NodeRef, base: Option<&Url>, paths: &HashMap<String, String>) -> bool {
+    let mut rewritten = false;
+    for image in elements(document, "img[src]") {
+        let path = attribute(&image, "src").and_then(|src| paths.get(&resolve(base, &src)));
+        if let (Some(path), Some(element)) = (path, image.as_element()) {
+            let mut attributes = element.attributes.borrow_mut();
+            attributes.insert("src", path.to_string());
+            attributes.remove("srcset");
+            rewritten = true;
+        }
+    }
+    rewritten
+}
+
+fn stylesheet_urls(document: &NodeRef, base: Option<&Url>) -> Vec<String> {
+    elements(document, "link[rel~=stylesheet][href]").iter()
+        .filter_map(|link| attribute(link, "href"))
+        .map(|href| resolve(base, &href))
+        .collect()
+}
+
+// The css embedded in <style> elements and style attributes.
+fn inline_css(document: &NodeRef) -> Vec<String> {
+    elements(document, "style").iter().map(|style| style.text_contents())
+        .chain(elements(document, "[style]").iter().filter_map(|element| attribute(element, "style")))
+        .collect()
+}
+
+fn rewrite_original(document: &NodeRef, base: &Url, paths: &HashMap<String, String>) -> bool {
+    let mut rewritten = rewrite_images(document, Some(base), paths);
+    for link in elements(document, "link[rel~=stylesheet][href]") {
+        let path = attribute(&link, "href").and_then(|href| paths.get(&urls::resolve_url(base, &href)));
+        if let (Some(path), Some(element)) = (path, link.as_element()) {
+            let mut attributes = element.attributes.borrow_mut();
+            attributes.insert("href", path.to_string());
+            // The archived stylesheet differs from the original if any urls in it were rewritten.
+            attributes.remove("integrity");
+            rewritten = true;
+        }
+    }
+    for style in elements(document, "style") {
+        let css = style.text_contents();
+        let replaced = replace_css_urls(&css, base, paths);
+        if replaced != css {
+            style.children().for_each(|child| child.detach());
+            style.append(NodeRef::new_text(replaced));
+            rewritten = true;
+        }
+    }
+    for element in elements(document, "[style]") {
+        if let (Some(css), Some(element)) = (attribute(&element, "style"), element.as_element()) {
+            let replaced = replace_css_urls(&css, base, paths);
+            if replaced != css {
+                element.attributes.borrow_mut().insert("style", replaced);
+                rewritten = true;
+            }
+        }
+    }
+    rewritten
+}
+
+/// Downloads the images in the readable contents of the webpage and its lead image and rewrites
+/// them to point at the archived copies. With `include_original` the images, stylesheets and
+/// fonts of the original html are archived too. Assets which cannot be downloaded keep pointing
+/// at the original site.
+/// Returns the assets which should be stored together with the webpage.
+pub(crate) async fn archive_webpage(http_client: &HttpClient, webpage: &mut Webpage,
+        url: &str, include_original: bool) -> Vec<Asset> {
+    let mut archiver = Archiver::new(http_client);
+    // The urls in the readable contents have already been made absolute.
+    let readable_urls = image_urls(&kuchiki::parse_html().one(webpage.contents.as_str()), None);
+    let mut paths = archiver.archive_all(readable_urls, AssetKind::Image).await;
+    if let Some(image_url) = &webpage.image_url {
+        if let Some(path) = archiver.archive(image_url, AssetKind::Image).await {
+            webpage.image_url = Some(path);
+        }
+    }
+    {
+        let document = kuchiki::parse_html().one(webpage.contents.as_str());
+        if rewrite_images(&document, None, &paths) {
+            webpage.contents = sanitize::node_to_html(&document);
+        }
+    }
+    if !include_original {
+        return archiver.into_assets();
+    }
+    let (original_urls, stylesheets, inline_css_urls, base) = {
+        let document = kuchiki::parse_html().one(webpage.original_html.as_str());
+        let base = match urls::base_url(&document, url) {
+            Some(base) => base,
+            None => return archiver.into_assets(),
+        };
+        let inline_css_urls: Vec<String> = inline_css(&document).iter()
+            .flat_map(|css| css_urls(css, &base))
+            .collect();
+        (image_urls(&document, Some(&base)), stylesheet_urls(&document, Some(&base)),
+            inline_css_urls, base)
+    };
+    paths.extend(archiver.archive_all(original_urls, AssetKind::Image).await);
+    paths.extend(archiver.archive_all(inline_css_urls, AssetKind::StylesheetResource).await);
+    for stylesheet in stylesheets {
+        if let Some(path) = archiver.archive_stylesheet(&stylesheet).await {
+            paths.insert(stylesheet, path);
+        }
+    }
+    let document = kuchiki::parse_html().one(webpage.original_html.as_str());
+    if rewrite_original(&document, &base, &paths) {
+        webpage.original_html = document.to_string();
+    }
+    archiver.into_assets()
+}
+
+/// Stores the assets and associates them with the webpage, or with a snapshot of it. Assets which
+/// are already stored for another webpage are reused.
+pub(crate) async fn store_assets(transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        webpage_id: i64, snapshot_id: Option<i64>, assets: &[Asset]) -> Result<(), sqlx::Error> {
+    for asset in assets {
+        // Updating the row on conflict makes it possible to get the id of an existing asset in
+        // the same query.
+        let (asset_id,) = sqlx::query_as::<_, (i64,)>("insert into assets(hash, content_type, data) values ($1, $2, $3) on conflict (hash) do update set hash = excluded.hash returning id")
+            .bind(&asset.hash)
+            .bind(&asset.content_type)
+            .bind(&asset.data)
+            .fetch_one(&mut *transaction).await?;
+        sqlx::query("insert into assets_to_webpages(asset_id, webpage_id, snapshot_id, url) values ($1, $2, $3, $4)")
+            .bind(asset_id)
+            .bind(webpage_id)
+            .bind(snapshot_id)
+            .bind(&asset.url)
+            .execute(&mut *transaction).await?;
+    }
+    Ok(())
+}
+
+/// Removes those of the assets which aren't used by any webpage or snapshot anymore.
+pub(crate) async fn remove_unused_assets(transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        asset_ids: &[i64]) -> Result<(), sqlx::Error> {
+    sqlx::query("delete from assets where id = any($1) and not exists (select 1 from assets_to_webpages where assets_to_webpages.asset_id = assets.id)")
+        .bind(asset_ids)
+        .execute(&mut *transaction).await?;
+    Ok(())
+}
+
+/// Gets the content type and data of an asset if it is used by one of the user's webpages.
+pub(crate) async fn get_asset(db_pool: &PgPool, hash: &str, user_id: i64) ->
+        Result<Option<(String, Vec<u8>)>, sqlx::Error> {
+    sqlx::query_as::<_, (String, Vec<u8>)>("select assets.content_type, assets.data from assets join assets_to_webpages on assets_to_webpages.asset_id = assets.id join webpages on webpages.id = assets_to_webpages.webpage_id where assets.hash = $1 and webpages.user_id = $2 limit 1")
+        .bind(hash)
+        .bind(user_id)
+        .fetch_optional(db_pool).await
+}
+
+/// Serves an archived asset. Users can only get assets used by one of their own webpages.
+pub async fn get_asset_handler(hash: String, db_pool: Arc<PgPool>, user_id: i64) ->
+        Result<impl warp::Reply, warp::Rejection> {
+    match get_asset(&db_pool, &hash, user_id).await {
+        Ok(Some((content_type, data))) => Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, content_type)
+            .header(header::X_CONTENT_TYPE_OPTIONS, "nosniff")
+            .header(header::CONTENT_SECURITY_POLICY, ASSET_CONTENT_SECURITY_POLICY)
+            // The content of an asset never changes since it is addressed by its hash.
+            .header(header::CACHE_CONTROL, "private, max-age=31536000, immutable")
+            .body(data)),
+        Ok(None) => Ok(errors::error_response(StatusCode::NOT_FOUND, "Asset not found")),
+        Err(error) => {
+            log::error!("Error when fetching asset {} for user {}: {}", hash, user_id, error);
+            Ok(errors::error_response(StatusCode::INTERNAL_SERVER_ERROR, "Unknown error"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(url: &str) -> Url {
+        Url::parse(url).expect("Unable to parse url")
+    }
+
+    #[test]
+    fn test_css_urls() {
+        let base = url("https://example.com/css/style.css");
+        let css = "@font-face { src: url(\"../fonts/a.woff2\") format(\"woff2\"), url('b.woff'); } body { background: url( /bg.png ); } i { background: url(data:image/png;base64,AAAA) }";
+        assert_eq!(css_urls(css, &base), vec![
+            "https://example.com/fonts/a.woff2".to_string(),
+            "https://example.com/css/b.woff".to_string(),
+            "https://example.com/bg.png".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn test_replace_css_urls() {
+        let base = url("https://example.com/css/style.css");
+        let paths = HashMap::from([
+            ("https://example.com/fonts/a.woff2".to_string(), "/api/asset/a".to_string()),
+        ]);
+        assert_eq!(replace_css_urls("src: url(\"../fonts/a.woff2\"), url(b.woff)", &base, &paths),
+            "src: url(\"/api/asset/a\"), url(b.woff)");
+    }
+
+    #[test]
+    fn test_rewrite_images() {
+        let document = kuchiki::parse_html().one("<img src=\"/a.png\" srcset=\"/a.png 1x, /a2.png 2x\"><img src=\"/b.png\" srcset=\"/b2.png 2x\">");
+        let base = url("https://example.com/page");
+        let paths = HashMap::from([
+            ("https://example.com/a.png".to_string(), "/api/asset/a".to_string()),
+        ]);
+        assert!(rewrite_images(&document, Some(&base), &paths));
+        assert_eq!(sanitize::node_to_html(&document),
+            "<img src=\"/api/asset/a\"><img src=\"/b.png\" srcset=\"/b2.png 2x\">");
+    }
+
+    #[test]
+    fn test_asset_hash() {
+        let asset = Asset::new("https://example.com/a.txt".to_string(), "text/plain".to_string(), b"abc".to_vec());
+        assert_eq!(asset.path(), "/api/asset/ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+    }
+}
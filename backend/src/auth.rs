@@ -116,7 +116,7 @@ pub fn generate_random_string() -> Vec<u8> {
 async fn store_new_user_with_jwt_secret(db_pool: &PgPool, username: &str, hashed_password: &str)
         -> Result<sqlx::postgres::PgQueryResult, sqlx::Error> {
     let generated_jwt_secret = generate_random_string();
-    Ok(sqlx::query("
+    sqlx::query("
 WITH new_user AS (
     INSERT INTO users(username, password_hash, roles) VALUES($1, $2, '{user}') RETURNING id
     )
@@ -125,7 +125,7 @@ INSERT INTO jwt_secrets(secret, user_id) (SELECT $3, id FROM new_user)
         .bind(username)
         .bind(hashed_password)
         .bind(generated_jwt_secret)
-        .execute(db_pool).await?)
+        .execute(db_pool).await
 }
 
 pub async fn register_handler(db_pool: Arc<PgPool>, body: User, _admin_user_id: i64) ->
@@ -158,10 +158,10 @@ pub async fn register_handler(db_pool: Arc<PgPool>, body: User, _admin_user_id:
             .body("".to_string())),
         Err(error) => {
             log::error!("Error when storing user {}: {}", &body.username, error);
-            return Ok(Response::builder()
+            Ok(Response::builder()
                 .status(StatusCode::CONFLICT)
                 .body("".to_string())
-            );
+            )
         }
     }
 }
@@ -170,7 +170,7 @@ fn verify_user_with_password(provided_pass: &str, optional_hashed_pass: &Option<
     let fallback_password = "$argon2id$v=19$m=4096,t=3,p=1$ewSM8Hmctto5QHVv27S1cA$o6GeMd3PriFhi2CalkBmG1cV/AMi+ry0r/6fjmeSaFQ";
     match optional_hashed_pass {
         Some((user_id, password_hash, jwt_secret)) => {
-            if verify_password(provided_pass, &password_hash)? {
+            if verify_password(provided_pass, password_hash)? {
                 Ok(Some((user_id.to_owned(), jwt_secret.to_owned())))
             } else {
                 Ok(None)
@@ -206,16 +206,11 @@ pub async fn verify_password_from_database(db_pool: &PgPool, username: &str, pas
 
 pub async fn login_handler(db_pool: Arc<PgPool>, body: User) ->
         Result<impl warp::Reply, warp::Rejection> {
-    let (response, status_code) = verify_password_from_database(&db_pool, &body.username, &body.password)
+    let reply = verify_password_from_database(&db_pool, &body.username, &body.password)
         .await
         .map_or_else(|error| {
             log::error!("Error when verifying password for user {}: {}", &body.username, error);
-            let status = StatusCode::INTERNAL_SERVER_ERROR;
-            let json = warp::reply::json(&errors::ErrorResponse {
-                message: "Unknown error".to_string(),
-                status: status.to_string(),
-            });
-            (json, status)
+            errors::error_reply(StatusCode::INTERNAL_SERVER_ERROR, "Unknown error")
             }, |verified_user_id| {
                     match verified_user_id {
                         Some((_, secret)) => {
@@ -224,34 +219,24 @@ pub async fn login_handler(db_pool: Arc<PgPool>, body: User) ->
                                     let json = warp::reply::json(&JWTResponse {
                                         jwt
                                     });
-                                    (json, StatusCode::OK)
+                                    warp::reply::with_status(json, StatusCode::OK)
                                 },
                                 Err(error) => {
                                     log::error!("Error when creating jwt for user {}: {}", &body.username, error);
-                                    let status = StatusCode::INTERNAL_SERVER_ERROR;
-                                    let json = warp::reply::json(&errors::ErrorResponse {
-                                        message: "Unknown error".to_string(),
-                                        status: status.to_string(),
-                                    });
-                                    (json, status)
+                                    errors::error_reply(StatusCode::INTERNAL_SERVER_ERROR, "Unknown error")
                                 }
                             }
                         },
                         None => {
-                            let status = StatusCode::UNAUTHORIZED;
-                            let json = warp::reply::json(&errors::ErrorResponse {
-                                message: "Password doesn't match".to_string(),
-                                status: status.to_string(),
-                            });
-                            (json, status)
+                            errors::error_reply(StatusCode::UNAUTHORIZED, "Password doesn't match")
                         }
                     }
             });
-    Ok(warp::reply::with_status(response, status_code))
+    Ok(reply)
 }
 
 fn decode_jwt(jwt: &str, secret: &[u8]) -> Result<String, jsonwebtoken::errors::Error> {
-    jsonwebtoken::decode::<Claims>(&jwt,
+    jsonwebtoken::decode::<Claims>(jwt,
         &jsonwebtoken::DecodingKey::from_secret(secret),
         &jsonwebtoken::Validation::new(jsonwebtoken::Algorithm::HS512))
     .map(|decoded_jwt| decoded_jwt.claims.sub)
@@ -259,7 +244,7 @@ fn decode_jwt(jwt: &str, secret: &[u8]) -> Result<String, jsonwebtoken::errors::
 
 pub fn get_sub_from_jwt_insecure(jwt: &str) -> Option<String> {
     match jsonwebtoken::dangerous_insecure_decode_with_validation::<Claims>(
-            &jwt, &jsonwebtoken::Validation::new(jsonwebtoken::Algorithm::HS512)) {
+            jwt, &jsonwebtoken::Validation::new(jsonwebtoken::Algorithm::HS512)) {
         Ok(token) => Some(token.claims.sub),
         Err(error) => {
             log::error!("Error decoding jwt {}: {}", jwt, error);
@@ -332,7 +317,7 @@ pub fn hash_password(password: &str) -> Result<String, argon2::password_hash::Er
 }
 
 fn verify_password(password: &str, hashed_password: &str) -> Result<bool, argon2::password_hash::Error> {
-    let hash = PasswordHash::new(&hashed_password)?;
+    let hash = PasswordHash::new(hashed_password)?;
     Ok(Argon2::default().verify_password(password.as_bytes(), &hash).is_ok())
 }
 
@@ -405,7 +390,7 @@ async fn authorize_from_self_provided_jwt(db_pool: &PgPool, requested_roles: Vec
             .fetch_optional(db_pool).await
             .map_or_else(|error| {
                 log::error!("Error when fetching jwt secret from database: {}", error);
-                return Err(warp::reject::custom(errors::Error::UnknownUser))
+                Err(warp::reject::custom(errors::Error::UnknownUser))
             }, |optional_secret| {
                 optional_secret.ok_or_else(|| warp::reject::custom(errors::Error::UnknownUser))
             })
@@ -455,7 +440,7 @@ async fn authorize_from_oauth2_provider_jwt(db_pool: &PgPool, requested_roles: V
                 .fetch_optional(db_pool).await
                 .map_or_else(|error| {
                     log::error!("Error when fetching connected apps from database: {}", error);
-                    return Err(warp::reject::custom(errors::Error::UnknownUser))
+                    Err(warp::reject::custom(errors::Error::UnknownUser))
                 }, |user| {
                     user.ok_or_else(|| warp::reject::custom(errors::Error::UnknownUser))
                 })
@@ -553,17 +538,12 @@ fn validate_requested_roles(actual_roles: Vec<Role>, requested_roles: Vec<Role>)
 
 pub async fn extend_jwt_handler(db_pool: Arc<PgPool>, user_id: i64) ->
         Result<impl warp::Reply, warp::Rejection> {
-    let (response, status_code) = sqlx::query_as::<_, (String, Vec<u8>)>("SELECT users.username, jwt_secrets.secret FROM users JOIN jwt_secrets ON users.id = jwt_secrets.user_id WHERE users.id = $1")
+    let reply = sqlx::query_as::<_, (String, Vec<u8>)>("SELECT users.username, jwt_secrets.secret FROM users JOIN jwt_secrets ON users.id = jwt_secrets.user_id WHERE users.id = $1")
         .bind(user_id)
         .fetch_optional(&*db_pool).await
         .map_or_else(|error| {
             log::error!("Error when creating jwt for user {}: {}", user_id, error);
-            let status = StatusCode::INTERNAL_SERVER_ERROR;
-            let json = warp::reply::json(&errors::ErrorResponse {
-                message: format!("Error creating jwt"),
-                status: status.to_string(),
-            });
-            (json, status)
+            errors::error_reply(StatusCode::INTERNAL_SERVER_ERROR, "Error creating jwt")
         }, |opt_row| {
             match opt_row {
                 Some((username, secret)) => {
@@ -572,30 +552,20 @@ pub async fn extend_jwt_handler(db_pool: Arc<PgPool>, user_id: i64) ->
                             let json = warp::reply::json(&JWTResponse {
                                 jwt
                             });
-                            (json, StatusCode::OK)
+                            warp::reply::with_status(json, StatusCode::OK)
                         },
                         Err(error) => {
                             log::error!("Error when creating jwt for user {}: {}", username, error);
-                            let status = StatusCode::INTERNAL_SERVER_ERROR;
-                            let json = warp::reply::json(&errors::ErrorResponse {
-                                message: format!("Error creating jwt: {}", error),
-                                status: status.to_string(),
-                            });
-                            (json, status)
+                            errors::error_reply(StatusCode::INTERNAL_SERVER_ERROR, &format!("Error creating jwt: {}", error))
                         }
                     }
                 },
                 None => {
-                    let status = StatusCode::UNAUTHORIZED;
-                    let json = warp::reply::json(&errors::ErrorResponse {
-                        message: "Unknown user".to_string(),
-                        status: status.to_string(),
-                    });
-                    (json, status)
+                    errors::error_reply(StatusCode::UNAUTHORIZED, "Unknown user")
                 }
             }
         });
-    Ok(warp::reply::with_status(response, status_code))
+    Ok(reply)
 }
 
 #[derive(Deserialize,Debug)]
@@ -659,7 +629,7 @@ mod tests {
             .expect("Password hashing failed");
         let password_is_ok = verify_password("password", &hashed_password)
             .expect("Password verification failed");
-        assert_eq!(password_is_ok, true);
+        assert!(password_is_ok);
     }
 
     #[test]
@@ -668,28 +638,27 @@ mod tests {
             .expect("Password hashing failed");
         let password_is_ok = verify_password("password", &hashed_password)
             .expect("Password verification failed");
-        assert_eq!(password_is_ok, false);
+        assert!(!password_is_ok);
     }
 
     #[test]
     fn test_validate_password_chars() {
-        assert_eq!(validate_password_chars("Passw*ord123"), true);
+        assert!(validate_password_chars("Passw*ord123"));
     }
 
     #[test]
     fn test_validate_password_chars_fails() {
-        assert_eq!(validate_password_chars("Pass"), false);
+        assert!(!validate_password_chars("Pass"));
     }
 
     #[test]
     fn test_validate_username_chars() {
-        assert_eq!(validate_username_chars("username"), true);
+        assert!(validate_username_chars("username"));
     }
 
     #[test]
     fn test_validate_username_chars_fails() {
-        assert_eq!(validate_username_chars(" username; drop table users"),
-            false);
+        assert!(!validate_username_chars(" username; drop table users"));
     }
 
     #[test]
@@ -1,6 +1,10 @@
 use clap::{App,AppSettings,Arg,ArgMatches,SubCommand};
 
+use sqlx::PgPool;
+
 use article_server_rs::auth::{generate_random_string,hash_password};
+use article_server_rs::epub::{create_epub,Selection};
+use article_server_rs::fetch::{HttpClient,HttpClientConfig};
 
 pub fn setup_args() -> ArgMatches<'static> {
     App::new("webpage-saver-utils 1.0.0")
@@ -10,6 +14,39 @@ pub fn setup_args() -> ArgMatches<'static> {
                 .required(true)
                 .help("Password to hash")))
         .subcommand(SubCommand::with_name("generate-random-string"))
+        .subcommand(SubCommand::with_name("export-epub")
+            .about("Export saved webpages as an EPUB file")
+            .arg(Arg::with_name("database-path")
+                .long("--db-path")
+                .required(true)
+                .takes_value(true)
+                .help("Path to the database the webpages are stored in"))
+            .arg(Arg::with_name("username")
+                .long("--username")
+                .required(true)
+                .takes_value(true)
+                .help("User whose webpages should be exported"))
+            .arg(Arg::with_name("ids")
+                .long("--ids")
+                .takes_value(true)
+                .use_delimiter(true)
+                .required_unless("tag")
+                .conflicts_with("tag")
+                .help("Comma separated ids of the webpages to export"))
+            .arg(Arg::with_name("tag")
+                .long("--tag")
+                .takes_value(true)
+                .help("Export all webpages with this tag"))
+            .arg(Arg::with_name("title")
+                .long("--title")
+                .takes_value(true)
+                .help("Title of the book"))
+            .arg(Arg::with_name("output")
+                .short("-o")
+                .long("--output")
+                .required(true)
+                .takes_value(true)
+                .help("Path to write the EPUB file to")))
     .get_matches()
 }
 
@@ -26,6 +63,29 @@ fn main() {
             let s = std::str::from_utf8(&r).expect("Unable to generate random string");
             println!("{}", s);
         },
+        ("export-epub", Some(args)) => {
+            let selection = match args.values_of("ids") {
+                Some(ids) => Selection::Ids(ids.map(|id| id.trim().parse::<i64>()
+                    .unwrap_or_else(|_| panic!("Unable to parse {} as a webpage id", id))).collect()),
+                None => Selection::Tag(args.value_of("tag").expect("Unable to get tag").to_string()),
+            };
+            let runtime = tokio::runtime::Runtime::new().expect("Unable to start tokio runtime");
+            let epub = runtime.block_on(async {
+                let db_url = args.value_of("database-path").expect("Unable to get database-path argument");
+                let pool = PgPool::connect(db_url).await.expect("Unable to get database connection pool");
+                let username = args.value_of("username").expect("Unable to get username");
+                let (user_id,) = sqlx::query_as::<_, (i64,)>("select id from users where username = $1")
+                    .bind(username)
+                    .fetch_one(&pool).await
+                    .unwrap_or_else(|error| panic!("Unable to find user {}: {}", username, error));
+                let http_client = HttpClient::new(&HttpClientConfig::default())
+                    .expect("Unable to create http client");
+                create_epub(&pool, &http_client, user_id, &selection, args.value_of("title")).await
+                    .unwrap_or_else(|error| panic!("Unable to create epub: {}", error))
+            });
+            let output = args.value_of("output").expect("Unable to get output path");
+            std::fs::write(output, epub).unwrap_or_else(|error| panic!("Unable to write {}: {}", output, error));
+        },
         _ => unreachable!()
     }
 }
@@ -0,0 +1,262 @@
+// Fetched webpages are decoded from bytes the way browsers do it, following "determining the
+// character encoding" in the HTML standard:
+// https://html.spec.whatwg.org/multipage/parsing.html#determining-the-character-encoding
+// A byte order mark wins over the charset in the Content-Type header, which wins over a <meta>
+// element in the first 1024 bytes. Documents declaring nothing are guessed from their content.
+
+use encoding_rs::Encoding;
+
+// How much of the document is searched for a <meta> element declaring the encoding.
+const PRESCAN_LENGTH: usize = 1024;
+
+/// The encoding named by the charset parameter of a Content-Type header.
+fn content_type_encoding(content_type: &str) -> Option<&'static Encoding> {
+    content_type.split(';').skip(1).find_map(|parameter| {
+        let (name, value) = parameter.split_once('=')?;
+        name.trim().eq_ignore_ascii_case("charset").then(|| value.trim().trim_matches(|c| c == '"' || c == '\''))
+    }).and_then(|charset| Encoding::for_label(charset.as_bytes()))
+}
+
+fn is_whitespace(byte: u8) -> bool {
+    matches!(byte, b'\t' | b'\n' | b'\x0c' | b'\r' | b' ')
+}
+
+fn starts_with_ignore_case(data: &[u8], prefix: &[u8]) -> bool {
+    data.len() >= prefix.len() && data[..prefix.len()].eq_ignore_ascii_case(prefix)
+}
+
+// Gets the next attribute of a tag starting at position and moves position past it. Returns None
+// at the end of the tag. Names and values are lowercased.
+fn get_attribute(data: &[u8], position: &mut usize) -> Option<(Vec<u8>, Vec<u8>)> {
+    while *position < data.len() && (is_whitespace(data[*position]) || data[*position] == b'/') {
+        *position += 1;
+    }
+    if *position >= data.len() || data[*position] == b'>' {
+        return None;
+    }
+    let mut name = Vec::new();
+    loop {
+        let byte = *data.get(*position)?;
+        if byte == b'=' && !name.is_empty() {
+            *position += 1;
+            break;
+        } else if is_whitespace(byte) {
+            while *position < data.len() && is_whitespace(data[*position]) {
+                *position += 1;
+            }
+            if data.get(*position) != Some(&b'=') {
+                return Some((name, Vec::new()));
+            }
+            *position += 1;
+            break;
+        } else if byte == b'/' || byte == b'>' {
+            return Some((name, Vec::new()));
+        }
+        name.push(byte.to_ascii_lowercase());
+        *position += 1;
+    }
+    while *position < data.len() && is_whitespace(data[*position]) {
+        *position += 1;
+    }
+    let mut value = Vec::new();
+    match *data.get(*position)? {
+        quote @ (b'"' | b'\'') => {
+            *position += 1;
+            loop {
+                let byte = *data.get(*position)?;
+                *position += 1;
+                if byte == quote {
+                    return Some((name, value));
+                }
+                value.push(byte.to_ascii_lowercase());
+            }
+        },
+        b'>' => return Some((name, value)),
+        _ => (),
+    }
+    while let Some(&byte) = data.get(*position) {
+        if is_whitespace(byte) || byte == b'>' {
+            break;
+        }
+        value.push(byte.to_ascii_lowercase());
+        *position += 1;
+    }
+    Some((name, value))
+}
+
+// Finds the charset in the content attribute of <meta http-equiv="content-type">, e.g.
+// "text/html; charset=shift_jis".
+fn encoding_from_meta_content(content: &[u8]) -> Option<&'static Encoding> {
+    let mut position = 0;
+    loop {
+        let start = content[position..].windows(7).position(|window| window.eq_ignore_ascii_case(b"charset"))?;
+        position += start + 7;
+        while position < content.len() && is_whitespace(content[position]) {
+            position += 1;
+        }
+        if content.get(position) == Some(&b'=') {
+            position += 1;
+            break;
+        }
+    }
+    while position < content.len() && is_whitespace(content[position]) {
+        position += 1;
+    }
+    let value = match *content.get(position)? {
+        quote @ (b'"' | b'\'') => {
+            let rest = &content[position + 1..];
+            &rest[..rest.iter().position(|&byte| byte == quote)?]
+        },
+        _ => {
+            let rest = &content[position..];
+            &rest[..rest.iter().position(|&byte| is_whitespace(byte) || byte == b';').unwrap_or(rest.len())]
+        },
+    };
+    Encoding::for_label(value)
+}
+
+// Handles the <meta> tag whose attributes start at position. Returns the encoding it declares.
+fn meta_encoding(data: &[u8], position: &mut usize) -> Option<&'static Encoding> {
+    let mut attribute_names = Vec::new();
+    let mut got_pragma = false;
+    let mut need_pragma = None;
+    let mut charset = None;
+    while let Some((name, value)) = get_attribute(data, position) {
+        if attribute_names.contains(&name) {
+            continue;
+        }
+        match name.as_slice() {
+            b"http-equiv" => got_pragma |= value == b"content-type",
+            b"content" if charset.is_none() => {
+                if let Some(encoding) = encoding_from_meta_content(&value) {
+                    charset = Some(encoding);
+                    need_pragma = Some(true);
+                }
+            },
+            b"charset" => {
+                charset = Encoding::for_label(&value);
+                need_pragma = Some(false);
+            },
+            _ => (),
+        }
+        attribute_names.push(name);
+    }
+    match need_pragma {
+        None => return None,
+        Some(true) if !got_pragma => return None,
+        _ => (),
+    }
+    // A document whose bytes can be scanned as ASCII can't be UTF-16.
+    charset.map(|encoding| if encoding == encoding_rs::UTF_16BE || encoding == encoding_rs::UTF_16LE {
+        encoding_rs::UTF_8
+    } else if encoding == encoding_rs::X_USER_DEFINED {
+        encoding_rs::WINDOWS_1252
+    } else {
+        encoding
+    })
+}
+
+/// Looks for a <meta> element declaring the encoding at the start of the document.
+fn prescan(data: &[u8]) -> Option<&'static Encoding> {
+    let data = &data[..data.len().min(PRESCAN_LENGTH)];
+    let mut position = 0;
+    while position < data.len() {
+        let rest = &data[position..];
+        if rest.starts_with(b"<!--") {
+            position += rest[2..].windows(3).position(|window| window == b"-->")? + 4;
+        } else if starts_with_ignore_case(rest, b"<meta")
+                && rest.get(5).is_some_and(|&byte| is_whitespace(byte) || byte == b'/') {
+            position += 6;
+            if let Some(encoding) = meta_encoding(data, &mut position) {
+                return Some(encoding);
+            }
+        } else if rest.starts_with(b"<") && (rest.get(1).is_some_and(u8::is_ascii_alphabetic)
+                || (rest.get(1) == Some(&b'/') && rest.get(2).is_some_and(u8::is_ascii_alphabetic))) {
+            // Skips the attributes of other tags, which might contain "<meta".
+            position += rest.iter().position(|&byte| is_whitespace(byte) || byte == b'>').unwrap_or(rest.len());
+            while get_attribute(data, &mut position).is_some() {}
+        } else if rest.starts_with(b"<!") || rest.starts_with(b"</") || rest.starts_with(b"<?") {
+            position += rest.iter().position(|&byte| byte == b'>')?;
+        }
+        position += 1;
+    }
+    None
+}
+
+/// Guesses the encoding from the content. The top level domain of the site helps telling apart
+/// legacy encodings which are common in different countries.
+fn detect(data: &[u8], top_level_domain: Option<&str>) -> &'static Encoding {
+    let mut detector = chardetng::EncodingDetector::new();
+    detector.feed(data, true);
+    detector.guess(top_level_domain.map(str::as_bytes), true)
+}
+
+/// Decodes an html document fetched from url and served with the given Content-Type header.
+pub(crate) fn decode_html(url: &str, content_type: Option<&str>, data: &[u8]) -> String {
+    if let Some((encoding, bom_length)) = Encoding::for_bom(data) {
+        return encoding.decode_without_bom_handling(&data[bom_length..]).0.into_owned();
+    }
+    let encoding = content_type.and_then(content_type_encoding)
+        .or_else(|| prescan(data))
+        .unwrap_or_else(|| {
+            let host = url::Url::parse(url).ok().and_then(|url| url.host_str().map(str::to_string));
+            let top_level_domain = host.as_deref().and_then(|host| host.rsplit('.').next());
+            detect(data, top_level_domain)
+        });
+    encoding.decode_without_bom_handling(data).0.into_owned()
+}
+
+/// Decodes text which isn't html, e.g. the body of an error response, using only the
+/// Content-Type header.
+pub(crate) fn decode_text(content_type: Option<&str>, data: &[u8]) -> String {
+    let encoding = content_type.and_then(content_type_encoding).unwrap_or(encoding_rs::UTF_8);
+    encoding.decode(data).0.into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_html() {
+        let url = "https://example.com/article";
+        let html = decode_html(url, Some("text/html"), include_bytes!("test-data/charset-windows-1252.html"));
+        assert!(html.contains("<title>Café crème</title>"), "{}", html);
+        assert!(html.contains("coûte 3 € à Paris –"), "{}", html);
+        // The commented out <meta> is skipped.
+        let html = decode_html(url, None, include_bytes!("test-data/charset-shift-jis.html"));
+        assert!(html.contains("<title>日本語の記事</title>"), "{}", html);
+        let html = decode_html("https://example.ru/", Some("text/html"), include_bytes!("test-data/charset-koi8-r.html"));
+        assert!(html.contains("<title>Статья на русском языке</title>"), "{}", html);
+        // The byte order mark wins over both the header and the <meta> element.
+        let html = decode_html(url, Some("text/html; charset=utf-8"), include_bytes!("test-data/charset-utf-16le.html"));
+        assert!(html.starts_with("<!DOCTYPE html>"), "{}", html);
+        assert!(html.contains("<title>Ærø og Æbeltoft</title>"), "{}", html);
+        // The header wins over the <meta> element.
+        let html = decode_html(url, Some("text/html; charset=\"utf-8\""), b"<meta charset=koi8-r><p>caf\xc3\xa9</p>");
+        assert_eq!(html, "<meta charset=koi8-r><p>café</p>");
+    }
+
+    #[test]
+    fn test_prescan() {
+        assert_eq!(prescan(b"<meta charset=\"ISO-8859-1\">"), Some(encoding_rs::WINDOWS_1252));
+        assert_eq!(prescan(b"<html lang=da><META CHARSET=utf-8>"), Some(encoding_rs::UTF_8));
+        assert_eq!(prescan(b"<meta content='text/html; charset=koi8-r' http-equiv=content-type>"),
+            Some(encoding_rs::KOI8_R));
+        // A charset in content only counts together with http-equiv.
+        assert_eq!(prescan(b"<meta name=description content='charset=koi8-r'>"), None);
+        assert_eq!(prescan(b"<meta charset=utf-16le>"), Some(encoding_rs::UTF_8));
+        assert_eq!(prescan(b"<meta charset=x-user-defined>"), Some(encoding_rs::WINDOWS_1252));
+        assert_eq!(prescan(b"<div title='<meta charset=koi8-r>'></div>"), None);
+        assert_eq!(prescan(b"<!-- <meta charset=koi8-r> --><meta charset=gbk>"), Some(encoding_rs::GBK));
+        let late = [" ".repeat(PRESCAN_LENGTH).as_bytes(), b"<meta charset=koi8-r>"].concat();
+        assert_eq!(prescan(&late), None);
+    }
+
+    #[test]
+    fn test_decode_text() {
+        assert_eq!(decode_text(Some("text/html; charset=iso-8859-1"), b"caf\xe9"), "café");
+        assert_eq!(decode_text(Some("text/html"), "café".as_bytes()), "café");
+        assert_eq!(decode_text(None, b"caf\xe9"), "caf\u{fffd}");
+    }
+}
@@ -0,0 +1,430 @@
+// Packages saved webpages as an EPUB 3 book with one chapter per webpage so they can be read on
+// an e-reader. The chapters are built from the readable content and images are embedded in the
+// book since e-readers are often offline.
+// https://www.w3.org/TR/epub-33/
+
+use std::collections::HashMap;
+use std::io::{Cursor,Write};
+use std::sync::Arc;
+
+use html5ever::tendril::TendrilSink;
+use kuchiki::NodeRef;
+use serde::Deserialize;
+use sha2::{Digest,Sha256};
+use sqlx::PgPool;
+use warp::http::{header,Response,StatusCode};
+use zip::write::{FileOptions,ZipWriter};
+
+use crate::assets::AssetKind;
+use crate::fetch::HttpClient;
+use crate::highlights::{self,Highlight};
+use crate::webpages::{self,ShowMode,StoredWebpage};
+use crate::{errors,export,sanitize};
+
+const CONTAINER_XML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles>
+    <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+  </rootfiles>
+</container>
+"#;
+const STYLESHEET: &str = "body { font-family: serif; line-height: 1.5; } img { max-width: 100%; } .source { font-size: 0.8em; }";
+// Images of other types would need a fallback to be readable everywhere so they are left out.
+// https://www.w3.org/TR/epub-33/#sec-core-media-types
+const IMAGE_TYPES: [(&str, &str); 5] = [("image/gif", "gif"), ("image/jpeg", "jpg"),
+    ("image/png", "png"), ("image/svg+xml", "svg"), ("image/webp", "webp")];
+const DEFAULT_TITLE: &str = "Saved webpages";
+
+/// Which of the user's webpages to include in the book.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub enum Selection {
+    Ids(Vec<i64>),
+    Tag(String),
+}
+
+#[derive(Debug)]
+pub struct EpubError {
+    message: String,
+    not_found: bool,
+}
+
+impl std::fmt::Display for EpubError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for EpubError {}
+
+impl From<sqlx::Error> for EpubError {
+    fn from(error: sqlx::Error) -> Self {
+        EpubError {message: error.to_string(), not_found: false}
+    }
+}
+
+impl From<zip::result::ZipError> for EpubError {
+    fn from(error: zip::result::ZipError) -> Self {
+        EpubError {message: error.to_string(), not_found: false}
+    }
+}
+
+impl From<std::io::Error> for EpubError {
+    fn from(error: std::io::Error) -> Self {
+        EpubError {message: error.to_string(), not_found: false}
+    }
+}
+
+#[derive(Debug)]
+struct Image {
+    path: String,
+    content_type: String,
+    data: Vec<u8>,
+}
+
+#[derive(Debug)]
+struct Chapter {
+    title: String,
+    url: String,
+    author: Option<String>,
+    language: Option<String>,
+    content: String,
+}
+
+// Titles and the like end up in xml so they get the same treatment as the content.
+fn xml_text(text: &str) -> String {
+    sanitize::escape_text(&text.chars().filter(|c| !c.is_control()).collect::<String>())
+}
+
+fn xml_attribute(value: &str) -> String {
+    sanitize::escape_attribute(&value.chars().filter(|c| !c.is_control()).collect::<String>())
+}
+
+async fn select_webpages(db_pool: &PgPool, user_id: i64, selection: &Selection) ->
+        Result<Vec<StoredWebpage>, EpubError> {
+    let ids = match selection {
+        Selection::Ids(ids) => {
+            let mut unique_ids = Vec::new();
+            for id in ids {
+                if !unique_ids.contains(id) {
+                    unique_ids.push(*id);
+                }
+            }
+            unique_ids
+        },
+        Selection::Tag(tag) => sqlx::query_as::<_, (i64,)>("select webpages.id from webpages join tags_to_webpages on tags_to_webpages.webpage_id = webpages.id join tags on tags.id = tags_to_webpages.tag_id where webpages.user_id = $1 and tags.user_id = $1 and tags.tag = $2 order by webpages.added")
+            .bind(user_id)
+            .bind(tag)
+            .fetch_all(db_pool).await?
+            .into_iter()
+            .map(|(id,)| id)
+            .collect(),
+    };
+    let mut selected = Vec::new();
+    for id in ids {
+        match webpages::get_stored_webpage(db_pool, id, user_id, &ShowMode::readable).await? {
+            Some(webpage) => selected.push(webpage),
+            None => return Err(EpubError {message: format!("Webpage {} not found", id), not_found: true}),
+        }
+    }
+    if selected.is_empty() {
+        return Err(EpubError {message: "No webpages selected".to_string(), not_found: true});
+    }
+    Ok(selected)
+}
+
+fn image_sources(content: &str) -> Vec<String> {
+    kuchiki::parse_html().one(content).select("img[src]").map_or_else(|_| Vec::new(), |images| {
+        images.filter_map(|image| image.attributes.borrow().get("src").map(|src| src.to_string()))
+            .collect()
+    })
+}
+
+// Points the images at their copies in the book and removes images which couldn't be embedded
+// since e-readers shouldn't have to go online to show a book.
+fn chapter_content(content: &str, highlights: &[Highlight], image_paths: &HashMap<String, String>) -> String {
+    let document = kuchiki::parse_html().one(content);
+    highlights::add_highlights(&document, highlights);
+    let images: Vec<NodeRef> = document.select("img").map_or_else(|_| Vec::new(), |images| {
+        images.map(|image| image.as_node().clone()).collect()
+    });
+    for image in images {
+        if let Some(element) = image.as_element() {
+            let path = element.attributes.borrow().get("src")
+                .and_then(|src| image_paths.get(src).cloned());
+            match path {
+                Some(path) => {
+                    let mut attributes = element.attributes.borrow_mut();
+                    attributes.insert("src", path);
+                    attributes.remove("srcset");
+                    // Every image in an EPUB must have an alt attribute.
+                    if attributes.get("alt").is_none() {
+                        attributes.insert("alt", String::new());
+                    }
+                },
+                None => image.detach(),
+            }
+        }
+    }
+    sanitize::node_to_xhtml(&document)
+}
+
+fn chapter_xhtml(chapter: &Chapter) -> String {
+    let language = chapter.language.as_deref().unwrap_or("en");
+    let byline = match &chapter.author {
+        Some(author) => format!("{}, ", xml_text(author)),
+        None => String::new(),
+    };
+    format!(r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE html>
+<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops" xml:lang="{0}" lang="{0}">
+<head><title>{1}</title><link rel="stylesheet" type="text/css" href="style.css"/></head>
+<body><h1>{1}</h1><p class="source">{2}<a href="{3}">{4}</a></p>{5}</body>
+</html>
+"#, xml_attribute(language), xml_text(&chapter.title), byline, xml_attribute(&chapter.url),
+        xml_text(&chapter.url), chapter.content)
+}
+
+fn nav_xhtml(title: &str, chapters: &[Chapter]) -> String {
+    let items: String = chapters.iter().enumerate()
+        .map(|(i, chapter)| format!("<li><a href=\"chapter-{}.xhtml\">{}</a></li>", i + 1, xml_text(&chapter.title)))
+        .collect();
+    format!(r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE html>
+<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops">
+<head><title>{0}</title></head>
+<body><nav epub:type="toc" id="toc"><h1>{0}</h1><ol>{1}</ol></nav></body>
+</html>
+"#, xml_text(title), items)
+}
+
+// The EPUB 2 table of contents which older e-readers still use.
+fn toc_ncx(identifier: &str, title: &str, chapters: &[Chapter]) -> String {
+    let points: String = chapters.iter().enumerate()
+        .map(|(i, chapter)| format!("<navPoint id=\"chapter-{0}\" playOrder=\"{0}\"><navLabel><text>{1}</text></navLabel><content src=\"chapter-{0}.xhtml\"/></navPoint>",
+            i + 1, xml_text(&chapter.title)))
+        .collect();
+    format!(r#"<?xml version="1.0" encoding="UTF-8"?>
+<ncx xmlns="http://www.daisy.org/z3986/2005/ncx/" version="2005-1">
+<head><meta name="dtb:uid" content="{}"/></head>
+<docTitle><text>{}</text></docTitle>
+<navMap>{}</navMap>
+</ncx>
+"#, xml_attribute(identifier), xml_text(title), points)
+}
+
+fn content_opf(identifier: &str, title: &str, chapters: &[Chapter], images: &[Image],
+        modified: chrono::DateTime<chrono::Utc>) -> String {
+    let language = chapters.iter().find_map(|chapter| chapter.language.as_deref()).unwrap_or("en");
+    let mut creators: Vec<&str> = Vec::new();
+    for author in chapters.iter().filter_map(|chapter| chapter.author.as_deref()) {
+        if !creators.contains(&author) {
+            creators.push(author);
+        }
+    }
+    let creators: String = creators.iter()
+        .map(|creator| format!("<dc:creator>{}</dc:creator>", xml_text(creator)))
+        .collect();
+    let sources: String = chapters.iter()
+        .map(|chapter| format!("<dc:source>{}</dc:source>", xml_text(&chapter.url)))
+        .collect();
+    let chapter_items: String = (1..=chapters.len())
+        .map(|i| format!("<item id=\"chapter-{0}\" href=\"chapter-{0}.xhtml\" media-type=\"application/xhtml+xml\"/>", i))
+        .collect();
+    let image_items: String = images.iter().enumerate()
+        .map(|(i, image)| format!("<item id=\"image-{}\" href=\"{}\" media-type=\"{}\"/>",
+            i + 1, xml_attribute(&image.path), xml_attribute(&image.content_type)))
+        .collect();
+    let spine: String = (1..=chapters.len())
+        .map(|i| format!("<itemref idref=\"chapter-{}\"/>", i))
+        .collect();
+    format!(r#"<?xml version="1.0" encoding="UTF-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0" unique-identifier="book-id">
+<metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+<dc:identifier id="book-id">{}</dc:identifier>
+<dc:title>{}</dc:title>
+<dc:language>{}</dc:language>
+{}{}
+<meta property="dcterms:modified">{}</meta>
+</metadata>
+<manifest>
+<item id="nav" href="nav.xhtml" media-type="application/xhtml+xml" properties="nav"/>
+<item id="ncx" href="toc.ncx" media-type="application/x-dtbncx+xml"/>
+<item id="style" href="style.css" media-type="text/css"/>
+{}{}
+</manifest>
+<spine toc="ncx">{}</spine>
+</package>
+"#, xml_text(identifier), xml_text(title), xml_text(language), creators, sources,
+        modified.format("%Y-%m-%dT%H:%M:%SZ"), chapter_items, image_items, spine)
+}
+
+fn write_epub(title: &str, chapters: &[Chapter], images: &[Image],
+        modified: chrono::DateTime<chrono::Utc>) -> Result<Vec<u8>, EpubError> {
+    // The same selection of webpages gets the same identifier so e-readers see a new export as an
+    // update of the same book.
+    let mut hasher = Sha256::new();
+    for chapter in chapters {
+        hasher.update(chapter.url.as_bytes());
+        hasher.update([0]);
+    }
+    let identifier = format!("urn:sha256:{:x}", hasher.finalize());
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    // The mimetype has to be the first file in the archive and it must not be compressed.
+    zip.start_file("mimetype", FileOptions::default().compression_method(zip::CompressionMethod::Stored))?;
+    zip.write_all(b"application/epub+zip")?;
+    let options = FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+    zip.start_file("META-INF/container.xml", options)?;
+    zip.write_all(CONTAINER_XML.as_bytes())?;
+    zip.start_file("OEBPS/content.opf", options)?;
+    zip.write_all(content_opf(&identifier, title, chapters, images, modified).as_bytes())?;
+    zip.start_file("OEBPS/nav.xhtml", options)?;
+    zip.write_all(nav_xhtml(title, chapters).as_bytes())?;
+    zip.start_file("OEBPS/toc.ncx", options)?;
+    zip.write_all(toc_ncx(&identifier, title, chapters).as_bytes())?;
+    zip.start_file("OEBPS/style.css", options)?;
+    zip.write_all(STYLESHEET.as_bytes())?;
+    for (i, chapter) in chapters.iter().enumerate() {
+        zip.start_file(format!("OEBPS/chapter-{}.xhtml", i + 1), options)?;
+        zip.write_all(chapter_xhtml(chapter).as_bytes())?;
+    }
+    for image in images {
+        zip.start_file(format!("OEBPS/{}", image.path), options)?;
+        zip.write_all(&image.data)?;
+    }
+    Ok(zip.finish()?.into_inner())
+}
+
+/// Creates an EPUB file from the selected webpages of the user. The title of the book is the
+/// title of the webpage if only one is selected unless a title is given.
+pub async fn create_epub(db_pool: &PgPool, http_client: &HttpClient, user_id: i64,
+        selection: &Selection, title: Option<&str>) -> Result<Vec<u8>, EpubError> {
+    let webpages = select_webpages(db_pool, user_id, selection).await?;
+    let mut images: Vec<Image> = Vec::new();
+    let mut image_paths: HashMap<String, String> = HashMap::new();
+    let mut chapters = Vec::new();
+    for webpage in webpages {
+        for src in image_sources(&webpage.content) {
+            if image_paths.contains_key(&src) {
+                continue;
+            }
+            let (content_type, data) = match export::load(db_pool, http_client, user_id, &src, AssetKind::Image).await {
+                Some(image) => image,
+                None => continue,
+            };
+            let extension = match IMAGE_TYPES.iter().find(|(image_type, _)| *image_type == content_type) {
+                Some((_, extension)) => extension,
+                None => continue,
+            };
+            let path = format!("images/{:x}.{}", Sha256::digest(&data), extension);
+            if !images.iter().any(|image| image.path == path) {
+                images.push(Image {path: path.clone(), content_type, data});
+            }
+            image_paths.insert(src, path);
+        }
+        chapters.push(Chapter {
+            content: chapter_content(&webpage.content, &webpage.highlights, &image_paths),
+            title: webpage.title,
+            url: webpage.url,
+            author: webpage.metadata.author,
+            language: webpage.metadata.language,
+        });
+    }
+    let title = match (title, chapters.as_slice()) {
+        (Some(title), _) => title.to_string(),
+        (None, [chapter]) => chapter.title.clone(),
+        _ => DEFAULT_TITLE.to_string(),
+    };
+    write_epub(&title, &chapters, &images, chrono::Utc::now())
+}
+
+#[derive(Deserialize,Debug)]
+pub struct EpubOptions {
+    // A comma separated list of webpage ids.
+    ids: Option<String>,
+    tag: Option<String>,
+    title: Option<String>,
+}
+
+fn parse_ids(ids: &str) -> Option<Vec<i64>> {
+    ids.split(',').map(|id| id.trim().parse::<i64>().ok()).collect()
+}
+
+pub async fn export_epub_handler(options: EpubOptions, db_pool: Arc<PgPool>,
+        http_client: HttpClient, user_id: i64) -> Result<impl warp::Reply, warp::Rejection> {
+    let selection = match (options.ids.as_deref().map(parse_ids), options.tag) {
+        (Some(Some(ids)), None) => Selection::Ids(ids),
+        (None, Some(tag)) => Selection::Tag(tag),
+        _ => return Ok(errors::error_response(StatusCode::BAD_REQUEST,
+            "Either a comma separated list of ids or a tag must be given")),
+    };
+    match create_epub(&db_pool, &http_client, user_id, &selection, options.title.as_deref()).await {
+        Ok(epub) => Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/epub+zip")
+            .header(header::CONTENT_DISPOSITION, "attachment; filename=\"webpages.epub\"")
+            .body(epub)),
+        Err(error) if error.not_found => Ok(errors::error_response(StatusCode::NOT_FOUND, &error.message)),
+        Err(error) => {
+            log::error!("Error when creating epub of {:?} for user {}: {}", selection, user_id, error);
+            Ok(errors::error_response(StatusCode::INTERNAL_SERVER_ERROR, "Unknown error"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Read;
+
+    fn chapter(title: &str, content: &str) -> Chapter {
+        Chapter {
+            title: title.to_string(),
+            url: "https://example.com/?a=1&b=2".to_string(),
+            author: Some("Author".to_string()),
+            language: Some("da".to_string()),
+            content: content.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_chapter_content() {
+        let image_paths = HashMap::from([("/api/asset/abc".to_string(), "images/abc.png".to_string())]);
+        assert_eq!(chapter_content("<p>Text<br><img src=\"/api/asset/abc\" srcset=\"a.png 2x\"><img src=\"https://example.com/missing.png\"></p>", &[], &image_paths),
+            "<p>Text<br/><img alt=\"\" src=\"images/abc.png\"/></p>");
+    }
+
+    #[test]
+    fn test_write_epub() {
+        let chapters = vec![chapter("First & best", "<p>One</p>"), chapter("Second", "<p>Two</p>")];
+        let images = vec![Image {path: "images/abc.png".to_string(), content_type: "image/png".to_string(), data: vec![1, 2, 3]}];
+        let epub = write_epub("Book", &chapters, &images, chrono::Utc::now()).expect("Unable to write epub");
+        let mut archive = zip::ZipArchive::new(Cursor::new(epub)).expect("Unable to read epub");
+        {
+            let mimetype = archive.by_index(0).expect("Unable to get first file");
+            assert_eq!(mimetype.name(), "mimetype");
+            assert_eq!(mimetype.compression(), zip::CompressionMethod::Stored);
+        }
+        let mut read = |name: &str| {
+            let mut content = String::new();
+            archive.by_name(name).expect("Missing file in epub").read_to_string(&mut content)
+                .expect("Unable to read file in epub");
+            content
+        };
+        let opf = read("OEBPS/content.opf");
+        assert!(opf.contains("<dc:title>Book</dc:title>"));
+        assert!(opf.contains("<dc:creator>Author</dc:creator>"));
+        assert!(opf.contains("<dc:source>https://example.com/?a=1&amp;b=2</dc:source>"));
+        assert!(opf.contains("<item id=\"image-1\" href=\"images/abc.png\" media-type=\"image/png\"/>"));
+        assert!(opf.contains("<itemref idref=\"chapter-1\"/><itemref idref=\"chapter-2\"/>"));
+        assert!(read("OEBPS/nav.xhtml").contains("<li><a href=\"chapter-1.xhtml\">First &amp; best</a></li><li><a href=\"chapter-2.xhtml\">Second</a></li>"));
+        assert!(read("OEBPS/chapter-2.xhtml").contains("<h1>Second</h1><p class=\"source\">Author, <a href=\"https://example.com/?a=1&amp;b=2\">https://example.com/?a=1&amp;b=2</a></p><p>Two</p>"));
+        assert!(archive.by_name("OEBPS/images/abc.png").is_ok());
+    }
+
+    #[test]
+    fn test_parse_ids() {
+        assert_eq!(parse_ids("1, 2,3"), Some(vec![1, 2, 3]));
+        assert_eq!(parse_ids("1,a"), None);
+    }
+}
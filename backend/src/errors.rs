@@ -1,9 +1,10 @@
 use serde::Serialize;
-use warp::http::StatusCode;
+use warp::http::{header,Response,StatusCode};
 
 // Largely taken from https://github.com/zupzup/rust-jwt-example/blob/main/src/error.rs
 
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum Error {
     MissingAuthorizationHeader,
     UnknownUser,
@@ -20,6 +21,28 @@ pub struct ErrorResponse {
     pub status: String,
 }
 
+/// An ErrorResponse as a json reply with the status.
+pub fn error_reply(status: StatusCode, message: &str) -> warp::reply::WithStatus<warp::reply::Json> {
+    let json = warp::reply::json(&ErrorResponse {
+        message: message.to_string(),
+        status: status.to_string(),
+    });
+    warp::reply::with_status(json, status)
+}
+
+/// An ErrorResponse for handlers which build their response with Response::builder() and
+/// therefore cannot use warp::reply::json.
+pub fn error_response(status: StatusCode, message: &str) -> Result<Response<Vec<u8>>, warp::http::Error> {
+    let json = serde_json::to_vec(&ErrorResponse {
+        message: message.to_string(),
+        status: status.to_string(),
+    }).unwrap_or_default();
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(json)
+}
+
 #[derive(Debug, Clone)]
 pub struct ParseDocumentError<'a> {
     message: &'a str,
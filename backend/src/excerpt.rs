@@ -0,0 +1,221 @@
+// A short excerpt of a webpage shown below its title in lists and search results. The description
+// the page gives of itself is used when there is one. Otherwise the excerpt is taken from the
+// readable text: articles usually start with a paragraph summing them up, so the first paragraphs
+// which read like prose are used. Pages without such paragraphs, e.g. pages made of lists and
+// short snippets, get the most central sentences chosen by TextRank instead.
+// https://aclanthology.org/W04-3252/
+
+use std::collections::HashSet;
+
+use crate::render;
+use crate::text_stats::TextStats;
+
+// Excerpts are cut off at a word boundary after this many characters.
+const MAX_LENGTH: usize = 300;
+// Paragraphs are added until the excerpt is at least this long.
+const MIN_LENGTH: usize = 120;
+// Headings, captions and bylines are shorter than this.
+const MIN_PARAGRAPH_WORDS: i32 = 12;
+// TextRank compares every pair of sentences, so only the first sentences are ranked.
+const MAX_SENTENCES: usize = 200;
+const DAMPING: f64 = 0.85;
+const ITERATIONS: usize = 30;
+const SENTENCE_ENDINGS: [char; 7] = ['.', '!', '?', '…', '。', '！', '？'];
+
+// Cuts the text off at the last word boundary before MAX_LENGTH characters.
+fn truncate(text: &str) -> String {
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if text.chars().count() <= MAX_LENGTH {
+        return text;
+    }
+    let end = text.char_indices().nth(MAX_LENGTH).map_or(text.len(), |(index, _)| index);
+    let cut = match text[..end].rfind(' ') {
+        Some(space) if space > 0 => &text[..space],
+        _ => &text[..end],
+    };
+    format!("{}…", cut.trim_end_matches(|c: char| c.is_ascii_punctuation()))
+}
+
+fn ends_sentence(text: &str) -> bool {
+    let text = text.trim_end_matches(['"', '\'', '”', '’', ')', '»']);
+    text.ends_with(SENTENCE_ENDINGS)
+}
+
+// A paragraph which reads like prose, i.e. not a heading, a list, a code block or a caption.
+fn is_meaningful(paragraph: &str) -> bool {
+    let first_line = paragraph.lines().next().unwrap_or_default();
+    let is_list = first_line.starts_with("- ")
+        || first_line.split_once(". ").is_some_and(|(number, _)| number.parse::<u32>().is_ok());
+    !is_list && !paragraph.contains('\t') && paragraph.lines().count() <= 3
+        && TextStats::new(paragraph).word_count >= MIN_PARAGRAPH_WORDS && ends_sentence(paragraph)
+}
+
+fn first_paragraphs(paragraphs: &[&str]) -> Option<String> {
+    let mut excerpt = String::new();
+    for paragraph in paragraphs.iter().filter(|paragraph| is_meaningful(paragraph)) {
+        if !excerpt.is_empty() {
+            excerpt.push(' ');
+        }
+        excerpt.push_str(paragraph);
+        if excerpt.chars().count() >= MIN_LENGTH {
+            break;
+        }
+    }
+    Some(excerpt).filter(|excerpt| !excerpt.is_empty())
+}
+
+fn split_sentences(text: &str) -> Vec<String> {
+    let mut sentences = Vec::new();
+    let mut sentence = String::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        sentence.push(c);
+        let at_end = chars.peek().is_none_or(|next| next.is_whitespace() || SENTENCE_ENDINGS.contains(next));
+        if c == '\n' || (SENTENCE_ENDINGS.contains(&c) && at_end) {
+            let trimmed = sentence.trim();
+            if !trimmed.is_empty() {
+                sentences.push(trimmed.to_string());
+            }
+            sentence.clear();
+        }
+    }
+    if !sentence.trim().is_empty() {
+        sentences.push(sentence.trim().to_string());
+    }
+    sentences
+}
+
+fn words(sentence: &str) -> HashSet<String> {
+    sentence.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+// The TextRank score of each sentence. Sentences are linked by the words they share, normalized by
+// their lengths so long sentences aren't favoured just for being long.
+fn rank_sentences(sentences: &[String]) -> Vec<f64> {
+    let words: Vec<HashSet<String>> = sentences.iter().map(|sentence| words(sentence)).collect();
+    let count = sentences.len();
+    let mut weights = vec![vec![0.0; count]; count];
+    for i in 0..count {
+        for j in (i + 1)..count {
+            if words[i].len() < 2 || words[j].len() < 2 {
+                continue;
+            }
+            let shared = words[i].intersection(&words[j]).count() as f64;
+            let weight = shared / ((words[i].len() as f64).ln() + (words[j].len() as f64).ln());
+            weights[i][j] = weight;
+            weights[j][i] = weight;
+        }
+    }
+    let totals: Vec<f64> = weights.iter().map(|row| row.iter().sum()).collect();
+    let mut scores = vec![1.0; count];
+    for _ in 0..ITERATIONS {
+        scores = (0..count).map(|i| {
+            let linked: f64 = (0..count)
+                .filter(|j| totals[*j] > 0.0)
+                .map(|j| weights[j][i] / totals[j] * scores[j])
+                .sum();
+            (1.0 - DAMPING) + DAMPING * linked
+        }).collect();
+    }
+    scores
+}
+
+fn strip_list_marker(line: &str) -> &str {
+    if let Some(item) = line.trim_start().strip_prefix("- ") {
+        return item;
+    }
+    match line.trim_start().split_once(". ") {
+        Some((number, item)) if number.parse::<u32>().is_ok() => item,
+        _ => line,
+    }
+}
+
+// The highest ranked sentences in the order they appear in the text.
+fn central_sentences(text: &str) -> Option<String> {
+    let text = text.lines().map(strip_list_marker).collect::<Vec<_>>().join("\n");
+    let sentences: Vec<String> = split_sentences(&text).into_iter()
+        .filter(|sentence| TextStats::new(sentence).word_count >= 4)
+        .take(MAX_SENTENCES)
+        .collect();
+    let scores = rank_sentences(&sentences);
+    let mut ranked: Vec<usize> = (0..sentences.len()).collect();
+    ranked.sort_by(|a, b| scores[*b].total_cmp(&scores[*a]).then(a.cmp(b)));
+    let mut chosen = Vec::new();
+    let mut length = 0;
+    for index in ranked {
+        if length >= MIN_LENGTH {
+            break;
+        }
+        length += sentences[index].chars().count() + 1;
+        chosen.push(index);
+    }
+    chosen.sort();
+    // Sentences from lists and tables often have no full stop, which they need to be read as
+    // separate sentences in the excerpt.
+    let excerpt = chosen.iter()
+        .map(|index| {
+            let sentence = &sentences[*index];
+            if ends_sentence(sentence) { sentence.clone() } else { format!("{}.", sentence) }
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+    Some(excerpt).filter(|excerpt| !excerpt.is_empty())
+}
+
+/// Makes an excerpt of a webpage from its description or its readable html.
+pub(crate) fn excerpt(description: Option<&str>, contents: &str) -> Option<String> {
+    if let Some(description) = description.filter(|description| !description.trim().is_empty()) {
+        return Some(truncate(description));
+    }
+    let text = render::render(contents, render::Format::Text);
+    let paragraphs: Vec<&str> = text.split("\n\n").map(str::trim).collect();
+    first_paragraphs(&paragraphs)
+        .or_else(|| central_sentences(&text))
+        .map(|excerpt| truncate(&excerpt))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate(" A short\n text. "), "A short text.");
+        let long = "word ".repeat(100);
+        let truncated = truncate(&long);
+        assert!(truncated.ends_with("word…"), "{}", truncated);
+        assert!(truncated.chars().count() <= MAX_LENGTH + 1);
+        assert_eq!(truncate(&format!("{}, and more", "a".repeat(MAX_LENGTH - 2))), format!("{}…", "a".repeat(MAX_LENGTH - 2)));
+    }
+
+    #[test]
+    fn test_excerpt_description() {
+        assert_eq!(excerpt(Some("The description of the page."), "<p>The text of the page.</p>").as_deref(),
+            Some("The description of the page."));
+    }
+
+    #[test]
+    fn test_excerpt_first_paragraphs() {
+        let contents = "<h1>A very long heading which is not a paragraph at all</h1><p>By An Author</p><figure><img src=\"a.png\"><figcaption>A caption of the image above which has many words in it</figcaption></figure><p>The first paragraph of the article, which tells what it is about in a few words.</p><ul><li>A list item which is long enough to be a paragraph but is in a list.</li></ul><p>The second paragraph of the article goes into more detail about the subject of the article.</p><p>The third paragraph isn't needed since the excerpt is long enough.</p>";
+        assert_eq!(excerpt(None, contents).as_deref(), Some("The first paragraph of the article, which tells what it is about in a few words. The second paragraph of the article goes into more detail about the subject of the article."));
+    }
+
+    #[test]
+    fn test_excerpt_central_sentences() {
+        // A page without any paragraphs of prose gets the sentences sharing the most words with
+        // the rest of the page.
+        let contents = "<ul><li>Hedgehogs eat insects, worms and snails in gardens</li><li>Hedgehogs sleep through the winter in gardens and hedges</li><li>The weather is nice today</li><li>Hedgehogs roll into a ball when they are afraid</li><li>Gardens with hedges are good for hedgehogs and insects</li></ul>";
+        assert_eq!(excerpt(None, contents).as_deref(),
+            Some("Hedgehogs eat insects, worms and snails in gardens. Hedgehogs sleep through the winter in gardens and hedges. Gardens with hedges are good for hedgehogs and insects."));
+        assert_eq!(excerpt(None, ""), None);
+    }
+
+    #[test]
+    fn test_split_sentences() {
+        assert_eq!(split_sentences("One sentence. Another one! Version 1.5 is out?\nA line\nwithout a stop"),
+            vec!["One sentence.", "Another one!", "Version 1.5 is out?", "A line", "without a stop"]);
+    }
+}
@@ -0,0 +1,436 @@
+// Exports a saved webpage as a single html file which can be opened without the webpage saver.
+// Images, stylesheets and fonts are embedded as data urls, either from the archived assets or
+// downloaded from the original site, and scripts are removed.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use html5ever::tendril::TendrilSink;
+use kuchiki::NodeRef;
+use kuchiki::iter::NodeIterator;
+use serde::Deserialize;
+use sqlx::PgPool;
+use url::Url;
+use warp::http::{header,Response,StatusCode};
+
+use crate::assets::{self,AssetKind};
+use crate::webpages::{self,ShowMode,StoredWebpage};
+use crate::fetch::HttpClient;
+use crate::{errors,highlights,sanitize,urls};
+
+const READABLE_STYLESHEET: &str = "body { max-width: 40em; margin: 2em auto; padding: 0 1em; font-family: Georgia, serif; line-height: 1.6; } img { max-width: 100%; height: auto; } pre { overflow-x: auto; }";
+// The export header is also added to the original html so it is styled inline to not depend on
+// the stylesheets of the page.
+const SOURCE_HEADER_STYLE: &str = "font: 14px/1.4 sans-serif; color: #333; background: #f4f4f4; border-bottom: 1px solid #ccc; padding: 0.5em 1em; margin: 0 0 1em 0;";
+const SCRIPT_ATTRIBUTES: [&str; 5] = ["href", "src", "action", "formaction", "xlink:href"];
+
+// The names of these enum variants should appear exactly as they are meant to be typed in as query
+// parameters by the user.
+#[allow(non_camel_case_types)]
+#[derive(Deserialize,Debug)]
+enum ExportFormat {
+    singlefile,
+}
+
+#[derive(Deserialize,Debug)]
+pub struct ExportOptions {
+    format: ExportFormat,
+    mode: Option<ShowMode>,
+}
+
+// The urls of everything which should be embedded in the exported document.
+#[derive(Debug,Default)]
+struct Resources {
+    images: Vec<String>,
+    stylesheets: Vec<String>,
+    // Urls used in <style> elements and style attributes.
+    css_urls: Vec<String>,
+}
+
+// Embedded resources keyed by their url. Stylesheets are embedded as <style> elements while
+// everything else is turned into data urls.
+#[derive(Debug,Default)]
+struct Embedded {
+    data_urls: HashMap<String, String>,
+    stylesheets: HashMap<String, String>,
+}
+
+fn data_url(content_type: &str, data: &[u8]) -> String {
+    format!("data:{};base64,{}", content_type, base64::encode(data))
+}
+
+// Archived assets are referred to by their path which must not be resolved against the page.
+fn resolve(base: &Url, url: &str) -> String {
+    match assets::asset_hash(url) {
+        Some(_) => url.to_string(),
+        None => urls::resolve_url(base, url),
+    }
+}
+
+fn attribute(node: &NodeRef, name: &str) -> Option<String> {
+    node.as_element().and_then(|element| {
+        element.attributes.borrow().get(name).map(|value| value.to_string())
+    })
+}
+
+fn select(document: &NodeRef, selector: &str) -> Vec<NodeRef> {
+    document.select(selector).map_or_else(|_| Vec::new(), |elements| {
+        elements.map(|element| element.as_node().clone()).collect()
+    })
+}
+
+fn find_resources(document: &NodeRef, base: &Url) -> Resources {
+    let images = select(document, "img[src]").iter()
+        .filter_map(|image| attribute(image, "src"))
+        .map(|src| resolve(base, &src))
+        .collect();
+    let stylesheets = select(document, "link[rel~=stylesheet][href]").iter()
+        .filter_map(|link| attribute(link, "href"))
+        .map(|href| resolve(base, &href))
+        .collect();
+    let css_urls = select(document, "style").iter().map(|style| style.text_contents())
+        .chain(select(document, "[style]").iter().filter_map(|element| attribute(element, "style")))
+        .flat_map(|css| assets::css_urls(&css, base))
+        .collect();
+    Resources {images, stylesheets, css_urls}
+}
+
+// Loads an image, stylesheet or font from the archived assets of the user or else from the
+// original site.
+pub(crate) async fn load(db_pool: &PgPool, http_client: &HttpClient, user_id: i64, url: &str,
+        kind: AssetKind) -> Option<(String, Vec<u8>)> {
+    if let Some(hash) = assets::asset_hash(url) {
+        return assets::get_asset(db_pool, hash, user_id).await
+            .map_err(|error| log::error!("Error when fetching asset {} for user {}: {}", hash, user_id, error))
+            .ok()
+            .flatten();
+    }
+    if !url.starts_with("http://") && !url.starts_with("https://") {
+        return None;
+    }
+    match assets::download(http_client, url, kind, assets::MAX_ASSET_SIZE).await {
+        Ok(asset) => Some((asset.content_type, asset.data)),
+        Err(error) => {
+            log::warn!("Unable to embed {} in export: {}", url, error);
+            None
+        }
+    }
+}
+
+async fn load_data_urls(db_pool: &PgPool, http_client: &HttpClient, user_id: i64,
+        urls: Vec<String>, kind: AssetKind, data_urls: &mut HashMap<String, String>) {
+    for url in urls {
+        if data_urls.contains_key(&url) {
+            continue;
+        }
+        if let Some((content_type, data)) = load(db_pool, http_client, user_id, &url, kind).await {
+            data_urls.insert(url, data_url(&content_type, &data));
+        }
+    }
+}
+
+async fn embed_resources(db_pool: &PgPool, http_client: &HttpClient, user_id: i64,
+        resources: Resources, base: &Url) -> Embedded {
+    let mut embedded = Embedded::default();
+    load_data_urls(db_pool, http_client, user_id, resources.images, AssetKind::Image,
+        &mut embedded.data_urls).await;
+    load_data_urls(db_pool, http_client, user_id, resources.css_urls,
+        AssetKind::StylesheetResource, &mut embedded.data_urls).await;
+    for url in resources.stylesheets {
+        let css = match load(db_pool, http_client, user_id, &url, AssetKind::Stylesheet).await {
+            Some((_, data)) => String::from_utf8_lossy(&data).to_string(),
+            None => continue,
+        };
+        // Urls in a stylesheet are relative to the stylesheet. Archived stylesheets only refer to
+        // other archived assets so the page url works as well as any.
+        let stylesheet_base = Url::parse(&url).unwrap_or_else(|_| base.clone());
+        load_data_urls(db_pool, http_client, user_id, assets::css_urls(&css, &stylesheet_base),
+            AssetKind::StylesheetResource, &mut embedded.data_urls).await;
+        let css = assets::replace_css_urls(&css, &stylesheet_base, &embedded.data_urls);
+        embedded.stylesheets.insert(url, css);
+    }
+    embedded
+}
+
+// Urls which run a script when followed, ignoring the tabs and newlines browsers ignore as well.
+// Data urls can hold an html page with scripts of its own, so only images are kept.
+fn is_script_url(url: &str) -> bool {
+    let url: String = url.chars()
+        .filter(|c| !c.is_ascii_whitespace() && !c.is_control())
+        .collect::<String>()
+        .to_lowercase();
+    url.starts_with("javascript:") || url.starts_with("vbscript:")
+        || (url.starts_with("data:") && !url.starts_with("data:image/"))
+}
+
+// Removes scripts, event handlers, script urls and the elements which can embed another document
+// with scripts of its own.
+fn remove_scripts(document: &NodeRef) {
+    select(document, "script, link[rel~=modulepreload], link[as=script], iframe, frame, object, embed").iter()
+        .for_each(|node| node.detach());
+    // SVG animations can set the href of a link to a javascript: url after the attributes were checked.
+    select(document, "svg animate, svg set, svg animateMotion").iter()
+        .for_each(|node| node.detach());
+    select(document, "meta[http-equiv]").iter()
+        .filter(|meta| attribute(meta, "http-equiv").is_some_and(|value| value.trim().eq_ignore_ascii_case("refresh")))
+        .for_each(|node| node.detach());
+    for element in document.descendants().elements() {
+        let mut attributes = element.attributes.borrow_mut();
+        let script_attributes: Vec<_> = attributes.map.keys()
+            .filter(|name| {
+                let name = &*name.local;
+                name.to_lowercase().starts_with("on") || name == "srcdoc" || (SCRIPT_ATTRIBUTES.contains(&name) &&
+                    attributes.get(name).is_some_and(is_script_url))
+            })
+            .cloned()
+            .collect();
+        for name in script_attributes {
+            attributes.map.remove(&name);
+        }
+    }
+}
+
+fn replace_resources(document: &NodeRef, base: &Url, embedded: &Embedded) {
+    for image in select(document, "img[src]") {
+        let data_url = attribute(&image, "src").and_then(|src| embedded.data_urls.get(&resolve(base, &src)));
+        if let (Some(data_url), Some(element)) = (data_url, image.as_element()) {
+            let mut attributes = element.attributes.borrow_mut();
+            attributes.insert("src", data_url.to_string());
+            attributes.remove("srcset");
+        }
+    }
+    // The browser would load one of the alternatives instead of the embedded image.
+    select(document, "picture > source").iter().for_each(|source| source.detach());
+    for link in select(document, "link[rel~=stylesheet][href]") {
+        let css = attribute(&link, "href").and_then(|href| embedded.stylesheets.get(&resolve(base, &href)));
+        if let Some(css) = css {
+            let style = kuchiki::parse_html().one("<style></style>").select_first("style")
+                .map(|style| style.as_node().clone());
+            if let Ok(style) = style {
+                style.append(NodeRef::new_text(css.as_str()));
+                link.insert_before(style);
+                link.detach();
+            }
+        }
+    }
+    for style in select(document, "style") {
+        let css = style.text_contents();
+        let replaced = assets::replace_css_urls(&css, base, &embedded.data_urls);
+        if replaced != css {
+            style.children().for_each(|child| child.detach());
+            style.append(NodeRef::new_text(replaced));
+        }
+    }
+    for element in select(document, "[style]") {
+        if let (Some(css), Some(element)) = (attribute(&element, "style"), element.as_element()) {
+            let replaced = assets::replace_css_urls(&css, base, &embedded.data_urls);
+            element.attributes.borrow_mut().insert("style", replaced);
+        }
+    }
+}
+
+fn source_header(webpage: &StoredWebpage) -> String {
+    format!("<div class=\"webpage-saver-source\" style=\"{}\">Saved from <a href=\"{}\">{}</a> on <time datetime=\"{}\">{}</time></div>",
+        SOURCE_HEADER_STYLE, sanitize::escape_attribute(&webpage.url), sanitize::escape_text(&webpage.url),
+        webpage.added.to_rfc3339(), webpage.added.format("%Y-%m-%d"))
+}
+
+fn readable_document(webpage: &StoredWebpage) -> String {
+    let lang = webpage.metadata.language.as_ref()
+        .map(|language| format!(" lang=\"{}\"", sanitize::escape_attribute(language)))
+        .unwrap_or_default();
+    let byline = webpage.metadata.author.as_ref()
+        .map(|author| format!("<p><em>{}</em></p>", sanitize::escape_text(author)))
+        .unwrap_or_default();
+    let content = if webpage.highlights.is_empty() {
+        webpage.content.clone()
+    } else {
+        let document = kuchiki::parse_html().one(webpage.content.as_str());
+        highlights::add_highlights(&document, &webpage.highlights);
+        sanitize::node_to_html(&document)
+    };
+    format!("<!DOCTYPE html><html{}><head><title>{}</title><style>{}</style></head><body><article><h1>{}</h1>{}{}</article></body></html>",
+        lang, sanitize::escape_text(&webpage.title), READABLE_STYLESHEET,
+        sanitize::escape_text(&webpage.title), byline, content)
+}
+
+// Adds the header recording where the webpage came from and declares the document as utf-8 since
+// that is what it is serialized as regardless of the encoding of the original.
+fn add_header(document: &NodeRef, webpage: &StoredWebpage) {
+    select(document, "meta[charset], meta[http-equiv], base").iter().for_each(|node| node.detach());
+    let fragment = kuchiki::parse_html().one(
+        format!("<head><meta charset=\"utf-8\"></head><body>{}</body>", source_header(webpage)));
+    if let (Ok(head), Ok(meta)) = (document.select_first("head"), fragment.select_first("meta")) {
+        head.as_node().prepend(meta.as_node().clone());
+    }
+    if let (Ok(body), Ok(header)) = (document.select_first("body"), fragment.select_first("div")) {
+        body.as_node().prepend(header.as_node().clone());
+    }
+}
+
+async fn single_file_html(db_pool: &PgPool, http_client: &HttpClient, user_id: i64,
+        webpage: &StoredWebpage, mode: &ShowMode) -> String {
+    let html = match mode {
+        ShowMode::original => webpage.content.clone(),
+        // Only readable and original webpages are exported as html.
+        ShowMode::readable | ShowMode::text | ShowMode::markdown => readable_document(webpage),
+    };
+    // Relative urls are left as they are if the url of the page cannot be parsed.
+    let (base, resources) = {
+        let document = kuchiki::parse_html().one(html.as_str());
+        let base = urls::base_url(&document, &webpage.url)
+            .unwrap_or_else(|| Url::parse("about:blank").expect("Unable to parse about:blank"));
+        let resources = find_resources(&document, &base);
+        (base, resources)
+    };
+    let embedded = embed_resources(db_pool, http_client, user_id, resources, &base).await;
+    let document = kuchiki::parse_html().one(html.as_str());
+    remove_scripts(&document);
+    replace_resources(&document, &base, &embedded);
+    // Links have to keep working when the file is opened from somewhere else than the site.
+    urls::resolve_urls(&document, &base);
+    add_header(&document, webpage);
+    document.to_string()
+}
+
+// Only keeps characters which are safe in a filename on every platform.
+fn filename(title: &str) -> String {
+    let name: String = title.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { ' ' })
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join("-");
+    let name: String = name.chars().take(100).collect();
+    if name.is_empty() {
+        "webpage".to_string()
+    } else {
+        name
+    }
+}
+
+pub async fn export_webpage_handler(webpage_id: i64, options: ExportOptions, db_pool: Arc<PgPool>,
+        http_client: HttpClient, user_id: i64) -> Result<impl warp::Reply, warp::Rejection> {
+    let mode = options.mode.unwrap_or(ShowMode::readable);
+    if !matches!(mode, ShowMode::readable | ShowMode::original) {
+        return Ok(errors::error_response(StatusCode::BAD_REQUEST,
+            "Only the readable and original modes can be exported as a single file"));
+    }
+    let webpage = match webpages::get_stored_webpage(&db_pool, webpage_id, user_id, &mode).await {
+        Ok(Some(webpage)) => webpage,
+        Ok(None) => return Ok(errors::error_response(StatusCode::NOT_FOUND, "Webpage not found")),
+        Err(error) => {
+            log::error!("Error when fetching webpage {} for user {} from database {}",
+                webpage_id, user_id, error);
+            return Ok(errors::error_response(StatusCode::INTERNAL_SERVER_ERROR, "Unknown error"));
+        }
+    };
+    let (html, extension) = match options.format {
+        ExportFormat::singlefile => (single_file_html(&db_pool, &http_client, user_id, &webpage, &mode).await, "html"),
+    };
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+        .header(header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}.{}\"", filename(&webpage.title), extension))
+        .body(html.into_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(html: &str) -> NodeRef {
+        kuchiki::parse_html().one(html)
+    }
+
+    fn url(url: &str) -> Url {
+        Url::parse(url).expect("Unable to parse url")
+    }
+
+    #[test]
+    fn test_find_resources() {
+        let document = parse("<head><link rel=\"stylesheet\" href=\"style.css\"><style>p { background: url(bg.png) }</style></head><body><img src=\"/api/asset/abc\"><img src=\"image.png\"><div style=\"background: url('/div.png')\"></div></body>");
+        let resources = find_resources(&document, &url("https://example.com/a/page"));
+        assert_eq!(resources.images, vec!["/api/asset/abc".to_string(), "https://example.com/a/image.png".to_string()]);
+        assert_eq!(resources.stylesheets, vec!["https://example.com/a/style.css".to_string()]);
+        assert_eq!(resources.css_urls, vec!["https://example.com/a/bg.png".to_string(), "https://example.com/div.png".to_string()]);
+    }
+
+    #[test]
+    fn test_remove_scripts() {
+        let document = parse("<head><script src=\"a.js\"></script><link rel=\"modulepreload\" href=\"b.js\"></head><body><a href=\"javascript:alert(1)\" onclick=\"alert(2)\">link</a><img src=\"a.png\" OnError=\"alert(3)\"></body>");
+        remove_scripts(&document);
+        assert_eq!(document.to_string(), "<html><head></head><body><a>link</a><img src=\"a.png\"></body></html>");
+    }
+
+    fn without_scripts(html: &str) -> String {
+        let document = parse(html);
+        remove_scripts(&document);
+        document.to_string()
+    }
+
+    #[test]
+    fn test_remove_scripts_iframe_srcdoc() {
+        assert_eq!(without_scripts("<body><iframe srcdoc=\"<script>alert(1)</script>\"></iframe><p>Text</p></body>"),
+            "<html><head></head><body><p>Text</p></body></html>");
+    }
+
+    #[test]
+    fn test_remove_scripts_object() {
+        assert_eq!(without_scripts("<body><object data=\"data:text/html,<script>alert(1)</script>\"></object></body>"),
+            "<html><head></head><body></body></html>");
+    }
+
+    #[test]
+    fn test_remove_scripts_embed() {
+        assert_eq!(without_scripts("<body><embed src=\"movie.swf\"></body>"), "<html><head></head><body></body></html>");
+    }
+
+    #[test]
+    fn test_remove_scripts_frame() {
+        assert_eq!(without_scripts("<frameset><frame src=\"javascript:alert(1)\"></frameset>"),
+            "<html><head></head><frameset></frameset></html>");
+    }
+
+    #[test]
+    fn test_remove_scripts_meta_refresh() {
+        assert_eq!(without_scripts("<head><meta http-equiv=\"Refresh\" content=\"0;url=javascript:alert(1)\"><meta charset=\"utf-8\"></head>"),
+            "<html><head><meta charset=\"utf-8\"></head><body></body></html>");
+    }
+
+    #[test]
+    fn test_remove_scripts_data_url() {
+        assert_eq!(without_scripts("<body><a href=\"data:text/html;base64,PHNjcmlwdD5hbGVydCgxKTwvc2NyaXB0Pg==\">link</a><img src=\"data:image/png;base64,AAAA\"></body>"),
+            "<html><head></head><body><a>link</a><img src=\"data:image/png;base64,AAAA\"></body></html>");
+    }
+
+    #[test]
+    fn test_remove_scripts_vbscript_url() {
+        assert_eq!(without_scripts("<body><a href=\" VBScript:MsgBox(1)\">link</a><a href=\"java&#9;script:alert(1)\">tab</a></body>"),
+            "<html><head></head><body><a>link</a><a>tab</a></body></html>");
+    }
+
+    #[test]
+    fn test_remove_scripts_svg_animation() {
+        assert_eq!(without_scripts("<body><svg><a><animate attributeName=\"href\" to=\"javascript:alert(1)\"/><set attributeName=\"href\" to=\"javascript:alert(2)\"/><animateMotion path=\"M0,0 L10,10\"/><text>link</text></a></svg></body>"),
+            "<html><head></head><body><svg><a><text>link</text></a></svg></body></html>");
+    }
+
+    #[test]
+    fn test_replace_resources() {
+        let document = parse("<head><link rel=\"stylesheet\" href=\"/style.css\"></head><body><picture><source srcset=\"a.webp\"><img src=\"/api/asset/abc\" srcset=\"a-2x.png 2x\"></picture><img src=\"missing.png\"></body>");
+        let embedded = Embedded {
+            data_urls: HashMap::from([("/api/asset/abc".to_string(), "data:image/png;base64,AAAA".to_string())]),
+            stylesheets: HashMap::from([("https://example.com/style.css".to_string(), "p { color: red }".to_string())]),
+        };
+        replace_resources(&document, &url("https://example.com/page"), &embedded);
+        assert_eq!(document.to_string(), "<html><head><style>p { color: red }</style></head><body><picture><img src=\"data:image/png;base64,AAAA\"></picture><img src=\"missing.png\"></body></html>");
+    }
+
+    #[test]
+    fn test_filename() {
+        assert_eq!(filename("A title: with / symbols?"), "A-title-with-symbols");
+        assert_eq!(filename("???"), "webpage");
+    }
+}
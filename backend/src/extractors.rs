@@ -0,0 +1,277 @@
+// The generic article extraction does well on regular articles but some sites are laid out so it
+// picks the wrong part of the page or misses most of it, e.g. the README of a GitHub repository
+// or the comments of a Hacker News thread. Extractors for such sites are registered for url
+// patterns and the first one matching the url of a page gets to pick its title and contents.
+// Sites can also be described by declarative rules, see site_config.rs. Rules loaded from the
+// site config directory come before the built-in extractors so they can override them.
+
+use std::path::Path;
+use std::str::FromStr;
+
+use html5ever::tendril::TendrilSink;
+use kuchiki::NodeRef;
+use url::Url;
+
+use crate::errors::ParseDocumentError;
+use crate::sanitize;
+use crate::site_config::SiteConfig;
+use crate::urls;
+
+// The built-in site configs, in the same format as the files in the site config directory.
+const BUILT_IN_SITE_CONFIGS: [(&str, &str); 2] = [
+    ("wikipedia.org", include_str!("site-config/wikipedia.org.txt")),
+    ("substack.com", include_str!("site-config/substack.com.txt")),
+];
+
+/// The title and contents picked by an extractor.
+pub(crate) struct Extraction {
+    pub title: Option<String>,
+    pub contents: Vec<NodeRef>,
+}
+
+pub(crate) trait Extractor: Send + Sync {
+    /// Extracts the title and contents of the page at url from its document, which has been
+    /// cleaned of scripts and styles and whose urls have been resolved. The extractor is free to
+    /// change the document. Returning None falls back to the generic extraction.
+    fn extract(&self, document: &NodeRef, url: &Url) -> Option<Extraction>;
+}
+
+/// A pattern matched against the host and path of a url, e.g. "*.wikipedia.org/wiki/*", where an
+/// asterisk matches any sequence of characters. A leading "*." also matches the domain itself and
+/// a pattern without a path matches every path.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub(crate) struct UrlPattern(String);
+
+impl UrlPattern {
+    pub fn matches(&self, url: &Url) -> bool {
+        let Some(host) = url.host_str() else {
+            return false;
+        };
+        let target = format!("{}{}", host, url.path());
+        urls::glob_matches(&self.0, &target)
+            || self.0.strip_prefix("*.").is_some_and(|pattern| urls::glob_matches(pattern, &target))
+    }
+}
+
+impl FromStr for UrlPattern {
+    type Err = String;
+
+    fn from_str(pattern: &str) -> Result<Self, Self::Err> {
+        let pattern = pattern.trim()
+            .trim_start_matches("https://").trim_start_matches("http://")
+            .to_lowercase();
+        if pattern.is_empty() || pattern.contains(char::is_whitespace) {
+            return Err(format!("Invalid url pattern {}", pattern));
+        }
+        Ok(UrlPattern(if pattern.contains('/') { pattern } else { format!("{}/*", pattern) }))
+    }
+}
+
+struct RegisteredExtractor {
+    name: String,
+    url_patterns: Vec<UrlPattern>,
+    extractor: Box<dyn Extractor>,
+}
+
+/// The extractors in the order they are tried.
+pub struct ExtractorRegistry {
+    extractors: Vec<RegisteredExtractor>,
+}
+
+impl std::fmt::Debug for ExtractorRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        f.debug_list().entries(self.extractors.iter().map(|extractor| &extractor.name)).finish()
+    }
+}
+
+impl ExtractorRegistry {
+    /// A registry without any extractors, which always uses the generic extraction.
+    pub fn empty() -> Self {
+        ExtractorRegistry {extractors: Vec::new()}
+    }
+
+    /// The registry with the built-in extractors.
+    pub fn built_in() -> Self {
+        let mut registry = ExtractorRegistry::empty();
+        registry.register("github", vec![UrlPattern("github.com/*/*".to_string())], Box::new(GitHubExtractor));
+        registry.register("hacker-news", vec![UrlPattern("news.ycombinator.com/item".to_string())],
+            Box::new(HackerNewsExtractor));
+        for (name, config) in BUILT_IN_SITE_CONFIGS {
+            let (url_patterns, config) = SiteConfig::parse(config, name)
+                .expect("The built-in site configs are valid");
+            registry.register(name, url_patterns, Box::new(config));
+        }
+        registry
+    }
+
+    /// The built-in extractors preceded by the site configs in the directory. Each file ending
+    /// with .txt holds the rules for one site and its name, e.g. example.com.txt, is the default
+    /// url pattern.
+    pub fn load(site_config_dir: &Path) -> Result<Self, String> {
+        let mut paths = std::fs::read_dir(site_config_dir)
+            .map_err(|error| format!("Unable to read {}: {}", site_config_dir.display(), error))?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|error| format!("Unable to read {}: {}", site_config_dir.display(), error))?;
+        paths.retain(|path| path.extension().is_some_and(|extension| extension == "txt"));
+        paths.sort();
+        let mut registry = ExtractorRegistry::empty();
+        for path in paths {
+            let name = path.file_stem().unwrap_or_default().to_string_lossy().to_string();
+            let content = std::fs::read_to_string(&path)
+                .map_err(|error| format!("Unable to read {}: {}", path.display(), error))?;
+            let (url_patterns, config) = SiteConfig::parse(&content, &name)
+                .map_err(|error| format!("Invalid site config {}: {}", path.display(), error))?;
+            registry.register(&name, url_patterns, Box::new(config));
+        }
+        registry.extractors.extend(ExtractorRegistry::built_in().extractors);
+        Ok(registry)
+    }
+
+    pub(crate) fn register(&mut self, name: &str, url_patterns: Vec<UrlPattern>, extractor: Box<dyn Extractor>) {
+        self.extractors.push(RegisteredExtractor {name: name.to_string(), url_patterns, extractor});
+    }
+
+    pub(crate) fn find(&self, url: &Url) -> Option<&dyn Extractor> {
+        self.extractors.iter()
+            .find(|registered| registered.url_patterns.iter().any(|pattern| pattern.matches(url)))
+            .map(|registered| registered.extractor.as_ref())
+    }
+
+    /// Extracts the webpage with the extractor for the url, if any, or else the generic
+    /// extraction.
+    pub(crate) fn extract(&self, html: &str, url: &str) -> Result<crate::Webpage, ParseDocumentError<'static>> {
+        let extractor = Url::parse(url).ok().and_then(|url| self.find(&url));
+        crate::traverse_document(html, url, extractor)
+    }
+}
+
+// Extracts the README of a repository and rendered markdown files.
+struct GitHubExtractor;
+
+impl Extractor for GitHubExtractor {
+    fn extract(&self, document: &NodeRef, url: &Url) -> Option<Extraction> {
+        let readme = document.select_first("article.markdown-body").ok()?;
+        // The links next to the headings only contain an icon.
+        let anchors: Vec<_> = readme.as_node().select("a.anchor").ok()?.collect();
+        anchors.iter().for_each(|anchor| anchor.as_node().detach());
+        // The title of a repository is "GitHub - owner/repo: description".
+        let segments: Vec<_> = url.path_segments()?.filter(|segment| !segment.is_empty()).collect();
+        let title = match segments.as_slice() {
+            [owner, repository] => Some(format!("{}/{}", owner, repository)),
+            _ => None,
+        };
+        Some(Extraction {title, contents: vec![readme.as_node().clone()]})
+    }
+}
+
+// Extracts the story of a thread and its comments. The comments are nested in blockquotes since
+// the page only indents them with the width of an image. The text of a comment starts with a
+// bare paragraph followed by <p> elements, so it is put after an unclosed <p> which the parser
+// closes at the next paragraph.
+struct HackerNewsExtractor;
+
+impl Extractor for HackerNewsExtractor {
+    fn extract(&self, document: &NodeRef, _url: &Url) -> Option<Extraction> {
+        let story = document.select_first(".fatitem").ok()?;
+        let title_link = story.as_node().select_first(".titleline > a").ok();
+        let title = title_link.as_ref().map(|link| link.text_contents().trim().to_string());
+        let mut html = String::new();
+        if let Some(link) = &title_link {
+            if let Some(href) = link.attributes.borrow().get("href") {
+                html.push_str(&format!("<p><a href=\"{}\">{}</a></p>", sanitize::escape_attribute(href),
+                    sanitize::escape_text(link.text_contents().trim())));
+            }
+        }
+        if let Ok(text) = story.as_node().select_first(".toptext") {
+            html.push_str(&format!("<p>{}", text.as_node().children().map(|child| child.to_string()).collect::<String>()));
+        }
+        let mut depth: usize = 0;
+        for comment in document.select("tr.comtr").ok()? {
+            let comment = comment.as_node();
+            let indent = comment.select_first("td.ind").ok()
+                .and_then(|indent| indent.attributes.borrow().get("indent").and_then(|indent| indent.parse::<usize>().ok()))
+                .unwrap_or(0);
+            let Ok(text) = comment.select_first(".commtext") else {
+                // Deleted and flagged comments have no text.
+                continue;
+            };
+            let user = comment.select_first(".hnuser").map(|user| user.text_contents()).unwrap_or_default();
+            html.push_str(&"</blockquote>".repeat(depth.saturating_sub(indent)));
+            html.push_str(&"<blockquote>".repeat(indent.saturating_sub(depth)));
+            depth = indent;
+            html.push_str(&format!("<p><strong>{}</strong></p><p>{}", sanitize::escape_text(&user),
+                text.as_node().children().map(|child| child.to_string()).collect::<String>()));
+        }
+        html.push_str(&"</blockquote>".repeat(depth));
+        let contents = kuchiki::parse_html().one(html).select_first("body").ok()?.as_node().children().collect();
+        Some(Extraction {title, contents})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url_pattern(pattern: &str) -> UrlPattern {
+        pattern.parse().unwrap()
+    }
+
+    fn url(url: &str) -> Url {
+        Url::parse(url).unwrap()
+    }
+
+    #[test]
+    fn test_url_pattern() {
+        assert!(url_pattern("*.wikipedia.org/wiki/*").matches(&url("https://en.wikipedia.org/wiki/Rust")));
+        assert!(url_pattern("*.wikipedia.org/wiki/*").matches(&url("https://wikipedia.org/wiki/Rust")));
+        assert!(!url_pattern("*.wikipedia.org/wiki/*").matches(&url("https://en.wikipedia.org/w/index.php")));
+        assert!(!url_pattern("*.wikipedia.org/wiki/*").matches(&url("https://notwikipedia.org/wiki/Rust")));
+        assert!(url_pattern("example.com").matches(&url("https://example.com/any/path?query")));
+        assert!(!url_pattern("example.com").matches(&url("https://www.example.com/")));
+        assert!(url_pattern("https://news.ycombinator.com/item").matches(&url("https://news.ycombinator.com/item?id=1")));
+        assert!(!url_pattern("news.ycombinator.com/item").matches(&url("https://news.ycombinator.com/items")));
+    }
+
+    #[test]
+    fn test_find() {
+        let registry = ExtractorRegistry::built_in();
+        assert!(registry.find(&url("https://github.com/owner/repository")).is_some());
+        assert!(registry.find(&url("https://en.wikipedia.org/wiki/Rust_(programming_language)")).is_some());
+        assert!(registry.find(&url("https://example.com/article")).is_none());
+        assert!(ExtractorRegistry::empty().find(&url("https://github.com/owner/repository")).is_none());
+    }
+
+    #[test]
+    fn test_github() {
+        let webpage = ExtractorRegistry::built_in().extract(include_str!("test-data/extractor-github.html"),
+            "https://github.com/example/widgets").unwrap();
+        assert_eq!(webpage.title, "example/widgets");
+        assert!(webpage.contents.starts_with("<h1>Widgets</h1>"), "{}", webpage.contents);
+        assert!(webpage.contents.contains("<code>cargo add widgets</code>"), "{}", webpage.contents);
+        assert!(!webpage.plain_text.contains("Sign in"), "{}", webpage.plain_text);
+        assert!(!webpage.plain_text.contains("Used by"), "{}", webpage.plain_text);
+    }
+
+    #[test]
+    fn test_hacker_news() {
+        let webpage = ExtractorRegistry::built_in().extract(include_str!("test-data/extractor-hacker-news.html"),
+            "https://news.ycombinator.com/item?id=1000").unwrap();
+        assert_eq!(webpage.title, "Show HN: A server for saving articles");
+        assert_eq!(webpage.contents, concat!(
+            "<p><a href=\"https://example.com/article-server\">Show HN: A server for saving articles</a></p> ",
+            "<p>I built this to read articles offline.</p> ",
+            "<p><strong>alice</strong></p> <p>Does it handle paywalls?</p> ",
+            "<blockquote><p><strong>bob</strong></p><p>No, it only saves what you can see.</p><p>Only public pages.</p></blockquote> ",
+            "<p><strong>carol</strong></p> <p>Nice work!</p>"));
+    }
+
+    #[test]
+    fn test_generic_fallback() {
+        // Without the README the page is extracted like any other.
+        let html = "<html><head><title>A file</title></head><body><p>The contents of a file</p></body></html>";
+        let webpage = ExtractorRegistry::built_in().extract(html, "https://github.com/example/widgets/blob/main/src/lib.rs").unwrap();
+        assert_eq!(webpage.title, "A file");
+        assert_eq!(webpage.contents, "<p>The contents of a file</p>");
+    }
+}
@@ -0,0 +1,286 @@
+// All requests to other sites go through one shared client configured here. Without limits a
+// single page which never finishes or never stops sending ties up a worker, and many sites block
+// requests without a User-Agent.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use reqwest::header::{self,HeaderMap,HeaderValue};
+use reqwest::StatusCode;
+
+use crate::charset;
+use crate::politeness::{self,Politeness,PolitenessConfig};
+use crate::url_policy::{PolicyResolver,UrlPolicy,UrlPolicyError};
+
+// How much of the body of an error response is included in the error message.
+const MAX_ERROR_BODY_LENGTH: usize = 1000;
+
+#[derive(Debug,Clone)]
+pub struct HttpClientConfig {
+    pub connect_timeout: Duration,
+    // The longest wait for the next part of a response.
+    pub read_timeout: Duration,
+    // The longest a whole request may take.
+    pub timeout: Duration,
+    pub max_redirects: usize,
+    pub user_agent: String,
+    pub accept_language: String,
+    // Pages larger than this are not downloaded.
+    pub max_response_size: usize,
+    pub url_policy: UrlPolicy,
+    pub politeness: PolitenessConfig,
+}
+
+impl Default for HttpClientConfig {
+    fn default() -> Self {
+        HttpClientConfig {
+            connect_timeout: Duration::from_secs(10),
+            read_timeout: Duration::from_secs(30),
+            timeout: Duration::from_secs(120),
+            max_redirects: 10,
+            user_agent: concat!("article-server/", env!("CARGO_PKG_VERSION")).to_string(),
+            accept_language: "en;q=0.9, *;q=0.5".to_string(),
+            max_response_size: 20 * 1024 * 1024,
+            url_policy: UrlPolicy::default(),
+            politeness: PolitenessConfig::default(),
+        }
+    }
+}
+
+/// A reqwest client together with the limits which reqwest cannot enforce itself.
+#[derive(Debug,Clone)]
+pub struct HttpClient {
+    client: reqwest::Client,
+    read_timeout: Duration,
+    max_response_size: usize,
+    url_policy: Arc<UrlPolicy>,
+    pub(crate) politeness: Arc<Politeness>,
+}
+
+impl HttpClient {
+    pub fn new(config: &HttpClientConfig) -> Result<Self, reqwest::Error> {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT,
+            HeaderValue::from_static("text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8"));
+        if let Ok(accept_language) = HeaderValue::from_str(&config.accept_language) {
+            headers.insert(header::ACCEPT_LANGUAGE, accept_language);
+        }
+        let url_policy = Arc::new(config.url_policy.clone());
+        let redirect_policy = url_policy.clone();
+        let max_redirects = config.max_redirects;
+        let client = reqwest::Client::builder()
+            .user_agent(config.user_agent.as_str())
+            .default_headers(headers)
+            .connect_timeout(config.connect_timeout)
+            .timeout(config.timeout)
+            .dns_resolver(Arc::new(PolicyResolver(url_policy.clone())))
+            .redirect(reqwest::redirect::Policy::custom(move |attempt| {
+                if attempt.previous().len() >= max_redirects {
+                    attempt.error("too many redirects")
+                } else if let Err(error) = redirect_policy.check_url(attempt.url()) {
+                    attempt.error(error)
+                } else {
+                    attempt.follow()
+                }
+            }))
+            .build()?;
+        Ok(HttpClient {
+            client,
+            read_timeout: config.read_timeout,
+            max_response_size: config.max_response_size,
+            url_policy,
+            politeness: Arc::new(Politeness::new(&config.politeness, &config.user_agent)),
+        })
+    }
+
+    pub(crate) fn get(&self, url: &str) -> reqwest::RequestBuilder {
+        self.client.get(url)
+    }
+
+    pub(crate) fn max_response_size(&self) -> usize {
+        self.max_response_size
+    }
+
+    /// Sends the request if the url policy allows it and waits for the response headers for at most
+    /// the read timeout.
+    pub(crate) async fn send(&self, request: reqwest::RequestBuilder) -> Result<reqwest::Response, FetchError> {
+        let request = request.build()?;
+        self.url_policy.check_url(request.url())
+            .map_err(|error| FetchError::new(FetchErrorKind::Forbidden, error.to_string()))?;
+        tokio::time::timeout(self.read_timeout, self.client.execute(request)).await
+            .map_err(|_| self.read_timeout_error())?
+            .map_err(FetchError::from)
+    }
+
+    fn read_timeout_error(&self) -> FetchError {
+        FetchError::new(FetchErrorKind::Timeout,
+            format!("No response received for {} seconds", self.read_timeout.as_secs_f64()))
+    }
+}
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub(crate) enum FetchErrorKind {
+    InvalidUrl,
+    Connect,
+    Timeout,
+    TooManyRedirects,
+    // The url policy or robots.txt doesn't allow fetching the url.
+    Forbidden,
+    // The host has asked for a break before the next request.
+    RateLimited,
+    Status(StatusCode),
+    TooLarge,
+    UnexpectedContentType,
+    Other,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct FetchError {
+    pub kind: FetchErrorKind,
+    message: String,
+    // How long the server asked to wait before trying again.
+    retry_after: Option<Duration>,
+}
+
+impl FetchError {
+    pub fn new(kind: FetchErrorKind, message: String) -> Self {
+        FetchError {kind, message, retry_after: None}
+    }
+
+    pub fn with_retry_after(mut self, retry_after: Option<Duration>) -> Self {
+        self.retry_after = retry_after;
+        self
+    }
+
+    pub fn retry_after(&self) -> Option<Duration> {
+        self.retry_after
+    }
+
+    /// Whether the server or the network might do better on a later try.
+    pub fn is_transient(&self) -> bool {
+        match self.kind {
+            FetchErrorKind::Connect | FetchErrorKind::Timeout | FetchErrorKind::RateLimited
+                | FetchErrorKind::Other => true,
+            FetchErrorKind::Status(status) => status.is_server_error()
+                || status == StatusCode::TOO_MANY_REQUESTS || status == StatusCode::REQUEST_TIMEOUT,
+            FetchErrorKind::InvalidUrl | FetchErrorKind::TooManyRedirects | FetchErrorKind::Forbidden
+                | FetchErrorKind::TooLarge
+                | FetchErrorKind::UnexpectedContentType => false,
+        }
+    }
+}
+
+impl std::fmt::Display for FetchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for FetchError {}
+
+impl From<reqwest::Error> for FetchError {
+    fn from(error: reqwest::Error) -> Self {
+        // Addresses left out by the resolver and redirects to forbidden urls end up as the source
+        // of connect and redirect errors.
+        let policy_error = std::iter::successors(std::error::Error::source(&error), |error| error.source())
+            .find_map(|error| error.downcast_ref::<UrlPolicyError>());
+        if let Some(policy_error) = policy_error {
+            return FetchError::new(FetchErrorKind::Forbidden, policy_error.to_string());
+        }
+        let kind = if error.is_builder() {
+            FetchErrorKind::InvalidUrl
+        } else if error.is_timeout() {
+            FetchErrorKind::Timeout
+        } else if error.is_redirect() {
+            FetchErrorKind::TooManyRedirects
+        } else if error.is_connect() {
+            FetchErrorKind::Connect
+        } else {
+            FetchErrorKind::Other
+        };
+        FetchError::new(kind, error.to_string())
+    }
+}
+
+/// Reads the body of the response. Reading stops with an error as soon as the body turns out to
+/// be larger than max_size or nothing has been received for the read timeout.
+pub(crate) async fn read_body(http_client: &HttpClient, mut response: reqwest::Response, max_size: usize) ->
+        Result<Vec<u8>, FetchError> {
+    let too_large = || FetchError::new(FetchErrorKind::TooLarge,
+        format!("The response is larger than {} bytes", max_size));
+    if response.content_length().is_some_and(|length| length > max_size as u64) {
+        return Err(too_large());
+    }
+    let mut data = Vec::new();
+    loop {
+        let chunk = tokio::time::timeout(http_client.read_timeout, response.chunk()).await
+            .map_err(|_| http_client.read_timeout_error())??;
+        match chunk {
+            Some(chunk) => {
+                if data.len() + chunk.len() > max_size {
+                    return Err(too_large());
+                }
+                data.extend_from_slice(&chunk);
+            },
+            None => return Ok(data),
+        }
+    }
+}
+
+// Reads at most max_size bytes of the body and leaves the rest unread.
+async fn read_body_start(http_client: &HttpClient, mut response: reqwest::Response, max_size: usize) ->
+        Result<Vec<u8>, FetchError> {
+    let mut data = Vec::new();
+    while data.len() < max_size {
+        let chunk = tokio::time::timeout(http_client.read_timeout, response.chunk()).await
+            .map_err(|_| http_client.read_timeout_error())??;
+        match chunk {
+            Some(chunk) => data.extend_from_slice(&chunk),
+            None => break,
+        }
+    }
+    data.truncate(max_size);
+    Ok(data)
+}
+
+pub(crate) async fn fetch_webpage(http_client: &HttpClient, url: &str) -> Result<String, FetchError> {
+    if let Ok(parsed_url) = url::Url::parse(url) {
+        politeness::check_robots_txt(http_client, &parsed_url).await
+            .map_err(|error| FetchError::new(error.kind, format!("Unable to fetch {}. {}", url, error)))?;
+    }
+    // The permit is held until the whole response has been read.
+    let (_permit, response) = politeness::send(http_client, url, http_client.get(url)).await.map_err(|error| {
+        FetchError::new(error.kind, format!("Unable to fetch {}. Error: {}", url, error))
+            .with_retry_after(error.retry_after())
+    })?;
+    let status = response.status();
+    let content_type = response.headers().get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string());
+    if !status.is_success() {
+        let retry_after = http_client.politeness.retry_after(status, response.headers());
+        let body = read_body_start(http_client, response, MAX_ERROR_BODY_LENGTH).await
+            .map(|body| charset::decode_text(content_type.as_deref(), &body))
+            .unwrap_or_default();
+        return Err(FetchError::new(FetchErrorKind::Status(status),
+            format!("Unable to fetch {}. Got status {}: {}", url, status, body))
+            .with_retry_after(retry_after));
+    }
+    let body = read_body(http_client, response, http_client.max_response_size).await
+        .map_err(|error| FetchError::new(error.kind, format!("Unable to fetch {}. {}", url, error)))?;
+    Ok(charset::decode_html(url, content_type.as_deref(), &body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_transient() {
+        let error = |kind| FetchError::new(kind, String::new());
+        assert!(error(FetchErrorKind::Timeout).is_transient());
+        assert!(error(FetchErrorKind::Status(StatusCode::SERVICE_UNAVAILABLE)).is_transient());
+        assert!(!error(FetchErrorKind::Status(StatusCode::NOT_FOUND)).is_transient());
+        assert!(!error(FetchErrorKind::TooLarge).is_transient());
+    }
+}
@@ -0,0 +1,357 @@
+// Highlighted passages of the readable content with optional notes. A highlight is anchored with
+// a text quote selector like in the W3C Web Annotation Data Model, i.e. the highlighted text
+// together with a bit of the text just before and after it. Unlike an offset into the text or a
+// path in the html it can still be found when the readable content is extracted again.
+// https://www.w3.org/TR/annotation-model/#text-quote-selector
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use html5ever::tendril::TendrilSink;
+use html5ever::{local_name,namespace_url,ns,QualName};
+use kuchiki::NodeRef;
+use kuchiki::iter::NodeIterator;
+use serde::{Deserialize,Serialize};
+use sqlx::PgPool;
+use warp::http::StatusCode;
+
+use crate::errors;
+use crate::sanitize;
+
+const MAX_QUOTE_LENGTH: usize = 10000;
+const MAX_CONTEXT_LENGTH: usize = 500;
+const MAX_NOTE_LENGTH: usize = 10000;
+const HIGHLIGHT_COLUMNS: &str = "highlights.id, highlights.exact, highlights.prefix, highlights.suffix, highlights.note, highlights.created, highlights.updated";
+
+#[derive(Deserialize,Serialize,Debug,Clone,PartialEq,Eq)]
+pub struct TextQuoteSelector {
+    pub exact: String,
+    // The text right before the highlighted text.
+    #[serde(default)]
+    pub prefix: String,
+    // The text right after the highlighted text.
+    #[serde(default)]
+    pub suffix: String,
+}
+
+#[derive(Deserialize,Serialize,Debug,Clone,PartialEq,Eq)]
+pub struct Highlight {
+    pub id: i64,
+    pub selector: TextQuoteSelector,
+    pub note: Option<String>,
+    pub created: chrono::DateTime<chrono::Utc>,
+    pub updated: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Serialize,Debug)]
+struct ListHighlightsResponse {
+    highlights: Vec<Highlight>,
+}
+
+/// The body of requests creating or changing a highlight.
+#[derive(Deserialize,Debug)]
+pub struct HighlightBody {
+    selector: TextQuoteSelector,
+    note: Option<String>,
+}
+
+impl HighlightBody {
+    fn validate(&self) -> Result<(), &'static str> {
+        if self.selector.exact.trim().is_empty() {
+            return Err("The highlighted text is empty");
+        }
+        if self.selector.exact.len() > MAX_QUOTE_LENGTH {
+            return Err("The highlighted text is too long");
+        }
+        if self.selector.prefix.len() > MAX_CONTEXT_LENGTH || self.selector.suffix.len() > MAX_CONTEXT_LENGTH {
+            return Err("The prefix or suffix is too long");
+        }
+        if self.note.as_ref().is_some_and(|note| note.len() > MAX_NOTE_LENGTH) {
+            return Err("The note is too long");
+        }
+        Ok(())
+    }
+}
+
+type HighlightRow = (i64, String, String, String, Option<String>, chrono::DateTime<chrono::Utc>, chrono::DateTime<chrono::Utc>);
+
+fn highlight_from_row((id, exact, prefix, suffix, note, created, updated): HighlightRow) -> Highlight {
+    Highlight {id, selector: TextQuoteSelector {exact, prefix, suffix}, note, created, updated}
+}
+
+/// Gets the highlights of a webpage in the order they were created. The caller is responsible for
+/// checking that the webpage belongs to the user.
+pub(crate) async fn webpage_highlights(db_pool: &PgPool, webpage_id: i64) -> Result<Vec<Highlight>, sqlx::Error> {
+    let rows = sqlx::query_as::<_, HighlightRow>(&format!("SELECT {} FROM highlights WHERE webpage_id = $1 ORDER BY created, id",
+            HIGHLIGHT_COLUMNS))
+        .bind(webpage_id)
+        .fetch_all(db_pool).await?;
+    Ok(rows.into_iter().map(highlight_from_row).collect())
+}
+
+async fn list_highlights(db_pool: &PgPool, webpage_id: i64, user_id: i64) ->
+        Result<Option<ListHighlightsResponse>, sqlx::Error> {
+    let webpage = sqlx::query_as::<_, (i64,)>("SELECT id FROM webpages WHERE id = $1 AND user_id = $2")
+        .bind(webpage_id)
+        .bind(user_id)
+        .fetch_optional(db_pool).await?;
+    match webpage {
+        Some(_) => Ok(Some(ListHighlightsResponse {highlights: webpage_highlights(db_pool, webpage_id).await?})),
+        None => Ok(None),
+    }
+}
+
+async fn create_highlight(db_pool: &PgPool, webpage_id: i64, user_id: i64, body: &HighlightBody) ->
+        Result<Option<Highlight>, sqlx::Error> {
+    let row = sqlx::query_as::<_, HighlightRow>(&format!("INSERT INTO highlights(webpage_id, exact, prefix, suffix, note) SELECT id, $3, $4, $5, $6 FROM webpages WHERE id = $1 AND user_id = $2 RETURNING {}",
+            HIGHLIGHT_COLUMNS))
+        .bind(webpage_id)
+        .bind(user_id)
+        .bind(&body.selector.exact)
+        .bind(&body.selector.prefix)
+        .bind(&body.selector.suffix)
+        .bind(&body.note)
+        .fetch_optional(db_pool).await?;
+    Ok(row.map(highlight_from_row))
+}
+
+async fn update_highlight(db_pool: &PgPool, webpage_id: i64, highlight_id: i64, user_id: i64,
+        body: &HighlightBody) -> Result<Option<Highlight>, sqlx::Error> {
+    let row = sqlx::query_as::<_, HighlightRow>(&format!("UPDATE highlights SET exact = $4, prefix = $5, suffix = $6, note = $7, updated = now() FROM webpages WHERE highlights.id = $1 AND highlights.webpage_id = $2 AND webpages.id = highlights.webpage_id AND webpages.user_id = $3 RETURNING {}",
+            HIGHLIGHT_COLUMNS))
+        .bind(highlight_id)
+        .bind(webpage_id)
+        .bind(user_id)
+        .bind(&body.selector.exact)
+        .bind(&body.selector.prefix)
+        .bind(&body.selector.suffix)
+        .bind(&body.note)
+        .fetch_optional(db_pool).await?;
+    Ok(row.map(highlight_from_row))
+}
+
+fn highlight_reply<T: Serialize>(result: Result<Option<T>, sqlx::Error>, status: StatusCode,
+        webpage_id: i64, user_id: i64) -> warp::reply::WithStatus<warp::reply::Json> {
+    match result {
+        Ok(Some(response)) => warp::reply::with_status(warp::reply::json(&response), status),
+        Ok(None) => errors::error_reply(StatusCode::NOT_FOUND, "Highlight not found"),
+        Err(error) => {
+            log::error!("Error when handling highlights of webpage {} for user {}: {}",
+                webpage_id, user_id, error);
+            errors::error_reply(StatusCode::INTERNAL_SERVER_ERROR, "Unknown error")
+        }
+    }
+}
+
+pub async fn list_highlights_handler(webpage_id: i64, db_pool: Arc<PgPool>, user_id: i64) ->
+        Result<impl warp::Reply, warp::Rejection> {
+    Ok(highlight_reply(list_highlights(&db_pool, webpage_id, user_id).await, StatusCode::OK,
+        webpage_id, user_id))
+}
+
+pub async fn create_highlight_handler(webpage_id: i64, body: HighlightBody, db_pool: Arc<PgPool>,
+        user_id: i64) -> Result<impl warp::Reply, warp::Rejection> {
+    if let Err(message) = body.validate() {
+        return Ok(errors::error_reply(StatusCode::BAD_REQUEST, message));
+    }
+    Ok(highlight_reply(create_highlight(&db_pool, webpage_id, user_id, &body).await,
+        StatusCode::CREATED, webpage_id, user_id))
+}
+
+pub async fn update_highlight_handler(webpage_id: i64, highlight_id: i64, body: HighlightBody,
+        db_pool: Arc<PgPool>, user_id: i64) -> Result<impl warp::Reply, warp::Rejection> {
+    if let Err(message) = body.validate() {
+        return Ok(errors::error_reply(StatusCode::BAD_REQUEST, message));
+    }
+    Ok(highlight_reply(update_highlight(&db_pool, webpage_id, highlight_id, user_id, &body).await,
+        StatusCode::OK, webpage_id, user_id))
+}
+
+pub async fn delete_highlight_handler(webpage_id: i64, highlight_id: i64, db_pool: Arc<PgPool>,
+        user_id: i64) -> Result<impl warp::Reply, warp::Rejection> {
+    match sqlx::query("DELETE FROM highlights USING webpages WHERE highlights.id = $1 AND highlights.webpage_id = $2 AND webpages.id = highlights.webpage_id AND webpages.user_id = $3")
+            .bind(highlight_id)
+            .bind(webpage_id)
+            .bind(user_id)
+            .execute(&*db_pool).await {
+        Ok(result) if result.rows_affected() == 0 => Ok(StatusCode::NOT_FOUND),
+        Ok(_) => Ok(StatusCode::NO_CONTENT),
+        Err(error) => {
+            log::error!("Error when deleting highlight {} of webpage {} for user {}: {}",
+                highlight_id, webpage_id, user_id, error);
+            Ok(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+fn non_whitespace(text: &str) -> Vec<char> {
+    text.chars().filter(|c| !c.is_whitespace()).collect()
+}
+
+// Finds the quote in the text. If the quote appears more than once the occurrence where the
+// surrounding text matches most of the prefix and suffix is chosen. Whitespace is left out of
+// both the text and the quote since it depends on how the client turned the html into text.
+fn find_quote(text: &[char], selector: &TextQuoteSelector) -> Option<(usize, usize)> {
+    let exact = non_whitespace(&selector.exact);
+    let prefix = non_whitespace(&selector.prefix);
+    let suffix = non_whitespace(&selector.suffix);
+    if exact.is_empty() || exact.len() > text.len() {
+        return None;
+    }
+    let mut best: Option<(usize, usize)> = None;
+    for start in 0..=text.len() - exact.len() {
+        let end = start + exact.len();
+        if text[start..end] != exact[..] {
+            continue;
+        }
+        let before = text[..start].iter().rev().zip(prefix.iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+        let after = text[end..].iter().zip(suffix.iter())
+            .take_while(|(a, b)| a == b)
+            .count();
+        if best.is_none_or(|(_, score)| before + after > score) {
+            best = Some((start, before + after));
+        }
+    }
+    best.map(|(start, _)| (start, start + exact.len()))
+}
+
+/// Wraps the highlighted text in <mark> elements. Highlights which cannot be found in the
+/// document are left out.
+pub(crate) fn mark_highlights(document: &NodeRef, highlights: &[Highlight]) {
+    let nodes: Vec<NodeRef> = document.inclusive_descendants().text_nodes()
+        .map(|text| text.as_node().clone())
+        .collect();
+    // Each character of the text without whitespace points at its text node and byte range.
+    let mut text = Vec::new();
+    let mut positions = Vec::new();
+    for (i, node) in nodes.iter().enumerate() {
+        if let Some(contents) = node.as_text() {
+            for (offset, c) in contents.borrow().char_indices() {
+                if !c.is_whitespace() {
+                    text.push(c);
+                    positions.push((i, offset, offset + c.len_utf8()));
+                }
+            }
+        }
+    }
+    let mut ranges: BTreeMap<usize, Vec<(usize, usize)>> = BTreeMap::new();
+    for highlight in highlights {
+        let (start, end) = match find_quote(&text, &highlight.selector) {
+            Some(range) => range,
+            None => continue,
+        };
+        let mut node_ranges: BTreeMap<usize, (usize, usize)> = BTreeMap::new();
+        for &(node, byte_start, byte_end) in &positions[start..end] {
+            let range = node_ranges.entry(node).or_insert((byte_start, byte_end));
+            range.1 = byte_end;
+        }
+        for (node, range) in node_ranges {
+            ranges.entry(node).or_default().push(range);
+        }
+    }
+    for (i, mut node_ranges) in ranges {
+        let node = &nodes[i];
+        let contents = match node.as_text() {
+            Some(contents) => contents.borrow().clone(),
+            None => continue,
+        };
+        // Overlapping highlights are merged into one <mark>.
+        node_ranges.sort();
+        let mut merged: Vec<(usize, usize)> = Vec::new();
+        for (start, end) in node_ranges {
+            match merged.last_mut() {
+                Some(last) if start <= last.1 => last.1 = last.1.max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+        let mut offset = 0;
+        for (start, end) in merged {
+            if start > offset {
+                node.insert_before(NodeRef::new_text(&contents[offset..start]));
+            }
+            let mark = NodeRef::new_element(QualName::new(None, ns!(html), local_name!("mark")), Vec::new());
+            mark.append(NodeRef::new_text(&contents[start..end]));
+            node.insert_before(mark);
+            offset = end;
+        }
+        if offset < contents.len() {
+            node.insert_before(NodeRef::new_text(&contents[offset..]));
+        }
+        node.detach();
+    }
+}
+
+/// Marks the highlights in the document and adds the highlights which have notes at the end.
+pub(crate) fn add_highlights(document: &NodeRef, highlights: &[Highlight]) {
+    mark_highlights(document, highlights);
+    let notes: Vec<String> = highlights.iter()
+        .filter_map(|highlight| highlight.note.as_ref().map(|note| {
+            format!("<blockquote><p>{}</p></blockquote><p>{}</p>",
+                sanitize::escape_text(&highlight.selector.exact), sanitize::escape_text(note))
+        }))
+        .collect();
+    if notes.is_empty() {
+        return;
+    }
+    let fragment = kuchiki::parse_html().one(format!("<section><h2>Notes</h2>{}</section>", notes.join("")));
+    let target = document.select_first("body").map(|body| body.as_node().clone())
+        .unwrap_or_else(|_| document.clone());
+    if let Ok(section) = fragment.select_first("section") {
+        target.append(section.as_node().clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn highlight(exact: &str, prefix: &str, suffix: &str, note: Option<&str>) -> Highlight {
+        let now = chrono::Utc::now();
+        Highlight {
+            id: 1,
+            selector: TextQuoteSelector {exact: exact.to_string(), prefix: prefix.to_string(),
+                suffix: suffix.to_string()},
+            note: note.map(|note| note.to_string()),
+            created: now,
+            updated: now,
+        }
+    }
+
+    fn marked(html: &str, highlights: &[Highlight]) -> String {
+        let document = kuchiki::parse_html().one(html);
+        add_highlights(&document, highlights);
+        sanitize::node_to_html(&document)
+    }
+
+    #[test]
+    fn test_find_quote() {
+        let text: Vec<char> = "onetwoonethree".chars().collect();
+        let selector = |prefix: &str, suffix: &str| TextQuoteSelector {exact: "one".to_string(),
+            prefix: prefix.to_string(), suffix: suffix.to_string()};
+        assert_eq!(find_quote(&text, &selector("", "")), Some((0, 3)));
+        assert_eq!(find_quote(&text, &selector("two", "three")), Some((6, 9)));
+        assert_eq!(find_quote(&text, &selector("", "t w o")), Some((0, 3)));
+        assert_eq!(find_quote(&text, &TextQuoteSelector {exact: "four".to_string(),
+            prefix: String::new(), suffix: String::new()}), None);
+    }
+
+    #[test]
+    fn test_mark_highlights() {
+        assert_eq!(marked("<p>The quick brown fox</p>", &[highlight("quick", "The ", " brown", None)]),
+            "<p>The <mark>quick</mark> brown fox</p>");
+        // The whitespace doesn't have to match and highlights can span several elements.
+        assert_eq!(marked("<p>One <b>two</b></p>\n<p>three four</p>", &[highlight("two three", "", "", None)]),
+            "<p>One <b><mark>two</mark></b></p> <p><mark>three</mark> four</p>");
+        assert_eq!(marked("<p>abc abc</p>", &[highlight("abc", "abc ", "", None), highlight("b", "", "", None)]),
+            "<p>a<mark>b</mark>c <mark>abc</mark></p>");
+        assert_eq!(marked("<p>Text</p>", &[highlight("Missing", "", "", None)]), "<p>Text</p>");
+    }
+
+    #[test]
+    fn test_add_highlights_notes() {
+        assert_eq!(marked("<p>Some text</p>", &[highlight("text", "", "", Some("A <note>"))]),
+            "<p>Some <mark>text</mark></p><h2>Notes</h2><blockquote><p>text</p></blockquote><p>A &lt;note&gt;</p>");
+    }
+}
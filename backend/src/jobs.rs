@@ -0,0 +1,262 @@
+// Webpages are fetched and saved by a pool of workers in the background so a slow site doesn't
+// hold the client's request open and a failed fetch can be retried. The queue is the jobs table.
+// Workers claim a job with SELECT ... FOR UPDATE SKIP LOCKED so every job is run by one worker
+// at a time, also when several servers share the database.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::Serialize;
+use sqlx::{PgPool,Row};
+use tokio::sync::Notify;
+use warp::http::StatusCode;
+
+use crate::extractors::ExtractorRegistry;
+use crate::fetch::HttpClient;
+use crate::{errors,FetchWebpage,OnDuplicate};
+
+// A job which has been running for this long is assumed to belong to a worker which has stopped,
+// e.g. because the server was restarted, and is run again.
+const RUNNING_TIMEOUT_SECONDS: f64 = 15.0 * 60.0;
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug,Clone)]
+pub struct JobQueueConfig {
+    pub workers: usize,
+    // How many times a job is tried before it fails for good.
+    pub max_attempts: i32,
+    // The delay before the first retry. It doubles for every following retry.
+    pub retry_delay: Duration,
+    // How often idle workers look for jobs which are due for a retry or were queued by another
+    // server.
+    pub poll_interval: Duration,
+}
+
+impl Default for JobQueueConfig {
+    fn default() -> Self {
+        JobQueueConfig {
+            workers: 4,
+            max_attempts: 5,
+            retry_delay: Duration::from_secs(30),
+            poll_interval: Duration::from_secs(1),
+        }
+    }
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+enum JobStatus {
+    queued,
+    running,
+    succeeded,
+    failed,
+}
+
+impl JobStatus {
+    fn as_str(&self) -> &'static str {
+        match self {
+            JobStatus::queued => "queued",
+            JobStatus::running => "running",
+            JobStatus::succeeded => "succeeded",
+            JobStatus::failed => "failed",
+        }
+    }
+}
+
+#[derive(Serialize,Debug)]
+struct JobResponse {
+    id: i64,
+    url: String,
+    status: String,
+    attempts: i32,
+    // When a queued job will be tried again.
+    next_attempt: Option<chrono::DateTime<chrono::Utc>>,
+    error: Option<String>,
+    result: Option<String>,
+    webpage_id: Option<i64>,
+    created: chrono::DateTime<chrono::Utc>,
+    updated: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Serialize,Debug)]
+struct EnqueueResponse {
+    job_id: i64,
+}
+
+struct ClaimedJob {
+    id: i64,
+    user_id: i64,
+    attempts: i32,
+    request: FetchWebpage,
+}
+
+async fn enqueue(db_pool: &PgPool, request: &FetchWebpage, user_id: i64) -> Result<i64, sqlx::Error> {
+    let (id,) = sqlx::query_as::<_, (i64,)>("INSERT INTO jobs(user_id, url, html, archive_original, tags, on_duplicate) VALUES ($1, $2, $3, $4, $5, $6) RETURNING id")
+        .bind(user_id)
+        .bind(&request.url)
+        .bind(&request.html)
+        .bind(request.archive_original.unwrap_or(false))
+        .bind(request.tags.clone().unwrap_or_default())
+        .bind(request.on_duplicate.unwrap_or(OnDuplicate::existing).as_str())
+        .fetch_one(db_pool).await?;
+    Ok(id)
+}
+
+/// Queues the webpage to be fetched and saved. Responds with 202 Accepted and the id of the job.
+pub(crate) async fn enqueue_reply(db_pool: &PgPool, wakeup: &Notify, request: &FetchWebpage, user_id: i64) ->
+        Result<warp::http::Response<String>, warp::http::Error> {
+    match enqueue(db_pool, request, user_id).await {
+        Ok(job_id) => {
+            wakeup.notify_one();
+            warp::http::Response::builder()
+                .status(StatusCode::ACCEPTED)
+                .header("content-type", "application/json")
+                .header("location", format!("/api/jobs/{}", job_id))
+                .body(serde_json::to_string(&EnqueueResponse {job_id}).unwrap_or_default())
+        },
+        Err(error) => {
+            log::error!("Error when queueing {} for user {}: {}", request.url, user_id, error);
+            warp::http::Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("".to_string())
+        },
+    }
+}
+
+async fn claim_job(db_pool: &PgPool) -> Result<Option<ClaimedJob>, sqlx::Error> {
+    let row = sqlx::query("UPDATE jobs SET status = $1, attempts = attempts + 1, updated = now() WHERE id = (SELECT id FROM jobs WHERE (status = $2 AND run_at <= now()) OR (status = $1 AND updated < now() - make_interval(secs => $3)) ORDER BY run_at, id LIMIT 1 FOR UPDATE SKIP LOCKED) RETURNING id, user_id, attempts, url, html, archive_original, tags, on_duplicate")
+        .bind(JobStatus::running.as_str())
+        .bind(JobStatus::queued.as_str())
+        .bind(RUNNING_TIMEOUT_SECONDS)
+        .fetch_optional(db_pool).await?;
+    row.map(|row| {
+        let on_duplicate: String = row.try_get("on_duplicate")?;
+        Ok(ClaimedJob {
+            id: row.try_get("id")?,
+            user_id: row.try_get("user_id")?,
+            attempts: row.try_get("attempts")?,
+            request: FetchWebpage {
+                url: row.try_get("url")?,
+                html: row.try_get("html")?,
+                archive_original: Some(row.try_get("archive_original")?),
+                tags: Some(row.try_get("tags")?),
+                on_duplicate: on_duplicate.parse().ok(),
+            },
+        })
+    }).transpose()
+}
+
+fn retry_delay(config: &JobQueueConfig, attempts: i32) -> Duration {
+    let exponent = attempts.saturating_sub(1).clamp(0, 20) as u32;
+    config.retry_delay.saturating_mul(2u32.saturating_pow(exponent)).min(MAX_RETRY_DELAY)
+}
+
+async fn run_job(db_pool: &PgPool, http_client: &HttpClient, extractors: &ExtractorRegistry, config: &JobQueueConfig,
+        job: ClaimedJob) -> Result<(), sqlx::Error> {
+    match crate::save_webpage(db_pool, http_client, extractors, &job.request, job.user_id).await {
+        Ok(saved) => {
+            sqlx::query("UPDATE jobs SET status = $2, result = $3, webpage_id = $4, error = NULL, updated = now() WHERE id = $1")
+                .bind(job.id)
+                .bind(JobStatus::succeeded.as_str())
+                .bind(saved.outcome.as_str())
+                .bind(saved.id)
+                .execute(db_pool).await?;
+        },
+        Err(error) if error.is_transient() && job.attempts < config.max_attempts => {
+            let delay = retry_delay(config, job.attempts).max(error.retry_after().unwrap_or_default());
+            log::warn!("Job {} failed on attempt {} and is retried in {:?}: {}", job.id, job.attempts, delay, error);
+            sqlx::query("UPDATE jobs SET status = $2, error = $3, run_at = now() + make_interval(secs => $4), updated = now() WHERE id = $1")
+                .bind(job.id)
+                .bind(JobStatus::queued.as_str())
+                .bind(error.to_string())
+                .bind(delay.as_secs_f64())
+                .execute(db_pool).await?;
+        },
+        Err(error) => {
+            log::error!("Job {} failed on attempt {}: {}", job.id, job.attempts, error);
+            sqlx::query("UPDATE jobs SET status = $2, error = $3, updated = now() WHERE id = $1")
+                .bind(job.id)
+                .bind(JobStatus::failed.as_str())
+                .bind(error.to_string())
+                .execute(db_pool).await?;
+        },
+    }
+    Ok(())
+}
+
+async fn work(db_pool: Arc<PgPool>, http_client: HttpClient, extractors: Arc<ExtractorRegistry>,
+        config: JobQueueConfig, wakeup: Arc<Notify>) {
+    loop {
+        match claim_job(&db_pool).await {
+            Ok(Some(job)) => {
+                let job_id = job.id;
+                if let Err(error) = run_job(&db_pool, &http_client, &extractors, &config, job).await {
+                    log::error!("Error when updating job {}: {}", job_id, error);
+                }
+                continue;
+            },
+            Ok(None) => (),
+            Err(error) => log::error!("Error when claiming a job: {}", error),
+        }
+        tokio::select! {
+            _ = wakeup.notified() => (),
+            _ = tokio::time::sleep(config.poll_interval) => (),
+        }
+    }
+}
+
+/// Starts the workers. They are woken up through `wakeup` when a job is queued.
+pub(crate) fn start_workers(db_pool: Arc<PgPool>, http_client: HttpClient, extractors: Arc<ExtractorRegistry>,
+        config: &JobQueueConfig, wakeup: Arc<Notify>) {
+    for _ in 0..config.workers {
+        tokio::spawn(work(db_pool.clone(), http_client.clone(), extractors.clone(), config.clone(), wakeup.clone()));
+    }
+}
+
+async fn get_job(db_pool: &PgPool, job_id: i64, user_id: i64) -> Result<Option<JobResponse>, sqlx::Error> {
+    let row = sqlx::query("SELECT id, url, status, attempts, run_at, error, result, webpage_id, created, updated FROM jobs WHERE id = $1 AND user_id = $2")
+        .bind(job_id)
+        .bind(user_id)
+        .fetch_optional(db_pool).await?;
+    row.map(|row| {
+        let status: String = row.try_get("status")?;
+        Ok(JobResponse {
+            id: row.try_get("id")?,
+            url: row.try_get("url")?,
+            attempts: row.try_get("attempts")?,
+            next_attempt: if status == JobStatus::queued.as_str() { Some(row.try_get("run_at")?) } else { None },
+            status,
+            error: row.try_get("error")?,
+            result: row.try_get("result")?,
+            webpage_id: row.try_get("webpage_id")?,
+            created: row.try_get("created")?,
+            updated: row.try_get("updated")?,
+        })
+    }).transpose()
+}
+
+pub async fn get_job_handler(job_id: i64, db_pool: Arc<PgPool>, user_id: i64) ->
+        Result<impl warp::Reply, warp::Rejection> {
+    Ok(match get_job(&db_pool, job_id, user_id).await {
+        Ok(Some(job)) => warp::reply::with_status(warp::reply::json(&job), StatusCode::OK),
+        Ok(None) => errors::error_reply(StatusCode::NOT_FOUND, "Job not found"),
+        Err(error) => {
+            log::error!("Error when fetching job {} for user {}: {}", job_id, user_id, error);
+            errors::error_reply(StatusCode::INTERNAL_SERVER_ERROR, "Unknown error")
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_delay() {
+        let config = JobQueueConfig {retry_delay: Duration::from_secs(30), ..JobQueueConfig::default()};
+        assert_eq!(retry_delay(&config, 1), Duration::from_secs(30));
+        assert_eq!(retry_delay(&config, 2), Duration::from_secs(60));
+        assert_eq!(retry_delay(&config, 4), Duration::from_secs(240));
+        assert_eq!(retry_delay(&config, 30), MAX_RETRY_DELAY);
+    }
+}
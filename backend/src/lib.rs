@@ -1,5 +1,29 @@
+mod assets;
 pub mod auth;
+mod charset;
+pub mod epub;
 mod errors;
+mod excerpt;
+mod export;
+mod extractors;
+pub mod fetch;
+pub mod highlights;
+pub mod jobs;
+pub mod metadata;
+mod pagination;
+pub mod politeness;
+mod progress;
+mod readability;
+mod render;
+mod robots;
+mod sanitize;
+mod search;
+mod site_config;
+mod snapshots;
+pub mod tags;
+mod text_stats;
+pub mod url_policy;
+mod urls;
 pub mod webpages;
 
 use std::net::SocketAddr;
@@ -13,6 +37,10 @@ use sqlx::PgPool;
 use warp::Filter;
 use warp::http::{StatusCode,Response};
 
+use extractors::ExtractorRegistry;
+use fetch::{FetchError,HttpClient};
+use text_stats::TextStats;
+
 // Using the migrate! macro embeds the migrations into the binary file
 static MIGRATOR: sqlx::migrate::Migrator = sqlx::migrate!("db/migrations");
 
@@ -22,96 +50,303 @@ static MIGRATOR: sqlx::migrate::Migrator = sqlx::migrate!("db/migrations");
 struct FetchWebpage {
     url: String,
     html: Option<String>,
+    // Also archive the images, stylesheets and fonts used by the original html.
+    archive_original: Option<bool>,
+    tags: Option<Vec<String>>,
+    // When a job finds out that the webpage is a duplicate, it ends with the saved webpage as its
+    // result for both existing and conflict.
+    on_duplicate: Option<OnDuplicate>,
 }
 
-#[derive(Debug, Clone)]
-struct FetchError {
-    message: String,
+// What to do when the user has saved the webpage before.
+#[allow(non_camel_case_types)]
+#[derive(Deserialize,Debug,Clone,Copy,PartialEq,Eq)]
+enum OnDuplicate {
+    // Respond with the id of the saved webpage and 200 OK.
+    existing,
+    // Respond with the id of the saved webpage and 409 Conflict.
+    conflict,
+    // Replace the contents of the saved webpage with the new version. The tags are added to the
+    // existing ones and the reading state and highlights are kept.
+    update,
 }
 
-impl std::fmt::Display for FetchError {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
-        write!(f, "{}", self.message)
+impl OnDuplicate {
+    fn as_str(&self) -> &'static str {
+        match self {
+            OnDuplicate::existing => "existing",
+            OnDuplicate::conflict => "conflict",
+            OnDuplicate::update => "update",
+        }
     }
 }
 
-impl std::error::Error for FetchError {}
+impl std::str::FromStr for OnDuplicate {
+    type Err = String;
 
-impl From<reqwest::Error> for FetchError {
-    fn from(error: reqwest::Error) -> Self {
-        FetchError {message: error.to_string()}
+    fn from_str(on_duplicate: &str) -> Result<Self, Self::Err> {
+        match on_duplicate {
+            "existing" => Ok(OnDuplicate::existing),
+            "conflict" => Ok(OnDuplicate::conflict),
+            "update" => Ok(OnDuplicate::update),
+            _ => Err(format!("Unknown on_duplicate option {}", on_duplicate)),
+        }
     }
 }
 
-async fn fetch_webpage(http_client: &reqwest::Client, url: &str) -> Result<String, FetchError> {
-    match http_client.get(url).send().await {
-        Ok(response) => {
-            if response.status().is_success() {
-                return Ok(response.text().await?);
-            } else {
-                return Err(FetchError {
-                    message: format!("Unable to fetch {}. Got status {}: {}",
-                        url, response.status(), response.text().await?)
-                });
-            }
-        },
-        Err(error) => {
-            return Err(FetchError{message: format!("Unable to fetch {}. Error: {}", url, error.to_string())});
+#[derive(Serialize,Debug)]
+struct FetchResponse {
+    id: i64,
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+enum SaveOutcome {
+    created,
+    updated,
+    // The user had saved the webpage already and it was left as it was.
+    duplicate,
+}
+
+impl SaveOutcome {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SaveOutcome::created => "created",
+            SaveOutcome::updated => "updated",
+            SaveOutcome::duplicate => "duplicate",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct SavedWebpage {
+    id: i64,
+    outcome: SaveOutcome,
+}
+
+#[derive(Debug)]
+enum SaveError {
+    Fetch(FetchError),
+    Parse(errors::ParseDocumentError<'static>),
+    Database(sqlx::Error),
+}
+
+impl SaveError {
+    // Whether trying again later might succeed.
+    fn is_transient(&self) -> bool {
+        match self {
+            SaveError::Fetch(error) => error.is_transient(),
+            SaveError::Parse(_) => false,
+            SaveError::Database(_) => true,
+        }
+    }
+
+    // How long the site asked to wait before trying again.
+    fn retry_after(&self) -> Option<std::time::Duration> {
+        match self {
+            SaveError::Fetch(error) => error.retry_after(),
+            SaveError::Parse(_) | SaveError::Database(_) => None,
         }
     }
 }
 
-async fn write_to_db(conn: &PgPool, url: &str, webpage: &Webpage,  user_id: i64) ->
-        Result<sqlx::postgres::PgQueryResult, sqlx::Error> {
-    Ok(sqlx::query("INSERT INTO webpages(url, title, text, html, image_url, user_id) VALUES ($1, $2, $3, $4, $5, $6)")
+impl std::fmt::Display for SaveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        match self {
+            SaveError::Fetch(error) => write!(f, "{}", error),
+            SaveError::Parse(error) => write!(f, "{}", error),
+            SaveError::Database(error) => write!(f, "Database error: {}", error),
+        }
+    }
+}
+
+impl From<sqlx::Error> for SaveError {
+    fn from(error: sqlx::Error) -> Self {
+        SaveError::Database(error)
+    }
+}
+
+/// The key a webpage is found by when the user saves it again. That is its canonical url if the
+/// page has a <link rel=canonical> on the same host, which it can only be trusted for since the
+/// page could otherwise claim to be any other page, and else the canonicalized url.
+fn url_key(url: &str, canonical_url: Option<&str>) -> String {
+    let host = |url: &str| url::Url::parse(url).ok().and_then(|url| url.host_str().map(str::to_string));
+    canonical_url
+        .map(urls::canonicalize_url)
+        .filter(|canonical_url| canonical_url.starts_with("http://") || canonical_url.starts_with("https://"))
+        .filter(|canonical_url| host(canonical_url).is_some() && host(canonical_url) == host(url))
+        .unwrap_or_else(|| url.to_string())
+}
+
+/// Finds the user's webpage saved with the url key. Webpages without a url key because they are
+/// duplicates of another webpage, see backfill_url_keys, are found by their url.
+async fn find_saved_webpage(conn: &PgPool, url_key: &str, user_id: i64) -> Result<Option<i64>, sqlx::Error> {
+    let row = sqlx::query_as::<_, (i64,)>("SELECT id FROM webpages WHERE user_id = $1 AND (url_key = $2 OR url = $2) ORDER BY id LIMIT 1")
+        .bind(user_id)
+        .bind(url_key)
+        .fetch_optional(conn).await?;
+    Ok(row.map(|(id,)| id))
+}
+
+/// Saves the webpage unless the user has saved a webpage with the same url key already, in which
+/// case None is returned.
+async fn write_to_db(conn: &PgPool, url: &str, url_key: &str, webpage: &Webpage, assets: &[assets::Asset],
+        tags: &[String], user_id: i64) -> Result<Option<i64>, sqlx::Error> {
+    let stats = TextStats::new(&webpage.plain_text);
+    let mut transaction = conn.begin().await?;
+    let row = sqlx::query_as::<_, (i64,)>("INSERT INTO webpages(url, title, text, html, image_url, user_id, description, site_name, author, published_time, canonical_url, language, plain_text, url_key, word_count, reading_time, excerpt) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17) ON CONFLICT (user_id, url_key) DO NOTHING RETURNING id")
         .bind(url)
         .bind(&webpage.title)
         .bind(&webpage.contents)
         .bind(&webpage.original_html)
         .bind(&webpage.image_url)
         .bind(user_id)
-        .execute(conn).await?)
+        .bind(&webpage.metadata.description)
+        .bind(&webpage.metadata.site_name)
+        .bind(&webpage.metadata.author)
+        .bind(webpage.metadata.published_time)
+        .bind(&webpage.metadata.canonical_url)
+        .bind(&webpage.metadata.language)
+        .bind(&webpage.plain_text)
+        .bind(url_key)
+        .bind(stats.word_count)
+        .bind(stats.reading_time)
+        .bind(&webpage.excerpt)
+        .fetch_optional(&mut transaction).await?;
+    let webpage_id = match row {
+        Some((webpage_id,)) => webpage_id,
+        None => return Ok(None),
+    };
+    assets::store_assets(&mut transaction, webpage_id, None, assets).await?;
+    tags::add_tags(&mut transaction, user_id, webpage_id, tags).await?;
+    transaction.commit().await?;
+    Ok(Some(webpage_id))
 }
 
-async fn fetch_handler(db_pool: Arc<PgPool>,
-        http_client: reqwest::Client, body: FetchWebpage, user_id: i64) ->
-        Result<impl warp::Reply, warp::Rejection> {
+/// Replaces the contents of a saved webpage with a new version of it. When and by whom it was
+/// added, its reading state and its highlights are kept.
+async fn update_in_db(conn: &PgPool, webpage_id: i64, url_key: &str, webpage: &Webpage,
+        assets: &[assets::Asset], tags: &[String], user_id: i64) -> Result<(), sqlx::Error> {
+    let stats = TextStats::new(&webpage.plain_text);
+    let mut transaction = conn.begin().await?;
+    sqlx::query("UPDATE webpages SET title = $1, text = $2, html = $3, image_url = $4, description = $5, site_name = $6, author = $7, published_time = $8, canonical_url = $9, language = $10, plain_text = $11, url_key = $12, word_count = $13, reading_time = $14, excerpt = $15 WHERE id = $16 AND user_id = $17")
+        .bind(&webpage.title)
+        .bind(&webpage.contents)
+        .bind(&webpage.original_html)
+        .bind(&webpage.image_url)
+        .bind(&webpage.metadata.description)
+        .bind(&webpage.metadata.site_name)
+        .bind(&webpage.metadata.author)
+        .bind(webpage.metadata.published_time)
+        .bind(&webpage.metadata.canonical_url)
+        .bind(&webpage.metadata.language)
+        .bind(&webpage.plain_text)
+        .bind(url_key)
+        .bind(stats.word_count)
+        .bind(stats.reading_time)
+        .bind(&webpage.excerpt)
+        .bind(webpage_id)
+        .bind(user_id)
+        .execute(&mut transaction).await?;
+    // The assets of the snapshots still belong to the snapshots.
+    let old_assets: Vec<i64> = sqlx::query_as::<_, (i64,)>("DELETE FROM assets_to_webpages WHERE webpage_id = $1 AND snapshot_id IS NULL RETURNING asset_id")
+        .bind(webpage_id)
+        .fetch_all(&mut transaction).await?
+        .into_iter()
+        .map(|(asset_id,)| asset_id)
+        .collect();
+    assets::store_assets(&mut transaction, webpage_id, None, assets).await?;
+    assets::remove_unused_assets(&mut transaction, &old_assets).await?;
+    tags::add_tags(&mut transaction, user_id, webpage_id, tags).await?;
+    transaction.commit().await?;
+    Ok(())
+}
 
-    let html = match body.html {
-        Some(html) => html,
-        None => match fetch_webpage(&http_client, &body.url).await {
-            Ok(html) => html,
-            Err(error) => {
-                return Ok(Response::builder()
-                    .status(StatusCode::NOT_FOUND)
-                    .body(error.to_string())
-                );
-            }
+fn fetch_response(status: StatusCode, webpage_id: i64) -> Result<Response<String>, warp::http::Error> {
+    Response::builder()
+        .status(status)
+        .header("content-type", "application/json")
+        .body(serde_json::to_string(&FetchResponse {id: webpage_id}).unwrap_or_default())
+}
+
+fn duplicate_response(on_duplicate: OnDuplicate, webpage_id: i64) -> Result<Response<String>, warp::http::Error> {
+    let status = match on_duplicate {
+        OnDuplicate::conflict => StatusCode::CONFLICT,
+        _ => StatusCode::OK,
+    };
+    fetch_response(status, webpage_id)
+}
+
+/// Fetches the webpage unless the html is given, extracts the readable contents and saves it.
+/// The url is canonicalized first and if the user has saved the same webpage before, possibly
+/// from a url with other tracking parameters or with the same <link rel=canonical>, the
+/// on_duplicate option decides what happens.
+async fn save_webpage(db_pool: &PgPool, http_client: &HttpClient, extractors: &ExtractorRegistry,
+        request: &FetchWebpage, user_id: i64) -> Result<SavedWebpage, SaveError> {
+    let url = urls::canonicalize_url(&request.url);
+    let on_duplicate = request.on_duplicate.unwrap_or(OnDuplicate::existing);
+    if on_duplicate != OnDuplicate::update {
+        if let Some(webpage_id) = find_saved_webpage(db_pool, &url, user_id).await? {
+            return Ok(SavedWebpage {id: webpage_id, outcome: SaveOutcome::duplicate});
         }
+    }
+    let html = match &request.html {
+        Some(html) => html.clone(),
+        None => fetch::fetch_webpage(http_client, &request.url).await.map_err(SaveError::Fetch)?,
+    };
+    let mut webpage = extractors.extract(&html, &request.url).map_err(SaveError::Parse)?;
+    let url_key = url_key(&url, webpage.metadata.canonical_url.as_deref());
+    let saved_webpage = if url_key != url || on_duplicate == OnDuplicate::update {
+        match find_saved_webpage(db_pool, &url_key, user_id).await? {
+            None if on_duplicate == OnDuplicate::update => find_saved_webpage(db_pool, &url, user_id).await?,
+            saved_webpage => saved_webpage,
+        }
+    } else {
+        None
     };
-    match traverse_document(&html) {
-        Ok(webpage) => {
-            match write_to_db(&db_pool, &body.url, &webpage, user_id).await {
-                // Return-typen bestemmes af Responsens body, så hvis den er String det ene sted,
-                // skal den også være String det andet. Og den er nødt til at være String i
-                // Err-delen, fordi man ikke kan sende en reference til error ud af funktionen.
-                Ok(_) => return Ok(Response::builder().status(StatusCode::CREATED).body("".to_string())),
-                Err(error) => {
-                    log::error!("Error getting database connection: {}", error.to_string());
-                    return Ok(Response::builder()
-                        .status(StatusCode::INTERNAL_SERVER_ERROR)
-                        .body("".to_string())
-                    );
-                }
+    match saved_webpage {
+        Some(webpage_id) if on_duplicate != OnDuplicate::update =>
+            return Ok(SavedWebpage {id: webpage_id, outcome: SaveOutcome::duplicate}),
+        _ => (),
+    }
+
+    pagination::append_following_pages(http_client, extractors, &mut webpage, &request.url).await;
+    let assets = assets::archive_webpage(http_client, &mut webpage, &request.url,
+        request.archive_original.unwrap_or(false)).await;
+    let tags = request.tags.clone().unwrap_or_default();
+    if let Some(webpage_id) = saved_webpage {
+        update_in_db(db_pool, webpage_id, &url_key, &webpage, &assets, &tags, user_id).await?;
+        return Ok(SavedWebpage {id: webpage_id, outcome: SaveOutcome::updated});
+    }
+    match write_to_db(db_pool, &url, &url_key, &webpage, &assets, &tags, user_id).await? {
+        Some(webpage_id) => Ok(SavedWebpage {id: webpage_id, outcome: SaveOutcome::created}),
+        // The same webpage was saved by another job while this one was being fetched.
+        None => find_saved_webpage(db_pool, &url_key, user_id).await?
+            .map(|webpage_id| SavedWebpage {id: webpage_id, outcome: SaveOutcome::duplicate})
+            .ok_or(SaveError::Database(sqlx::Error::RowNotFound)),
+    }
+}
+
+// Queues the webpage to be saved by the job workers and responds with the id of the job. A url
+// the user has saved before is handled right away according to the on_duplicate option. Pages
+// which turn out to be duplicates because of their <link rel=canonical> are only found by the job.
+async fn fetch_handler(db_pool: Arc<PgPool>, job_wakeup: Arc<tokio::sync::Notify>, body: FetchWebpage,
+        user_id: i64) -> Result<impl warp::Reply, warp::Rejection> {
+    let url = urls::canonicalize_url(&body.url);
+    let on_duplicate = body.on_duplicate.unwrap_or(OnDuplicate::existing);
+    if on_duplicate != OnDuplicate::update {
+        match find_saved_webpage(&db_pool, &url, user_id).await {
+            Ok(Some(webpage_id)) => return Ok(duplicate_response(on_duplicate, webpage_id)),
+            Ok(None) => (),
+            Err(error) => {
+                log::error!("Error when looking up {} for user {}: {}", url, user_id, error);
+                return Ok(Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .body("".to_string()));
             }
-        },
-        Err(error) => {
-            log::error!("Error parsing document for url {}: {}", &body.url, error);
-            Ok(Response::builder()
-                .status(StatusCode::INTERNAL_SERVER_ERROR)
-                .body(error.to_string()))
         }
     }
+    Ok(jobs::enqueue_reply(&db_pool, &job_wakeup, &body, user_id).await)
 }
 
 #[derive(Serialize,Debug)]
@@ -126,129 +361,160 @@ async fn userinfo_handler(db_pool: Arc<PgPool>, user_id: i64) ->
             .fetch_optional(&*db_pool).await
             .map_or_else(|error| {
                 log::error!("Error when fetching user from database: {}", error);
-                return Err(warp::reject::custom(errors::Error::UnknownUser))
+                Err(warp::reject::custom(errors::Error::UnknownUser))
             }, |optional_user| {
                 optional_user.ok_or_else(|| warp::reject::custom(errors::Error::UnknownUser))
             })
-            .and_then(|(username,)| {
+            .map(|(username,)| {
                 let json = warp::reply::json(&UserInfo {
                     username,
                 });
-                Ok(warp::reply::with_status(json, StatusCode::OK))
+                warp::reply::with_status(json, StatusCode::OK)
             })
 }
 
 pub async fn migrate_db(conn: &PgPool) -> Result<(), sqlx::Error> {
-    Ok(MIGRATOR.run(conn).await?)
+    MIGRATOR.run(conn).await?;
+    backfill_url_keys(conn).await
+}
+
+// Webpages saved before url keys were added only have the url they were saved from, possibly with
+// tracking parameters and fragments, so their url keys are computed here rather than in sql where
+// urls can't be canonicalized the same way. A webpage which turns out to be a duplicate of an
+// older webpage of the user can't have the same url key and is reported instead so the two can be
+// merged by hand.
+async fn backfill_url_keys(conn: &PgPool) -> Result<(), sqlx::Error> {
+    let rows = sqlx::query_as::<_, (i64, i64, String, Option<String>)>("SELECT id, user_id, url, canonical_url FROM webpages WHERE url_key IS NULL ORDER BY id")
+        .fetch_all(conn).await?;
+    for (id, user_id, url, canonical_url) in rows {
+        let url_key = url_key(&urls::canonicalize_url(&url), canonical_url.as_deref());
+        let result = sqlx::query("UPDATE webpages SET url_key = $1 WHERE id = $2 AND NOT EXISTS (SELECT 1 FROM webpages AS other WHERE other.user_id = $3 AND other.url_key = $1)")
+            .bind(&url_key)
+            .bind(id)
+            .bind(user_id)
+            .execute(conn).await?;
+        if result.rows_affected() == 0 {
+            log::warn!("Webpage {} of user {} is a duplicate of another webpage saved from {}", id, user_id, url_key);
+        }
+    }
+    Ok(())
 }
 
 #[derive(Debug)]
 struct Webpage {
     title: String,
     contents: String,
+    // The readable contents without markup.
+    plain_text: String,
+    excerpt: Option<String>,
     image_url: Option<String>,
     original_html: String,
+    metadata: metadata::Metadata,
 }
 
-fn traverse_document(html: &str) -> Result<Webpage, errors::ParseDocumentError> {
-    let document = kuchiki::parse_html().one(html);
+fn clean_document(document: &kuchiki::NodeRef, base_url: Option<&url::Url>) {
     // https://stackoverflow.com/a/66277475
     document.inclusive_descendants()
-        .filter(|node| node.as_element().map_or(false, |e| {
+        .filter(|node| node.as_element().is_some_and(|e| {
             matches!(e.name.local.as_ref(), "script" | "style" | "noscript")
         }))
         .collect::<Vec<_>>()
         .iter()
         .for_each(|node| node.detach());
-    let title = document.select_first("title").map_or_else(|_| {
-            let mut i = 0;
-            let mut string_builder = Vec::new();
-            for child in document.inclusive_descendants().text_nodes() {
-                string_builder.push(child.borrow().to_string());
-                if i >= 5 {
-                    break;
-                }
-                i += 1;
-            }
-            string_builder.join(" ").trim().to_string()
-        }, |node| node.text_contents().trim().to_string());
-    let image_url = document.select_first("img").map_or(None, |node| {
-        match node.as_node().data() {
-            kuchiki::NodeData::Element(element_data) => {
-                for (key, value) in &element_data.attributes.borrow().map {
-                    if key.local.to_string() == "src" {
-                        return Some(value.value.to_string())
-                    }
-                }
-                None
-            },
-            _ => None
+    if let Some(base_url) = base_url {
+        urls::resolve_urls(document, base_url);
+    }
+}
+
+// Returns the src of the first image in the node which isn't a tracking pixel.
+fn first_image_url(node: &kuchiki::NodeRef) -> Option<String> {
+    node.select("img[src]").ok()?.find_map(|image| {
+        let attributes = image.attributes.borrow();
+        let is_pixel = ["width", "height"].iter().any(|dimension| {
+            attributes.get(*dimension).is_some_and(|value| matches!(value.trim(), "0" | "1" | "1px"))
+        });
+        if is_pixel {
+            None
+        } else {
+            attributes.get("src").map(|src| src.to_string())
         }
+    })
+}
+
+// Extracts the title and readable contents of the document with the site specific extractor if
+// there is one for the page and it finds anything, and with the generic article extraction
+// otherwise.
+fn traverse_document(html: &str, url: &str, extractor: Option<&dyn extractors::Extractor>) ->
+        Result<Webpage, errors::ParseDocumentError<'static>> {
+    let document = kuchiki::parse_html().one(html);
+    let base_url = urls::base_url(&document, url);
+    let document_metadata = metadata::extract_metadata(&document, base_url.as_ref());
+    clean_document(&document, base_url.as_ref());
+    let extraction = extractor.zip(url::Url::parse(url).ok()).and_then(|(extractor, page_url)| {
+        let site_document = kuchiki::parse_html().one(html);
+        clean_document(&site_document, base_url.as_ref());
+        extractor.extract(&site_document, &page_url)
     });
-    let tags_to_ignore = ["html", "head", "meta", "link", "style", "body",
-        "main", "article", "div", "script", "nav", "ul", "footer", "svg",
-        "path", "figure", "picture", "iframe"];
-    let mut string_builder = Vec::new();
-    for node_edge in document.traverse_inclusive() {
-        match node_edge {
-            kuchiki::iter::NodeEdge::Start(node) => {
-                match node.data() {
-                    kuchiki::NodeData::Element(element_data) => {
-                        let tag = &element_data.name.local.to_string();
-                        if tags_to_ignore.iter().all(|item| item != tag) {
-                            let attributes = attributes_to_string(
-                                &element_data.attributes.borrow());
-                            string_builder.push(format!("<{} {}>", tag,
-                                attributes));
-                        }
-                    },
-                    kuchiki::NodeData::Text(value) => {
-                        let text = value.borrow().trim().to_string();
-                        if text != "" {
-                            string_builder.push(text.to_string());
-                        }
-                    },
-                    _ => {}
-                }
-            },
-            kuchiki::iter::NodeEdge::End(node) => {
-                match node.data() {
-                    kuchiki::NodeData::Element(element_data) => {
-                        let tag = &element_data.name.local.to_string();
-                        if tags_to_ignore.iter().all(|item| item != tag) {
-                            string_builder.push(format!("</{}>", tag));
-                        }
-                    },
-                    _ => {}
-                }
-            },
-        }
-    }
-    let contents = string_builder.join(" ");
+    let (site_title, article) = match extraction {
+        Some(extraction) => (extraction.title, Some(extraction.contents)),
+        None => {
+            // The article extraction removes boilerplate from the document it is given so it
+            // gets its own copy. If no article can be found the whole document is used instead.
+            let article_document = kuchiki::parse_html().one(html);
+            clean_document(&article_document, base_url.as_ref());
+            (None, readability::extract_article(&article_document))
+        },
+    };
+    let title = site_title.or(document_metadata.title).unwrap_or_else(|| {
+        document.select_first("title").map_or_else(|_| {
+                document.inclusive_descendants().text_nodes()
+                    .take(6)
+                    .map(|child| child.borrow().to_string())
+                    .collect::<Vec<_>>()
+                    .join(" ").trim().to_string()
+            }, |node| node.text_contents().trim().to_string())
+    });
+    let image_url = document_metadata.image_url
+        .or_else(|| article.as_ref().and_then(|article| article.iter().find_map(first_image_url)))
+        .or_else(|| first_image_url(&document));
+    let (contents, plain_text) = match article {
+        Some(article) => (
+            article.iter()
+                .map(sanitize::node_to_html)
+                .filter(|text| !text.is_empty())
+                .collect::<Vec<_>>()
+                .join(" "),
+            article.iter()
+                .map(sanitize::node_to_text)
+                .filter(|text| !text.is_empty())
+                .collect::<Vec<_>>()
+                .join(" ")),
+        None => (sanitize::node_to_html(&document), sanitize::node_to_text(&document)),
+    };
     if !contents.is_empty() {
+        let mut metadata = document_metadata.metadata;
+        if metadata.language.is_none() {
+            metadata.language = text_stats::detect_language(&plain_text);
+        }
+        let excerpt = excerpt::excerpt(metadata.description.as_deref(), &contents);
         Ok(Webpage {
             title,
             contents,
+            plain_text,
+            excerpt,
             image_url,
             original_html: html.to_string(),
+            metadata,
         })
     } else {
         Err(errors::ParseDocumentError::new("Unable to extract any text from document"))
     }
 }
 
-fn attributes_to_string(attributes: &kuchiki::Attributes) -> String {
-    let mut string_builder = Vec::with_capacity(attributes.map.len());
-    for (key, value) in &attributes.map {
-        string_builder.push(format!("{}={}", key.local, value.value));
-    }
-    string_builder.join(" ")
-}
-
 fn validate_int_arg(v: String) -> Result<(), String> {
     match v.parse::<u16>() {
         Ok(_) => Ok(()),
-        Err(error) => Err(format!("Error parsing {} as u16: {}", v, error.to_string()))
+        Err(error) => Err(format!("Error parsing {} as u16: {}", v, error))
     }
 }
 
@@ -259,6 +525,10 @@ fn validate_host_arg(v: String) -> Result<(), String> {
     }
 }
 
+fn validate_host_pattern_arg(v: String) -> Result<(), String> {
+    v.parse::<url_policy::HostPattern>().map(|_| ())
+}
+
 pub fn setup_args() -> ArgMatches<'static> {
     App::new("article-server")
         .arg(Arg::with_name("host")
@@ -276,6 +546,53 @@ pub fn setup_args() -> ArgMatches<'static> {
             .long("--db-path")
             .help("Path to the database to store webpages in")
             .default_value("webpages.db"))
+        .arg(Arg::with_name("user-agent")
+            .long("--user-agent")
+            .help("User-Agent header sent when fetching webpages")
+            .takes_value(true))
+        .arg(Arg::with_name("accept-language")
+            .long("--accept-language")
+            .help("Accept-Language header sent when fetching webpages")
+            .takes_value(true))
+        .arg(Arg::with_name("fetch-workers")
+            .long("--fetch-workers")
+            .help("Number of workers fetching and saving webpages in the background")
+            .validator(validate_int_arg)
+            .default_value("4"))
+        .arg(Arg::with_name("allow-private-addresses")
+            .long("--allow-private-addresses")
+            .help("Allow fetching from loopback, private and link-local addresses. Only meant for development"))
+        .arg(Arg::with_name("allow-host")
+            .long("--allow-host")
+            .help("Domain or network which can be fetched from even if it isn't public. Can be given several times")
+            .validator(validate_host_pattern_arg)
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1))
+        .arg(Arg::with_name("deny-host")
+            .long("--deny-host")
+            .help("Domain or network which is never fetched from. Can be given several times")
+            .validator(validate_host_pattern_arg)
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1))
+        .arg(Arg::with_name("site-config-dir")
+            .long("--site-config-dir")
+            .help("Directory of site configs telling where the contents of particular sites are")
+            .takes_value(true))
+        .arg(Arg::with_name("respect-robots-txt")
+            .long("--respect-robots-txt")
+            .help("Don't fetch webpages which robots.txt disallows"))
+        .arg(Arg::with_name("max-requests-per-host")
+            .long("--max-requests-per-host")
+            .help("Number of requests sent to the same host at a time")
+            .validator(validate_int_arg)
+            .default_value("2"))
+        .arg(Arg::with_name("request-delay")
+            .long("--request-delay")
+            .help("Milliseconds between the requests sent to the same host")
+            .validator(validate_int_arg)
+            .default_value("1000"))
     .get_matches()
 }
 
@@ -283,6 +600,10 @@ pub fn setup_args() -> ArgMatches<'static> {
 pub struct ServerArgs {
     pub pool: PgPool,
     pub addr: SocketAddr,
+    pub jobs: jobs::JobQueueConfig,
+    pub http: fetch::HttpClientConfig,
+    // A directory of site configs for extracting the contents of particular sites.
+    pub site_config_dir: Option<std::path::PathBuf>,
 }
 
 pub fn start_server(args: ServerArgs) -> impl std::future::Future<Output = ()> + 'static {
@@ -299,41 +620,214 @@ pub fn start_server(args: ServerArgs) -> impl std::future::Future<Output = ()> +
     // This db pool is passed to the jwt authorization filter. It needs to be a regular object and
     // not a warp filter so the pool object passed to the handlers cannot be used here.
     let auth_pool = pool.clone();
+    let worker_pool = pool.clone();
     let pool = warp::any().map(move|| pool.clone());
-    let http_client = warp::any().map(move|| reqwest::Client::new());
-    let api_routes = warp::post()
-            .and(warp::path("fetch"))
+    let http_client = HttpClient::new(&args.http).expect("Unable to create http client");
+    let worker_http_client = http_client.clone();
+    let http_client = warp::any().map(move || http_client.clone());
+    let extractors = Arc::new(match &args.site_config_dir {
+        Some(site_config_dir) => ExtractorRegistry::load(site_config_dir).unwrap_or_else(|error| panic!("{}", error)),
+        None => ExtractorRegistry::built_in(),
+    });
+    let worker_extractors = extractors.clone();
+    let extractors = warp::any().map(move || extractors.clone());
+    // Wakes up a job worker when a webpage is queued to be fetched.
+    let job_wakeup = Arc::new(tokio::sync::Notify::new());
+    let worker_wakeup = job_wakeup.clone();
+    let job_wakeup = warp::any().map(move || job_wakeup.clone());
+    // The routes are boxed in groups. Chaining all of them directly creates such deeply nested
+    // futures that serving a request can overflow the stack in debug builds.
+    let webpage_routes = warp::get()
+            .and(warp::path("webpage"))
+            .and(warp::path::param())
+            .and(warp::path("export"))
+            .and(warp::path::end())
+            .and(warp::query::<export::ExportOptions>())
             .and(pool.clone())
-            .and(http_client)
-            .and(warp::body::json())
+            .and(http_client.clone())
             .and(auth::with_jwt_auth(auth_pool.clone(), vec![auth::Role::User, auth::Role::Admin]))
-            .and_then(fetch_handler)
-        .or(
-            warp::get().and(warp::path("status")).map(|| "OK"))
+            .and_then(export::export_webpage_handler)
+        .or(warp::get()
+            .and(warp::path("export"))
+            .and(warp::path("epub"))
+            .and(warp::path::end())
+            .and(warp::query::<epub::EpubOptions>())
+            .and(pool.clone())
+            .and(http_client.clone())
+            .and(auth::with_jwt_auth(auth_pool.clone(), vec![auth::Role::User, auth::Role::Admin]))
+            .and_then(epub::export_epub_handler))
         .or(warp::get()
             .and(warp::path("webpage"))
             .and(warp::path::param())
+            .and(warp::path::end())
             .and(warp::query::<webpages::ShowOptions>())
             .and(pool.clone())
             .and(auth::with_jwt_auth(auth_pool.clone(), vec![auth::Role::User, auth::Role::Admin]))
             .and_then(webpages::show_stored_webpage_handler))
+        .or(warp::put()
+            .and(warp::path("webpage"))
+            .and(warp::path::param())
+            .and(warp::path::param::<webpages::StateFlag>())
+            .and(warp::path::end())
+            .and(pool.clone())
+            .and(auth::with_jwt_auth(auth_pool.clone(), vec![auth::Role::User, auth::Role::Admin]))
+            .and_then(webpages::add_state_handler))
+        .or(warp::delete()
+            .and(warp::path("webpage"))
+            .and(warp::path::param())
+            .and(warp::path::param::<webpages::StateFlag>())
+            .and(warp::path::end())
+            .and(pool.clone())
+            .and(auth::with_jwt_auth(auth_pool.clone(), vec![auth::Role::User, auth::Role::Admin]))
+            .and_then(webpages::remove_state_handler))
+        .or(warp::get()
+            .and(warp::path("webpage"))
+            .and(warp::path::param())
+            .and(warp::path("highlights"))
+            .and(warp::path::end())
+            .and(pool.clone())
+            .and(auth::with_jwt_auth(auth_pool.clone(), vec![auth::Role::User, auth::Role::Admin]))
+            .and_then(highlights::list_highlights_handler))
+        .or(warp::post()
+            .and(warp::path("webpage"))
+            .and(warp::path::param())
+            .and(warp::path("highlights"))
+            .and(warp::path::end())
+            .and(warp::body::json())
+            .and(pool.clone())
+            .and(auth::with_jwt_auth(auth_pool.clone(), vec![auth::Role::User, auth::Role::Admin]))
+            .and_then(highlights::create_highlight_handler))
+        .or(warp::put()
+            .and(warp::path("webpage"))
+            .and(warp::path::param())
+            .and(warp::path("highlights"))
+            .and(warp::path::param())
+            .and(warp::path::end())
+            .and(warp::body::json())
+            .and(pool.clone())
+            .and(auth::with_jwt_auth(auth_pool.clone(), vec![auth::Role::User, auth::Role::Admin]))
+            .and_then(highlights::update_highlight_handler))
         .or(warp::delete()
             .and(warp::path("webpage"))
             .and(warp::path::param())
+            .and(warp::path("highlights"))
+            .and(warp::path::param())
+            .and(warp::path::end())
+            .and(pool.clone())
+            .and(auth::with_jwt_auth(auth_pool.clone(), vec![auth::Role::User, auth::Role::Admin]))
+            .and_then(highlights::delete_highlight_handler))
+        .or(warp::get()
+            .and(warp::path("webpage"))
+            .and(warp::path::param())
+            .and(warp::path("progress"))
+            .and(warp::path::end())
+            .and(pool.clone())
+            .and(auth::with_jwt_auth(auth_pool.clone(), vec![auth::Role::User, auth::Role::Admin]))
+            .and_then(progress::get_progress_handler))
+        .or(warp::put()
+            .and(warp::path("webpage"))
+            .and(warp::path::param())
+            .and(warp::path("progress"))
+            .and(warp::path::end())
+            .and(warp::body::json())
+            .and(pool.clone())
+            .and(auth::with_jwt_auth(auth_pool.clone(), vec![auth::Role::User, auth::Role::Admin]))
+            .and_then(progress::save_progress_handler))
+        .or(warp::delete()
+            .and(warp::path("webpage"))
+            .and(warp::path::param())
+            .and(warp::path::end())
             .and(pool.clone())
             .and(auth::with_jwt_auth(auth_pool.clone(), vec![auth::Role::User, auth::Role::Admin]))
             .and_then(webpages::delete_stored_webpage_handler))
+        .or(warp::get()
+            .and(warp::path("asset"))
+            .and(warp::path::param())
+            .and(warp::path::end())
+            .and(pool.clone())
+            .and(auth::with_jwt_auth(auth_pool.clone(), vec![auth::Role::User, auth::Role::Admin]))
+            .and_then(assets::get_asset_handler))
         .or(warp::get()
             .and(warp::path("list-stored-webpages"))
+            .and(warp::query::<webpages::ListOptions>())
             .and(pool.clone())
             .and(auth::with_jwt_auth(auth_pool.clone(), vec![auth::Role::User, auth::Role::Admin]))
             .and_then(webpages::get_stored_webpages_for_user))
+        .or(warp::get()
+            .and(warp::path("search"))
+            .and(warp::path::end())
+            .and(warp::query::<search::SearchOptions>())
+            .and(pool.clone())
+            .and(auth::with_jwt_auth(auth_pool.clone(), vec![auth::Role::User, auth::Role::Admin]))
+            .and_then(search::search_handler))
+        .boxed();
+    let snapshot_routes = warp::post()
+            .and(warp::path("webpage"))
+            .and(warp::path::param())
+            .and(warp::path("refetch"))
+            .and(warp::path::end())
+            .and(pool.clone())
+            .and(http_client)
+            .and(extractors)
+            .and(auth::with_jwt_auth(auth_pool.clone(), vec![auth::Role::User, auth::Role::Admin]))
+            .and_then(snapshots::refetch_handler)
+        .or(warp::get()
+            .and(warp::path("webpage"))
+            .and(warp::path::param())
+            .and(warp::path("snapshots"))
+            .and(warp::path::end())
+            .and(pool.clone())
+            .and(auth::with_jwt_auth(auth_pool.clone(), vec![auth::Role::User, auth::Role::Admin]))
+            .and_then(snapshots::list_snapshots_handler))
+        .or(warp::get()
+            .and(warp::path("webpage"))
+            .and(warp::path::param())
+            .and(warp::path("diff"))
+            .and(warp::path::end())
+            .and(warp::query::<snapshots::DiffOptions>())
+            .and(pool.clone())
+            .and(auth::with_jwt_auth(auth_pool.clone(), vec![auth::Role::User, auth::Role::Admin]))
+            .and_then(snapshots::diff_handler))
+        .boxed();
+    let tag_routes = warp::post()
+            .and(warp::path("webpage"))
+            .and(warp::path::param())
+            .and(warp::path("tags"))
+            .and(warp::path::end())
+            .and(warp::body::json())
+            .and(pool.clone())
+            .and(auth::with_jwt_auth(auth_pool.clone(), vec![auth::Role::User, auth::Role::Admin]))
+            .and_then(tags::add_tags_handler)
+        .or(warp::delete()
+            .and(warp::path("webpage"))
+            .and(warp::path::param())
+            .and(warp::path("tags"))
+            .and(warp::path::end())
+            .and(warp::body::json())
+            .and(pool.clone())
+            .and(auth::with_jwt_auth(auth_pool.clone(), vec![auth::Role::User, auth::Role::Admin]))
+            .and_then(tags::remove_tags_handler))
+        .or(warp::get()
+            .and(warp::path("tags"))
+            .and(warp::path::end())
+            .and(pool.clone())
+            .and(auth::with_jwt_auth(auth_pool.clone(), vec![auth::Role::User, auth::Role::Admin]))
+            .and_then(tags::list_tags_handler))
         .or(warp::post()
+            .and(warp::path("tags"))
+            .and(warp::path("rename"))
+            .and(warp::path::end())
+            .and(warp::body::json())
+            .and(pool.clone())
+            .and(auth::with_jwt_auth(auth_pool.clone(), vec![auth::Role::User, auth::Role::Admin]))
+            .and_then(tags::rename_tag_handler))
+        .boxed();
+    let auth_routes = warp::post()
             .and(warp::path("register"))
             .and(pool.clone())
             .and(warp::body::json())
             .and(auth::with_jwt_auth(auth_pool.clone(), vec![auth::Role::Admin]))
-            .and_then(auth::register_handler))
+            .and_then(auth::register_handler)
         .or(warp::post()
             .and(warp::path("login"))
             .and(pool.clone())
@@ -358,10 +852,32 @@ pub fn start_server(args: ServerArgs) -> impl std::future::Future<Output = ()> +
         .or(warp::get()
             .and(warp::path("userinfo"))
             .and(pool.clone())
+            .and(auth::with_jwt_auth(auth_pool.clone(), vec![auth::Role::User, auth::Role::Admin]))
+            .and_then(userinfo_handler))
+        .boxed();
+    let api_routes = warp::post()
+            .and(warp::path("fetch"))
+            .and(pool.clone())
+            .and(job_wakeup)
+            .and(warp::body::json())
+            .and(auth::with_jwt_auth(auth_pool.clone(), vec![auth::Role::User, auth::Role::Admin]))
+            .and_then(fetch_handler)
+        .or(warp::get()
+            .and(warp::path("jobs"))
+            .and(warp::path::param())
+            .and(warp::path::end())
+            .and(pool.clone())
             .and(auth::with_jwt_auth(auth_pool, vec![auth::Role::User, auth::Role::Admin]))
-            .and_then(userinfo_handler));
+            .and_then(jobs::get_job_handler))
+        .or(
+            warp::get().and(warp::path("status")).map(|| "OK"))
+        .or(webpage_routes)
+        .or(snapshot_routes)
+        .or(tag_routes)
+        .or(auth_routes);
     let routes = warp::path("api").and(api_routes.with(
         warp::log("article-saver"))).recover(errors::handle_rejection);
+    jobs::start_workers(worker_pool, worker_http_client, worker_extractors, &args.jobs, worker_wakeup);
     warp::serve(routes).bind(args.addr)
 }
 
@@ -1,4 +1,5 @@
-use article_server_rs::{setup_args,start_server,migrate_db,ServerArgs};
+use article_server_rs::{setup_args,start_server,migrate_db,ServerArgs,
+    fetch::HttpClientConfig,jobs::JobQueueConfig,url_policy::HostPattern};
 
 use std::io::Write;
 
@@ -13,7 +14,7 @@ async fn main() {
                 "message": record.args().as_str().map_or_else(|| {record.args().to_string()}, |s| s.to_string()),
                 "target": record.target().to_string(),
             });
-            writeln!(buf, "{}", message.to_string())
+            writeln!(buf, "{}", message)
         })
         .filter(None, log::LevelFilter::Info)
         .target(env_logger::Target::Stdout)
@@ -25,12 +26,37 @@ async fn main() {
     let service_address_str = format!("{}:{}", host, port);
     let db_url = args.value_of("database-path")
         .expect("Unable to get database-path argument");
+    let fetch_workers = args.value_of("fetch-workers").expect("Unable to get fetch-workers argument")
+        .parse::<usize>().expect("Unable to parse fetch-workers argument");
+    let mut http_config = HttpClientConfig::default();
+    if let Some(user_agent) = args.value_of("user-agent") {
+        http_config.user_agent = user_agent.to_string();
+    }
+    if let Some(accept_language) = args.value_of("accept-language") {
+        http_config.accept_language = accept_language.to_string();
+    }
+    http_config.url_policy.allow_private_addresses = args.is_present("allow-private-addresses");
+    let host_patterns = |name| args.values_of(name).into_iter().flatten()
+        .map(|pattern| pattern.parse::<HostPattern>().expect("Unable to parse host pattern"))
+        .collect::<Vec<_>>();
+    http_config.url_policy.allow = host_patterns("allow-host");
+    http_config.url_policy.deny = host_patterns("deny-host");
+    http_config.politeness.respect_robots_txt = args.is_present("respect-robots-txt");
+    http_config.politeness.max_requests_per_host = args.value_of("max-requests-per-host")
+        .expect("Unable to get max-requests-per-host argument")
+        .parse::<usize>().expect("Unable to parse max-requests-per-host argument");
+    http_config.politeness.request_delay = std::time::Duration::from_millis(args.value_of("request-delay")
+        .expect("Unable to get request-delay argument")
+        .parse::<u64>().expect("Unable to parse request-delay argument"));
     let pool = PgPool::connect(db_url).await.expect("Unable to get database connection pool");
     migrate_db(&pool).await.expect("Unable to migrate database");
     let server_args = ServerArgs {
         pool,
-        addr: service_address_str.parse().expect(
-            &format!("Unable to parse {} as a socket address", service_address_str)),
+        addr: service_address_str.parse().unwrap_or_else(|_| panic!(
+            "Unable to parse {} as a socket address", service_address_str)),
+        jobs: JobQueueConfig {workers: fetch_workers, ..JobQueueConfig::default()},
+        http: http_config,
+        site_config_dir: args.value_of("site-config-dir").map(std::path::PathBuf::from),
     };
     start_server(server_args).await;
 }
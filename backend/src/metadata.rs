@@ -0,0 +1,254 @@
+// Reads metadata about a page from OpenGraph and Twitter card meta tags, ordinary meta tags and
+// JSON-LD descriptions of the article. These are written by the site itself so they are usually
+// more accurate than anything which can be guessed from the contents of the page.
+// https://ogp.me/
+// https://developer.twitter.com/en/docs/twitter-for-websites/cards/markup-reference
+// https://schema.org/Article
+
+use std::collections::HashMap;
+
+use kuchiki::NodeRef;
+use serde::{Deserialize,Serialize};
+use serde_json::Value;
+use url::Url;
+
+use crate::urls;
+
+const ARTICLE_TYPES: [&str; 8] = ["Article", "NewsArticle", "BlogPosting", "Report",
+    "ScholarlyArticle", "TechArticle", "SocialMediaPosting", "AnalysisNewsArticle"];
+
+/// The metadata stored for each webpage besides the title and the lead image.
+#[derive(Deserialize,Serialize,Debug,Default,Clone,PartialEq,Eq)]
+pub struct Metadata {
+    pub description: Option<String>,
+    pub site_name: Option<String>,
+    pub author: Option<String>,
+    pub published_time: Option<chrono::DateTime<chrono::Utc>>,
+    pub canonical_url: Option<String>,
+    pub language: Option<String>,
+}
+
+#[derive(Debug,Default)]
+pub struct DocumentMetadata {
+    pub title: Option<String>,
+    pub image_url: Option<String>,
+    pub metadata: Metadata,
+}
+
+fn non_empty(value: Option<&str>) -> Option<String> {
+    value.map(|value| value.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|value| !value.is_empty())
+}
+
+// Returns the content of all meta tags keyed by their property, name or http-equiv attribute in
+// lower case. If a key appears more than once the first value is kept.
+fn meta_tags(document: &NodeRef) -> HashMap<String, String> {
+    let mut tags = HashMap::new();
+    if let Ok(elements) = document.select("meta[content]") {
+        for element in elements {
+            let attributes = element.attributes.borrow();
+            let key = attributes.get("property")
+                .or_else(|| attributes.get("name"))
+                .or_else(|| attributes.get("http-equiv"));
+            if let (Some(key), Some(content)) = (key, non_empty(attributes.get("content"))) {
+                tags.entry(key.to_lowercase()).or_insert(content);
+            }
+        }
+    }
+    tags
+}
+
+fn has_article_type(item: &Value) -> bool {
+    match item.get("@type") {
+        Some(Value::String(item_type)) => ARTICLE_TYPES.contains(&item_type.as_str()),
+        Some(Value::Array(item_types)) => item_types.iter()
+            .any(|item_type| item_type.as_str().is_some_and(|item_type| ARTICLE_TYPES.contains(&item_type))),
+        _ => false,
+    }
+}
+
+fn find_article(value: &Value) -> Option<&Value> {
+    match value {
+        Value::Array(items) => items.iter().find_map(find_article),
+        Value::Object(object) => {
+            if has_article_type(value) {
+                Some(value)
+            } else {
+                object.get("@graph").and_then(find_article)
+            }
+        },
+        _ => None,
+    }
+}
+
+// Returns the first article described in a <script type="application/ld+json"> element.
+fn json_ld_article(document: &NodeRef) -> Option<Value> {
+    document.select("script[type=\"application/ld+json\"]").ok()?
+        .filter_map(|script| serde_json::from_str::<Value>(&script.text_contents()).ok())
+        .find_map(|value| find_article(&value).cloned())
+}
+
+// Many properties can either be a plain string, an object with the value stored under a specific
+// key, e.g. {"@type": "Person", "name": "..."}, or a list of either.
+fn json_ld_text(value: Option<&Value>, key: &str) -> Option<String> {
+    match value? {
+        Value::String(text) => non_empty(Some(text)),
+        Value::Object(object) => json_ld_text(object.get(key), key),
+        Value::Array(items) => {
+            let texts: Vec<String> = items.iter()
+                .filter_map(|item| json_ld_text(Some(item), key))
+                .collect();
+            non_empty(Some(&texts.join(", ")))
+        },
+        _ => None,
+    }
+}
+
+fn json_ld_first(value: Option<&Value>, key: &str) -> Option<String> {
+    match value? {
+        Value::Array(items) => items.iter().find_map(|item| json_ld_first(Some(item), key)),
+        value => json_ld_text(Some(value), key),
+    }
+}
+
+fn parse_time(time: &str) -> Option<chrono::DateTime<chrono::Utc>> {
+    chrono::DateTime::parse_from_rfc3339(time).ok()
+        .map(|time| time.with_timezone(&chrono::Utc))
+        .or_else(|| {
+            chrono::NaiveDate::parse_from_str(time.get(..10)?, "%Y-%m-%d").ok()
+                .map(|date| chrono::DateTime::from_utc(date.and_hms(0, 0, 0), chrono::Utc))
+        })
+}
+
+// og:locale uses an underscore as separator, e.g. en_US, while the lang attribute uses a dash.
+fn normalize_language(language: &str) -> String {
+    language.trim().replace('_', "-")
+}
+
+fn resolve(base_url: Option<&Url>, url: String) -> String {
+    match base_url {
+        Some(base_url) => urls::resolve_url(base_url, &url),
+        None => url,
+    }
+}
+
+/// Extracts the metadata of a document. This has to be done before any <script> elements are
+/// removed from the document since JSON-LD is embedded in those.
+pub fn extract_metadata(document: &NodeRef, base_url: Option<&Url>) -> DocumentMetadata {
+    let tags = meta_tags(document);
+    let tag = |key: &str| tags.get(key).cloned();
+    let article = json_ld_article(document);
+    let article_value = |key: &str| article.as_ref().and_then(|article| article.get(key));
+    let title = tag("og:title")
+        .or_else(|| tag("twitter:title"))
+        .or_else(|| json_ld_text(article_value("headline"), "name"));
+    let image_url = tag("og:image")
+        .or_else(|| tag("og:image:url"))
+        .or_else(|| tag("og:image:secure_url"))
+        .or_else(|| tag("twitter:image"))
+        .or_else(|| tag("twitter:image:src"))
+        .or_else(|| json_ld_first(article_value("image"), "url"))
+        .map(|url| resolve(base_url, url));
+    let description = tag("og:description")
+        .or_else(|| tag("twitter:description"))
+        .or_else(|| tag("description"))
+        .or_else(|| json_ld_text(article_value("description"), "name"));
+    let site_name = tag("og:site_name")
+        .or_else(|| json_ld_text(article_value("publisher"), "name"))
+        .or_else(|| tag("application-name"));
+    // article:author is often a link to a profile page rather than a name.
+    let author = tag("author")
+        .or_else(|| json_ld_text(article_value("author"), "name"))
+        .or_else(|| tag("article:author").filter(|author| Url::parse(author).is_err()));
+    let published_time = tag("article:published_time")
+        .or_else(|| json_ld_text(article_value("datePublished"), "@value"))
+        .and_then(|time| parse_time(&time));
+    let canonical_url = document.select_first("link[rel=\"canonical\"][href]").ok()
+        .and_then(|link| non_empty(link.attributes.borrow().get("href")))
+        .or_else(|| tag("og:url"))
+        .map(|url| resolve(base_url, url));
+    let language = document.select_first("html[lang]").ok()
+        .and_then(|html| non_empty(html.attributes.borrow().get("lang")))
+        .or_else(|| tag("content-language"))
+        .or_else(|| tag("og:locale"))
+        .or_else(|| json_ld_text(article_value("inLanguage"), "name"))
+        .map(|language| normalize_language(&language));
+    DocumentMetadata {
+        title,
+        image_url,
+        metadata: Metadata {
+            description,
+            site_name,
+            author,
+            published_time,
+            canonical_url,
+            language,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::TimeZone;
+    use html5ever::tendril::TendrilSink;
+
+    fn extract(html: &str) -> DocumentMetadata {
+        let document = kuchiki::parse_html().one(html);
+        let base_url = Url::parse("https://example.com/articles/article.html").expect("Unable to parse url");
+        extract_metadata(&document, Some(&base_url))
+    }
+
+    #[test]
+    fn test_extract_metadata_opengraph() {
+        let result = extract(include_str!("test-data/metadata.html"));
+        assert_eq!(result.title, Some("The real headline".to_string()));
+        assert_eq!(result.image_url, Some("https://example.com/images/lead.jpg".to_string()));
+        assert_eq!(result.metadata, Metadata {
+            description: Some("A short description of the article".to_string()),
+            site_name: Some("Example News".to_string()),
+            author: Some("Jane Doe".to_string()),
+            published_time: Some(chrono::Utc.ymd(2022, 5, 1).and_hms(12, 30, 0)),
+            canonical_url: Some("https://example.com/articles/the-real-headline".to_string()),
+            language: Some("en-GB".to_string()),
+        });
+    }
+
+    #[test]
+    fn test_extract_metadata_twitter() {
+        let result = extract("<head><meta name=\"twitter:title\" content=\"Twitter title\"><meta name=\"twitter:image\" content=\"/twitter.png\"><meta name=\"twitter:description\" content=\"Twitter description\"></head>");
+        assert_eq!(result.title, Some("Twitter title".to_string()));
+        assert_eq!(result.image_url, Some("https://example.com/twitter.png".to_string()));
+        assert_eq!(result.metadata.description, Some("Twitter description".to_string()));
+    }
+
+    #[test]
+    fn test_extract_metadata_json_ld() {
+        let result = extract(include_str!("test-data/metadata-json-ld.html"));
+        assert_eq!(result.title, Some("A headline from JSON-LD".to_string()));
+        assert_eq!(result.image_url, Some("https://example.com/images/first.jpg".to_string()));
+        assert_eq!(result.metadata, Metadata {
+            description: Some("Described in JSON-LD".to_string()),
+            site_name: Some("The Publisher".to_string()),
+            author: Some("Ann Author, Bob Writer".to_string()),
+            published_time: Some(chrono::Utc.ymd(2021, 11, 3).and_hms(0, 0, 0)),
+            canonical_url: None,
+            language: Some("da-DK".to_string()),
+        });
+    }
+
+    #[test]
+    fn test_extract_metadata_none() {
+        let result = extract("<html><head><title>Title</title></head><body><p>text</p></body></html>");
+        assert_eq!(result.title, None);
+        assert_eq!(result.image_url, None);
+        assert_eq!(result.metadata, Metadata::default());
+    }
+
+    #[test]
+    fn test_extract_metadata_author_url() {
+        let result = extract("<head><meta property=\"article:author\" content=\"https://example.com/authors/jane\"></head>");
+        assert_eq!(result.metadata.author, None);
+    }
+}
@@ -0,0 +1,198 @@
+// News sites often split an article across several pages linked with <link rel="next"> or plain
+// "?page=2" links. The following pages are fetched and their contents appended to the first page
+// so the whole article is saved. Blogs also use rel="next" on links to the next post, so links in
+// the body are only followed when they point to another page of the same path, and a fetched page
+// whose canonical url is on another path ends the article. At most MAX_PAGES pages are fetched.
+
+use html5ever::tendril::TendrilSink;
+use url::Url;
+
+use crate::extractors::ExtractorRegistry;
+use crate::fetch::{self,HttpClient};
+use crate::{urls,Webpage};
+
+const MAX_PAGES: usize = 10;
+// Query parameters holding the page number.
+const PAGE_PARAMETERS: [&str; 2] = ["page", "pg"];
+
+// The url with the page number in the query increased by one. Urls without a page number are the
+// first page.
+fn with_next_page_number(url: &Url) -> Url {
+    let (parameter, page) = url.query_pairs()
+        .find_map(|(name, value)| {
+            let parameter = PAGE_PARAMETERS.iter().find(|parameter| **parameter == name)?;
+            Some((*parameter, value.parse::<u32>().ok()?))
+        })
+        .unwrap_or((PAGE_PARAMETERS[0], 1));
+    let pairs: Vec<(String, String)> = url.query_pairs()
+        .filter(|(name, _)| name != parameter)
+        .map(|(name, value)| (name.into_owned(), value.into_owned()))
+        .collect();
+    let mut next_url = url.clone();
+    next_url.set_fragment(None);
+    next_url.query_pairs_mut().clear().extend_pairs(pairs).append_pair(parameter, &(page + 1).to_string());
+    next_url
+}
+
+// Whether the urls point to the same page, ignoring the order of the query parameters.
+fn is_same_page(url: &Url, other: &Url) -> bool {
+    let query = |url: &Url| {
+        let mut pairs: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+        pairs.sort();
+        pairs
+    };
+    url.host_str() == other.host_str() && url.port_or_known_default() == other.port_or_known_default()
+        && url.path() == other.path() && query(url) == query(other)
+}
+
+// The path without a trailing page number, e.g. /article for /article/2/.
+fn path_stem(url: &Url) -> &str {
+    let path = url.path().trim_end_matches('/');
+    match path.rsplit_once('/') {
+        Some((stem, page)) if !page.is_empty() && page.chars().all(|c| c.is_ascii_digit()) => stem,
+        _ => path,
+    }
+}
+
+fn has_page_number(url: &Url) -> bool {
+    url.query_pairs().any(|(name, value)| PAGE_PARAMETERS.contains(&name.as_ref()) && value.parse::<u32>().is_ok())
+        || path_stem(url) != url.path().trim_end_matches('/')
+}
+
+// Whether the url is another page of the article at article_url.
+fn is_page_of(url: &Url, article_url: &Url) -> bool {
+    url.host_str() == article_url.host_str() && path_stem(url) == path_stem(article_url)
+}
+
+fn is_rel_next(rel: Option<&str>) -> bool {
+    rel.is_some_and(|rel| rel.split_whitespace().any(|rel| rel.eq_ignore_ascii_case("next")))
+}
+
+/// Finds the url of the page following the page at page_url in its html. That is a <link
+/// rel="next"> in the head, a link with rel="next" to another page of the same path or else a link
+/// to the same url with the next page number.
+fn next_page_url(html: &str, page_url: &str) -> Option<Url> {
+    let page_url = Url::parse(page_url).ok()?;
+    let document = kuchiki::parse_html().one(html);
+    let base_url = urls::base_url(&document, page_url.as_str()).unwrap_or_else(|| page_url.clone());
+    let links = |selector: &str| -> Vec<(Option<String>, Url)> {
+        document.select(selector).into_iter().flatten()
+            .filter_map(|link| {
+                let attributes = link.attributes.borrow();
+                let url = base_url.join(attributes.get("href")?.trim()).ok()?;
+                Some((attributes.get("rel").map(str::to_string), url))
+            })
+            .filter(|(_, url)| url.host_str() == page_url.host_str() && !is_same_page(url, &page_url))
+            .collect()
+    };
+    let head_next = links("head link[href]").into_iter()
+        .find(|(rel, _)| is_rel_next(rel.as_deref()));
+    if let Some((_, url)) = head_next {
+        return Some(url);
+    }
+    let anchors = links("a[href]");
+    let rel_next = anchors.iter().find(|(rel, url)| {
+        is_rel_next(rel.as_deref()) && is_page_of(url, &page_url) && has_page_number(url)
+    });
+    if let Some((_, url)) = rel_next {
+        return Some(url.clone());
+    }
+    let next_page = with_next_page_number(&page_url);
+    anchors.into_iter().map(|(_, url)| url).find(|url| is_same_page(url, &next_page))
+}
+
+// The canonical url the page gives itself, or else the url it was fetched from.
+fn canonical_url(page: &Webpage, url: &Url) -> Url {
+    page.metadata.canonical_url.as_deref()
+        .and_then(|canonical_url| Url::parse(canonical_url).ok())
+        .unwrap_or_else(|| url.clone())
+}
+
+/// Fetches the pages following the first page of the webpage and appends their contents. A page
+/// which can't be fetched, doesn't add anything new or belongs to another article ends the article.
+pub(crate) async fn append_following_pages(http_client: &HttpClient, extractors: &ExtractorRegistry,
+        webpage: &mut Webpage, url: &str) {
+    let Ok(first_url) = Url::parse(url) else {
+        return;
+    };
+    let article_url = canonical_url(webpage, &first_url);
+    let mut visited = vec![first_url];
+    let mut page_url = url.to_string();
+    let mut html = webpage.original_html.clone();
+    let mut previous_text = webpage.plain_text.clone();
+    for _ in 1..MAX_PAGES {
+        let Some(next_url) = next_page_url(&html, &page_url) else {
+            break;
+        };
+        if visited.iter().any(|visited_url| is_same_page(visited_url, &next_url)) {
+            break;
+        }
+        html = match fetch::fetch_webpage(http_client, next_url.as_str()).await {
+            Ok(html) => html,
+            Err(error) => {
+                log::warn!("Unable to fetch the next page of {}: {}", url, error);
+                break;
+            },
+        };
+        let page = match extractors.extract(&html, next_url.as_str()) {
+            Ok(page) if page.plain_text != previous_text => page,
+            _ => break,
+        };
+        if !is_page_of(&canonical_url(&page, &next_url), &article_url) {
+            break;
+        }
+        webpage.contents.push(' ');
+        webpage.contents.push_str(&page.contents);
+        webpage.plain_text.push(' ');
+        webpage.plain_text.push_str(&page.plain_text);
+        previous_text = page.plain_text;
+        page_url = next_url.to_string();
+        visited.push(next_url);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_page_url() {
+        let next = |html: &str, url: &str| next_page_url(html, url).map(|url| url.to_string());
+        assert_eq!(next("<head><link rel=\"next\" href=\"/article/2\"></head>", "https://example.com/article"),
+            Some("https://example.com/article/2".to_string()));
+        assert_eq!(next("<a rel=\"noopener next\" href=\"?page=2\">Next</a>", "https://example.com/article?id=1"),
+            Some("https://example.com/article?page=2".to_string()));
+        assert_eq!(next("<a href=\"/article?id=1&amp;page=3#top\">3</a><a href=\"/article?id=1&amp;page=2\">2</a>",
+                "https://example.com/article?id=1"),
+            Some("https://example.com/article?id=1&page=2".to_string()));
+        assert_eq!(next("<a href=\"/article?page=2\">2</a><a href=\"/article?page=3\">3</a>",
+                "https://example.com/article?page=2"),
+            Some("https://example.com/article?page=3".to_string()));
+        // Other hosts and links to the page itself aren't followed.
+        assert_eq!(next("<link rel=\"next\" href=\"https://other.example.com/article/2\">",
+            "https://example.com/article"), None);
+        assert_eq!(next("<a rel=\"next\" href=\"#comments\">", "https://example.com/article"), None);
+        assert_eq!(next("<a href=\"/other-article\">Other article</a>", "https://example.com/article"), None);
+        // A link to the next post of a blog isn't another page of the article.
+        assert_eq!(next("<article><p>Text</p><footer><a rel=\"next\" href=\"/another-post\">Next post</a></footer></article>",
+            "https://example.com/a-post"), None);
+        assert_eq!(next("<a rel=\"next\" href=\"/a-post/2/\">Next page</a><a rel=\"next\" href=\"/another-post\">Next post</a>",
+            "https://example.com/a-post/"), Some("https://example.com/a-post/2/".to_string()));
+    }
+
+    #[test]
+    fn test_path_stem() {
+        let stem = |url: &str| path_stem(&Url::parse(url).unwrap()).to_string();
+        assert_eq!(stem("https://example.com/a-post/2/"), "/a-post");
+        assert_eq!(stem("https://example.com/a-post?page=2"), "/a-post");
+        assert_eq!(stem("https://example.com/2024/a-post"), "/2024/a-post");
+        assert_eq!(stem("https://example.com/"), "");
+    }
+
+    #[test]
+    fn test_with_next_page_number() {
+        let next = |url: &str| with_next_page_number(&Url::parse(url).unwrap()).to_string();
+        assert_eq!(next("https://example.com/article"), "https://example.com/article?page=2");
+        assert_eq!(next("https://example.com/article?pg=4&id=1#top"), "https://example.com/article?id=1&pg=5");
+    }
+}
@@ -0,0 +1,277 @@
+// Importing a long list of bookmarks queues many webpages from the same few sites. To not hammer
+// those sites, only a few requests are sent to a host at a time with a delay between them, a host
+// answering 429 or 503 with Retry-After isn't sent anything until the time is up, and robots.txt
+// can be respected.
+
+use std::collections::HashMap;
+use std::sync::{Arc,Mutex};
+use std::time::Duration;
+
+use reqwest::header::{self,HeaderMap};
+use reqwest::StatusCode;
+use tokio::sync::{OwnedSemaphorePermit,Semaphore};
+use tokio::time::Instant;
+
+use crate::fetch::{self,FetchError,FetchErrorKind,HttpClient};
+use crate::robots::RobotsTxt;
+
+// Files larger than this are cut off. RFC 9309 asks crawlers to parse at least 500 KiB.
+const MAX_ROBOTS_TXT_SIZE: usize = 512 * 1024;
+// The number of hosts remembered before hosts which are neither busy nor waiting are forgotten.
+const MAX_HOSTS: usize = 10000;
+
+#[derive(Debug,Clone)]
+pub struct PolitenessConfig {
+    pub respect_robots_txt: bool,
+    pub robots_txt_cache_duration: Duration,
+    pub max_requests_per_host: usize,
+    // The minimum time between the start of two requests to the same host.
+    pub request_delay: Duration,
+    // Workers wait at most this long for their turn. Jobs for a host which has asked for a longer
+    // break are retried after the break instead.
+    pub max_wait: Duration,
+    // Longer Retry-After values are cut down to this.
+    pub max_retry_after: Duration,
+}
+
+impl Default for PolitenessConfig {
+    fn default() -> Self {
+        PolitenessConfig {
+            respect_robots_txt: false,
+            robots_txt_cache_duration: Duration::from_secs(60 * 60),
+            max_requests_per_host: 2,
+            request_delay: Duration::from_secs(1),
+            max_wait: Duration::from_secs(60),
+            max_retry_after: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+struct HostState {
+    semaphore: Arc<Semaphore>,
+    // The earliest time the next request may be sent.
+    next_request: Instant,
+}
+
+/// The state shared by all clones of an HttpClient.
+pub(crate) struct Politeness {
+    config: PolitenessConfig,
+    // The product token of the User-Agent header, which is what robots.txt files refer to.
+    robots_user_agent: String,
+    hosts: Mutex<HashMap<String, HostState>>,
+    robots_txt: Mutex<HashMap<String, (Instant, Arc<RobotsTxt>)>>,
+}
+
+impl std::fmt::Debug for Politeness {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        f.debug_struct("Politeness").field("config", &self.config).finish()
+    }
+}
+
+impl Politeness {
+    pub fn new(config: &PolitenessConfig, user_agent: &str) -> Self {
+        let robots_user_agent = user_agent.split(|c: char| c == '/' || c.is_whitespace())
+            .next().unwrap_or_default().to_string();
+        Politeness {
+            config: config.clone(),
+            robots_user_agent,
+            hosts: Mutex::new(HashMap::new()),
+            robots_txt: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn with_host<T>(&self, host: &str, f: impl FnOnce(&mut HostState) -> T) -> T {
+        let mut hosts = self.hosts.lock().unwrap_or_else(|error| error.into_inner());
+        if hosts.len() >= MAX_HOSTS && !hosts.contains_key(host) {
+            let now = Instant::now();
+            let max_requests = self.config.max_requests_per_host;
+            hosts.retain(|_, state| state.next_request > now || state.semaphore.available_permits() < max_requests);
+        }
+        let state = hosts.entry(host.to_string()).or_insert_with(|| HostState {
+            semaphore: Arc::new(Semaphore::new(self.config.max_requests_per_host.max(1))),
+            next_request: Instant::now(),
+        });
+        f(state)
+    }
+
+    /// Waits until a request may be sent to the host. The returned permit counts towards the
+    /// requests to the host until it is dropped. Fails with the time left if the host can't be
+    /// sent anything for longer than the maximum wait.
+    pub async fn wait_for_turn(&self, host: &str) -> Result<OwnedSemaphorePermit, Duration> {
+        let (semaphore, wait) = self.with_host(host, |state| {
+            (state.semaphore.clone(), state.next_request.saturating_duration_since(Instant::now()))
+        });
+        if wait > self.config.max_wait {
+            return Err(wait);
+        }
+        let permit = semaphore.acquire_owned().await
+            .expect("The semaphores of hosts are never closed");
+        let start = self.with_host(host, |state| {
+            let start = state.next_request.max(Instant::now());
+            if start.saturating_duration_since(Instant::now()) <= self.config.max_wait {
+                state.next_request = start + self.config.request_delay;
+            }
+            start
+        });
+        let wait = start.saturating_duration_since(Instant::now());
+        if wait > self.config.max_wait {
+            return Err(wait);
+        }
+        tokio::time::sleep_until(start).await;
+        Ok(permit)
+    }
+
+    /// Sends nothing to the host for the given time, e.g. because it answered with Retry-After.
+    pub fn postpone(&self, host: &str, delay: Duration) {
+        let until = Instant::now() + delay.min(self.config.max_retry_after);
+        self.with_host(host, |state| state.next_request = state.next_request.max(until));
+    }
+
+    /// How long a response with the status and headers asks to wait before the next request.
+    pub fn retry_after(&self, status: StatusCode, headers: &HeaderMap) -> Option<Duration> {
+        if status != StatusCode::TOO_MANY_REQUESTS && status != StatusCode::SERVICE_UNAVAILABLE {
+            return None;
+        }
+        let value = headers.get(header::RETRY_AFTER)?.to_str().ok()?;
+        parse_retry_after(value, chrono::Utc::now()).map(|delay| delay.min(self.config.max_retry_after))
+    }
+
+    fn cached_robots_txt(&self, origin: &str) -> Option<Arc<RobotsTxt>> {
+        let robots_txt = self.robots_txt.lock().unwrap_or_else(|error| error.into_inner());
+        robots_txt.get(origin)
+            .filter(|(fetched, _)| fetched.elapsed() < self.config.robots_txt_cache_duration)
+            .map(|(_, robots)| robots.clone())
+    }
+
+    fn cache_robots_txt(&self, origin: &str, robots: Arc<RobotsTxt>) {
+        let mut robots_txt = self.robots_txt.lock().unwrap_or_else(|error| error.into_inner());
+        if robots_txt.len() >= MAX_HOSTS {
+            let cache_duration = self.config.robots_txt_cache_duration;
+            robots_txt.retain(|_, (fetched, _)| fetched.elapsed() < cache_duration);
+        }
+        robots_txt.insert(origin.to_string(), (Instant::now(), robots));
+    }
+}
+
+// Retry-After is either a number of seconds or an http date.
+fn parse_retry_after(value: &str, now: chrono::DateTime<chrono::Utc>) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    Some(date.with_timezone(&chrono::Utc).signed_duration_since(now).to_std().unwrap_or_default())
+}
+
+/// Waits for the turn of the host of the url and sends a GET request to it. The returned permit
+/// counts towards the requests to the host and should be held until the whole response has been
+/// read. A host answering 429 or 503 with Retry-After isn't sent anything until the time is up.
+pub(crate) async fn send(http_client: &HttpClient, url: &str, request: reqwest::RequestBuilder) ->
+        Result<(Option<OwnedSemaphorePermit>, reqwest::Response), FetchError> {
+    // Urls which can't be parsed fail when they are sent below.
+    let host = url::Url::parse(url).ok().and_then(|url| url.host_str().map(str::to_string));
+    let permit = match &host {
+        Some(host) => Some(http_client.politeness.wait_for_turn(host).await.map_err(|wait| {
+            FetchError::new(FetchErrorKind::RateLimited,
+                format!("{} can't be sent requests for another {} seconds", host, wait.as_secs()))
+                .with_retry_after(Some(wait))
+        })?),
+        None => None,
+    };
+    let response = http_client.send(request).await?;
+    if let (Some(host), Some(retry_after)) = (&host, http_client.politeness.retry_after(response.status(), response.headers())) {
+        http_client.politeness.postpone(host, retry_after);
+    }
+    Ok((permit, response))
+}
+
+async fn fetch_robots_txt(http_client: &HttpClient, url: &url::Url) -> Result<RobotsTxt, FetchError> {
+    let robots_url = url.join("/robots.txt").map_err(|error| FetchError::new(FetchErrorKind::InvalidUrl,
+        format!("Unable to fetch robots.txt: {}", error)))?;
+    let (_permit, response) = send(http_client, robots_url.as_str(), http_client.get(robots_url.as_str())).await?;
+    let status = response.status();
+    // RFC 9309 asks crawlers to assume that everything is allowed if robots.txt is unavailable
+    // and that nothing is allowed if the server fails.
+    if status.is_client_error() {
+        return Ok(RobotsTxt::parse(""));
+    } else if !status.is_success() {
+        return Err(FetchError::new(FetchErrorKind::Status(status),
+            format!("Unable to fetch robots.txt. Got status {}", status)));
+    }
+    let mut body = fetch::read_body(http_client, response, http_client.max_response_size()).await?;
+    body.truncate(MAX_ROBOTS_TXT_SIZE);
+    Ok(RobotsTxt::parse(&String::from_utf8_lossy(&body)))
+}
+
+/// Fails if robots.txt is respected and doesn't allow fetching the url.
+pub(crate) async fn check_robots_txt(http_client: &HttpClient, url: &url::Url) -> Result<(), FetchError> {
+    let politeness = &http_client.politeness;
+    if !politeness.config.respect_robots_txt {
+        return Ok(());
+    }
+    let origin = url.origin().ascii_serialization();
+    let robots = match politeness.cached_robots_txt(&origin) {
+        Some(robots) => robots,
+        None => {
+            let robots = Arc::new(fetch_robots_txt(http_client, url).await?);
+            politeness.cache_robots_txt(&origin, robots.clone());
+            robots
+        },
+    };
+    let path = match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_string(),
+    };
+    if robots.is_allowed(&politeness.robots_user_agent, &path) {
+        Ok(())
+    } else {
+        Err(FetchError::new(FetchErrorKind::Forbidden, "It is disallowed by robots.txt".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_retry_after() {
+        let now = chrono::DateTime::parse_from_rfc3339("2015-10-21T07:28:00Z").unwrap().with_timezone(&chrono::Utc);
+        assert_eq!(parse_retry_after("120", now), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:30:00 GMT", now), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:00:00 GMT", now), Some(Duration::ZERO));
+        assert_eq!(parse_retry_after("soon", now), None);
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_turn() {
+        let config = PolitenessConfig {
+            max_requests_per_host: 1,
+            request_delay: Duration::from_millis(100),
+            max_wait: Duration::from_secs(1),
+            ..PolitenessConfig::default()
+        };
+        let politeness = Arc::new(Politeness::new(&config, "article-server/1.0"));
+        assert_eq!(politeness.robots_user_agent, "article-server");
+        let start = Instant::now();
+        let tasks: Vec<_> = (0..3).map(|_| {
+            let politeness = politeness.clone();
+            tokio::spawn(async move {
+                let _permit = politeness.wait_for_turn("example.com").await.unwrap();
+                Instant::now()
+            })
+        }).collect();
+        let mut started = Vec::new();
+        for task in tasks {
+            started.push(task.await.unwrap());
+        }
+        started.sort();
+        assert!(started[2].duration_since(start) >= Duration::from_millis(200));
+        // Other hosts don't wait.
+        let other_start = Instant::now();
+        let _permit = politeness.wait_for_turn("example.org").await.unwrap();
+        assert!(other_start.elapsed() < Duration::from_millis(100));
+
+        politeness.postpone("example.org", Duration::from_secs(30));
+        assert!(politeness.wait_for_turn("example.org").await.unwrap_err() > Duration::from_secs(29));
+    }
+}
@@ -0,0 +1,105 @@
+// Reading progress is saved by each of the apps and shown where the user left off on whichever
+// app the webpage is opened in next. The apps can save progress while offline so the position with
+// the newest client timestamp wins rather than the position which reaches the server last.
+
+use std::sync::Arc;
+
+use serde::{Deserialize,Serialize};
+use sqlx::PgPool;
+use warp::http::StatusCode;
+
+use crate::errors;
+
+const MAX_ANCHOR_LENGTH: usize = 1000;
+
+#[derive(Deserialize,Serialize,Debug,Clone,PartialEq)]
+pub struct ReadingProgress {
+    // How far the webpage has been scrolled from 0 to 100.
+    pub percentage: Option<f64>,
+    pub anchor: Option<String>,
+    // When the user was at this position according to the client.
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl ReadingProgress {
+    fn validate(&self) -> Result<(), &'static str> {
+        if self.percentage.is_none() && self.anchor.is_none() {
+            return Err("Either a percentage or an anchor is required");
+        }
+        if self.percentage.is_some_and(|percentage| !(0.0..=100.0).contains(&percentage)) {
+            return Err("The percentage must be between 0 and 100");
+        }
+        if self.anchor.as_ref().is_some_and(|anchor| anchor.len() > MAX_ANCHOR_LENGTH) {
+            return Err("The anchor is too long");
+        }
+        Ok(())
+    }
+}
+
+async fn get_progress(db_pool: &PgPool, webpage_id: i64, user_id: i64) ->
+        Result<Option<ReadingProgress>, sqlx::Error> {
+    let progress = sqlx::query_as::<_, (Option<f64>, Option<String>, chrono::DateTime<chrono::Utc>)>("SELECT reading_progress.percentage, reading_progress.anchor, reading_progress.client_updated FROM reading_progress JOIN webpages ON webpages.id = reading_progress.webpage_id WHERE webpages.id = $1 AND webpages.user_id = $2")
+        .bind(webpage_id)
+        .bind(user_id)
+        .fetch_optional(db_pool).await?;
+    Ok(progress.map(|(percentage, anchor, updated_at)| ReadingProgress {percentage, anchor, updated_at}))
+}
+
+// Returns the progress which is stored after the update. It is the existing progress if that is
+// newer than the given progress and None if the user doesn't have the webpage.
+async fn save_progress(db_pool: &PgPool, webpage_id: i64, user_id: i64, progress: &ReadingProgress) ->
+        Result<Option<ReadingProgress>, sqlx::Error> {
+    sqlx::query("INSERT INTO reading_progress(webpage_id, percentage, anchor, client_updated) SELECT id, $3, $4, $5 FROM webpages WHERE id = $1 AND user_id = $2 ON CONFLICT (webpage_id) DO UPDATE SET percentage = excluded.percentage, anchor = excluded.anchor, client_updated = excluded.client_updated, updated = now() WHERE reading_progress.client_updated < excluded.client_updated")
+        .bind(webpage_id)
+        .bind(user_id)
+        .bind(progress.percentage)
+        .bind(&progress.anchor)
+        .bind(progress.updated_at)
+        .execute(db_pool).await?;
+    get_progress(db_pool, webpage_id, user_id).await
+}
+
+fn progress_reply(result: Result<Option<ReadingProgress>, sqlx::Error>, webpage_id: i64, user_id: i64) ->
+        warp::reply::WithStatus<warp::reply::Json> {
+    match result {
+        Ok(Some(progress)) => warp::reply::with_status(warp::reply::json(&progress), StatusCode::OK),
+        Ok(None) => errors::error_reply(StatusCode::NOT_FOUND, "No reading progress for webpage"),
+        Err(error) => {
+            log::error!("Error when handling reading progress of webpage {} for user {}: {}",
+                webpage_id, user_id, error);
+            errors::error_reply(StatusCode::INTERNAL_SERVER_ERROR, "Unknown error")
+        },
+    }
+}
+
+pub async fn get_progress_handler(webpage_id: i64, db_pool: Arc<PgPool>, user_id: i64) ->
+        Result<impl warp::Reply, warp::Rejection> {
+    Ok(progress_reply(get_progress(&db_pool, webpage_id, user_id).await, webpage_id, user_id))
+}
+
+pub async fn save_progress_handler(webpage_id: i64, progress: ReadingProgress, db_pool: Arc<PgPool>,
+        user_id: i64) -> Result<impl warp::Reply, warp::Rejection> {
+    if let Err(message) = progress.validate() {
+        return Ok(errors::error_reply(StatusCode::BAD_REQUEST, message));
+    }
+    Ok(progress_reply(save_progress(&db_pool, webpage_id, user_id, &progress).await, webpage_id, user_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn progress(percentage: Option<f64>, anchor: Option<&str>) -> ReadingProgress {
+        ReadingProgress {percentage, anchor: anchor.map(|anchor| anchor.to_string()),
+            updated_at: chrono::Utc::now()}
+    }
+
+    #[test]
+    fn test_validate_progress() {
+        assert!(progress(Some(42.5), None).validate().is_ok());
+        assert!(progress(None, Some("section-2")).validate().is_ok());
+        assert!(progress(None, None).validate().is_err());
+        assert!(progress(Some(100.5), None).validate().is_err());
+        assert!(progress(Some(f64::NAN), None).validate().is_err());
+    }
+}
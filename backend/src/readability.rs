@@ -0,0 +1,265 @@
+// A simplified version of the content scoring done by Mozilla's Readability.js. Every paragraph
+// gets a score based on its length and number of commas and that score is handed on to its
+// parent and grandparent. The element which ends up with the highest score after adjusting for
+// the amount of links in it is taken to be the one containing the article.
+// https://github.com/mozilla/readability/blob/main/Readability.js
+
+use std::collections::HashMap;
+
+use kuchiki::NodeRef;
+use kuchiki::iter::NodeIterator;
+use regex::Regex;
+
+// Paragraphs shorter than this are not scored since they are mostly captions, bylines and the
+// like.
+const MIN_PARAGRAPH_LENGTH: usize = 25;
+// If the extracted article is shorter than this the scoring has most likely picked out something
+// that isn't the article and the caller should fall back to using the whole document.
+const MIN_ARTICLE_LENGTH: usize = 140;
+
+lazy_static::lazy_static! {
+    static ref UNLIKELY_CANDIDATES: Regex = Regex::new("(?i)-ad-|ai2html|banner|breadcrumbs|combx|comment|community|cover-wrap|disqus|extra|footer|gdpr|header|legends|menu|related|remark|replies|rss|shoutbox|sidebar|skyscraper|social|sponsor|supplemental|ad-break|agegate|pagination|pager|popup|yom-remote|cookie|consent")
+        .expect("Compiling the regex failed");
+    static ref MAYBE_CANDIDATE: Regex = Regex::new("(?i)and|article|body|column|content|main|shadow")
+        .expect("Compiling the regex failed");
+    static ref POSITIVE: Regex = Regex::new("(?i)article|body|content|entry|hentry|h-entry|main|page|pagination|post|text|blog|story")
+        .expect("Compiling the regex failed");
+    static ref NEGATIVE: Regex = Regex::new("(?i)-ad-|hidden|^hid$| hid$| hid |^hid |banner|combx|comment|com-|contact|foot|footer|footnote|gdpr|masthead|media|meta|outbrain|promo|related|scroll|share|shoutbox|sidebar|skyscraper|sponsor|shopping|tags|tool|widget|cookie|consent")
+        .expect("Compiling the regex failed");
+}
+
+// Elements which are never part of an article no matter what they are called.
+const BOILERPLATE_TAGS: [&str; 11] = ["nav", "aside", "footer", "form", "button", "input",
+    "select", "textarea", "iframe", "object", "embed"];
+const BOILERPLATE_ROLES: [&str; 7] = ["menu", "menubar", "complementary", "navigation",
+    "alert", "alertdialog", "dialog"];
+// A div without any of these as children is scored as if it were a paragraph.
+const BLOCK_TAGS: [&str; 17] = ["a", "blockquote", "dl", "div", "img", "ol", "p", "pre", "table",
+    "ul", "section", "article", "h1", "h2", "h3", "h4", "h5"];
+
+fn tag_name(node: &NodeRef) -> Option<String> {
+    node.as_element().map(|element| element.name.local.to_string())
+}
+
+fn attribute(node: &NodeRef, name: &str) -> Option<String> {
+    node.as_element().and_then(|element| {
+        element.attributes.borrow().get(name).map(|value| value.to_string())
+    })
+}
+
+fn class_and_id(node: &NodeRef) -> String {
+    format!("{} {}", attribute(node, "class").unwrap_or_default(),
+        attribute(node, "id").unwrap_or_default())
+}
+
+fn class_weight(node: &NodeRef) -> f64 {
+    let mut weight = 0.0;
+    for value in [attribute(node, "class"), attribute(node, "id")].iter().flatten() {
+        if NEGATIVE.is_match(value) {
+            weight -= 25.0;
+        }
+        if POSITIVE.is_match(value) {
+            weight += 25.0;
+        }
+    }
+    weight
+}
+
+fn initial_score(node: &NodeRef) -> f64 {
+    let base = match tag_name(node).as_deref() {
+        Some("div") => 5.0,
+        Some("pre" | "td" | "blockquote") => 3.0,
+        Some("address" | "ol" | "ul" | "dl" | "dd" | "dt" | "li" | "form") => -3.0,
+        Some("h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "th") => -5.0,
+        _ => 0.0,
+    };
+    base + class_weight(node)
+}
+
+// The length of the text in a node with runs of whitespace counted as a single character.
+fn text_length(node: &NodeRef) -> usize {
+    node.text_contents().split_whitespace().map(|word| word.chars().count() + 1).sum::<usize>()
+        .saturating_sub(1)
+}
+
+fn link_density(node: &NodeRef) -> f64 {
+    let length = text_length(node);
+    if length == 0 {
+        return 0.0;
+    }
+    let link_length: usize = node.descendants().elements()
+        .filter(|element| &*element.name.local == "a")
+        .map(|element| text_length(element.as_node()))
+        .sum();
+    link_length as f64 / length as f64
+}
+
+fn is_boilerplate(node: &NodeRef) -> bool {
+    let tag = match tag_name(node) {
+        Some(tag) => tag,
+        None => return false,
+    };
+    if BOILERPLATE_TAGS.contains(&tag.as_str()) {
+        return true;
+    }
+    if attribute(node, "role").is_some_and(|role| BOILERPLATE_ROLES.contains(&role.as_str())) {
+        return true;
+    }
+    if matches!(tag.as_str(), "html" | "body" | "article" | "main" | "a") {
+        return false;
+    }
+    let class_and_id = class_and_id(node);
+    UNLIKELY_CANDIDATES.is_match(&class_and_id) && !MAYBE_CANDIDATE.is_match(&class_and_id)
+}
+
+fn is_scorable(node: &NodeRef) -> bool {
+    match tag_name(node).as_deref() {
+        Some("p" | "pre" | "td") => true,
+        Some("div") => node.children().all(|child| {
+            tag_name(&child).is_none_or(|tag| !BLOCK_TAGS.contains(&tag.as_str()))
+        }),
+        _ => false,
+    }
+}
+
+// Paragraphs next to the article container are often part of the article even though they
+// aren't scored highly themselves, e.g. when a site wraps the lead paragraph separately.
+fn is_related_paragraph(node: &NodeRef) -> bool {
+    if tag_name(node).as_deref() != Some("p") {
+        return false;
+    }
+    let length = text_length(node);
+    let density = link_density(node);
+    (length > 80 && density < 0.25) ||
+        (length > 0 && density == 0.0 && node.text_contents().contains(". "))
+}
+
+fn remove_boilerplate(node: &NodeRef) {
+    node.descendants()
+        .filter(is_boilerplate)
+        .collect::<Vec<_>>()
+        .iter()
+        .for_each(|node| node.detach());
+}
+
+// Removes lists, tables and containers inside the article which mostly consist of links or are
+// marked as something other than content by their class names, e.g. share buttons and tag lists.
+fn clean_article(node: &NodeRef) {
+    node.descendants()
+        .filter(|descendant| {
+            let weight = class_weight(descendant);
+            if weight < 0.0 {
+                return true;
+            }
+            if matches!(tag_name(descendant).as_deref(), Some("div" | "section" | "ul" | "ol" | "table")) {
+                let max_density = if weight >= 25.0 { 0.5 } else { 0.2 };
+                return link_density(descendant) > max_density;
+            }
+            false
+        })
+        .collect::<Vec<_>>()
+        .iter()
+        .for_each(|node| node.detach());
+}
+
+/// Finds the nodes making up the main content of the document. Boilerplate like navigation,
+/// sidebars and comment sections is removed from the document in the process so it should be
+/// parsed separately if the original is needed afterwards.
+/// Returns None if no part of the document looks like an article.
+pub fn extract_article(document: &NodeRef) -> Option<Vec<NodeRef>> {
+    remove_boilerplate(document);
+    let mut candidates: HashMap<*const kuchiki::Node, (NodeRef, f64)> = HashMap::new();
+    for element in document.descendants().elements() {
+        let node = element.as_node();
+        if !is_scorable(node) {
+            continue;
+        }
+        let length = text_length(node);
+        if length < MIN_PARAGRAPH_LENGTH {
+            continue;
+        }
+        let commas = node.text_contents().matches(',').count() as f64;
+        let score = 1.0 + commas + f64::min((length / 100) as f64, 3.0);
+        for (level, ancestor) in node.ancestors().take(3).enumerate() {
+            if ancestor.as_element().is_none() {
+                continue;
+            }
+            let divider = match level {
+                0 => 1.0,
+                1 => 2.0,
+                _ => level as f64 * 3.0,
+            };
+            let entry = candidates.entry(&*ancestor as *const kuchiki::Node)
+                .or_insert_with(|| (ancestor.clone(), initial_score(&ancestor)));
+            entry.1 += score / divider;
+        }
+    }
+    let scores: Vec<(NodeRef, f64)> = candidates.into_values()
+        .map(|(node, score)| {
+            let density = link_density(&node);
+            (node, score * (1.0 - density))
+        })
+        .collect();
+    let (top_candidate, top_score) = scores.iter()
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))?;
+    let threshold = f64::max(10.0, top_score * 0.2);
+    let article = match top_candidate.parent() {
+        Some(parent) => parent.children()
+            .filter(|sibling| {
+                sibling == top_candidate || is_related_paragraph(sibling) ||
+                    scores.iter().any(|(node, score)| node == sibling && *score >= threshold)
+            })
+            .collect::<Vec<_>>(),
+        None => vec![top_candidate.clone()],
+    };
+    article.iter().for_each(clean_article);
+    let length: usize = article.iter().map(text_length).sum();
+    if length < MIN_ARTICLE_LENGTH {
+        return None;
+    }
+    Some(article)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use html5ever::tendril::TendrilSink;
+
+    fn parse(html: &str) -> NodeRef {
+        kuchiki::parse_html().one(html)
+    }
+
+    #[test]
+    fn test_class_weight() {
+        let document = parse("<div class=\"post-content\"></div><div id=\"sidebar\"></div><div class=\"entry\" id=\"comments\"></div>");
+        let weights: Vec<f64> = document.select("div").expect("Unable to select divs")
+            .map(|element| class_weight(element.as_node()))
+            .collect();
+        assert_eq!(weights, vec![25.0, -25.0, 0.0]);
+    }
+
+    #[test]
+    fn test_link_density() {
+        let document = parse("<p>0123456789 <a href=\"link\">0123456789</a></p>");
+        let paragraph = document.select_first("p").expect("Unable to select paragraph");
+        // 10 link characters out of 21 characters in total.
+        assert!((link_density(paragraph.as_node()) - 10.0 / 21.0).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn test_is_boilerplate() {
+        let document = parse("<nav></nav><div class=\"cookie-banner\"></div><div class=\"sidebar-widget\"></div><div role=\"navigation\"></div><div class=\"comment-content\"></div>");
+        let boilerplate: Vec<bool> = document.select("body > *").expect("Unable to select elements")
+            .map(|element| is_boilerplate(element.as_node()))
+            .collect();
+        // The last element is kept since `content` makes it a possible candidate.
+        assert_eq!(boilerplate, vec![true, true, true, true, false]);
+    }
+
+    #[test]
+    fn test_extract_article_no_candidate() {
+        let document = parse("<html><body><p>Too short to be an article</p></body></html>");
+        assert!(extract_article(&document).is_none());
+    }
+}
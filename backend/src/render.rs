@@ -0,0 +1,446 @@
+// Renders the readable content of a webpage, which is stored as sanitized html, as plain text or
+// as Markdown so articles can be piped into note taking tools and other programs which don't
+// read html. The content is split into blocks like paragraphs, headings and lists which are
+// separated by an empty line. Inline elements only add formatting in Markdown.
+// https://spec.commonmark.org/
+
+use html5ever::tendril::TendrilSink;
+use kuchiki::{NodeData,NodeRef};
+
+// Elements which start a new block. Everything else is rendered as part of the surrounding text.
+const BLOCK_TAGS: [&str; 33] = ["address", "article", "aside", "blockquote", "caption", "dd",
+    "details", "div", "dl", "dt", "figcaption", "figure", "footer", "h1", "h2", "h3", "h4", "h5",
+    "h6", "header", "hr", "li", "main", "nav", "ol", "p", "pre", "section", "table", "td", "th",
+    "tr", "ul"];
+// Characters which would otherwise be read as Markdown formatting.
+const MARKDOWN_SPECIAL: [char; 7] = ['\\', '*', '_', '[', ']', '`', '<'];
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub(crate) enum Format {
+    Text,
+    Markdown,
+}
+
+fn tag(node: &NodeRef) -> Option<String> {
+    node.as_element().map(|element| element.name.local.to_string())
+}
+
+fn attribute(node: &NodeRef, name: &str) -> Option<String> {
+    node.as_element().and_then(|element| element.attributes.borrow().get(name).map(str::to_string))
+}
+
+fn is_block(node: &NodeRef) -> bool {
+    tag(node).is_some_and(|tag| BLOCK_TAGS.contains(&tag.as_str()))
+}
+
+fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if MARKDOWN_SPECIAL.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+// Escapes a marker at the start of a line which would otherwise start a heading, a block quote,
+// a list, a thematic break or a code fence. The rest of the line is escaped by escape_markdown.
+// https://spec.commonmark.org/0.30/#backslash-escapes
+fn escape_line_start(line: &str) -> String {
+    let followed_by_space = |marker_length: usize| line[marker_length..].chars().next().is_none_or(char::is_whitespace);
+    let is_rule = |character: char| line.chars().all(|c| c == character || c == ' ');
+    let digits = line.chars().take_while(char::is_ascii_digit).count();
+    match line.chars().next() {
+        Some('#' | '>') => format!("\\{}", line),
+        Some(marker @ ('-' | '+' | '=' | '~')) if followed_by_space(1) || is_rule(marker) || line.starts_with("~~~") =>
+            format!("\\{}", line),
+        _ if (1..=9).contains(&digits) && line[digits..].starts_with(['.', ')']) && followed_by_space(digits + 1) =>
+            format!("{}\\{}", &line[..digits], &line[digits..]),
+        _ => line.to_string(),
+    }
+}
+
+// A fence made of more of the character than the longest run of it in the text so the text can
+// contain the character itself.
+fn fence(text: &str, character: char, min_length: usize) -> String {
+    let longest_run = text.split(|c| c != character).map(str::len).max().unwrap_or_default();
+    character.to_string().repeat(min_length.max(longest_run + 1))
+}
+
+// Collapses whitespace the way a browser would. Line breaks from <br> are kept as "\n".
+fn collapse_whitespace(text: &str, format: Format) -> String {
+    let line_break = match format {
+        Format::Text => "\n",
+        // A line ending in two spaces is a hard line break.
+        Format::Markdown => "  \n",
+    };
+    text.split('\n')
+        .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join(line_break)
+}
+
+// Prefixes the first line with the marker and indents the following lines to line up with it.
+fn prefix_lines(text: &str, first: &str, rest: &str) -> String {
+    text.lines().enumerate()
+        .map(|(index, line)| match (index, line.is_empty()) {
+            (0, _) => format!("{}{}", first, line),
+            (_, true) => rest.trim_end().to_string(),
+            (_, false) => format!("{}{}", rest, line),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+struct Renderer {
+    format: Format,
+}
+
+impl Renderer {
+    fn markdown(&self) -> bool {
+        self.format == Format::Markdown
+    }
+
+    // Renders the children of the node as blocks. Text and inline elements between block
+    // elements form a paragraph.
+    fn blocks(&self, node: &NodeRef) -> Vec<String> {
+        let mut blocks = Vec::new();
+        let mut inline = String::new();
+        for child in node.children() {
+            if is_block(&child) {
+                self.push_paragraph(&mut inline, &mut blocks);
+                blocks.extend(self.block(&child));
+            } else {
+                self.write_inline(&child, &mut inline);
+            }
+        }
+        self.push_paragraph(&mut inline, &mut blocks);
+        blocks
+    }
+
+    fn push_paragraph(&self, inline: &mut String, blocks: &mut Vec<String>) {
+        let mut paragraph = collapse_whitespace(inline, self.format);
+        if self.markdown() {
+            paragraph = paragraph.split('\n').map(escape_line_start).collect::<Vec<_>>().join("\n");
+        }
+        if !paragraph.is_empty() {
+            blocks.push(paragraph);
+        }
+        inline.clear();
+    }
+
+    fn block(&self, node: &NodeRef) -> Vec<String> {
+        let tag = tag(node).unwrap_or_default();
+        let block = match tag.as_str() {
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                let text = self.blocks(node).join(" ").replace('\n', " ");
+                if text.is_empty() {
+                    return Vec::new();
+                }
+                match self.format {
+                    Format::Text => text,
+                    Format::Markdown => {
+                        let level = tag[1..].parse().unwrap_or(1);
+                        format!("{} {}", "#".repeat(level), text)
+                    },
+                }
+            },
+            "pre" => {
+                let code = node.text_contents();
+                let code = code.strip_prefix('\n').unwrap_or(&code).trim_end();
+                if code.is_empty() {
+                    return Vec::new();
+                }
+                match self.format {
+                    Format::Text => code.to_string(),
+                    Format::Markdown => {
+                        let fence = fence(code, '`', 3);
+                        format!("{}\n{}\n{}", fence, code, fence)
+                    },
+                }
+            },
+            "blockquote" if self.markdown() => {
+                let quote = self.blocks(node).join("\n\n");
+                if quote.is_empty() {
+                    return Vec::new();
+                }
+                quote.lines()
+                    .map(|line| if line.is_empty() { ">".to_string() } else { format!("> {}", line) })
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            },
+            "ul" | "ol" => self.list(node, tag == "ol"),
+            "table" => self.table(node),
+            "hr" if self.markdown() => "---".to_string(),
+            _ => return self.blocks(node),
+        };
+        if block.is_empty() {
+            Vec::new()
+        } else {
+            vec![block]
+        }
+    }
+
+    fn list(&self, node: &NodeRef, ordered: bool) -> String {
+        let mut number = attribute(node, "start").and_then(|start| start.trim().parse::<i64>().ok()).unwrap_or(1);
+        let mut items = Vec::new();
+        for child in node.children() {
+            let item = if tag(&child).as_deref() == Some("li") {
+                self.blocks(&child).join("\n\n")
+            } else {
+                // Text directly inside the list is rendered as an item of its own.
+                let mut blocks = Vec::new();
+                let mut inline = String::new();
+                if is_block(&child) {
+                    blocks.extend(self.block(&child));
+                } else {
+                    self.write_inline(&child, &mut inline);
+                    self.push_paragraph(&mut inline, &mut blocks);
+                }
+                blocks.join("\n\n")
+            };
+            if item.is_empty() {
+                continue;
+            }
+            let marker = if ordered {
+                let marker = format!("{}. ", number);
+                number += 1;
+                marker
+            } else {
+                "- ".to_string()
+            };
+            items.push(prefix_lines(&item, &marker, &" ".repeat(marker.len())));
+        }
+        items.join("\n")
+    }
+
+    fn table(&self, node: &NodeRef) -> String {
+        let rows: Vec<Vec<String>> = node.descendants()
+            .filter(|row| tag(row).as_deref() == Some("tr"))
+            .map(|row| {
+                row.children()
+                    .filter(|cell| matches!(tag(cell).as_deref(), Some("td" | "th")))
+                    .map(|cell| {
+                        let text = self.blocks(&cell).join(" ").replace('\n', " ");
+                        match self.format {
+                            Format::Text => text,
+                            Format::Markdown => text.replace('|', "\\|"),
+                        }
+                    })
+                    .collect()
+            })
+            .filter(|cells: &Vec<String>| !cells.is_empty())
+            .collect();
+        let columns = rows.iter().map(Vec::len).max().unwrap_or_default();
+        if columns == 0 {
+            return String::new();
+        }
+        let format_row = |cells: &[String]| {
+            let mut cells = cells.to_vec();
+            cells.resize(columns, String::new());
+            match self.format {
+                Format::Text => cells.join("\t").trim_end().to_string(),
+                Format::Markdown => format!("| {} |", cells.join(" | ")),
+            }
+        };
+        let mut lines: Vec<String> = rows.iter().map(|cells| format_row(cells)).collect();
+        // Markdown tables always have a header row, which is the first row of the html table.
+        if self.markdown() {
+            lines.insert(1, format!("|{}", " --- |".repeat(columns)));
+        }
+        lines.join("\n")
+    }
+
+    // Surrounds the rendered children with a Markdown delimiter. Whitespace is moved outside the
+    // delimiters since `** bold **` isn't bold.
+    fn write_delimited(&self, node: &NodeRef, delimiter: &str, output: &mut String) {
+        let mut inner = String::new();
+        for child in node.children() {
+            self.write_inline(&child, &mut inner);
+        }
+        let trimmed = inner.trim();
+        if trimmed.is_empty() {
+            output.push_str(&inner);
+            return;
+        }
+        if inner.starts_with(char::is_whitespace) {
+            output.push(' ');
+        }
+        output.push_str(&format!("{}{}{}", delimiter, trimmed, delimiter));
+        if inner.ends_with(char::is_whitespace) {
+            output.push(' ');
+        }
+    }
+
+    fn write_inline(&self, node: &NodeRef, output: &mut String) {
+        match node.data() {
+            NodeData::Text(text) => {
+                // Line breaks in the source are just whitespace. Only <br> breaks lines.
+                let text = text.borrow().replace('\n', " ");
+                match self.format {
+                    Format::Text => output.push_str(&text),
+                    Format::Markdown => output.push_str(&escape_markdown(&text)),
+                }
+            },
+            NodeData::Element(_) => {
+                let tag = tag(node).unwrap_or_default();
+                match (tag.as_str(), self.format) {
+                    ("br", _) => output.push('\n'),
+                    ("a", Format::Markdown) => {
+                        let mut text = String::new();
+                        for child in node.children() {
+                            self.write_inline(&child, &mut text);
+                        }
+                        match attribute(node, "href") {
+                            Some(href) if !text.trim().is_empty() && !href.starts_with('#') => output.push_str(
+                                &format!("[{}]({})", text.trim(), href.replace('(', "%28").replace(')', "%29").replace(' ', "%20"))),
+                            _ => output.push_str(&text),
+                        }
+                    },
+                    ("img", Format::Markdown) => {
+                        if let Some(src) = attribute(node, "src") {
+                            let alt = attribute(node, "alt").unwrap_or_default();
+                            output.push_str(&format!("![{}]({})", escape_markdown(alt.trim()),
+                                src.replace('(', "%28").replace(')', "%29").replace(' ', "%20")));
+                        }
+                    },
+                    ("b" | "strong", Format::Markdown) => self.write_delimited(node, "**", output),
+                    ("i" | "em" | "cite" | "dfn", Format::Markdown) => self.write_delimited(node, "*", output),
+                    ("s" | "del", Format::Markdown) => self.write_delimited(node, "~~", output),
+                    ("code" | "kbd" | "samp", Format::Markdown) => {
+                        let code = node.text_contents().replace('\n', " ");
+                        if !code.trim().is_empty() {
+                            let fence = fence(&code, '`', 1);
+                            // Code starting or ending with a backtick needs a space to the fence.
+                            let padding = if code.starts_with('`') || code.ends_with('`') { " " } else { "" };
+                            output.push_str(&format!("{}{}{}{}{}", fence, padding, code, padding, fence));
+                        }
+                    },
+                    _ => {
+                        for child in node.children() {
+                            if is_block(&child) {
+                                output.push('\n');
+                                self.write_inline(&child, output);
+                                output.push('\n');
+                            } else {
+                                self.write_inline(&child, output);
+                            }
+                        }
+                    },
+                }
+            },
+            _ => {},
+        }
+    }
+}
+
+/// Renders stored readable html in the format.
+pub(crate) fn render(html: &str, format: Format) -> String {
+    let document = kuchiki::parse_html().one(html);
+    let body = match document.select_first("body") {
+        Ok(body) => body.as_node().clone(),
+        Err(_) => document,
+    };
+    let mut text = Renderer {format}.blocks(&body).join("\n\n");
+    if !text.is_empty() {
+        text.push('\n');
+    }
+    text
+}
+
+/// Renders the title as the heading of a document in the format.
+pub(crate) fn title(title: &str, format: Format) -> String {
+    let title = title.split_whitespace().collect::<Vec<_>>().join(" ");
+    match format {
+        Format::Text => format!("{}\n\n", title),
+        Format::Markdown => format!("# {}\n\n", escape_markdown(&title)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ARTICLE: &str = "<h1>A title</h1> <p>Some <strong>bold</strong> and <em>emphasized </em>text with a <a href=\"https://example.com/page\">link</a>.</p><p>A line<br>break and <code>x * y</code></p><ul><li>One</li><li><p>Two</p><ol start=\"3\"><li>Nested</li></ol></li></ul><blockquote><p>A quote</p><p>over two paragraphs</p></blockquote><pre>fn main() {\n    println!(\"```\");\n}</pre><figure><img src=\"https://example.com/image.png\" alt=\"An image\"><figcaption>A caption</figcaption></figure><table><thead><tr><th>Name</th><th>Value</th></tr></thead><tbody><tr><td>a | b</td><td>1</td></tr><tr><td>c</td></tr></tbody></table>";
+
+    #[test]
+    fn test_render_text() {
+        assert_eq!(render(ARTICLE, Format::Text), "A title
+
+Some bold and emphasized text with a link.
+
+A line
+break and x * y
+
+- One
+- Two
+
+  3. Nested
+
+A quote
+
+over two paragraphs
+
+fn main() {
+    println!(\"```\");
+}
+
+A caption
+
+Name\tValue
+a | b\t1
+c
+");
+    }
+
+    #[test]
+    fn test_render_markdown() {
+        assert_eq!(render(ARTICLE, Format::Markdown), "# A title
+
+Some **bold** and *emphasized* text with a [link](https://example.com/page).
+
+A line  
+break and `x * y`
+
+- One
+- Two
+
+  3. Nested
+
+> A quote
+>
+> over two paragraphs
+
+````
+fn main() {
+    println!(\"```\");
+}
+````
+
+![An image](https://example.com/image.png)
+
+A caption
+
+| Name | Value |
+| --- | --- |
+| a \\| b | 1 |
+| c |  |
+");
+    }
+
+    #[test]
+    fn test_render_markdown_escaping() {
+        assert_eq!(render("<p>2 * 3 = [6] and <code>`tick`</code></p>", Format::Markdown),
+            "2 \\* 3 = \\[6\\] and `` `tick` ``\n");
+        // Text which would start a block when it starts a paragraph or a line.
+        assert_eq!(render("<p># not a heading</p><p>&gt; not a quote</p><p>- not<br>+ a list<br>-5 degrees</p>", Format::Markdown),
+            "\\# not a heading\n\n\\> not a quote\n\n\\- not  \n\\+ a list  \n-5 degrees\n");
+        assert_eq!(render("<p>1984. Not a list</p><p>2) Neither<br>3.5 is a number</p><p>---</p><p>A setext<br>===</p>", Format::Markdown),
+            "1984\\. Not a list\n\n2\\) Neither  \n3.5 is a number\n\n\\---\n\nA setext  \n\\===\n");
+        assert_eq!(render("<ul><li>- item</li></ul><blockquote><p># quoted</p></blockquote>", Format::Markdown),
+            "- \\- item\n\n> \\# quoted\n");
+        assert_eq!(render("", Format::Markdown), "");
+    }
+}
@@ -0,0 +1,136 @@
+// A parser for robots.txt files as described in RFC 9309. Only the user-agent, allow and disallow
+// lines are used.
+
+use crate::urls;
+
+struct Rule {
+    allow: bool,
+    pattern: String,
+}
+
+struct Group {
+    user_agents: Vec<String>,
+    rules: Vec<Rule>,
+}
+
+pub(crate) struct RobotsTxt {
+    groups: Vec<Group>,
+}
+
+// Matches the path against a pattern where * matches any sequence of characters. Patterns match
+// the beginning of the path unless a trailing $ anchors them at the end of the path.
+fn matches(pattern: &str, path: &str) -> bool {
+    match pattern.strip_suffix('$') {
+        Some(pattern) => urls::glob_matches(pattern, path),
+        None => urls::glob_matches(&format!("{}*", pattern), path),
+    }
+}
+
+impl RobotsTxt {
+    pub fn parse(content: &str) -> Self {
+        let mut groups: Vec<Group> = Vec::new();
+        // Whether the previous line was a user-agent line, in which case a following user-agent
+        // line belongs to the same group.
+        let mut in_user_agents = false;
+        for line in content.lines() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+            match key.trim().to_ascii_lowercase().as_str() {
+                "user-agent" => {
+                    match groups.last_mut() {
+                        Some(group) if in_user_agents => group.user_agents.push(value.to_ascii_lowercase()),
+                        _ => groups.push(Group {user_agents: vec![value.to_ascii_lowercase()], rules: Vec::new()}),
+                    }
+                    in_user_agents = true;
+                },
+                key @ ("allow" | "disallow") => {
+                    in_user_agents = false;
+                    // An empty disallow line allows everything, which is the same as no rule.
+                    if value.is_empty() {
+                        continue;
+                    }
+                    if let Some(group) = groups.last_mut() {
+                        group.rules.push(Rule {allow: key == "allow", pattern: value.to_string()});
+                    }
+                },
+                _ => in_user_agents = false,
+            }
+        }
+        RobotsTxt {groups}
+    }
+
+    /// Whether a crawler calling itself user_agent may fetch the path, which includes the query.
+    /// The rules of the groups naming the user agent are used if there are any and otherwise the
+    /// rules of the groups for all user agents. The longest matching rule wins.
+    pub fn is_allowed(&self, user_agent: &str, path: &str) -> bool {
+        if path == "/robots.txt" {
+            return true;
+        }
+        let user_agent = user_agent.to_ascii_lowercase();
+        let mut groups: Vec<&Group> = self.groups.iter()
+            .filter(|group| group.user_agents.contains(&user_agent))
+            .collect();
+        if groups.is_empty() {
+            groups = self.groups.iter()
+                .filter(|group| group.user_agents.iter().any(|name| name == "*"))
+                .collect();
+        }
+        groups.iter().flat_map(|group| group.rules.iter())
+            .filter(|rule| matches(&rule.pattern, path))
+            // Allow wins when an allow and a disallow rule are equally long.
+            .max_by_key(|rule| (rule.pattern.len(), rule.allow))
+            .is_none_or(|rule| rule.allow)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches() {
+        assert!(matches("/private", "/private/page"));
+        assert!(!matches("/private", "/public"));
+        assert!(matches("/*.pdf$", "/files/report.pdf"));
+        assert!(!matches("/*.pdf$", "/files/report.pdf?download=1"));
+        assert!(matches("/*/edit", "/articles/1/edit"));
+        assert!(matches("/page$", "/page"));
+        assert!(!matches("/page$", "/pages"));
+        assert!(matches("/*", "/anything"));
+    }
+
+    #[test]
+    fn test_is_allowed() {
+        let robots = RobotsTxt::parse("
+# Everybody
+User-agent: *
+Disallow: /private/
+Allow: /private/public-page
+Disallow: /search?
+
+User-agent: article-server
+User-agent: other-bot
+Disallow: /articles/ # no articles for us
+Allow: /articles/free
+
+User-agent: blocked-bot
+Disallow: /
+");
+        assert!(robots.is_allowed("some-bot", "/"));
+        assert!(!robots.is_allowed("some-bot", "/private/page"));
+        assert!(robots.is_allowed("some-bot", "/private/public-page"));
+        assert!(!robots.is_allowed("some-bot", "/search?q=article"));
+        assert!(robots.is_allowed("some-bot", "/articles/1"));
+        assert!(!robots.is_allowed("Article-Server", "/articles/1"));
+        assert!(robots.is_allowed("article-server", "/articles/free"));
+        // The group for article-server replaces the group for all user agents.
+        assert!(robots.is_allowed("article-server", "/private/page"));
+        assert!(!robots.is_allowed("other-bot", "/articles/1"));
+        assert!(!robots.is_allowed("blocked-bot", "/page"));
+        assert!(robots.is_allowed("blocked-bot", "/robots.txt"));
+        assert!(RobotsTxt::parse("").is_allowed("article-server", "/page"));
+    }
+}
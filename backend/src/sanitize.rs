@@ -0,0 +1,319 @@
+// Serializes the readable part of a document as html which is safe to render directly in the
+// frontend. Only an allowlist of tags and attributes are kept. Elements which aren't on the list
+// are unwrapped, i.e. their children are kept but the element itself is left out, except for
+// elements like <script> and <svg> whose contents are never meant to be displayed as text.
+// The same content can be serialized as xhtml for formats like EPUB which require xml or as plain
+// text for searching.
+// https://cheatsheetseries.owasp.org/cheatsheets/Cross_Site_Scripting_Prevention_Cheat_Sheet.html
+
+use kuchiki::{NodeData,NodeRef};
+
+const ALLOWED_TAGS: [&str; 54] = ["a", "abbr", "b", "bdi", "bdo", "blockquote", "br", "caption",
+    "cite", "code", "col", "colgroup", "dd", "del", "dfn", "dl", "dt", "em", "figcaption",
+    "figure", "h1", "h2", "h3", "h4", "h5", "h6", "hr", "i", "img", "ins", "kbd", "li", "mark",
+    "ol", "p", "pre", "q", "s", "samp", "small", "span", "strong", "sub", "sup", "table", "tbody",
+    "td", "tfoot", "th", "thead", "time", "tr", "u", "ul"];
+// Elements which are left out together with everything inside them.
+const DROPPED_TAGS: [&str; 15] = ["head", "script", "style", "noscript", "template", "iframe",
+    "frame", "frameset", "object", "embed", "applet", "svg", "math", "textarea", "select"];
+// Elements which don't separate words when the content is converted to plain text.
+const INLINE_TAGS: [&str; 24] = ["a", "abbr", "b", "bdi", "bdo", "cite", "code", "data", "del",
+    "dfn", "em", "i", "ins", "kbd", "mark", "q", "s", "samp", "small", "span", "strong", "sub",
+    "sup", "u"];
+const VOID_TAGS: [&str; 5] = ["br", "col", "hr", "img", "wbr"];
+const URL_ATTRIBUTES: [&str; 3] = ["href", "src", "cite"];
+const SAFE_URL_SCHEMES: [&str; 3] = ["http", "https", "mailto"];
+
+fn is_allowed_attribute(tag: &str, attribute: &str) -> bool {
+    match attribute {
+        "title" | "lang" | "dir" => true,
+        "href" => tag == "a",
+        "src" | "alt" | "width" | "height" | "srcset" => tag == "img",
+        "colspan" | "rowspan" | "headers" | "scope" => matches!(tag, "td" | "th"),
+        "span" => matches!(tag, "col" | "colgroup"),
+        "start" | "reversed" | "type" => tag == "ol",
+        "cite" => matches!(tag, "blockquote" | "q" | "del" | "ins"),
+        "datetime" => matches!(tag, "time" | "del" | "ins"),
+        _ => false,
+    }
+}
+
+// Relative urls and urls with one of the schemes in SAFE_URL_SCHEMES are allowed. Images can
+// also be embedded as data urls except for svg images since they can contain scripts.
+fn is_safe_url(tag: &str, url: &str) -> bool {
+    // Browsers ignore whitespace and control characters when parsing the scheme so
+    // `jav&#x09;ascript:` is as dangerous as `javascript:`.
+    let url: String = url.chars()
+        .filter(|c| !c.is_ascii_whitespace() && !c.is_control())
+        .collect::<String>()
+        .to_lowercase();
+    match url.find([':', '/', '?', '#']) {
+        Some(index) if url[index..].starts_with(':') => {
+            let scheme = &url[..index];
+            SAFE_URL_SCHEMES.contains(&scheme) || (tag == "img" && scheme == "data" &&
+                url.starts_with("data:image/") && !url.starts_with("data:image/svg"))
+        },
+        _ => true,
+    }
+}
+
+fn is_safe_srcset(srcset: &str) -> bool {
+    srcset.split(',')
+        .filter_map(|candidate| candidate.split_whitespace().next())
+        .all(|url| is_safe_url("img", url))
+}
+
+pub(crate) fn escape_text(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+pub(crate) fn escape_attribute(value: &str) -> String {
+    escape_text(value).replace('"', "&quot;")
+}
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+enum Syntax {
+    Html,
+    Xhtml,
+}
+
+// Control characters other than whitespace aren't allowed anywhere in an xml document.
+fn is_xml_char(c: char) -> bool {
+    !c.is_control() || matches!(c, '\t' | '\n' | '\r')
+}
+
+fn write_attributes(tag: &str, attributes: &kuchiki::Attributes, html: &mut String) {
+    for (key, value) in &attributes.map {
+        // Namespaced attributes like xlink:href are never needed for the readable content.
+        if !key.ns.is_empty() {
+            continue;
+        }
+        let name = &*key.local;
+        if !is_allowed_attribute(tag, name) {
+            continue;
+        }
+        if URL_ATTRIBUTES.contains(&name) && !is_safe_url(tag, &value.value) {
+            continue;
+        }
+        if name == "srcset" && !is_safe_srcset(&value.value) {
+            continue;
+        }
+        html.push_str(&format!(" {}=\"{}\"", name, escape_attribute(&value.value)));
+    }
+}
+
+fn write_text(text: &str, preformatted: bool, syntax: Syntax, html: &mut String) {
+    let filtered: String;
+    let text = if syntax == Syntax::Xhtml {
+        filtered = text.chars().filter(|c| is_xml_char(*c)).collect();
+        &filtered
+    } else {
+        text
+    };
+    if preformatted {
+        html.push_str(&escape_text(text));
+        return;
+    }
+    // Runs of whitespace are collapsed the same way the browser would render them.
+    for (i, word) in text.split(|c: char| c.is_ascii_whitespace()).enumerate() {
+        if i > 0 && !html.is_empty() && !html.ends_with(' ') {
+            html.push(' ');
+        }
+        html.push_str(&escape_text(word));
+    }
+}
+
+fn write_node(node: &NodeRef, preformatted: bool, syntax: Syntax, html: &mut String) {
+    match node.data() {
+        NodeData::Element(element_data) => {
+            let tag = &*element_data.name.local;
+            if DROPPED_TAGS.contains(&tag) {
+                return;
+            }
+            let allowed = ALLOWED_TAGS.contains(&tag);
+            if allowed {
+                html.push('<');
+                html.push_str(tag);
+                write_attributes(tag, &element_data.attributes.borrow(), html);
+                if VOID_TAGS.contains(&tag) {
+                    html.push_str(if syntax == Syntax::Xhtml { "/>" } else { ">" });
+                    return;
+                }
+                html.push('>');
+            }
+            for child in node.children() {
+                write_node(&child, preformatted || tag == "pre", syntax, html);
+            }
+            if allowed {
+                html.push_str(&format!("</{}>", tag));
+            }
+        },
+        NodeData::Text(text) => write_text(&text.borrow(), preformatted, syntax, html),
+        NodeData::Document(_) | NodeData::DocumentFragment => {
+            for child in node.children() {
+                write_node(&child, preformatted, syntax, html);
+            }
+        },
+        _ => {}
+    }
+}
+
+fn write_plain_text(node: &NodeRef, text: &mut String) {
+    match node.data() {
+        NodeData::Element(element_data) => {
+            let tag = &*element_data.name.local;
+            if DROPPED_TAGS.contains(&tag) {
+                return;
+            }
+            let inline = INLINE_TAGS.contains(&tag);
+            if !inline {
+                text.push(' ');
+            }
+            for child in node.children() {
+                write_plain_text(&child, text);
+            }
+            if !inline {
+                text.push(' ');
+            }
+        },
+        NodeData::Text(contents) => text.extend(contents.borrow().chars().filter(|c| is_xml_char(*c))),
+        NodeData::Document(_) | NodeData::DocumentFragment => {
+            for child in node.children() {
+                write_plain_text(&child, text);
+            }
+        },
+        _ => {}
+    }
+}
+
+/// Serializes a node and its descendants as sanitized html.
+pub fn node_to_html(node: &NodeRef) -> String {
+    let mut html = String::new();
+    write_node(node, false, Syntax::Html, &mut html);
+    html.trim().to_string()
+}
+
+/// Serializes a node and its descendants as sanitized xhtml, i.e. html which is also well-formed
+/// xml.
+pub fn node_to_xhtml(node: &NodeRef) -> String {
+    let mut xhtml = String::new();
+    write_node(node, false, Syntax::Xhtml, &mut xhtml);
+    xhtml.trim().to_string()
+}
+
+/// Returns the text of a node and its descendants with whitespace collapsed. Block elements like
+/// paragraphs and list items are separated by a space.
+pub fn node_to_text(node: &NodeRef) -> String {
+    let mut text = String::new();
+    write_plain_text(node, &mut text);
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use html5ever::tendril::TendrilSink;
+
+    fn sanitize(html: &str) -> String {
+        node_to_html(&kuchiki::parse_html().one(html))
+    }
+
+    #[test]
+    fn test_node_to_html() {
+        assert_eq!(sanitize("<div><h1 class=\"title\">Title</h1>\n<p>Some <b>bold</b> text with a <a href=\"link\" target=\"_blank\">link</a></p></div>"),
+            "<h1>Title</h1> <p>Some <b>bold</b> text with a <a href=\"link\">link</a></p>");
+    }
+
+    #[test]
+    fn test_node_to_text() {
+        let document = kuchiki::parse_html().one("<div><h1>Title</h1><p>Some <b>bold</b>text</p><ul><li>One</li><li>Two\n  items</li></ul><script>alert(1)</script></div>");
+        assert_eq!(node_to_text(&document), "Title Some boldtext One Two items");
+    }
+
+    #[test]
+    fn test_node_to_html_void_elements() {
+        assert_eq!(sanitize("<p>Line<br>break</p><img src=\"image.png\" alt=\"An image\"><hr>"),
+            "<p>Line<br>break</p><img alt=\"An image\" src=\"image.png\"><hr>");
+    }
+
+    #[test]
+    fn test_node_to_html_escaping() {
+        assert_eq!(sanitize("<p>1 &lt; 2 &amp;&amp; &lt;script&gt;alert(1)&lt;/script&gt;</p>"),
+            "<p>1 &lt; 2 &amp;&amp; &lt;script&gt;alert(1)&lt;/script&gt;</p>");
+        assert_eq!(sanitize("<img alt='\"><script>alert(1)</script>' src=\"image.png\">"),
+            "<img alt=\"&quot;&gt;&lt;script&gt;alert(1)&lt;/script&gt;\" src=\"image.png\">");
+    }
+
+    #[test]
+    fn test_node_to_html_preformatted() {
+        assert_eq!(sanitize("<p>collapsed   \n  text</p><pre>fn main() {\n    x &lt; y\n}</pre>"),
+            "<p>collapsed text</p><pre>fn main() {\n    x &lt; y\n}</pre>");
+    }
+
+    #[test]
+    fn test_node_to_html_table() {
+        assert_eq!(sanitize("<table><tr><td colspan=\"2\" style=\"color: red\">cell</td></tr></table>"),
+            "<table><tbody><tr><td colspan=\"2\">cell</td></tr></tbody></table>");
+    }
+
+    #[test]
+    fn test_node_to_html_event_handlers() {
+        assert_eq!(sanitize("<p onclick=\"alert(1)\" onmouseover=\"alert(2)\">text</p><img src=\"x\" onerror=\"alert(3)\">"),
+            "<p>text</p><img src=\"x\">");
+    }
+
+    #[test]
+    fn test_node_to_html_style() {
+        assert_eq!(sanitize("<style>p {}</style><p style=\"background: url(javascript:alert(1))\">text</p>"),
+            "<p>text</p>");
+    }
+
+    #[test]
+    fn test_node_to_html_dangerous_urls() {
+        assert_eq!(sanitize("<a href=\"javascript:alert(1)\">1</a><a href=\" JaVaScRiPt:alert(2)\">2</a><a href=\"jav&#x09;ascript:alert(3)\">3</a><a href=\"vbscript:msgbox(4)\">4</a><a href=\"data:text/html,<script>alert(5)</script>\">5</a>"),
+            "<a>1</a><a>2</a><a>3</a><a>4</a><a>5</a>");
+    }
+
+    #[test]
+    fn test_node_to_html_safe_urls() {
+        assert_eq!(sanitize("<a href=\"https://example.com/a:b\">1</a><a href=\"/path?x=javascript:\">2</a><a href=\"mailto:user@example.com\">3</a>"),
+            "<a href=\"https://example.com/a:b\">1</a><a href=\"/path?x=javascript:\">2</a><a href=\"mailto:user@example.com\">3</a>");
+    }
+
+    #[test]
+    fn test_node_to_html_data_urls() {
+        assert_eq!(sanitize("<img src=\"data:image/png;base64,AAAA\"><img src=\"data:image/svg+xml;base64,AAAA\"><a href=\"data:image/png;base64,AAAA\">link</a>"),
+            "<img src=\"data:image/png;base64,AAAA\"><img><a>link</a>");
+    }
+
+    #[test]
+    fn test_node_to_html_srcset() {
+        assert_eq!(sanitize("<img src=\"a.png\" srcset=\"a.png 1x, b.png 2x\"><img src=\"a.png\" srcset=\"a.png 1x, javascript:alert(1) 2x\">"),
+            "<img src=\"a.png\" srcset=\"a.png 1x, b.png 2x\"><img src=\"a.png\">");
+    }
+
+    #[test]
+    fn test_node_to_html_dropped_elements() {
+        assert_eq!(sanitize("<p>before</p><script>alert(1)</script><iframe srcdoc=\"<script>alert(2)</script>\"></iframe><svg><script>alert(3)</script><a href=\"javascript:alert(4)\">svg</a></svg><math><mtext>math</mtext></math><noscript><img src=x onerror=alert(5)></noscript><p>after</p>"),
+            "<p>before</p><p>after</p>");
+    }
+
+    #[test]
+    fn test_node_to_html_unwrapped_elements() {
+        assert_eq!(sanitize("<form action=\"javascript:alert(1)\"><button formaction=\"javascript:alert(2)\">click</button></form><object data=\"x\">object</object><base href=\"javascript:alert(3)//\"><p>text</p>"),
+            "click<p>text</p>");
+    }
+
+    #[test]
+    fn test_node_to_xhtml() {
+        assert_eq!(node_to_xhtml(&kuchiki::parse_html().one("<p>Line<br>break\u{1}</p><img src=\"image.png\" alt=\"a &amp; b\"><hr>")),
+            "<p>Line<br/>break</p><img alt=\"a &amp; b\" src=\"image.png\"/><hr/>");
+    }
+
+    #[test]
+    fn test_node_to_html_comments() {
+        assert_eq!(sanitize("<p>text<!-- --><script>alert(1)</script> --></p>"),
+            "<p>text --&gt;</p>");
+    }
+}
@@ -0,0 +1,187 @@
+// Full-text search over the title and readable text of the saved webpages. Each webpage is
+// indexed with the text search configuration matching its language (see the text_search_config
+// function in the migrations) and the search is parsed with the same configuration so stemming
+// and stop words work the same way on both sides.
+// https://www.postgresql.org/docs/current/textsearch-controls.html
+
+use std::iter::Peekable;
+use std::str::Chars;
+use std::sync::Arc;
+
+use serde::{Deserialize,Serialize};
+use sqlx::{PgPool,Row};
+use warp::http::StatusCode;
+
+use crate::errors;
+use crate::sanitize;
+use crate::webpages::{self,WebpageInfo};
+
+const MAX_RESULTS: i64 = 50;
+// The matches in the snippets are marked with control characters which can't appear in the
+// text so the snippet can be escaped before the matches are wrapped in <mark> elements.
+const MATCH_START: char = '\u{2}';
+const MATCH_END: char = '\u{3}';
+
+#[derive(Deserialize,Debug)]
+pub struct SearchOptions {
+    q: String,
+}
+
+#[derive(Serialize,Debug)]
+struct SearchResult {
+    #[serde(flatten)]
+    info: WebpageInfo,
+    // Html with the matching words wrapped in <mark> elements.
+    snippet: String,
+    rank: f32,
+}
+
+#[derive(Serialize,Debug)]
+struct SearchResponse {
+    results: Vec<SearchResult>,
+}
+
+// Converts a word or a quoted phrase to to_tsquery syntax. Only letters and digits are kept so
+// the user can't write to_tsquery syntax directly. Words separated by other characters, e.g.
+// "full-text", have to appear next to each other like in a phrase.
+fn query_term(text: &str, prefix: bool) -> Option<String> {
+    let words: Vec<&str> = text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect();
+    let mut term = words.join(" <-> ");
+    if term.is_empty() {
+        return None;
+    }
+    if prefix {
+        term.push_str(":*");
+    }
+    if words.len() > 1 {
+        term = format!("({})", term);
+    }
+    Some(term)
+}
+
+fn take_until(chars: &mut Peekable<Chars>, end: impl Fn(char) -> bool) -> String {
+    let mut text = String::new();
+    while let Some(c) = chars.next_if(|c| !end(*c)) {
+        text.push(c);
+    }
+    text
+}
+
+/// Converts a search as typed by the user to the syntax understood by to_tsquery. Words must
+/// all match unless they are separated by OR. Quoted phrases must match as a phrase, words and
+/// phrases ending with * match as prefixes and a leading - excludes webpages matching the word
+/// or phrase. Returns None if there is nothing to search for.
+pub(crate) fn to_tsquery(search: &str) -> Option<String> {
+    // Terms joined by OR form a group and all groups must match. The groups are parenthesised
+    // since & binds tighter than | in to_tsquery.
+    let mut groups: Vec<Vec<String>> = Vec::new();
+    let mut or = false;
+    let mut chars = search.chars().peekable();
+    loop {
+        take_until(&mut chars, |c| !c.is_whitespace());
+        let negated = chars.next_if_eq(&'-').is_some();
+        let (text, quoted) = if chars.next_if_eq(&'"').is_some() {
+            let text = take_until(&mut chars, |c| c == '"');
+            chars.next();
+            (text, true)
+        } else {
+            (take_until(&mut chars, char::is_whitespace), false)
+        };
+        if text.is_empty() && chars.peek().is_none() {
+            break;
+        }
+        if text == "OR" && !quoted && !negated {
+            or = !groups.is_empty();
+            continue;
+        }
+        let prefix = text.ends_with('*') || (quoted && chars.next_if_eq(&'*').is_some());
+        if let Some(term) = query_term(&text, prefix) {
+            let term = if negated { format!("!{}", term) } else { term };
+            match groups.last_mut() {
+                Some(group) if or => group.push(term),
+                _ => groups.push(vec![term]),
+            }
+        }
+        or = false;
+    }
+    if groups.is_empty() {
+        return None;
+    }
+    Some(groups.into_iter()
+        .map(|group| if group.len() > 1 { format!("({})", group.join(" | ")) } else { group.join("") })
+        .collect::<Vec<_>>()
+        .join(" & "))
+}
+
+fn snippet_to_html(snippet: &str) -> String {
+    sanitize::escape_text(snippet)
+        .replace(MATCH_START, "<mark>")
+        .replace(MATCH_END, "</mark>")
+}
+
+async fn search(db_pool: &PgPool, user_id: i64, query: &str) -> Result<Vec<SearchResult>, sqlx::Error> {
+    let headline_options = format!("StartSel={}, StopSel={}, MaxWords=35, MinWords=15, MaxFragments=3, FragmentDelimiter=\" … \"",
+        MATCH_START, MATCH_END);
+    // The search is parsed once for each text search configuration used by the user's webpages
+    // which lets postgres use the index on search_vector.
+    let rows = sqlx::query(&format!("WITH queries AS (SELECT config, to_tsquery(config, $2) AS query FROM (SELECT DISTINCT search_config AS config FROM webpages WHERE user_id = $1) AS configs) SELECT {}, ts_headline(queries.config, plain_text, queries.query, $3) AS snippet, ts_rank(search_vector, queries.query) AS rank FROM webpages JOIN queries ON queries.config = webpages.search_config WHERE webpages.user_id = $1 AND webpages.search_vector @@ queries.query ORDER BY rank DESC, added DESC LIMIT $4",
+            webpages::webpage_info_columns()))
+        .bind(user_id)
+        .bind(query)
+        .bind(headline_options)
+        .bind(MAX_RESULTS)
+        .fetch_all(db_pool).await?;
+    rows.iter().map(|row| {
+        Ok(SearchResult {
+            info: WebpageInfo::from_row(row)?,
+            snippet: snippet_to_html(row.try_get("snippet")?),
+            rank: row.try_get("rank")?,
+        })
+    }).collect()
+}
+
+pub async fn search_handler(options: SearchOptions, db_pool: Arc<PgPool>, user_id: i64) ->
+        Result<impl warp::Reply, warp::Rejection> {
+    let query = match to_tsquery(&options.q) {
+        Some(query) => query,
+        None => return Ok(errors::error_reply(StatusCode::BAD_REQUEST, "Nothing to search for")),
+    };
+    Ok(match search(&db_pool, user_id, &query).await {
+        Ok(results) => warp::reply::with_status(warp::reply::json(&SearchResponse {results}), StatusCode::OK),
+        Err(error) => {
+            log::error!("Error when searching for {} for user {}: {}", options.q, user_id, error);
+            errors::error_reply(StatusCode::INTERNAL_SERVER_ERROR, "Unknown error")
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_tsquery() {
+        assert_eq!(to_tsquery("rust web"), Some("rust & web".to_string()));
+        assert_eq!(to_tsquery("\"full text search\" postgres"),
+            Some("(full <-> text <-> search) & postgres".to_string()));
+        assert_eq!(to_tsquery("rust OR go -java"), Some("(rust | go) & !java".to_string()));
+        assert_eq!(to_tsquery("-java rust OR go OR \"c sharp\""), Some("!java & (rust | go | (c <-> sharp))".to_string()));
+        assert_eq!(to_tsquery("data* \"web ass\"*"), Some("data:* & (web <-> ass:*)".to_string()));
+        assert_eq!(to_tsquery("full-text"), Some("(full <-> text)".to_string()));
+    }
+
+    #[test]
+    fn test_to_tsquery_special_characters() {
+        assert_eq!(to_tsquery("a&b | !c:* 'd' (e)"), Some("(a <-> b) & c:* & d & e".to_string()));
+        assert_eq!(to_tsquery("\"unterminated phrase"), Some("(unterminated <-> phrase)".to_string()));
+        assert_eq!(to_tsquery("  OR -- \"\" *  "), None);
+    }
+
+    #[test]
+    fn test_snippet_to_html() {
+        assert_eq!(snippet_to_html("a <b> \u{2}match\u{3} & more"),
+            "a &lt;b&gt; <mark>match</mark> &amp; more");
+    }
+}
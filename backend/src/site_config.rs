@@ -0,0 +1,147 @@
+// Declarative rules describing where the contents of a site are, in the spirit of the FiveFilters
+// site configs (https://github.com/fivefilters/ftr-site-config) but with CSS selectors instead of
+// XPath. A site config has one directive per line:
+//
+//     # Comments start with #
+//     url: *.example.com/articles/*
+//     title: h1.headline
+//     body: article .story
+//     strip: .share-buttons, aside
+//
+// url, title, body and strip can all be given several times. The first title selector and the
+// first body selector matching anything are used and everything matching a strip selector is
+// removed first. Without any url directive the config is used for the site it is named after.
+
+use kuchiki::NodeRef;
+use url::Url;
+
+use crate::extractors::{Extraction,Extractor,UrlPattern};
+
+#[derive(Debug,Default)]
+pub(crate) struct SiteConfig {
+    title: Vec<String>,
+    body: Vec<String>,
+    strip: Vec<String>,
+}
+
+impl SiteConfig {
+    /// Parses the config for the site called name. Returns the url patterns it is for and the
+    /// config.
+    pub fn parse(content: &str, name: &str) -> Result<(Vec<UrlPattern>, Self), String> {
+        let mut url_patterns = Vec::new();
+        let mut config = SiteConfig::default();
+        for (index, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (directive, value) = line.split_once(':')
+                .ok_or_else(|| format!("Line {} is not a directive: {}", index + 1, line))?;
+            let value = value.trim();
+            let selectors = match directive.trim() {
+                "url" => {
+                    url_patterns.push(value.parse()?);
+                    continue;
+                },
+                "title" => &mut config.title,
+                "body" => &mut config.body,
+                "strip" => &mut config.strip,
+                directive => return Err(format!("Unknown directive {} on line {}", directive, index + 1)),
+            };
+            if kuchiki::Selectors::compile(value).is_err() {
+                return Err(format!("Invalid selector {} on line {}", value, index + 1));
+            }
+            selectors.push(value.to_string());
+        }
+        if config.body.is_empty() {
+            return Err("A body selector is required".to_string());
+        }
+        if url_patterns.is_empty() {
+            url_patterns.push(format!("*.{}", name).parse()?);
+        }
+        Ok((url_patterns, config))
+    }
+}
+
+impl Extractor for SiteConfig {
+    fn extract(&self, document: &NodeRef, _url: &Url) -> Option<Extraction> {
+        for selector in &self.strip {
+            if let Ok(nodes) = document.select(selector) {
+                nodes.collect::<Vec<_>>().iter().for_each(|node| node.as_node().detach());
+            }
+        }
+        let title = self.title.iter().find_map(|selector| {
+            let title = document.select_first(selector).ok()?.text_contents();
+            Some(title.trim().to_string()).filter(|title| !title.is_empty())
+        });
+        let contents = self.body.iter().find_map(|selector| {
+            let nodes: Vec<_> = document.select(selector).ok()?.map(|node| node.as_node().clone()).collect();
+            Some(nodes).filter(|nodes| !nodes.is_empty())
+        })?;
+        Some(Extraction {title, contents})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extractors::ExtractorRegistry;
+
+    #[test]
+    fn test_parse() {
+        let (url_patterns, config) = SiteConfig::parse("
+# Example
+title: h1
+body: article .story
+body: main
+strip: .ads, aside
+", "example.com").unwrap();
+        assert_eq!(url_patterns, vec!["*.example.com".parse().unwrap()]);
+        assert_eq!(config.body, vec!["article .story", "main"]);
+        assert_eq!(config.strip, vec![".ads, aside"]);
+        assert!(SiteConfig::parse("title: h1", "example.com").is_err());
+        assert!(SiteConfig::parse("body: article[", "example.com").is_err());
+        assert!(SiteConfig::parse("body: article\nauthor: .byline", "example.com").is_err());
+    }
+
+    #[test]
+    fn test_wikipedia() {
+        let webpage = ExtractorRegistry::built_in().extract(include_str!("test-data/extractor-wikipedia.html"),
+            "https://en.wikipedia.org/wiki/Hedgehog").unwrap();
+        assert_eq!(webpage.title, "Hedgehog");
+        assert!(webpage.plain_text.starts_with("A hedgehog is a spiny mammal"), "{}", webpage.plain_text);
+        assert!(webpage.plain_text.contains("Hedgehogs are found in Europe, Asia and Africa."), "{}", webpage.plain_text);
+        for boilerplate in ["[edit]", "[1]", "Jump to navigation", "This article needs additional citations",
+                "Navigation menu"] {
+            assert!(!webpage.plain_text.contains(boilerplate), "{}", webpage.plain_text);
+        }
+    }
+
+    #[test]
+    fn test_substack() {
+        let webpage = ExtractorRegistry::built_in().extract(include_str!("test-data/extractor-substack.html"),
+            "https://example.substack.com/p/the-first-post").unwrap();
+        assert_eq!(webpage.title, "The first post");
+        assert!(webpage.plain_text.starts_with("Welcome to the newsletter."), "{}", webpage.plain_text);
+        assert!(!webpage.plain_text.contains("Subscribe"), "{}", webpage.plain_text);
+        assert!(!webpage.plain_text.contains("Share"), "{}", webpage.plain_text);
+    }
+
+    #[test]
+    fn test_load() {
+        let directory = std::env::temp_dir().join(format!("site-config-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("example.com.txt"), "title: .headline\nbody: .story\nstrip: .ad\n").unwrap();
+        std::fs::write(directory.join("README"), "Not a site config").unwrap();
+        let registry = ExtractorRegistry::load(&directory).unwrap();
+        let html = "<html><head><title>Example</title></head><body><h1 class=headline>Headline</h1><div class=story><p>The story</p><p class=ad>An ad</p></div><p>Comments</p></body></html>";
+        let webpage = registry.extract(html, "https://www.example.com/story").unwrap();
+        assert_eq!(webpage.title, "Headline");
+        assert_eq!(webpage.contents, "<p>The story</p>");
+        // The built-in extractors are still there.
+        assert!(registry.find(&Url::parse("https://github.com/owner/repository").unwrap()).is_some());
+        std::fs::write(directory.join("broken.txt"), "body: [").unwrap();
+        assert!(ExtractorRegistry::load(&directory).unwrap_err().contains("broken.txt"));
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
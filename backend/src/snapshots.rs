@@ -0,0 +1,275 @@
+// Pages change or disappear behind paywalls after they are saved. A saved webpage can be fetched
+// again and every new version is stored as a snapshot next to the version saved first, so the
+// user can read an older version or see what has changed.
+
+use std::sync::Arc;
+
+use serde::{Deserialize,Serialize};
+use similar::{ChangeTag,TextDiff};
+use sqlx::{PgPool,Row};
+use warp::http::StatusCode;
+
+use crate::assets;
+use crate::errors;
+use crate::extractors::ExtractorRegistry;
+use crate::fetch::{self,FetchError,HttpClient};
+use crate::pagination;
+
+#[derive(Deserialize,Serialize,Debug,Clone,PartialEq,Eq)]
+pub struct SnapshotInfo {
+    pub id: i64,
+    pub title: String,
+    pub fetched: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Serialize,Debug)]
+struct ListSnapshotsResponse {
+    snapshots: Vec<SnapshotInfo>,
+}
+
+/// A snapshot with its content in the column of the show mode.
+#[derive(Debug)]
+pub(crate) struct Snapshot {
+    pub info: SnapshotInfo,
+    pub image_url: Option<String>,
+    pub content: String,
+}
+
+/// Which version of a webpage to show. Without either option the version saved first is shown.
+#[derive(Debug)]
+pub(crate) struct SnapshotSelector {
+    pub snapshot: Option<i64>,
+    // The newest version fetched at or before this time.
+    pub at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Deserialize,Debug)]
+pub struct DiffOptions {
+    // The snapshots to compare. The version saved first is used when from is missing and the
+    // newest snapshot when to is missing.
+    from: Option<i64>,
+    to: Option<i64>,
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Serialize,Debug,Clone,Copy,PartialEq,Eq)]
+enum Change {
+    equal,
+    delete,
+    insert,
+}
+
+#[derive(Serialize,Debug,PartialEq,Eq)]
+struct DiffChunk {
+    change: Change,
+    text: String,
+}
+
+#[derive(Serialize,Debug)]
+struct DiffResponse {
+    from: Option<i64>,
+    to: Option<i64>,
+    changes: Vec<DiffChunk>,
+}
+
+#[derive(Debug)]
+enum RefetchError {
+    WebpageNotFound,
+    Fetch(FetchError),
+    Parse(errors::ParseDocumentError<'static>),
+    Database(sqlx::Error),
+}
+
+impl From<sqlx::Error> for RefetchError {
+    fn from(error: sqlx::Error) -> Self {
+        RefetchError::Database(error)
+    }
+}
+
+async fn webpage_url(db_pool: &PgPool, webpage_id: i64, user_id: i64) -> Result<Option<String>, sqlx::Error> {
+    let row = sqlx::query_as::<_, (String,)>("SELECT url FROM webpages WHERE id = $1 AND user_id = $2")
+        .bind(webpage_id)
+        .bind(user_id)
+        .fetch_optional(db_pool).await?;
+    Ok(row.map(|(url,)| url))
+}
+
+async fn refetch(db_pool: &PgPool, http_client: &HttpClient, extractors: &ExtractorRegistry, webpage_id: i64,
+        user_id: i64) -> Result<SnapshotInfo, RefetchError> {
+    let url = webpage_url(db_pool, webpage_id, user_id).await?
+        .ok_or(RefetchError::WebpageNotFound)?;
+    let html = fetch::fetch_webpage(http_client, &url).await.map_err(RefetchError::Fetch)?;
+    let mut webpage = extractors.extract(&html, &url).map_err(RefetchError::Parse)?;
+    pagination::append_following_pages(http_client, extractors, &mut webpage, &url).await;
+    let assets = assets::archive_webpage(http_client, &mut webpage, &url, false).await;
+    let mut transaction = db_pool.begin().await?;
+    let (id, fetched) = sqlx::query_as::<_, (i64, chrono::DateTime<chrono::Utc>)>("INSERT INTO snapshots(webpage_id, title, text, html, plain_text, image_url) VALUES ($1, $2, $3, $4, $5, $6) RETURNING id, fetched")
+        .bind(webpage_id)
+        .bind(&webpage.title)
+        .bind(&webpage.contents)
+        .bind(&webpage.original_html)
+        .bind(&webpage.plain_text)
+        .bind(&webpage.image_url)
+        .fetch_one(&mut transaction).await?;
+    assets::store_assets(&mut transaction, webpage_id, Some(id), &assets).await?;
+    transaction.commit().await?;
+    Ok(SnapshotInfo {id, title: webpage.title, fetched})
+}
+
+/// Gets the snapshot picked by the selector with the content in the given column. None is
+/// returned when the version saved first is picked or the snapshot doesn't exist. The caller must
+/// have checked that the webpage belongs to the user.
+pub(crate) async fn get_snapshot(db_pool: &PgPool, webpage_id: i64, selector: &SnapshotSelector,
+        column: &str) -> Result<Option<Snapshot>, sqlx::Error> {
+    let query = format!("SELECT id, title, fetched, image_url, {} AS content FROM snapshots WHERE webpage_id = $1", column);
+    let row = match (selector.snapshot, selector.at) {
+        (Some(snapshot_id), _) => sqlx::query(&format!("{} AND id = $2", query))
+            .bind(webpage_id)
+            .bind(snapshot_id)
+            .fetch_optional(db_pool).await?,
+        (None, Some(at)) => sqlx::query(&format!("{} AND fetched <= $2 ORDER BY fetched DESC, id DESC LIMIT 1", query))
+            .bind(webpage_id)
+            .bind(at)
+            .fetch_optional(db_pool).await?,
+        (None, None) => None,
+    };
+    row.map(|row| {
+        Ok(Snapshot {
+            info: SnapshotInfo {
+                id: row.try_get("id")?,
+                title: row.try_get("title")?,
+                fetched: row.try_get("fetched")?,
+            },
+            image_url: row.try_get("image_url")?,
+            content: row.try_get("content")?,
+        })
+    }).transpose()
+}
+
+async fn list_snapshots(db_pool: &PgPool, webpage_id: i64, user_id: i64) ->
+        Result<Option<Vec<SnapshotInfo>>, sqlx::Error> {
+    if webpage_url(db_pool, webpage_id, user_id).await?.is_none() {
+        return Ok(None);
+    }
+    let snapshots = sqlx::query_as::<_, (i64, String, chrono::DateTime<chrono::Utc>)>("SELECT id, title, fetched FROM snapshots WHERE webpage_id = $1 ORDER BY fetched, id")
+        .bind(webpage_id)
+        .fetch_all(db_pool).await?;
+    Ok(Some(snapshots.into_iter().map(|(id, title, fetched)| SnapshotInfo {id, title, fetched}).collect()))
+}
+
+// Gets the readable text of the snapshot or of the version saved first.
+async fn snapshot_text(db_pool: &PgPool, webpage_id: i64, user_id: i64, snapshot_id: Option<i64>) ->
+        Result<Option<String>, sqlx::Error> {
+    let row = match snapshot_id {
+        Some(snapshot_id) => sqlx::query_as::<_, (String,)>("SELECT snapshots.plain_text FROM snapshots JOIN webpages ON webpages.id = snapshots.webpage_id WHERE snapshots.id = $1 AND webpages.id = $2 AND webpages.user_id = $3")
+            .bind(snapshot_id)
+            .bind(webpage_id)
+            .bind(user_id)
+            .fetch_optional(db_pool).await?,
+        None => sqlx::query_as::<_, (String,)>("SELECT plain_text FROM webpages WHERE id = $1 AND user_id = $2")
+            .bind(webpage_id)
+            .bind(user_id)
+            .fetch_optional(db_pool).await?,
+    };
+    Ok(row.map(|(text,)| text))
+}
+
+/// Compares two texts word by word. Consecutive words with the same change are joined in one
+/// chunk.
+fn diff_text(old: &str, new: &str) -> Vec<DiffChunk> {
+    let mut chunks: Vec<DiffChunk> = Vec::new();
+    for change in TextDiff::from_words(old, new).iter_all_changes() {
+        let kind = match change.tag() {
+            ChangeTag::Equal => Change::equal,
+            ChangeTag::Delete => Change::delete,
+            ChangeTag::Insert => Change::insert,
+        };
+        match chunks.last_mut() {
+            Some(chunk) if chunk.change == kind => chunk.text.push_str(change.value()),
+            _ => chunks.push(DiffChunk {change: kind, text: change.value().to_string()}),
+        }
+    }
+    chunks
+}
+
+async fn diff(db_pool: &PgPool, webpage_id: i64, user_id: i64, options: &DiffOptions) ->
+        Result<Option<DiffResponse>, sqlx::Error> {
+    let to = match options.to {
+        Some(to) => Some(to),
+        None => sqlx::query_as::<_, (i64,)>("SELECT id FROM snapshots WHERE webpage_id = $1 ORDER BY fetched DESC, id DESC LIMIT 1")
+            .bind(webpage_id)
+            .fetch_optional(db_pool).await?
+            .map(|(id,)| id),
+    };
+    let old = snapshot_text(db_pool, webpage_id, user_id, options.from).await?;
+    let new = snapshot_text(db_pool, webpage_id, user_id, to).await?;
+    Ok(old.zip(new).map(|(old, new)| DiffResponse {
+        from: options.from,
+        to,
+        changes: diff_text(&old, &new),
+    }))
+}
+
+pub async fn refetch_handler(webpage_id: i64, db_pool: Arc<PgPool>, http_client: HttpClient,
+        extractors: Arc<ExtractorRegistry>, user_id: i64) -> Result<impl warp::Reply, warp::Rejection> {
+    Ok(match refetch(&db_pool, &http_client, &extractors, webpage_id, user_id).await {
+        Ok(snapshot) => warp::reply::with_status(warp::reply::json(&snapshot), StatusCode::CREATED),
+        Err(RefetchError::WebpageNotFound) => errors::error_reply(StatusCode::NOT_FOUND, "Webpage not found"),
+        Err(RefetchError::Fetch(error)) => errors::error_reply(StatusCode::BAD_GATEWAY, &error.to_string()),
+        Err(RefetchError::Parse(error)) => {
+            log::error!("Error parsing document when fetching webpage {} again: {}", webpage_id, error);
+            errors::error_reply(StatusCode::INTERNAL_SERVER_ERROR, "Unknown error")
+        },
+        Err(RefetchError::Database(error)) => {
+            log::error!("Error when saving snapshot of webpage {} for user {}: {}", webpage_id, user_id, error);
+            errors::error_reply(StatusCode::INTERNAL_SERVER_ERROR, "Unknown error")
+        },
+    })
+}
+
+pub async fn list_snapshots_handler(webpage_id: i64, db_pool: Arc<PgPool>, user_id: i64) ->
+        Result<impl warp::Reply, warp::Rejection> {
+    Ok(match list_snapshots(&db_pool, webpage_id, user_id).await {
+        Ok(Some(snapshots)) => warp::reply::with_status(
+            warp::reply::json(&ListSnapshotsResponse {snapshots}), StatusCode::OK),
+        Ok(None) => errors::error_reply(StatusCode::NOT_FOUND, "Webpage not found"),
+        Err(error) => {
+            log::error!("Error when listing snapshots of webpage {} for user {}: {}", webpage_id, user_id, error);
+            errors::error_reply(StatusCode::INTERNAL_SERVER_ERROR, "Unknown error")
+        },
+    })
+}
+
+pub async fn diff_handler(webpage_id: i64, options: DiffOptions, db_pool: Arc<PgPool>, user_id: i64) ->
+        Result<impl warp::Reply, warp::Rejection> {
+    Ok(match diff(&db_pool, webpage_id, user_id, &options).await {
+        Ok(Some(diff)) => warp::reply::with_status(warp::reply::json(&diff), StatusCode::OK),
+        Ok(None) => errors::error_reply(StatusCode::NOT_FOUND, "Snapshot not found"),
+        Err(error) => {
+            log::error!("Error when comparing snapshots of webpage {} for user {}: {}", webpage_id, user_id, error);
+            errors::error_reply(StatusCode::INTERNAL_SERVER_ERROR, "Unknown error")
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(change: Change, text: &str) -> DiffChunk {
+        DiffChunk {change, text: text.to_string()}
+    }
+
+    #[test]
+    fn test_diff_text() {
+        assert_eq!(diff_text("The quick brown fox jumps", "The slow brown fox jumps high"), vec![
+            chunk(Change::equal, "The "),
+            chunk(Change::delete, "quick"),
+            chunk(Change::insert, "slow"),
+            chunk(Change::equal, " brown fox jumps"),
+            chunk(Change::insert, " high"),
+        ]);
+        assert_eq!(diff_text("same", "same"), vec![chunk(Change::equal, "same")]);
+        assert_eq!(diff_text("", ""), vec![]);
+    }
+}
@@ -0,0 +1,223 @@
+use std::sync::Arc;
+
+use serde::{Deserialize,Serialize};
+use sqlx::PgPool;
+use warp::http::StatusCode;
+
+use crate::errors;
+
+#[derive(Deserialize,Serialize,Debug,PartialEq,Eq)]
+pub struct TagList {
+    pub tags: Vec<String>,
+}
+
+#[derive(Deserialize,Serialize,Debug,PartialEq,Eq)]
+pub struct TagCount {
+    pub tag: String,
+    pub count: i64,
+}
+
+#[derive(Deserialize,Serialize,Debug,PartialEq,Eq)]
+pub struct ListTagsResponse {
+    pub tags: Vec<TagCount>,
+}
+
+#[derive(Deserialize,Debug)]
+pub struct RenameTag {
+    from: String,
+    to: String,
+}
+
+/// Trims the tags and removes empty and duplicate tags.
+pub(crate) fn normalize_tags(tags: &[String]) -> Vec<String> {
+    let mut normalized: Vec<String> = Vec::new();
+    for tag in tags {
+        let tag = tag.split_whitespace().collect::<Vec<_>>().join(" ");
+        if !tag.is_empty() && !normalized.contains(&tag) {
+            normalized.push(tag);
+        }
+    }
+    normalized
+}
+
+/// Parses a comma separated list of tags as given in a query parameter.
+pub(crate) fn parse_tags(tags: &str) -> Vec<String> {
+    normalize_tags(&tags.split(',').map(|tag| tag.to_string()).collect::<Vec<_>>())
+}
+
+/// Adds the tags to a webpage creating the tags which the user doesn't have already. The caller
+/// is responsible for checking that the webpage belongs to the user.
+pub(crate) async fn add_tags(transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>, user_id: i64,
+        webpage_id: i64, tags: &[String]) -> Result<(), sqlx::Error> {
+    let tags = normalize_tags(tags);
+    if tags.is_empty() {
+        return Ok(());
+    }
+    // Updating the tag on conflict makes the query return the id of existing tags as well.
+    sqlx::query("with user_tags as (insert into tags(user_id, tag) select $1, unnest($2::text[]) on conflict (user_id, tag) do update set tag = excluded.tag returning id) insert into tags_to_webpages(tag_id, webpage_id) select id, $3 from user_tags on conflict (tag_id, webpage_id) do nothing")
+        .bind(user_id)
+        .bind(&tags)
+        .bind(webpage_id)
+        .execute(&mut *transaction).await?;
+    Ok(())
+}
+
+async fn remove_tags(transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>, user_id: i64,
+        webpage_id: i64, tags: &[String]) -> Result<(), sqlx::Error> {
+    let tags = normalize_tags(tags);
+    sqlx::query("delete from tags_to_webpages using tags where tags.id = tags_to_webpages.tag_id and tags.user_id = $1 and tags.tag = any($2) and tags_to_webpages.webpage_id = $3")
+        .bind(user_id)
+        .bind(&tags)
+        .bind(webpage_id)
+        .execute(&mut *transaction).await?;
+    remove_unused_tags(transaction, user_id, &tags).await
+}
+
+/// Removes those of the user's tags which aren't used by any webpage anymore. They would
+/// otherwise show up with a count of 0 forever.
+pub(crate) async fn remove_unused_tags(transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>, user_id: i64,
+        tags: &[String]) -> Result<(), sqlx::Error> {
+    sqlx::query("delete from tags where user_id = $1 and tag = any($2) and not exists (select 1 from tags_to_webpages where tags_to_webpages.tag_id = tags.id)")
+        .bind(user_id)
+        .bind(tags)
+        .execute(&mut *transaction).await?;
+    Ok(())
+}
+
+async fn webpage_tags(db_pool: &PgPool, webpage_id: i64) -> Result<Vec<String>, sqlx::Error> {
+    let tags = sqlx::query_as::<_, (String,)>("select tags.tag from tags join tags_to_webpages on tags_to_webpages.tag_id = tags.id where tags_to_webpages.webpage_id = $1 order by tags.tag")
+        .bind(webpage_id)
+        .fetch_all(db_pool).await?;
+    Ok(tags.into_iter().map(|(tag,)| tag).collect())
+}
+
+// Whether the tags are added or removed the webpage has to belong to the user and the response
+// is the resulting tags of the webpage.
+async fn change_tags(webpage_id: i64, tags: &[String], add: bool, db_pool: &PgPool, user_id: i64) ->
+        Result<Option<Vec<String>>, sqlx::Error> {
+    let mut transaction = db_pool.begin().await?;
+    let webpage = sqlx::query_as::<_, (i64,)>("select id from webpages where id = $1 and user_id = $2")
+        .bind(webpage_id)
+        .bind(user_id)
+        .fetch_optional(&mut transaction).await?;
+    if webpage.is_none() {
+        return Ok(None);
+    }
+    if add {
+        add_tags(&mut transaction, user_id, webpage_id, tags).await?;
+    } else {
+        remove_tags(&mut transaction, user_id, webpage_id, tags).await?;
+    }
+    transaction.commit().await?;
+    Ok(Some(webpage_tags(db_pool, webpage_id).await?))
+}
+
+async fn change_tags_handler(webpage_id: i64, body: TagList, add: bool, db_pool: Arc<PgPool>,
+        user_id: i64) -> Result<impl warp::Reply, warp::Rejection> {
+    Ok(match change_tags(webpage_id, &body.tags, add, &db_pool, user_id).await {
+        Ok(Some(tags)) => warp::reply::with_status(warp::reply::json(&TagList {tags}), StatusCode::OK),
+        Ok(None) => errors::error_reply(StatusCode::NOT_FOUND, "Webpage not found"),
+        Err(error) => {
+            log::error!("Error when changing tags of webpage {} for user {}: {}", webpage_id, user_id, error);
+            errors::error_reply(StatusCode::INTERNAL_SERVER_ERROR, "Unknown error")
+        },
+    })
+}
+
+pub async fn add_tags_handler(webpage_id: i64, body: TagList, db_pool: Arc<PgPool>, user_id: i64) ->
+        Result<impl warp::Reply, warp::Rejection> {
+    change_tags_handler(webpage_id, body, true, db_pool, user_id).await
+}
+
+pub async fn remove_tags_handler(webpage_id: i64, body: TagList, db_pool: Arc<PgPool>, user_id: i64) ->
+        Result<impl warp::Reply, warp::Rejection> {
+    change_tags_handler(webpage_id, body, false, db_pool, user_id).await
+}
+
+pub async fn list_tags_handler(db_pool: Arc<PgPool>, user_id: i64) ->
+        Result<impl warp::Reply, warp::Rejection> {
+    let rows = sqlx::query_as::<_, (String, i64)>("select tags.tag, count(tags_to_webpages.id) from tags left join tags_to_webpages on tags_to_webpages.tag_id = tags.id where tags.user_id = $1 group by tags.id order by tags.tag")
+        .bind(user_id)
+        .fetch_all(&*db_pool).await;
+    Ok(match rows {
+        Ok(rows) => {
+            let tags = rows.into_iter().map(|(tag, count)| TagCount {tag, count}).collect();
+            warp::reply::with_status(warp::reply::json(&ListTagsResponse {tags}), StatusCode::OK)
+        },
+        Err(error) => {
+            log::error!("Error when fetching tags for user {}: {}", user_id, error);
+            errors::error_reply(StatusCode::INTERNAL_SERVER_ERROR, "Unknown error")
+        },
+    })
+}
+
+// Renames the tag or merges it into another tag if the user already has a tag with the new name.
+// Returns false if the user doesn't have the tag.
+async fn rename_tag(db_pool: &PgPool, user_id: i64, from: &str, to: &str) -> Result<bool, sqlx::Error> {
+    let mut transaction = db_pool.begin().await?;
+    let from_id = sqlx::query_as::<_, (i64,)>("select id from tags where user_id = $1 and tag = $2")
+        .bind(user_id)
+        .bind(from)
+        .fetch_optional(&mut transaction).await?;
+    let from_id = match from_id {
+        Some((id,)) => id,
+        None => return Ok(false),
+    };
+    let to_id = sqlx::query_as::<_, (i64,)>("select id from tags where user_id = $1 and tag = $2")
+        .bind(user_id)
+        .bind(to)
+        .fetch_optional(&mut transaction).await?;
+    match to_id {
+        None => {
+            sqlx::query("update tags set tag = $1 where id = $2")
+                .bind(to)
+                .bind(from_id)
+                .execute(&mut transaction).await?;
+        },
+        Some((to_id,)) if to_id != from_id => {
+            sqlx::query("insert into tags_to_webpages(tag_id, webpage_id) select $1, webpage_id from tags_to_webpages where tag_id = $2 on conflict (tag_id, webpage_id) do nothing")
+                .bind(to_id)
+                .bind(from_id)
+                .execute(&mut transaction).await?;
+            sqlx::query("delete from tags where id = $1")
+                .bind(from_id)
+                .execute(&mut transaction).await?;
+        },
+        Some(_) => {},
+    }
+    transaction.commit().await?;
+    Ok(true)
+}
+
+pub async fn rename_tag_handler(body: RenameTag, db_pool: Arc<PgPool>, user_id: i64) ->
+        Result<impl warp::Reply, warp::Rejection> {
+    let (from, to) = match (normalize_tags(&[body.from]).pop(), normalize_tags(&[body.to]).pop()) {
+        (Some(from), Some(to)) => (from, to),
+        _ => return Ok(StatusCode::BAD_REQUEST),
+    };
+    match rename_tag(&db_pool, user_id, &from, &to).await {
+        Ok(true) => Ok(StatusCode::NO_CONTENT),
+        Ok(false) => Ok(StatusCode::NOT_FOUND),
+        Err(error) => {
+            log::error!("Error when renaming tag {} to {} for user {}: {}", from, to, user_id, error);
+            Ok(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_tags() {
+        assert_eq!(normalize_tags(&[" rust ".to_string(), "".to_string(), "long   read".to_string(), "rust".to_string()]),
+            vec!["rust".to_string(), "long read".to_string()]);
+    }
+
+    #[test]
+    fn test_parse_tags() {
+        assert_eq!(parse_tags("rust, web,,rust"), vec!["rust".to_string(), "web".to_string()]);
+        assert!(parse_tags("").is_empty());
+    }
+}
@@ -21,24 +21,77 @@ impl std::cmp::PartialEq for WebpagesRow {
         (self.id == other.id) &&
         (self.url == other.url) && (self.text == other.text) &&
             (self.html == other.html) && (self.title == other.title) &&
-            (self.image_url == other.image_url) && (self.user_id == self.user_id)
+            (self.image_url == other.image_url) && (self.user_id == other.user_id)
     }
 }
 
 #[test]
 fn test_traverse_document() {
     let html = include_str!("test-data/file.html");
-    let result = traverse_document(html).expect("Unable to parse document");
+    let result = traverse_document(html, "https://example.com/articles/file.html", None)
+        .expect("Unable to parse document");
     assert_eq!(result.title, "HUR".to_string());
-    assert_eq!(result.contents, "<title > HUR </title> <h1 > overskrift </h1> <h2 > underoverskrift med <a href=link1> link </a> </h2> <img src=image.url> </img> <p > tekst 1 </p> <p > tekst med <a href=link2> link </a> og tekst </p> <p id=10> tekst med <span id=20> tekst inde i <span id=30> tekst </span> </span> </p>");
-    assert_eq!(result.image_url, Some("image.url".to_string()));
+    assert_eq!(result.contents, "<h1>overskrift</h1> <h2>underoverskrift med <a href=\"https://example.com/articles/link1\">link</a></h2> <img src=\"https://example.com/articles/image.url\"> <p>tekst 1</p> <p>tekst med <a href=\"https://example.com/articles/link2\">link</a> og tekst</p> <p>tekst med <span>tekst inde i <span>tekst</span></span></p>");
+    assert_eq!(result.image_url, Some("https://example.com/articles/image.url".to_string()));
+}
+
+#[test]
+fn test_traverse_document_article() {
+    let html = include_str!("test-data/article.html");
+    let result = traverse_document(html, "https://example.com/article.html", None)
+        .expect("Unable to parse document");
+    assert_eq!(result.title, "Growing tomatoes on a balcony | The Garden Blog".to_string());
+    assert_eq!(result.contents, "<h1>Growing tomatoes on a balcony</h1> <p>Tomatoes are surprisingly easy to grow in containers, as long as they get plenty of sun, water and a bit of support when the fruits start weighing the plants down.</p> <p>Pick a bush variety if your balcony is small. Cordon varieties grow tall, need to be tied to a cane and have their side shoots pinched out every week, which takes more time than most people expect.</p> <p>Water every day during the summer, preferably in the morning, and feed the plants with a potassium rich fertiliser once the first flowers have appeared.</p>");
+}
+
+#[test]
+fn test_traverse_document_article_lead_paragraph() {
+    let html = include_str!("test-data/article-lead-paragraph.html");
+    let result = traverse_document(html, "https://example.com/article.html", None)
+        .expect("Unable to parse document");
+    assert_eq!(result.contents, "<p>The council has approved the new cycle lanes. Construction starts in the spring.</p> <p>The lanes will run along both sides of the harbour road, connecting the station with the new residential areas on the waterfront, according to the plans published on Monday.</p> <p>Local businesses have been sceptical, arguing that the loss of parking spaces will hurt trade, but the council points to similar projects in other cities where shops saw more customers.</p>");
+}
+
+#[test]
+fn test_traverse_document_base_url() {
+    let html = "<html><head><base href=\"https://cdn.example.com/media/\"></head><body><p><img src=\"image.png\" srcset=\"image.png 1x, image-large.png 2x\"><a href=\"/about\">about</a> <a href=\"#notes\">notes</a></p></body></html>";
+    let result = traverse_document(html, "https://example.com/article.html", None)
+        .expect("Unable to parse document");
+    assert_eq!(result.contents, "<p><img src=\"https://cdn.example.com/media/image.png\" srcset=\"https://cdn.example.com/media/image.png 1x, https://cdn.example.com/media/image-large.png 2x\"><a href=\"https://cdn.example.com/about\">about</a> <a href=\"#notes\">notes</a></p>");
+    assert_eq!(result.image_url, Some("https://cdn.example.com/media/image.png".to_string()));
+}
+
+#[test]
+fn test_traverse_document_metadata() {
+    let html = include_str!("test-data/metadata.html");
+    let result = traverse_document(html, "https://example.com/articles/article.html", None)
+        .expect("Unable to parse document");
+    assert_eq!(result.title, "The real headline".to_string());
+    assert_eq!(result.image_url, Some("https://example.com/images/lead.jpg".to_string()));
+    assert_eq!(result.metadata.site_name, Some("Example News".to_string()));
+}
+
+#[test]
+fn test_traverse_document_image_url_skips_tracking_pixels() {
+    let html = "<html><body><img src=\"pixel.gif\" width=\"1\" height=\"1\"><p><img src=\"photo.jpg\">text</p></body></html>";
+    let result = traverse_document(html, "https://example.com/", None)
+        .expect("Unable to parse document");
+    assert_eq!(result.image_url, Some("https://example.com/photo.jpg".to_string()));
+}
+
+#[test]
+fn test_url_key() {
+    assert_eq!(url_key("https://example.com/amp/article", Some("https://example.com/article/")), "https://example.com/article");
+    assert_eq!(url_key("https://other.example.org/page", Some("https://example.com/article")), "https://other.example.org/page");
+    assert_eq!(url_key("https://example.com/article", Some("/relative")), "https://example.com/article");
+    assert_eq!(url_key("https://example.com/article", None), "https://example.com/article");
 }
 
 #[test]
 fn test_traverse_document_invalid_document() {
     // This html contains an invalid script element.
     let html_response = "<!DOCTYPE html><html><head><script src=\"script.js\"/><title>Title</title></head><body><div><p>An html document</p></div></body></html>";
-    let result = traverse_document(html_response);
+    let result = traverse_document(html_response, "https://example.com", None);
     assert!(result.is_err());
 }
 
@@ -79,5 +132,5 @@ async fn test_database_migration() {
         image_url: Some("image.url".to_string()),
         added: chrono::Local::now(),
     });
-    assert_eq!(rows[0].added > before && rows[0].added < after, true);
+    assert!(rows[0].added > before && rows[0].added < after);
 }
@@ -0,0 +1,116 @@
+// The length of the readable text of a webpage, the time it takes to read it and the language it
+// is written in, so webpages can be listed with e.g. "7 min read" and a language badge. The
+// language declared by the page itself is preferred and the language is only detected from the
+// text when there is none.
+
+// The average silent reading speed of adults reading non-fiction in English.
+// https://doi.org/10.1016/j.jml.2019.104047
+const WORDS_PER_MINUTE: usize = 238;
+// A common estimate for Chinese and Japanese.
+const CJK_CHARACTERS_PER_MINUTE: usize = 500;
+// Detecting the language of the beginning of a text is just as reliable as detecting it for the
+// whole text.
+const MAX_DETECTION_LENGTH: usize = 10000;
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub(crate) struct TextStats {
+    pub word_count: i32,
+    // In whole minutes, rounded up.
+    pub reading_time: i32,
+}
+
+// Chinese and Japanese aren't written with spaces between words, so every character is counted
+// instead.
+fn is_cjk(c: char) -> bool {
+    matches!(c, '\u{3040}'..='\u{30FF}' | '\u{3400}'..='\u{4DBF}' | '\u{4E00}'..='\u{9FFF}'
+        | '\u{F900}'..='\u{FAFF}' | '\u{20000}'..='\u{2FA1F}')
+}
+
+impl TextStats {
+    pub fn new(text: &str) -> Self {
+        let mut words = 0;
+        let mut cjk_characters = 0;
+        for word in text.split_whitespace() {
+            let cjk = word.chars().filter(|c| is_cjk(*c)).count();
+            cjk_characters += cjk;
+            // Latin letters and digits mixed in with the characters are counted as one word.
+            // Punctuation on its own isn't a word.
+            if word.chars().any(|c| !is_cjk(c) && c.is_alphanumeric()) {
+                words += 1;
+            }
+        }
+        let minutes = words as f64 / WORDS_PER_MINUTE as f64
+            + cjk_characters as f64 / CJK_CHARACTERS_PER_MINUTE as f64;
+        TextStats {
+            word_count: i32::try_from(words + cjk_characters).unwrap_or(i32::MAX),
+            reading_time: minutes.ceil() as i32,
+        }
+    }
+}
+
+// whatlang names languages by their ISO 639-3 code while the lang attribute uses the shorter ISO
+// 639-1 codes where there is one.
+fn iso_639_1(language: whatlang::Lang) -> &'static str {
+    use whatlang::Lang;
+    match language {
+        Lang::Afr => "af", Lang::Aka => "ak", Lang::Amh => "am", Lang::Ara => "ar",
+        Lang::Aze => "az", Lang::Bel => "be", Lang::Ben => "bn", Lang::Bul => "bg",
+        Lang::Cat => "ca", Lang::Ces => "cs", Lang::Cmn => "zh", Lang::Dan => "da",
+        Lang::Deu => "de", Lang::Ell => "el", Lang::Eng => "en", Lang::Epo => "eo",
+        Lang::Est => "et", Lang::Fin => "fi", Lang::Fra => "fr", Lang::Guj => "gu",
+        Lang::Heb => "he", Lang::Hin => "hi", Lang::Hrv => "hr", Lang::Hun => "hu",
+        Lang::Hye => "hy", Lang::Ind => "id", Lang::Ita => "it", Lang::Jav => "jv",
+        Lang::Jpn => "ja", Lang::Kan => "kn", Lang::Kat => "ka", Lang::Khm => "km",
+        Lang::Kor => "ko", Lang::Lat => "la", Lang::Lav => "lv", Lang::Lit => "lt",
+        Lang::Mal => "ml", Lang::Mar => "mr", Lang::Mkd => "mk", Lang::Mya => "my",
+        Lang::Nep => "ne", Lang::Nld => "nl", Lang::Nob => "nb", Lang::Ori => "or",
+        Lang::Pan => "pa", Lang::Pes => "fa", Lang::Pol => "pl", Lang::Por => "pt",
+        Lang::Ron => "ro", Lang::Rus => "ru", Lang::Sin => "si", Lang::Slk => "sk",
+        Lang::Slv => "sl", Lang::Sna => "sn", Lang::Spa => "es", Lang::Srp => "sr",
+        Lang::Swe => "sv", Lang::Tam => "ta", Lang::Tel => "te", Lang::Tgl => "tl",
+        Lang::Tha => "th", Lang::Tuk => "tk", Lang::Tur => "tr", Lang::Ukr => "uk",
+        Lang::Urd => "ur", Lang::Uzb => "uz", Lang::Vie => "vi", Lang::Yid => "yi",
+        Lang::Zul => "zu",
+    }
+}
+
+/// Detects the language of the text. Returns None unless the detection is reliable, which it
+/// rarely is for a few words.
+pub(crate) fn detect_language(text: &str) -> Option<String> {
+    let end = text.char_indices().nth(MAX_DETECTION_LENGTH).map_or(text.len(), |(index, _)| index);
+    let info = whatlang::detect(&text[..end])?;
+    if info.is_reliable() {
+        Some(iso_639_1(info.lang()).to_string())
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text_stats() {
+        assert_eq!(TextStats::new(""), TextStats {word_count: 0, reading_time: 0});
+        assert_eq!(TextStats::new(" A few words,\nand a - dash. "), TextStats {word_count: 6, reading_time: 1});
+        assert_eq!(TextStats::new(&"word ".repeat(1000)), TextStats {word_count: 1000, reading_time: 5});
+        // 東京 and タワー are counted character by character and "Tower" as a word.
+        assert_eq!(TextStats::new("東京タワー Tower"), TextStats {word_count: 6, reading_time: 1});
+        assert_eq!(TextStats::new("東京タワーはTokyo"), TextStats {word_count: 7, reading_time: 1});
+    }
+
+    #[test]
+    fn test_detect_language() {
+        assert_eq!(detect_language("The hedgehog is a small spiny mammal which is found in Europe, Asia and Africa. It eats insects and sleeps through the winter.").as_deref(),
+            Some("en"));
+        assert_eq!(detect_language("Der Igel ist ein kleines Säugetier mit Stacheln, das in Europa, Asien und Afrika lebt. Er frisst Insekten und hält Winterschlaf.").as_deref(),
+            Some("de"));
+        assert_eq!(detect_language("Ёжик в тумане — советский мультипликационный фильм, снятый режиссёром Юрием Норштейном.").as_deref(),
+            Some("ru"));
+        // Closely related languages like Danish and Norwegian are hard to tell apart.
+        assert_eq!(detect_language("Pindsvinet er et lille pattedyr med pigge, som lever i Europa, Asien og Afrika."), None);
+        assert_eq!(detect_language("Hi"), None);
+        assert_eq!(detect_language(""), None);
+    }
+}
@@ -0,0 +1,244 @@
+// Any user can make the server fetch a url, so without a policy users could reach services which
+// are only meant to be reachable from the server itself, e.g. databases on localhost, admin
+// interfaces on the internal network or the metadata endpoint of the cloud provider at
+// 169.254.169.254. The policy is checked for the url of every request and every redirect, and
+// host names are resolved by the policy itself so the addresses which are checked are the ones
+// which are connected to.
+
+use std::net::{IpAddr,Ipv4Addr,Ipv6Addr,SocketAddr};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use ipnet::IpNet;
+
+/// A host and its subdomains, or a network of ip addresses.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub enum HostPattern {
+    Domain(String),
+    Network(IpNet),
+}
+
+impl HostPattern {
+    fn matches_domain(&self, host: &str) -> bool {
+        match self {
+            HostPattern::Domain(domain) => host.strip_suffix(domain.as_str())
+                .is_some_and(|prefix| prefix.is_empty() || prefix.ends_with('.')),
+            HostPattern::Network(_) => false,
+        }
+    }
+
+    fn matches_address(&self, address: &IpAddr) -> bool {
+        match self {
+            HostPattern::Domain(_) => false,
+            HostPattern::Network(network) => network.contains(address),
+        }
+    }
+}
+
+impl FromStr for HostPattern {
+    type Err = String;
+
+    /// Parses a network like "10.1.0.0/16", an address or a domain like "example.com", which also
+    /// matches its subdomains. A leading "*." is ignored.
+    fn from_str(pattern: &str) -> Result<Self, Self::Err> {
+        let pattern = pattern.trim();
+        if let Ok(network) = pattern.parse::<IpNet>() {
+            return Ok(HostPattern::Network(network.trunc()));
+        }
+        if let Ok(address) = pattern.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+            return Ok(HostPattern::Network(IpNet::from(address)));
+        }
+        let domain = pattern.trim_start_matches("*.").trim_start_matches('.').trim_end_matches('.')
+            .to_lowercase();
+        if domain.is_empty() || domain.contains(|c: char| c.is_whitespace() || c == '/' || c == ':') {
+            return Err(format!("{} is neither a domain nor a network", pattern));
+        }
+        Ok(HostPattern::Domain(domain))
+    }
+}
+
+#[derive(Debug,Clone,Default)]
+pub struct UrlPolicy {
+    // Only meant for development and tests, where the fetched sites run on the same machine.
+    pub allow_private_addresses: bool,
+    // Hosts and networks which can be fetched even though their addresses aren't public, e.g. an
+    // internal wiki.
+    pub allow: Vec<HostPattern>,
+    // Hosts and networks which are never fetched.
+    pub deny: Vec<HostPattern>,
+}
+
+#[derive(Debug,Clone)]
+pub(crate) struct UrlPolicyError(String);
+
+impl std::fmt::Display for UrlPolicyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for UrlPolicyError {}
+
+// Whether the address is reachable on the public internet. Ipv4Addr::is_global is unstable.
+fn is_public(address: &IpAddr) -> bool {
+    match address {
+        IpAddr::V4(address) => is_public_v4(address),
+        IpAddr::V6(address) => match address.to_ipv4_mapped() {
+            Some(address) => is_public_v4(&address),
+            None => is_public_v6(address),
+        },
+    }
+}
+
+fn is_public_v4(address: &Ipv4Addr) -> bool {
+    let [first, second, ..] = address.octets();
+    !(address.is_unspecified() || address.is_loopback() || address.is_private()
+        || address.is_link_local() || address.is_multicast() || address.is_broadcast()
+        || address.is_documentation()
+        // "This network"
+        || first == 0
+        // Shared address space used by carrier-grade NAT
+        || (first == 100 && (64..128).contains(&second))
+        // Reserved
+        || first >= 240)
+}
+
+fn is_public_v6(address: &Ipv6Addr) -> bool {
+    let first = address.segments()[0];
+    !(address.is_unspecified() || address.is_loopback() || address.is_multicast()
+        // Unique local addresses
+        || (first & 0xfe00) == 0xfc00
+        // Link-local addresses
+        || (first & 0xffc0) == 0xfe80
+        // Documentation addresses
+        || (first == 0x2001 && address.segments()[1] == 0xdb8))
+}
+
+impl UrlPolicy {
+    fn allows_domain(&self, host: &str) -> Result<bool, UrlPolicyError> {
+        if self.deny.iter().any(|pattern| pattern.matches_domain(host)) {
+            return Err(UrlPolicyError(format!("Fetching from {} is not allowed", host)));
+        }
+        Ok(self.allow.iter().any(|pattern| pattern.matches_domain(host)))
+    }
+
+    // Checks an address of the host. Addresses of allowed domains are only checked against the
+    // deny list.
+    fn check_address(&self, host: &str, domain_allowed: bool, address: &IpAddr) -> Result<(), UrlPolicyError> {
+        if self.deny.iter().any(|pattern| pattern.matches_address(address)) {
+            return Err(UrlPolicyError(format!("Fetching from {} is not allowed", host)));
+        }
+        if domain_allowed || self.allow_private_addresses || is_public(address)
+                || self.allow.iter().any(|pattern| pattern.matches_address(address)) {
+            Ok(())
+        } else if host == address.to_string() {
+            Err(UrlPolicyError(format!("{} is not a public address", address)))
+        } else {
+            Err(UrlPolicyError(format!("{} resolves to {}, which is not a public address", host, address)))
+        }
+    }
+
+    /// Checks the scheme and host of a url. Host names are checked further when they are
+    /// resolved.
+    pub(crate) fn check_url(&self, url: &url::Url) -> Result<(), UrlPolicyError> {
+        if url.scheme() != "http" && url.scheme() != "https" {
+            return Err(UrlPolicyError(format!("Only http and https urls can be fetched, not {}", url.scheme())));
+        }
+        match url.host() {
+            Some(url::Host::Domain(domain)) => self.allows_domain(domain).map(|_| ()),
+            Some(url::Host::Ipv4(address)) => self.check_address(&address.to_string(), false, &IpAddr::V4(address)),
+            Some(url::Host::Ipv6(address)) => self.check_address(&address.to_string(), false, &IpAddr::V6(address)),
+            None => Err(UrlPolicyError("The url has no host".to_string())),
+        }
+    }
+
+    // Leaves out the addresses of the host which aren't allowed. Fails if none are left.
+    fn filter_addresses(&self, host: &str, addresses: impl Iterator<Item = SocketAddr>) ->
+            Result<Vec<SocketAddr>, UrlPolicyError> {
+        let domain_allowed = self.allows_domain(host)?;
+        let mut allowed = Vec::new();
+        let mut error = None;
+        for address in addresses {
+            match self.check_address(host, domain_allowed, &address.ip()) {
+                Ok(()) => allowed.push(address),
+                Err(address_error) => error = Some(address_error),
+            }
+        }
+        match error {
+            Some(error) if allowed.is_empty() => Err(error),
+            _ => Ok(allowed),
+        }
+    }
+}
+
+/// Resolves host names for reqwest and leaves out the addresses the policy doesn't allow.
+pub(crate) struct PolicyResolver(pub Arc<UrlPolicy>);
+
+impl reqwest::dns::Resolve for PolicyResolver {
+    fn resolve(&self, name: hyper::client::connect::dns::Name) -> reqwest::dns::Resolving {
+        let policy = self.0.clone();
+        Box::pin(async move {
+            let host = name.as_str();
+            // Denied hosts aren't even looked up.
+            policy.allows_domain(host)?;
+            let addresses = tokio::net::lookup_host((host, 0)).await?;
+            let addresses = policy.filter_addresses(host, addresses)?;
+            Ok(Box::new(addresses.into_iter()) as reqwest::dns::Addrs)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(policy: &UrlPolicy, url: &str) -> Result<(), UrlPolicyError> {
+        policy.check_url(&url::Url::parse(url).unwrap())
+    }
+
+    #[test]
+    fn test_check_url() {
+        let policy = UrlPolicy::default();
+        assert!(check(&policy, "https://example.com/article").is_ok());
+        assert!(check(&policy, "http://93.184.216.34/").is_ok());
+        for url in ["file:///etc/passwd", "ftp://example.com/", "http://127.0.0.1:5432/",
+                "http://169.254.169.254/latest/meta-data/", "http://10.0.0.1/", "http://192.168.1.1/",
+                "http://100.64.0.1/", "http://224.0.0.1/", "http://0.0.0.0/", "http://[::1]/",
+                "http://[fe80::1]/", "http://[fd00::1]/", "http://[::ffff:127.0.0.1]/"] {
+            assert!(check(&policy, url).is_err(), "{}", url);
+        }
+
+        let policy = UrlPolicy {
+            allow: vec!["10.1.0.0/16".parse().unwrap()],
+            deny: vec!["*.example.com".parse().unwrap(), "93.184.216.34".parse().unwrap()],
+            ..UrlPolicy::default()
+        };
+        assert!(check(&policy, "http://10.1.2.3/").is_ok());
+        assert!(check(&policy, "http://10.2.0.1/").is_err());
+        assert!(check(&policy, "https://example.com/").is_err());
+        assert!(check(&policy, "https://www.example.com/").is_err());
+        assert!(check(&policy, "https://notexample.com/").is_ok());
+        assert!(check(&policy, "http://93.184.216.34/").is_err());
+
+        let policy = UrlPolicy {allow_private_addresses: true, ..UrlPolicy::default()};
+        assert!(check(&policy, "http://127.0.0.1:8080/").is_ok());
+        assert!(check(&policy, "file:///etc/passwd").is_err());
+    }
+
+    #[test]
+    fn test_filter_addresses() {
+        let addresses = || ["127.0.0.1:0", "[::1]:0", "93.184.216.34:0"].into_iter()
+            .map(|address| address.parse::<SocketAddr>().unwrap());
+        let policy = UrlPolicy::default();
+        assert_eq!(policy.filter_addresses("example.com", addresses()).unwrap(),
+            vec!["93.184.216.34:0".parse().unwrap()]);
+        assert_eq!(policy.filter_addresses("localhost", addresses().take(2)).unwrap_err().to_string(),
+            "localhost resolves to ::1, which is not a public address");
+        let policy = UrlPolicy {allow: vec!["localhost".parse().unwrap()], ..UrlPolicy::default()};
+        assert_eq!(policy.filter_addresses("localhost", addresses()).unwrap().len(), 3);
+        let policy = UrlPolicy {allow: vec!["127.0.0.0/8".parse().unwrap()], ..UrlPolicy::default()};
+        assert_eq!(policy.filter_addresses("localhost", addresses()).unwrap().len(), 2);
+        let policy = UrlPolicy {deny: vec!["93.184.216.0/24".parse().unwrap()], ..UrlPolicy::default()};
+        assert!(policy.filter_addresses("example.com", addresses().skip(2)).is_err());
+    }
+}
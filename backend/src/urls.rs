@@ -0,0 +1,208 @@
+// Links and images in a saved page are shown from our own frontend so relative urls have to be
+// made absolute while the location of the original page is still known.
+
+use kuchiki::NodeRef;
+use kuchiki::iter::NodeIterator;
+use url::Url;
+
+const URL_ATTRIBUTES: [&str; 4] = ["href", "src", "cite", "poster"];
+// Query parameters added by newsletters, social networks and ad platforms to track where a
+// visitor came from. Parameters starting with utm_ are removed as well.
+const TRACKING_PARAMETERS: [&str; 12] = ["fbclid", "gclid", "dclid", "gbraid", "wbraid", "msclkid",
+    "mc_cid", "mc_eid", "igshid", "yclid", "_hsenc", "_hsmi"];
+
+/// Returns the url which relative urls in the document should be resolved against. That is the
+/// url of the page itself unless the document has a <base href> element.
+/// https://html.spec.whatwg.org/multipage/urls-and-fetching.html#document-base-url
+pub fn base_url(document: &NodeRef, page_url: &str) -> Option<Url> {
+    let page_url = Url::parse(page_url).ok()?;
+    let base_href = document.select_first("base[href]").ok().and_then(|base| {
+        base.attributes.borrow().get("href").map(|href| href.to_string())
+    });
+    match base_href {
+        Some(href) => Some(page_url.join(&href).unwrap_or(page_url)),
+        None => Some(page_url),
+    }
+}
+
+/// Resolves a possibly relative url against the base url. Links to fragments within the page
+/// itself and urls which cannot be parsed are left as they are.
+pub fn resolve_url(base: &Url, url: &str) -> String {
+    let url = url.trim();
+    if url.is_empty() || url.starts_with('#') {
+        return url.to_string();
+    }
+    base.join(url).map_or_else(|_| url.to_string(), |resolved| resolved.to_string())
+}
+
+/// Resolves every image candidate in a srcset attribute. A candidate is a url optionally followed
+/// by a width or density descriptor and the candidates are separated by commas.
+/// https://html.spec.whatwg.org/multipage/images.html#srcset-attribute
+pub fn resolve_srcset(base: &Url, srcset: &str) -> String {
+    let mut candidates = Vec::new();
+    let mut rest = srcset;
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_ascii_whitespace() || c == ',');
+        if rest.is_empty() {
+            break;
+        }
+        let url_end = rest.find(|c: char| c.is_ascii_whitespace()).unwrap_or(rest.len());
+        let (url, remaining) = rest.split_at(url_end);
+        // A url directly followed by a comma has no descriptor. The comma isn't part of the url.
+        let (url, descriptor, remaining) = if url.ends_with(',') {
+            (url.trim_end_matches(','), "", remaining)
+        } else {
+            let descriptor_end = remaining.find(',').unwrap_or(remaining.len());
+            let (descriptor, remaining) = remaining.split_at(descriptor_end);
+            (url, descriptor.trim(), remaining)
+        };
+        let resolved = resolve_url(base, url);
+        if descriptor.is_empty() {
+            candidates.push(resolved);
+        } else {
+            candidates.push(format!("{} {}", resolved, descriptor));
+        }
+        rest = remaining;
+    }
+    candidates.join(", ")
+}
+
+/// Rewrites all relative urls in the document to absolute urls.
+pub fn resolve_urls(document: &NodeRef, base: &Url) {
+    for element in document.descendants().elements() {
+        let mut attributes = element.attributes.borrow_mut();
+        for name in URL_ATTRIBUTES {
+            if let Some(value) = attributes.get(name).map(|value| resolve_url(base, value)) {
+                attributes.insert(name, value);
+            }
+        }
+        if let Some(value) = attributes.get("srcset").map(|value| resolve_srcset(base, value)) {
+            attributes.insert("srcset", value);
+        }
+    }
+}
+
+fn is_tracking_parameter(parameter: &str) -> bool {
+    let name = parameter.split('=').next().unwrap_or_default().to_lowercase();
+    name.starts_with("utm_") || TRACKING_PARAMETERS.contains(&name.as_str())
+}
+
+/// Returns the url in a form which is the same for the different urls people use to link to a
+/// page. The fragment, tracking parameters, the default port and trailing slashes in the path are
+/// removed. The remaining query parameters are kept as they are since their order and encoding
+/// can matter to the server. Urls which aren't http or https urls are only trimmed.
+pub fn canonicalize_url(url: &str) -> String {
+    let url = url.trim();
+    let mut parsed = match Url::parse(url) {
+        Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => parsed,
+        _ => return url.to_string(),
+    };
+    parsed.set_fragment(None);
+    let query = parsed.query().map(|query| {
+        query.split('&')
+            .filter(|parameter| !parameter.is_empty() && !is_tracking_parameter(parameter))
+            .collect::<Vec<_>>()
+            .join("&")
+    });
+    parsed.set_query(query.as_deref().filter(|query| !query.is_empty()));
+    let path = parsed.path().trim_end_matches('/').to_string();
+    parsed.set_path(if path.is_empty() { "/" } else { &path });
+    parsed.to_string()
+}
+
+/// Whether the whole text matches the pattern where * matches any sequence of characters. Used
+/// for url patterns and the paths in robots.txt.
+pub(crate) fn glob_matches(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(position) => rest = &rest[position + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use html5ever::tendril::TendrilSink;
+
+    fn url(url: &str) -> Url {
+        Url::parse(url).expect("Unable to parse url")
+    }
+
+    #[test]
+    fn test_base_url() {
+        let document = kuchiki::parse_html().one("<p>text</p>");
+        assert_eq!(base_url(&document, "https://example.com/a/b.html"),
+            Some(url("https://example.com/a/b.html")));
+    }
+
+    #[test]
+    fn test_base_url_base_element() {
+        let document = kuchiki::parse_html().one("<head><base href=\"/static/\"></head><p>text</p>");
+        assert_eq!(base_url(&document, "https://example.com/a/b.html"),
+            Some(url("https://example.com/static/")));
+    }
+
+    #[test]
+    fn test_base_url_invalid_page_url() {
+        let document = kuchiki::parse_html().one("<p>text</p>");
+        assert_eq!(base_url(&document, "not a url"), None);
+    }
+
+    #[test]
+    fn test_resolve_url() {
+        let base = url("https://example.com/a/b.html");
+        assert_eq!(resolve_url(&base, "image.png"), "https://example.com/a/image.png");
+        assert_eq!(resolve_url(&base, "../image.png"), "https://example.com/image.png");
+        assert_eq!(resolve_url(&base, "/image.png"), "https://example.com/image.png");
+        assert_eq!(resolve_url(&base, "//cdn.example.com/image.png"), "https://cdn.example.com/image.png");
+        assert_eq!(resolve_url(&base, "https://other.example.com/"), "https://other.example.com/");
+        assert_eq!(resolve_url(&base, "#section"), "#section");
+        assert_eq!(resolve_url(&base, "mailto:user@example.com"), "mailto:user@example.com");
+    }
+
+    #[test]
+    fn test_resolve_srcset() {
+        let base = url("https://example.com/a/b.html");
+        assert_eq!(resolve_srcset(&base, "small.png 480w, /large.png 1080w"),
+            "https://example.com/a/small.png 480w, https://example.com/large.png 1080w");
+        assert_eq!(resolve_srcset(&base, "a.png, b.png 2x"),
+            "https://example.com/a/a.png, https://example.com/a/b.png 2x");
+        assert_eq!(resolve_srcset(&base, " a.png 1x ,\n b.png 2x "),
+            "https://example.com/a/a.png 1x, https://example.com/a/b.png 2x");
+    }
+
+    #[test]
+    fn test_canonicalize_url() {
+        assert_eq!(canonicalize_url("https://example.com:443/a/?utm_source=feed&id=1&fbclid=x#top"),
+            "https://example.com/a?id=1");
+        assert_eq!(canonicalize_url(" HTTP://Example.com:80// "), "http://example.com/");
+        assert_eq!(canonicalize_url("https://example.com:8080/a//?UTM_MEDIUM=x&&"),
+            "https://example.com:8080/a");
+        assert_eq!(canonicalize_url("https://example.com/?b=2&a=%20+1"), "https://example.com/?b=2&a=%20+1");
+        assert_eq!(canonicalize_url("mailto:user@example.com"), "mailto:user@example.com");
+        assert_eq!(canonicalize_url("not a url"), "not a url");
+    }
+
+    #[test]
+    fn test_glob_matches() {
+        assert!(glob_matches("example.com/*", "example.com/a/b"));
+        assert!(glob_matches("*.example.com/*/edit", "www.example.com/articles/1/edit"));
+        assert!(!glob_matches("*.example.com/*/edit", "www.example.com/articles/1/edit/more"));
+        assert!(glob_matches("/page", "/page"));
+        assert!(!glob_matches("/page", "/pages"));
+        assert!(!glob_matches("a*a", "a"));
+    }
+}
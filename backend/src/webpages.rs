@@ -1,104 +1,308 @@
 use std::sync::Arc;
 
-use crate::errors;
+use crate::{assets,errors,render};
+use crate::highlights::{self,Highlight};
+use crate::snapshots::{self,SnapshotInfo,SnapshotSelector};
+use crate::tags;
+use crate::metadata::Metadata;
 
 use serde::Deserialize;
 use serde::Serialize;
-use sqlx::PgPool;
-use warp::http::StatusCode;
+use sqlx::{PgPool,Row};
+use sqlx::postgres::PgRow;
+use warp::http::{header,Response,StatusCode};
 
 #[derive(Deserialize,Serialize,Debug,PartialEq,Eq)]
 pub struct ShowWebpageResponse {
     title: String,
     image_url: Option<String>,
     content: String,
+    #[serde(flatten)]
+    metadata: Metadata,
+    tags: Vec<String>,
+    #[serde(flatten)]
+    state: ReadingState,
+    highlights: Vec<Highlight>,
+    // The snapshot shown instead of the version saved first.
+    snapshot: Option<SnapshotInfo>,
+}
+
+/// Whether a webpage has been read, archived or starred.
+#[derive(Deserialize,Serialize,Debug,Default,Clone,PartialEq,Eq)]
+pub struct ReadingState {
+    pub read_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub archived: bool,
+    pub starred: bool,
 }
 
 impl ShowWebpageResponse {
     pub fn new(title: String, image_url: Option<String>, content: String) -> Self {
-        ShowWebpageResponse {title, image_url, content}
+        ShowWebpageResponse {title, image_url, content, metadata: Metadata::default(), tags: Vec::new(),
+            state: ReadingState::default(), highlights: Vec::new(), snapshot: None}
+    }
+
+    pub fn with_metadata(self, metadata: Metadata) -> Self {
+        ShowWebpageResponse {metadata, ..self}
+    }
+
+    pub fn with_tags(self, tags: Vec<String>) -> Self {
+        ShowWebpageResponse {tags, ..self}
+    }
+
+    pub fn with_state(self, state: ReadingState) -> Self {
+        ShowWebpageResponse {state, ..self}
+    }
+
+    pub fn with_highlights(self, highlights: Vec<Highlight>) -> Self {
+        ShowWebpageResponse {highlights, ..self}
+    }
+
+    pub fn with_snapshot(self, snapshot: Option<SnapshotInfo>) -> Self {
+        ShowWebpageResponse {snapshot, ..self}
     }
 }
 
 #[derive(Serialize,Debug)]
 struct ListWebpagesResponse {
     webpage_infos: Vec<WebpageInfo>,
+    // The number of webpages matching the filters on all pages.
+    total: i64,
+    next_cursor: Option<String>,
 }
 
 #[derive(Serialize,Debug)]
-struct WebpageInfo {
-    id: i64,
-    title: String,
-    image_url: Option<String>,
+pub(crate) struct WebpageInfo {
+    pub id: i64,
+    pub url: String,
+    pub title: String,
+    pub image_url: Option<String>,
+    pub added: chrono::DateTime<chrono::Utc>,
+    #[serde(flatten)]
+    pub metadata: Metadata,
+    pub tags: Vec<String>,
+    #[serde(flatten)]
+    pub state: ReadingState,
+    pub word_count: i32,
+    // The estimated reading time in minutes.
+    pub reading_time: i32,
+    pub excerpt: Option<String>,
 }
 
+pub(crate) const METADATA_COLUMNS: &str = "description, site_name, author, published_time, canonical_url, language";
+pub(crate) const TAGS_COLUMN: &str = "array(select tags.tag from tags join tags_to_webpages on tags_to_webpages.tag_id = tags.id where tags_to_webpages.webpage_id = webpages.id order by tags.tag)";
+
 // The names of these enum variants should appear exactly as they are meant to be typed in as query
 // parameters by the user.
 #[allow(non_camel_case_types)]
 #[derive(Deserialize,Debug)]
-enum ShowMode {
+pub(crate) enum ShowMode {
     readable,
     original,
+    // The readable content as plain text or as Markdown.
+    text,
+    markdown,
 }
 
 #[derive(Deserialize,Debug)]
 pub struct ShowOptions {
     mode: Option<ShowMode>,
+    // Marks the webpage as read unless it has been read already.
+    mark_read: Option<bool>,
+    // Shows a snapshot by its id or the newest version fetched at or before a time instead.
+    snapshot: Option<i64>,
+    at: Option<chrono::DateTime<chrono::Utc>>,
 }
 
-fn showmode_to_db_table(mode: &ShowMode) -> &str {
-    match mode {
-        ShowMode::readable => "text",
-        ShowMode::original => "html",
+impl ShowMode {
+    // The column the content is rendered from. Everything but the original html is rendered from
+    // the readable content.
+    pub(crate) fn column(&self) -> &'static str {
+        match self {
+            ShowMode::original => "html",
+            ShowMode::readable | ShowMode::text | ShowMode::markdown => "text",
+        }
     }
+
+    fn format(&self) -> Option<render::Format> {
+        match self {
+            ShowMode::readable | ShowMode::original => None,
+            ShowMode::text => Some(render::Format::Text),
+            ShowMode::markdown => Some(render::Format::Markdown),
+        }
+    }
+
+    /// Renders the content stored in the column of the mode.
+    pub(crate) fn render(&self, content: String) -> String {
+        match self.format() {
+            Some(format) => render::render(&content, format),
+            None => content,
+        }
+    }
+
+    /// The Content-Type and the whole document with the title of webpages shown in a mode which
+    /// is sent as is instead of wrapped in json.
+    pub(crate) fn document(&self, webpage: &StoredWebpage) -> Option<(&'static str, String)> {
+        let format = self.format()?;
+        let content_type = match format {
+            render::Format::Text => "text/plain; charset=utf-8",
+            render::Format::Markdown => "text/markdown; charset=utf-8",
+        };
+        Some((content_type, format!("{}{}", render::title(&webpage.title, format), webpage.content)))
+    }
+}
+
+/// A webpage as stored in the database with the content shown in a specific mode.
+#[derive(Debug)]
+pub(crate) struct StoredWebpage {
+    pub url: String,
+    pub title: String,
+    pub image_url: Option<String>,
+    pub content: String,
+    pub added: chrono::DateTime<chrono::Utc>,
+    pub metadata: Metadata,
+    pub tags: Vec<String>,
+    pub state: ReadingState,
+    pub highlights: Vec<Highlight>,
+    pub snapshot: Option<SnapshotInfo>,
+}
+
+fn metadata_from_row(row: &PgRow) -> Result<Metadata, sqlx::Error> {
+    Ok(Metadata {
+        description: row.try_get("description")?,
+        site_name: row.try_get("site_name")?,
+        author: row.try_get("author")?,
+        published_time: row.try_get("published_time")?,
+        canonical_url: row.try_get("canonical_url")?,
+        language: row.try_get("language")?,
+    })
+}
+
+fn reading_state_from_row(row: &PgRow) -> Result<ReadingState, sqlx::Error> {
+    Ok(ReadingState {
+        read_at: row.try_get("read_at")?,
+        archived: row.try_get("archived")?,
+        starred: row.try_get("starred")?,
+    })
+}
+
+/// Gets a webpage if it belongs to the user. Every handler showing a single webpage should get it
+/// from here so users can never see each other's webpages.
+pub(crate) async fn get_stored_webpage(db_pool: &PgPool, webpage_id: i64, user_id: i64, mode: &ShowMode) ->
+        Result<Option<StoredWebpage>, sqlx::Error> {
+    let row = sqlx::query(&format!("SELECT url, title, image_url, {} AS content, added, {}, {} AS tags, read_at, archived, starred FROM webpages WHERE id = $1 AND user_id = $2",
+            mode.column(), METADATA_COLUMNS, TAGS_COLUMN))
+        .bind(webpage_id)
+        .bind(user_id)
+        .fetch_optional(db_pool).await?;
+    let row = match row {
+        Some(row) => row,
+        None => return Ok(None),
+    };
+    Ok(Some(StoredWebpage {
+        url: row.try_get("url")?,
+        title: row.try_get("title")?,
+        image_url: row.try_get("image_url")?,
+        content: mode.render(row.try_get("content")?),
+        added: row.try_get("added")?,
+        metadata: metadata_from_row(&row)?,
+        tags: row.try_get("tags")?,
+        state: reading_state_from_row(&row)?,
+        highlights: highlights::webpage_highlights(db_pool, webpage_id).await?,
+        snapshot: None,
+    }))
+}
+
+async fn show_webpage(db_pool: &PgPool, webpage_id: i64, user_id: i64, mode: &ShowMode, mark_read: bool,
+        selector: &SnapshotSelector) -> Result<Option<StoredWebpage>, sqlx::Error> {
+    if mark_read {
+        sqlx::query("UPDATE webpages SET read_at = now() WHERE id = $1 AND user_id = $2 AND read_at IS NULL")
+            .bind(webpage_id)
+            .bind(user_id)
+            .execute(db_pool).await?;
+    }
+    let mut webpage = match get_stored_webpage(db_pool, webpage_id, user_id, mode).await? {
+        Some(webpage) => webpage,
+        None => return Ok(None),
+    };
+    match snapshots::get_snapshot(db_pool, webpage_id, selector, mode.column()).await? {
+        Some(snapshot) => {
+            webpage.title = snapshot.info.title.clone();
+            webpage.image_url = snapshot.image_url;
+            webpage.content = mode.render(snapshot.content);
+            webpage.snapshot = Some(snapshot.info);
+        },
+        // Asking for a snapshot which doesn't exist is the same as asking for a webpage which
+        // doesn't exist.
+        None if selector.snapshot.is_some() => return Ok(None),
+        None => (),
+    }
+    Ok(Some(webpage))
 }
 
 pub async fn show_stored_webpage_handler(webpage_id: i64, query_params: ShowOptions, db_pool: Arc<PgPool>, user_id: i64) ->
         Result<impl warp::Reply, warp::Rejection> {
     let mode = &query_params.mode.unwrap_or(ShowMode::readable);
-    let table = showmode_to_db_table(mode);
-    let (response, status_code) = sqlx::query_as::<_, (String,Option<String>,String)>(&format!("SELECT title, image_url, {} FROM webpages WHERE id = $1 AND user_id = $2", table))
-        .bind(webpage_id)
-        .bind(user_id)
-        .fetch_optional(&*db_pool).await
-        .map_or_else(|error| {
+    let selector = SnapshotSelector {snapshot: query_params.snapshot, at: query_params.at};
+    let webpage = match show_webpage(&db_pool, webpage_id, user_id, mode,
+            query_params.mark_read.unwrap_or(false), &selector).await {
+        Ok(Some(webpage)) => webpage,
+        Ok(None) => return Ok(errors::error_response(StatusCode::NOT_FOUND, "Webpage not found")),
+        Err(error) => {
             log::error!("Error when fetching webpage {}  for user {} from database {}",
                 webpage_id, user_id, error);
-            let status = StatusCode::INTERNAL_SERVER_ERROR;
-            let json = warp::reply::json(&errors::ErrorResponse {
-                message: "Unknown error".to_string(),
-                status: status.to_string(),
-            });
-            (json, status)
-        }, |optional_webpage| {
-            match optional_webpage {
-                Some((title, image_url, text)) => {
-                    let json = warp::reply::json(&ShowWebpageResponse {
-                        title,
-                        image_url,
-                        content: text
-                    });
-                    (json, StatusCode::OK)
-                },
-                None => {
-                    let status = StatusCode::NOT_FOUND;
-                    let json = warp::reply::json(&errors::ErrorResponse {
-                        message: "Webpage not found".to_string(),
-                        status: status.to_string()
-                    });
-                    (json, status)
-                }
-            }
-        });
-    Ok(warp::reply::with_status(response, status_code))
+            return Ok(errors::error_response(StatusCode::INTERNAL_SERVER_ERROR, "Unknown error"));
+        }
+    };
+    // Plain text and Markdown are sent as they are so they can be piped into other programs.
+    let (content_type, body) = match mode.document(&webpage) {
+        Some((content_type, document)) => (content_type, document.into_bytes()),
+        None => ("application/json", serde_json::to_vec(&ShowWebpageResponse {
+            title: webpage.title,
+            image_url: webpage.image_url,
+            content: webpage.content,
+            metadata: webpage.metadata,
+            tags: webpage.tags,
+            state: webpage.state,
+            highlights: webpage.highlights,
+            snapshot: webpage.snapshot,
+        }).unwrap_or_default()),
+    };
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, content_type)
+        .body(body))
+}
+
+// The tags and the archived assets of the webpage and its snapshots are removed along with it if
+// no other webpage uses them.
+async fn delete_stored_webpage(db_pool: &PgPool, webpage_id: i64, user_id: i64) -> Result<(), sqlx::Error> {
+    let mut transaction = db_pool.begin().await?;
+    let asset_ids: Vec<i64> = sqlx::query_as::<_, (i64,)>("SELECT DISTINCT assets_to_webpages.asset_id FROM assets_to_webpages JOIN webpages ON webpages.id = assets_to_webpages.webpage_id WHERE webpages.id = $1 AND webpages.user_id = $2")
+        .bind(webpage_id)
+        .bind(user_id)
+        .fetch_all(&mut transaction).await?
+        .into_iter()
+        .map(|(asset_id,)| asset_id)
+        .collect();
+    let tags: Vec<String> = sqlx::query_as::<_, (String,)>("SELECT tags.tag FROM tags JOIN tags_to_webpages ON tags_to_webpages.tag_id = tags.id WHERE tags_to_webpages.webpage_id = $1 AND tags.user_id = $2")
+        .bind(webpage_id)
+        .bind(user_id)
+        .fetch_all(&mut transaction).await?
+        .into_iter()
+        .map(|(tag,)| tag)
+        .collect();
+    sqlx::query("DELETE FROM webpages WHERE id = $1 AND user_id = $2")
+        .bind(webpage_id)
+        .bind(user_id)
+        .execute(&mut transaction).await?;
+    tags::remove_unused_tags(&mut transaction, user_id, &tags).await?;
+    assets::remove_unused_assets(&mut transaction, &asset_ids).await?;
+    transaction.commit().await
 }
 
 pub async fn delete_stored_webpage_handler(webpage_id: i64, db_pool: Arc<PgPool>, user_id: i64) ->
         Result<impl warp::Reply, warp::Rejection> {
-    match sqlx::query("DELETE FROM webpages WHERE id = $1 AND user_id = $2")
-            .bind(webpage_id)
-            .bind(user_id)
-            .execute(&*db_pool).await {
+    match delete_stored_webpage(&db_pool, webpage_id, user_id).await {
         Ok(_) => Ok(StatusCode::NO_CONTENT),
         Err(error) => {
             log::error!("Error when deleting webpage id {} for user {}: {}", webpage_id, user_id, error);
@@ -107,32 +311,320 @@ pub async fn delete_stored_webpage_handler(webpage_id: i64, db_pool: Arc<PgPool>
     }
 }
 
-pub async fn get_stored_webpages_for_user(db_pool: Arc<PgPool>, user_id: i64) ->
-        Result<impl warp::Reply, warp::Rejection> {
-    let (response, status_code) = sqlx::query_as::<_, (i64,String,Option<String>)>("SELECT id, title, image_url FROM webpages WHERE user_id = $1")
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 200;
+
+#[allow(non_camel_case_types)]
+#[derive(Deserialize,Serialize,Debug,Clone,Copy,PartialEq,Eq)]
+pub enum SortBy {
+    added,
+    title,
+    domain,
+    word_count,
+    reading_time,
+    language,
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Deserialize,Serialize,Debug,Clone,Copy,PartialEq,Eq)]
+pub enum SortOrder {
+    asc,
+    desc,
+}
+
+impl SortBy {
+    // The expression to sort by and the type it is cast to when it is read back from a cursor.
+    fn expression(&self) -> (&'static str, &'static str) {
+        match self {
+            SortBy::added => ("added", "timestamptz"),
+            SortBy::title => ("lower(title)", "text"),
+            SortBy::domain => ("coalesce(domain, '')", "text"),
+            SortBy::word_count => ("word_count", "integer"),
+            SortBy::reading_time => ("reading_time", "integer"),
+            SortBy::language => ("coalesce(lower(language), '')", "text"),
+        }
+    }
+
+    fn default_order(&self) -> SortOrder {
+        match self {
+            SortBy::added => SortOrder::desc,
+            SortBy::title | SortBy::domain | SortBy::word_count | SortBy::reading_time | SortBy::language =>
+                SortOrder::asc,
+        }
+    }
+}
+
+/// Points at the last webpage of a page in the list. The next page starts right after it in the
+/// sort order so webpages which are added or deleted in the meantime don't shift the pages.
+#[derive(Deserialize,Serialize,Debug,PartialEq,Eq)]
+struct Cursor {
+    sort: SortBy,
+    order: SortOrder,
+    // The value of the sort expression as text.
+    key: String,
+    id: i64,
+}
+
+impl Cursor {
+    fn encode(&self) -> String {
+        let json = serde_json::to_vec(self).expect("A cursor can always be serialized");
+        base64::encode_config(json, base64::URL_SAFE_NO_PAD)
+    }
+
+    fn decode(cursor: &str) -> Option<Cursor> {
+        let json = base64::decode_config(cursor, base64::URL_SAFE_NO_PAD).ok()?;
+        serde_json::from_slice(&json).ok()
+    }
+}
+
+/// The parts of the reading state which can be turned on and off, e.g. with
+/// PUT /api/webpage/{id}/starred and DELETE /api/webpage/{id}/starred.
+#[allow(non_camel_case_types)]
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum StateFlag {
+    read,
+    archived,
+    starred,
+}
+
+impl std::str::FromStr for StateFlag {
+    type Err = String;
+
+    fn from_str(flag: &str) -> Result<Self, Self::Err> {
+        match flag {
+            "read" => Ok(StateFlag::read),
+            "archived" => Ok(StateFlag::archived),
+            "starred" => Ok(StateFlag::starred),
+            _ => Err(format!("Unknown state {}", flag)),
+        }
+    }
+}
+
+impl StateFlag {
+    fn assignment(&self) -> &'static str {
+        match self {
+            // Marking a webpage as read again keeps the time it was first read.
+            StateFlag::read => "read_at = CASE WHEN $3 THEN coalesce(read_at, now()) END",
+            StateFlag::archived => "archived = $3",
+            StateFlag::starred => "starred = $3",
+        }
+    }
+}
+
+async fn set_state(db_pool: &PgPool, webpage_id: i64, user_id: i64, flag: StateFlag, value: bool) ->
+        Result<Option<ReadingState>, sqlx::Error> {
+    let row = sqlx::query(&format!("UPDATE webpages SET {} WHERE id = $1 AND user_id = $2 RETURNING read_at, archived, starred",
+            flag.assignment()))
+        .bind(webpage_id)
         .bind(user_id)
-        .fetch_all(&*db_pool).await
-        .map_or_else(|error| {
+        .bind(value)
+        .fetch_optional(db_pool).await?;
+    row.map(|row| reading_state_from_row(&row)).transpose()
+}
+
+async fn set_state_handler(webpage_id: i64, flag: StateFlag, value: bool, db_pool: Arc<PgPool>,
+        user_id: i64) -> Result<impl warp::Reply, warp::Rejection> {
+    Ok(match set_state(&db_pool, webpage_id, user_id, flag, value).await {
+        Ok(Some(state)) => warp::reply::with_status(warp::reply::json(&state), StatusCode::OK),
+        Ok(None) => errors::error_reply(StatusCode::NOT_FOUND, "Webpage not found"),
+        Err(error) => {
+            log::error!("Error when setting {:?} to {} for webpage {} for user {}: {}",
+                flag, value, webpage_id, user_id, error);
+            errors::error_reply(StatusCode::INTERNAL_SERVER_ERROR, "Unknown error")
+        },
+    })
+}
+
+pub async fn add_state_handler(webpage_id: i64, flag: StateFlag, db_pool: Arc<PgPool>, user_id: i64) ->
+        Result<impl warp::Reply, warp::Rejection> {
+    set_state_handler(webpage_id, flag, true, db_pool, user_id).await
+}
+
+pub async fn remove_state_handler(webpage_id: i64, flag: StateFlag, db_pool: Arc<PgPool>, user_id: i64) ->
+        Result<impl warp::Reply, warp::Rejection> {
+    set_state_handler(webpage_id, flag, false, db_pool, user_id).await
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Deserialize,Debug,Clone,Copy,PartialEq,Eq)]
+pub enum ListView {
+    // Unread webpages which haven't been archived.
+    inbox,
+    archive,
+    starred,
+    all,
+}
+
+impl ListView {
+    fn condition(&self) -> &'static str {
+        match self {
+            ListView::inbox => "read_at IS NULL AND NOT archived",
+            ListView::archive => "archived",
+            ListView::starred => "starred",
+            ListView::all => "true",
+        }
+    }
+}
+
+#[derive(Deserialize,Debug)]
+pub struct ListOptions {
+    // Defaults to the inbox.
+    view: Option<ListView>,
+    // A comma separated list of tags. Only webpages with all of the tags are listed.
+    tags: Option<String>,
+    // The sort order is taken from the cursor when one is given.
+    sort: Option<SortBy>,
+    order: Option<SortOrder>,
+    added_after: Option<chrono::DateTime<chrono::Utc>>,
+    added_before: Option<chrono::DateTime<chrono::Utc>>,
+    // Webpages from subdomains of the domain are included as well.
+    domain: Option<String>,
+    read: Option<bool>,
+    // Webpages in regional variants of the language are included as well, e.g. en-GB for en.
+    language: Option<String>,
+    min_word_count: Option<i32>,
+    max_word_count: Option<i32>,
+    // In minutes.
+    min_reading_time: Option<i32>,
+    max_reading_time: Option<i32>,
+    limit: Option<i64>,
+    cursor: Option<String>,
+}
+
+/// The columns needed by WebpageInfo::from_row.
+pub(crate) fn webpage_info_columns() -> String {
+    format!("id, url, title, image_url, added, {}, {} AS tags, read_at, archived, starred, word_count, reading_time, excerpt", METADATA_COLUMNS, TAGS_COLUMN)
+}
+
+impl WebpageInfo {
+    pub(crate) fn from_row(row: &PgRow) -> Result<Self, sqlx::Error> {
+        Ok(WebpageInfo {
+            id: row.try_get("id")?,
+            url: row.try_get("url")?,
+            title: row.try_get("title")?,
+            image_url: row.try_get("image_url")?,
+            added: row.try_get("added")?,
+            metadata: metadata_from_row(row)?,
+            tags: row.try_get("tags")?,
+            state: reading_state_from_row(row)?,
+            word_count: row.try_get("word_count")?,
+            reading_time: row.try_get("reading_time")?,
+            excerpt: row.try_get("excerpt")?,
+        })
+    }
+}
+
+fn normalize_domain(domain: &str) -> String {
+    let domain = domain.trim().to_lowercase();
+    domain.strip_prefix("www.").map(|domain| domain.to_string()).unwrap_or(domain)
+}
+
+fn normalize_language(language: &str) -> String {
+    language.trim().to_lowercase().replace('_', "-")
+}
+
+// Every filter is bound whether it is used or not so the same parameters can be bound for the
+// list and for the total count.
+const LIST_FILTERS: &str = "user_id = $1 AND (cardinality($2::text[]) = 0 OR id IN (SELECT tags_to_webpages.webpage_id FROM tags_to_webpages JOIN tags ON tags.id = tags_to_webpages.tag_id WHERE tags.user_id = $1 AND tags.tag = ANY($2) GROUP BY tags_to_webpages.webpage_id HAVING count(*) = cardinality($2::text[]))) AND ($3::timestamptz IS NULL OR added >= $3) AND ($4::timestamptz IS NULL OR added < $4) AND ($5::text IS NULL OR domain = $5 OR right(domain, length($5) + 1) = '.' || $5) AND ($6::boolean IS NULL OR (read_at IS NOT NULL) = $6) AND ($7::text IS NULL OR lower(language) = $7 OR left(lower(language), length($7) + 1) = $7 || '-') AND ($8::integer IS NULL OR word_count >= $8) AND ($9::integer IS NULL OR word_count <= $9) AND ($10::integer IS NULL OR reading_time >= $10) AND ($11::integer IS NULL OR reading_time <= $11)";
+
+fn bind_list_filters<'q>(query: sqlx::query::Query<'q, sqlx::Postgres, sqlx::postgres::PgArguments>,
+        options: &'q ListOptions, user_id: i64, tags: &'q [String], domain: Option<String>,
+        language: Option<String>) ->
+        sqlx::query::Query<'q, sqlx::Postgres, sqlx::postgres::PgArguments> {
+    query.bind(user_id)
+        .bind(tags)
+        .bind(options.added_after)
+        .bind(options.added_before)
+        .bind(domain)
+        .bind(options.read)
+        .bind(language)
+        .bind(options.min_word_count)
+        .bind(options.max_word_count)
+        .bind(options.min_reading_time)
+        .bind(options.max_reading_time)
+}
+
+async fn list_webpages(db_pool: &PgPool, user_id: i64, options: &ListOptions, cursor: Option<Cursor>) ->
+        Result<ListWebpagesResponse, sqlx::Error> {
+    let tags = options.tags.as_deref().map(tags::parse_tags).unwrap_or_default();
+    let domain = options.domain.as_deref().map(normalize_domain);
+    let language = options.language.as_deref().map(normalize_language);
+    let (sort, order) = match &cursor {
+        Some(cursor) => (cursor.sort, cursor.order),
+        None => {
+            let sort = options.sort.unwrap_or(SortBy::added);
+            (sort, options.order.unwrap_or_else(|| sort.default_order()))
+        },
+    };
+    let (expression, cast) = sort.expression();
+    let (direction, comparison) = match order {
+        SortOrder::asc => ("ASC", ">"),
+        SortOrder::desc => ("DESC", "<"),
+    };
+    let limit = options.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    let filters = format!("{} AND {}", LIST_FILTERS, options.view.unwrap_or(ListView::inbox).condition());
+    let total: i64 = bind_list_filters(sqlx::query(&format!("SELECT count(*) FROM webpages WHERE {}", filters)),
+            options, user_id, &tags, domain.clone(), language.clone())
+        .fetch_one(db_pool).await?
+        .try_get(0)?;
+    // One more webpage than requested is fetched to find out if there is a next page.
+    let rows = bind_list_filters(sqlx::query(&format!("SELECT {}, {}::text AS sort_key FROM webpages WHERE {} AND ($12::text IS NULL OR ({}, id) {} ($12::{}, $13)) ORDER BY {} {}, id {} LIMIT $14",
+                webpage_info_columns(), expression, filters, expression, comparison, cast, expression, direction, direction)),
+            options, user_id, &tags, domain, language)
+        .bind(cursor.as_ref().map(|cursor| cursor.key.clone()))
+        .bind(cursor.as_ref().map(|cursor| cursor.id))
+        .bind(limit + 1)
+        .fetch_all(db_pool).await?;
+    let next_cursor = if rows.len() as i64 > limit {
+        let last = &rows[limit as usize - 1];
+        Some(Cursor {sort, order, key: last.try_get("sort_key")?, id: last.try_get("id")?}.encode())
+    } else {
+        None
+    };
+    let webpage_infos = rows.iter()
+        .take(limit as usize)
+        .map(WebpageInfo::from_row)
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(ListWebpagesResponse {webpage_infos, total, next_cursor})
+}
+
+pub async fn get_stored_webpages_for_user(query_params: ListOptions, db_pool: Arc<PgPool>, user_id: i64) ->
+        Result<impl warp::Reply, warp::Rejection> {
+    let cursor = match query_params.cursor.as_deref().map(Cursor::decode) {
+        Some(None) => return Ok(errors::error_reply(StatusCode::BAD_REQUEST, "Invalid cursor")),
+        Some(cursor) => cursor,
+        None => None,
+    };
+    Ok(match list_webpages(&db_pool, user_id, &query_params, cursor).await {
+        Ok(response) => warp::reply::with_status(warp::reply::json(&response), StatusCode::OK),
+        Err(error) => {
             log::error!("Error when fetching list of webpages for user {}: {}",
                 user_id, error);
-            let status = StatusCode::INTERNAL_SERVER_ERROR;
-            let json = warp::reply::json(&errors::ErrorResponse {
-                message: "Unknown error".to_string(),
-                status: status.to_string(),
-            });
-            (json, status)
-        }, |rows| {
-            let webpage_infos = rows.iter().map(|(id, title, image_url)| {
-                WebpageInfo {
-                    id: id.to_owned(),
-                    title: title.to_owned(),
-                    image_url: image_url.to_owned()
-                }
-            }).collect::<Vec<WebpageInfo>>();
-            let json = warp::reply::json(&ListWebpagesResponse {
-                webpage_infos,
-            });
-            (json, StatusCode::OK)
-        });
-    Ok(warp::reply::with_status(response, status_code))
+            errors::error_reply(StatusCode::INTERNAL_SERVER_ERROR, "Unknown error")
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cursor() {
+        let cursor = Cursor {sort: SortBy::title, order: SortOrder::asc, key: "a title".to_string(), id: 42};
+        assert_eq!(Cursor::decode(&cursor.encode()), Some(cursor));
+        assert_eq!(Cursor::decode("not a cursor"), None);
+    }
+
+    #[test]
+    fn test_normalize_domain() {
+        assert_eq!(normalize_domain(" WWW.Example.com"), "example.com");
+        assert_eq!(normalize_domain("blog.example.com"), "blog.example.com");
+    }
+
+    #[test]
+    fn test_normalize_language() {
+        assert_eq!(normalize_language(" en_GB"), "en-gb");
+        assert_eq!(normalize_language("da"), "da");
+    }
 }
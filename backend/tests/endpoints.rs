@@ -1,5 +1,6 @@
 use std::net::{SocketAddr,TcpListener};
 
+use sha2::{Digest,Sha256};
 use sqlx::PgPool;
 use wiremock::{MockServer, Mock, ResponseTemplate};
 
@@ -7,6 +8,13 @@ use test_utils::{create_db, execute_sql_from_file, init_logging};
 
 use article_server_rs::{migrate_db,ServerArgs,start_server,
     auth::{create_jwt,Role},
+    fetch::HttpClientConfig,
+    jobs::JobQueueConfig,
+    politeness::PolitenessConfig,
+    metadata::Metadata,
+    highlights::{Highlight,TextQuoteSelector},
+    tags::{ListTagsResponse,TagCount,TagList},
+    url_policy::UrlPolicy,
     webpages::ShowWebpageResponse};
 
 struct TestResources {
@@ -34,7 +42,7 @@ async fn test_status() {
         .send()
         .await
         .expect("Error sending request to server");
-    assert_eq!(response.status().is_success(), true);
+    assert!(response.status().is_success());
 }
 
 #[tokio::test]
@@ -60,14 +68,15 @@ async fn test_fetch_webpage() {
         .send()
         .await
         .expect("Error sending request to server");
-    assert!(response.status().is_success());
+    let job = wait_for_job(&test_resources, response).await;
+    assert_eq!(job["status"], "succeeded", "{}", job);
     let results = sqlx::query_as::<_, (String, String, String)>("select text, html, title from webpages where url = $1 and user_id = 1")
         .bind(url)
         .fetch_optional(&test_resources.pool).await.expect("Unable to query for fetched webpage");
     assert!(results.is_some());
     if let Some(results) = results {
         let (text, html, title) = results;
-        assert_eq!(text, "<title > Title </title> <p > An html document </p>");
+        assert_eq!(text, "<p>An html document</p>");
         assert_eq!(html, "<head><script src=\"script.js\"></script><title>Title</title></head><body><p>An html document</p></body>");
         assert_eq!(title, "Title");
     } else {
@@ -99,9 +108,11 @@ async fn test_fetch_webpage_invalid_html() {
         .send()
         .await
         .expect("Error sending request to server");
-    assert_eq!(response.status(), warp::http::StatusCode::INTERNAL_SERVER_ERROR);
-    let error_text = "Unable to extract any text from document";
-    assert_eq!(error_text, response.text().await.expect("Unable to get text of response"));
+    // The document won't get any better so the job isn't retried.
+    let job = wait_for_job(&test_resources, response).await;
+    assert_eq!(job["status"], "failed");
+    assert_eq!(job["attempts"], 1);
+    assert_eq!(job["error"], "Unable to extract any text from document");
 }
 
 #[tokio::test]
@@ -128,14 +139,15 @@ async fn test_fetch_webpage_html_provided() {
         .send()
         .await
         .expect("Error sending request to server");
-    assert_eq!(response.status().is_success(), true);
+    let job = wait_for_job(&test_resources, response).await;
+    assert_eq!(job["status"], "succeeded", "{}", job);
     let results = sqlx::query_as::<_, (String, String, String)>("select text, html, title from webpages where url = $1 and user_id = 1")
         .bind(url)
         .fetch_optional(&test_resources.pool).await.expect("Unable to query for fetched webpage");
     assert!(results.is_some());
     if let Some(results) = results {
         let (text, html, title) = results;
-        assert_eq!(text, "<title > Self-provided title </title> <p > A self-provided html document </p>");
+        assert_eq!(text, "<p>A self-provided html document</p>");
         assert_eq!(html, "<head><script src=\"script.js\"></script><title>Self-provided title</title></head><body><p>A self-provided html document</p></body>");
         assert_eq!(title, "Self-provided title");
     } else {
@@ -143,6 +155,340 @@ async fn test_fetch_webpage_html_provided() {
     }
 }
 
+#[tokio::test]
+async fn test_fetch_webpage_relative_urls() {
+    let test_resources = start_test_server().await;
+    let client = reqwest::Client::new();
+    let mock_server = MockServer::start().await;
+    let html_response = "<head><title>Title</title></head><body><p><img src=\"/images/image.png\"><a href=\"other-page\">A link</a> in an html document</p></body>";
+    let mock_response = ResponseTemplate::new(200)
+        .set_body_string(html_response);
+    Mock::given(wiremock::matchers::method("GET"))
+        .and(wiremock::matchers::path("articles/fetch-page"))
+        .respond_with(mock_response)
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+    let url = &format!("{}/articles/fetch-page", mock_server.uri());
+    let response = client.post(format!("http://{}:{}/api/fetch",
+            test_resources.addr.ip(), test_resources.addr.port()))
+        .header(reqwest::header::AUTHORIZATION, &format!("bearer {}", test_resources.jwt))
+        .body(serde_json::json!({"url": url}).to_string())
+        .send()
+        .await
+        .expect("Error sending request to server");
+    let job = wait_for_job(&test_resources, response).await;
+    assert_eq!(job["status"], "succeeded", "{}", job);
+    let (text, image_url) = sqlx::query_as::<_, (String, Option<String>)>("select text, image_url from webpages where url = $1 and user_id = 1")
+        .bind(url)
+        .fetch_one(&test_resources.pool).await.expect("Unable to query for fetched webpage");
+    assert_eq!(text, format!("<p><img src=\"{0}/images/image.png\"><a href=\"{0}/articles/other-page\">A link</a> in an html document</p>",
+        mock_server.uri()));
+    assert_eq!(image_url, Some(format!("{}/images/image.png", mock_server.uri())));
+}
+
+#[tokio::test]
+async fn test_fetch_webpage_archives_images() {
+    let test_resources = start_test_server().await;
+    let client = reqwest::Client::new();
+    let mock_server = MockServer::start().await;
+    let html_response = "<head><title>Title</title></head><body><p><img src=\"/images/image.png\" srcset=\"/images/image-2x.png 2x\"><img src=\"/images/missing.png\">An html document</p></body>";
+    Mock::given(wiremock::matchers::method("GET"))
+        .and(wiremock::matchers::path("fetch-page"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(html_response))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+    let image = b"\x89PNG image data".to_vec();
+    // The image is used both in the content and as the lead image but it is only downloaded once.
+    Mock::given(wiremock::matchers::method("GET"))
+        .and(wiremock::matchers::path("images/image.png"))
+        .respond_with(ResponseTemplate::new(200)
+            .insert_header("content-type", "image/png")
+            .set_body_bytes(image.clone()))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+    let url = &format!("{}/fetch-page", mock_server.uri());
+    let response = client.post(format!("http://{}:{}/api/fetch",
+            test_resources.addr.ip(), test_resources.addr.port()))
+        .header(reqwest::header::AUTHORIZATION, &format!("bearer {}", test_resources.jwt))
+        .body(serde_json::json!({"url": url}).to_string())
+        .send()
+        .await
+        .expect("Error sending request to server");
+    let job = wait_for_job(&test_resources, response).await;
+    assert_eq!(job["status"], "succeeded", "{}", job);
+    let hash = format!("{:x}", Sha256::digest(&image));
+    let (text, image_url) = sqlx::query_as::<_, (String, Option<String>)>("select text, image_url from webpages where url = $1 and user_id = 1")
+        .bind(url)
+        .fetch_one(&test_resources.pool).await.expect("Unable to query for fetched webpage");
+    // Images which cannot be downloaded keep pointing at the original site.
+    assert_eq!(text, format!("<p><img src=\"/api/asset/{}\"><img src=\"{}/images/missing.png\">An html document</p>",
+        hash, mock_server.uri()));
+    assert_eq!(image_url, Some(format!("/api/asset/{}", hash)));
+    let response = client.get(format!("http://{}:{}/api/asset/{}",
+            test_resources.addr.ip(), test_resources.addr.port(), hash))
+        .header(reqwest::header::AUTHORIZATION, &format!("bearer {}", test_resources.jwt))
+        .send()
+        .await
+        .expect("Error sending request to server");
+    assert_eq!(response.status(), warp::http::StatusCode::OK);
+    assert_eq!(response.headers().get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok()), Some("image/png"));
+    assert_eq!(response.bytes().await.expect("Unable to get body of response").to_vec(), image);
+    // Assets are only served to users who have saved a webpage using them.
+    let response = client.get(format!("http://{}:{}/api/asset/{}",
+            test_resources.addr.ip(), test_resources.addr.port(), hash))
+        .header(reqwest::header::AUTHORIZATION, &format!("bearer {}", test_resources.admin_jwt))
+        .send()
+        .await
+        .expect("Error sending request to server");
+    assert_eq!(response.status(), warp::http::StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_fetch_webpage_archives_original_stylesheets() {
+    let test_resources = start_test_server().await;
+    let client = reqwest::Client::new();
+    let mock_server = MockServer::start().await;
+    let font = b"font data".to_vec();
+    Mock::given(wiremock::matchers::method("GET"))
+        .and(wiremock::matchers::path("fonts/font.woff2"))
+        .respond_with(ResponseTemplate::new(200)
+            .insert_header("content-type", "font/woff2")
+            .set_body_bytes(font.clone()))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+    Mock::given(wiremock::matchers::method("GET"))
+        .and(wiremock::matchers::path("css/style.css"))
+        .respond_with(ResponseTemplate::new(200)
+            .set_body_raw("@font-face { src: url(\"../fonts/font.woff2\"); }", "text/css; charset=utf-8"))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+    let url = &format!("{}/page", mock_server.uri());
+    let html = "<html><head><title>Title</title><link rel=\"stylesheet\" href=\"/css/style.css\"></head><body><p>An html document</p></body></html>";
+    let response = client.post(format!("http://{}:{}/api/fetch",
+            test_resources.addr.ip(), test_resources.addr.port()))
+        .header(reqwest::header::AUTHORIZATION, &format!("bearer {}", test_resources.jwt))
+        .body(serde_json::json!({"url": url, "html": html, "archive_original": true}).to_string())
+        .send()
+        .await
+        .expect("Error sending request to server");
+    let job = wait_for_job(&test_resources, response).await;
+    assert_eq!(job["status"], "succeeded", "{}", job);
+    let stylesheet = format!("@font-face {{ src: url(\"/api/asset/{:x}\"); }}", Sha256::digest(&font));
+    let stylesheet_hash = format!("{:x}", Sha256::digest(stylesheet.as_bytes()));
+    let (html,) = sqlx::query_as::<_, (String,)>("select html from webpages where url = $1 and user_id = 1")
+        .bind(url)
+        .fetch_one(&test_resources.pool).await.expect("Unable to query for fetched webpage");
+    assert!(html.contains(&format!("<link href=\"/api/asset/{}\" rel=\"stylesheet\">", stylesheet_hash)));
+    let response = client.get(format!("http://{}:{}/api/asset/{}",
+            test_resources.addr.ip(), test_resources.addr.port(), stylesheet_hash))
+        .header(reqwest::header::AUTHORIZATION, &format!("bearer {}", test_resources.jwt))
+        .send()
+        .await
+        .expect("Error sending request to server");
+    assert_eq!(response.status(), warp::http::StatusCode::OK);
+    assert_eq!(response.text().await.expect("Unable to get text of response"), stylesheet);
+}
+
+#[tokio::test]
+async fn test_export_webpage_singlefile() {
+    let test_resources = start_test_server().await;
+    let client = reqwest::Client::new();
+    let mock_server = MockServer::start().await;
+    let image = b"\x89PNG image data".to_vec();
+    Mock::given(wiremock::matchers::method("GET"))
+        .and(wiremock::matchers::path("image.png"))
+        .respond_with(ResponseTemplate::new(200)
+            .insert_header("content-type", "image/png")
+            .set_body_bytes(image.clone()))
+        // Once when the page is saved and once for exporting the original html. The export of the
+        // readable content uses the archived copy.
+        .expect(2)
+        .mount(&mock_server)
+        .await;
+    let url = format!("{}/page", mock_server.uri());
+    let html = "<html><head><title>Title</title><script>alert(1)</script></head><body><p><img src=\"/image.png\">An html document</p></body></html>";
+    let response = client.post(format!("http://{}:{}/api/fetch",
+            test_resources.addr.ip(), test_resources.addr.port()))
+        .header(reqwest::header::AUTHORIZATION, &format!("bearer {}", test_resources.jwt))
+        .body(serde_json::json!({"url": url, "html": html}).to_string())
+        .send()
+        .await
+        .expect("Error sending request to server");
+    let job = wait_for_job(&test_resources, response).await;
+    assert_eq!(job["status"], "succeeded", "{}", job);
+    let (id, added) = sqlx::query_as::<_, (i64, chrono::DateTime<chrono::Utc>)>("select id, added from webpages where user_id = 1")
+        .fetch_one(&test_resources.pool).await.expect("Unable to query for fetched webpage");
+    for mode in ["readable", "original"] {
+        let response = client.get(format!("http://{}:{}/api/webpage/{}/export?format=singlefile&mode={}",
+                test_resources.addr.ip(), test_resources.addr.port(), id, mode))
+            .header(reqwest::header::AUTHORIZATION, &format!("bearer {}", test_resources.jwt))
+            .send()
+            .await
+            .expect("Error sending request to server");
+        assert_eq!(response.status(), warp::http::StatusCode::OK);
+        assert_eq!(response.headers().get(reqwest::header::CONTENT_DISPOSITION)
+            .and_then(|value| value.to_str().ok()), Some("attachment; filename=\"Title.html\""));
+        let export = response.text().await.expect("Unable to get text of response");
+        assert!(export.contains(&format!("<img src=\"data:image/png;base64,{}\">", base64::encode(&image))), "{}", export);
+        assert!(export.contains(&format!("Saved from <a href=\"{0}\">{0}</a> on <time datetime=\"{1}\">{2}</time>",
+            url, added.to_rfc3339(), added.format("%Y-%m-%d"))), "{}", export);
+        assert!(!export.contains("<script"), "{}", export);
+    }
+    // Users can only export their own webpages.
+    let response = client.get(format!("http://{}:{}/api/webpage/{}/export?format=singlefile",
+            test_resources.addr.ip(), test_resources.addr.port(), id))
+        .header(reqwest::header::AUTHORIZATION, &format!("bearer {}", test_resources.admin_jwt))
+        .send()
+        .await
+        .expect("Error sending request to server");
+    assert_eq!(response.status(), warp::http::StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_export_epub() {
+    let test_resources = start_test_server().await;
+    let client = reqwest::Client::new();
+    let mock_server = MockServer::start().await;
+    Mock::given(wiremock::matchers::method("GET"))
+        .and(wiremock::matchers::path("image.png"))
+        .respond_with(ResponseTemplate::new(200)
+            .insert_header("content-type", "image/png")
+            .set_body_bytes(b"\x89PNG image data".to_vec()))
+        .mount(&mock_server)
+        .await;
+    for (page, title) in [("first", "First article"), ("second", "Second article")] {
+        let html = format!("<html><head><title>{}</title><meta name=\"author\" content=\"Author\"></head><body><p><img src=\"/image.png\">The {} article</p></body></html>", title, page);
+        let response = client.post(format!("http://{}:{}/api/fetch",
+                test_resources.addr.ip(), test_resources.addr.port()))
+            .header(reqwest::header::AUTHORIZATION, &format!("bearer {}", test_resources.jwt))
+            .body(serde_json::json!({"url": format!("{}/{}", mock_server.uri(), page), "html": html}).to_string())
+            .send()
+            .await
+            .expect("Error sending request to server");
+        let job = wait_for_job(&test_resources, response).await;
+        assert_eq!(job["status"], "succeeded", "{}", job);
+    }
+    let ids = sqlx::query_as::<_, (i64,)>("select id from webpages where user_id = 1 order by id")
+        .fetch_all(&test_resources.pool).await.expect("Unable to query for fetched webpages");
+    sqlx::query("insert into tags(id, user_id, tag) values (1, 1, 'e-reader')")
+        .execute(&test_resources.pool).await.expect("Unable to insert tag");
+    sqlx::query("insert into tags_to_webpages(tag_id, webpage_id) values (1, $1)")
+        .bind(ids[1].0)
+        .execute(&test_resources.pool).await.expect("Unable to tag webpage");
+    let query = format!("ids={},{}", ids[1].0, ids[0].0);
+    for (query, chapters) in [(query.as_str(), vec!["Second article", "First article"]), ("tag=e-reader", vec!["Second article"])] {
+        let response = client.get(format!("http://{}:{}/api/export/epub?{}",
+                test_resources.addr.ip(), test_resources.addr.port(), query))
+            .header(reqwest::header::AUTHORIZATION, &format!("bearer {}", test_resources.jwt))
+            .send()
+            .await
+            .expect("Error sending request to server");
+        assert_eq!(response.status(), warp::http::StatusCode::OK);
+        assert_eq!(response.headers().get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok()), Some("application/epub+zip"));
+        let epub = response.bytes().await.expect("Unable to get body of response").to_vec();
+        let mut archive = zip::ZipArchive::new(std::io::Cursor::new(epub)).expect("Unable to read epub");
+        let mut read = |name: &str| {
+            let mut content = String::new();
+            std::io::Read::read_to_string(&mut archive.by_name(name).expect("Missing file in epub"), &mut content)
+                .expect("Unable to read file in epub");
+            content
+        };
+        assert_eq!(read("mimetype"), "application/epub+zip");
+        let nav = read("OEBPS/nav.xhtml");
+        for (i, title) in chapters.iter().enumerate() {
+            assert!(nav.contains(&format!("<li><a href=\"chapter-{}.xhtml\">{}</a></li>", i + 1, title)), "{}", nav);
+        }
+        let chapter = read("OEBPS/chapter-1.xhtml");
+        assert!(chapter.contains("<img alt=\"\" src=\"images/"), "{}", chapter);
+        assert!(read("OEBPS/content.opf").contains("<dc:creator>Author</dc:creator>"));
+    }
+    // Other users' webpages cannot be exported.
+    let response = client.get(format!("http://{}:{}/api/export/epub?ids={}",
+            test_resources.addr.ip(), test_resources.addr.port(), ids[0].0))
+        .header(reqwest::header::AUTHORIZATION, &format!("bearer {}", test_resources.admin_jwt))
+        .send()
+        .await
+        .expect("Error sending request to server");
+    assert_eq!(response.status(), warp::http::StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_unused_assets_removed() {
+    let test_resources = start_test_server().await;
+    let client = reqwest::Client::new();
+    let mock_server = MockServer::start().await;
+    let html_response = "<html><head><title>Title</title></head><body><p><img src=\"/images/image.png\">An html document</p></body></html>";
+    for path in ["first", "second", "third"] {
+        Mock::given(wiremock::matchers::method("GET"))
+            .and(wiremock::matchers::path(path))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(html_response, "text/html"))
+            .mount(&mock_server)
+            .await;
+    }
+    let image = b"\x89PNG image data".to_vec();
+    Mock::given(wiremock::matchers::method("GET"))
+        .and(wiremock::matchers::path("images/image.png"))
+        .respond_with(ResponseTemplate::new(200)
+            .insert_header("content-type", "image/png")
+            .set_body_bytes(image.clone()))
+        .mount(&mock_server)
+        .await;
+    let asset_count = || async {
+        sqlx::query_as::<_, (i64,)>("select count(*) from assets")
+            .fetch_one(&test_resources.pool).await.expect("Unable to count assets").0
+    };
+    let mut ids = Vec::new();
+    for path in ["first", "second"] {
+        let job = wait_for_job(&test_resources, post_fetch(&test_resources,
+            serde_json::json!({"url": format!("{}/{}", mock_server.uri(), path)})).await).await;
+        assert_eq!(job["status"], "succeeded", "{}", job);
+        ids.push(job["webpage_id"].as_i64().expect("Missing webpage id"));
+    }
+    assert_eq!(asset_count().await, 1);
+    // Only the asset itself is served.
+    let response = client.get(format!("http://{}:{}/api/asset/{:x}/anything",
+            test_resources.addr.ip(), test_resources.addr.port(), Sha256::digest(&image)))
+        .header(reqwest::header::AUTHORIZATION, &format!("bearer {}", test_resources.jwt))
+        .send()
+        .await
+        .expect("Error sending request to server");
+    assert!(response.status().is_client_error(), "{}", response.status());
+
+    // The asset is kept as long as a webpage uses it.
+    let response = client.delete(format!("http://{}:{}/api/webpage/{}",
+            test_resources.addr.ip(), test_resources.addr.port(), ids[0]))
+        .header(reqwest::header::AUTHORIZATION, &format!("bearer {}", test_resources.jwt))
+        .send()
+        .await
+        .expect("Error sending request to server");
+    assert_eq!(response.status(), warp::http::StatusCode::NO_CONTENT);
+    assert_eq!(asset_count().await, 1);
+    let job = wait_for_job(&test_resources, post_fetch(&test_resources, serde_json::json!({
+        "url": format!("{}/second", mock_server.uri()), "on_duplicate": "update",
+        "html": "<html><head><title>Title</title></head><body><p>No images anymore</p></body></html>"})).await).await;
+    assert_eq!(job["result"], "updated");
+    assert_eq!(asset_count().await, 0);
+
+    let job = wait_for_job(&test_resources, post_fetch(&test_resources,
+        serde_json::json!({"url": format!("{}/third", mock_server.uri())})).await).await;
+    assert_eq!(asset_count().await, 1);
+    let response = client.delete(format!("http://{}:{}/api/webpage/{}",
+            test_resources.addr.ip(), test_resources.addr.port(), job["webpage_id"]))
+        .header(reqwest::header::AUTHORIZATION, &format!("bearer {}", test_resources.jwt))
+        .send()
+        .await
+        .expect("Error sending request to server");
+    assert_eq!(response.status(), warp::http::StatusCode::NO_CONTENT);
+    assert_eq!(asset_count().await, 0);
+}
+
 #[tokio::test]
 async fn test_fetch_webpage_error_on_fetch() {
     let test_resources = start_test_server().await;
@@ -153,8 +499,8 @@ async fn test_fetch_webpage_error_on_fetch() {
     Mock::given(wiremock::matchers::method("GET"))
         .and(wiremock::matchers::path("fetch-page"))
         .respond_with(mock_response)
-        // Expect the mock to be called exactly once.
-        .expect(1)
+        // The server error is assumed to be temporary and the fetch is tried three times.
+        .expect(3)
         .mount(&mock_server)
         .await;
     let url = &format!("{}/fetch-page", mock_server.uri());
@@ -165,10 +511,32 @@ async fn test_fetch_webpage_error_on_fetch() {
         .send()
         .await
         .expect("Error sending request to server");
-    assert_eq!(response.status(), warp::http::StatusCode::NOT_FOUND);
+    let job = wait_for_job(&test_resources, response).await;
+    assert_eq!(job["status"], "failed");
+    assert_eq!(job["attempts"], 3);
     let error_text = format!("Unable to fetch {}/fetch-page. Got status 500 Internal Server Error: Some unknown error",
         mock_server.uri());
-    assert_eq!(error_text, response.text().await.expect("Unable to get text of response"));
+    assert_eq!(job["error"], error_text);
+}
+
+#[tokio::test]
+async fn test_fetch_webpage_error_long_body() {
+    let test_resources = start_test_server().await;
+    let mock_server = MockServer::start().await;
+    let body = format!("<html><body><h1>Not found</h1>{}</body></html>", "x".repeat(2000));
+    Mock::given(wiremock::matchers::method("GET"))
+        .and(wiremock::matchers::path("missing"))
+        .respond_with(ResponseTemplate::new(404).set_body_raw(body.clone(), "text/html"))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+    let job = wait_for_job(&test_resources, post_fetch(&test_resources,
+        serde_json::json!({"url": format!("{}/missing", mock_server.uri())})).await).await;
+    assert_eq!(job["status"], "failed");
+    // Only the beginning of the body is included in the error.
+    let error_text = format!("Unable to fetch {}/missing. Got status 404 Not Found: {}",
+        mock_server.uri(), &body[..1000]);
+    assert_eq!(job["error"], error_text);
 }
 
 #[tokio::test]
@@ -183,7 +551,7 @@ async fn test_get_webpage() {
         .send()
         .await
         .expect("Error sending request to server");
-    assert_eq!(response.status().is_success(), true);
+    assert!(response.status().is_success());
     let webpage: ShowWebpageResponse = serde_json::from_str(
         &response.text().await.expect("Unable to get text from response"))
         .expect("Unable to parse response as webpage response");
@@ -194,6 +562,50 @@ async fn test_get_webpage() {
     ));
 }
 
+#[tokio::test]
+async fn test_get_webpage_metadata() {
+    let test_resources = start_test_server().await;
+    let client = reqwest::Client::new();
+    // The lead image is downloaded when the page is saved so it is served by the mock server to
+    // keep the test from making requests to the internet.
+    let mock_server = MockServer::start().await;
+    let html = "<html lang=\"en\"><head><title>Title</title><meta property=\"og:title\" content=\"OpenGraph title\"><meta property=\"og:image\" content=\"/lead.png\"><meta property=\"og:site_name\" content=\"Site\"><meta property=\"og:description\" content=\"Description\"><meta property=\"article:published_time\" content=\"2022-01-02T03:04:05Z\"><meta name=\"author\" content=\"Author\"><link rel=\"canonical\" href=\"https://example.com/canonical\"></head><body><p>An html document</p></body></html>";
+    let response = client.post(format!("http://{}:{}/api/fetch",
+            test_resources.addr.ip(), test_resources.addr.port()))
+        .header(reqwest::header::AUTHORIZATION, &format!("bearer {}", test_resources.jwt))
+        .body(serde_json::json!({"url": format!("{}/page", mock_server.uri()), "html": html}).to_string())
+        .send()
+        .await
+        .expect("Error sending request to server");
+    let job = wait_for_job(&test_resources, response).await;
+    assert_eq!(job["status"], "succeeded", "{}", job);
+    let (id,) = sqlx::query_as::<_, (i64,)>("select id from webpages where user_id = 1")
+        .fetch_one(&test_resources.pool).await.expect("Unable to query for fetched webpage");
+    let response = client.get(format!("http://{}:{}/api/webpage/{}",
+            test_resources.addr.ip(), test_resources.addr.port(), id))
+        .header(reqwest::header::AUTHORIZATION, &format!("bearer {}", test_resources.jwt))
+        .send()
+        .await
+        .expect("Error sending request to server");
+    assert!(response.status().is_success());
+    let webpage: ShowWebpageResponse = serde_json::from_str(
+        &response.text().await.expect("Unable to get text from response"))
+        .expect("Unable to parse response as webpage response");
+    assert_eq!(webpage, ShowWebpageResponse::new(
+        "OpenGraph title".into(),
+        Some(format!("{}/lead.png", mock_server.uri())),
+        "<p>An html document</p>".into(),
+    ).with_metadata(Metadata {
+        description: Some("Description".into()),
+        site_name: Some("Site".into()),
+        author: Some("Author".into()),
+        published_time: Some(chrono::DateTime::parse_from_rfc3339("2022-01-02T03:04:05Z")
+            .expect("Unable to parse time").into()),
+        canonical_url: Some("https://example.com/canonical".into()),
+        language: Some("en".into()),
+    }));
+}
+
 #[tokio::test]
 async fn test_get_webpage_oauth2_provider_jwt() {
     let test_resources = start_test_server().await;
@@ -250,7 +662,7 @@ async fn test_get_webpage_oauth2_provider_jwt() {
         .send()
         .await
         .expect("Error sending request to server");
-    assert_eq!(response.status().is_success(), true);
+    assert!(response.status().is_success());
     let webpage: ShowWebpageResponse = serde_json::from_str(
         &response.text().await.expect("Unable to get text from response"))
         .expect("Unable to parse response as webpage response");
@@ -342,114 +754,580 @@ async fn test_register_oauth2_provider_jwt_insufficient_role() {
     assert_eq!(response.status(), warp::http::StatusCode::UNAUTHORIZED);
 }
 
-#[tokio::test]
-async fn test_delete_webpage() {
-    let test_resources = start_test_server().await;
-    execute_sql_from_file("tests/data/insert-webpage.sql", &test_resources.pool).await.expect("Unable to insert webpages");
-    let results = sqlx::query_as::<_, (String, String)>("SELECT url, title FROM webpages WHERE id = 1")
-        .fetch_optional(&test_resources.pool).await.expect("Unable to query for webpage prior to deleting");
-    assert_eq!(results.is_some(), true);
+async fn save_webpage(test_resources: &TestResources, url: &str, html: &str, tags: &[&str]) -> i64 {
     let client = reqwest::Client::new();
-    let response = client.delete(format!("http://{}:{}/api/webpage/1",
+    let response = client.post(format!("http://{}:{}/api/fetch",
             test_resources.addr.ip(), test_resources.addr.port()))
         .header(reqwest::header::AUTHORIZATION, &format!("bearer {}", test_resources.jwt))
+        .body(serde_json::json!({"url": url, "html": html, "tags": tags}).to_string())
         .send()
         .await
         .expect("Error sending request to server");
-    assert_eq!(response.status().is_success(), true);
-    let results = sqlx::query_as::<_, (String, String)>("SELECT url, title FROM webpages WHERE id = 1")
-        .fetch_optional(&test_resources.pool).await.expect("Unable to query for webpage after deleting");
-    assert_eq!(results.is_some(), false);
+    let job = wait_for_job(test_resources, response).await;
+    assert_eq!(job["status"], "succeeded", "{}", job);
+    assert_eq!(job["result"], "created");
+    job["webpage_id"].as_i64().expect("Missing webpage id")
 }
 
-#[tokio::test]
-async fn test_register_user() {
-    let test_resources = start_test_server().await;
-    let users_pre = sqlx::query_as::<_, (i64,)>("select count(id) from users")
-        .fetch_one(&test_resources.pool).await.expect("Unable to get user count before registering");
-    assert_eq!(users_pre.0, 2);
-    let client = reqwest::Client::new();
-    let response = client.post(format!("http://{}:{}/api/register",
+// Waits until the job queued by the response from /api/fetch has succeeded or failed and returns
+// the job.
+async fn wait_for_job(test_resources: &TestResources, response: reqwest::Response) -> serde_json::Value {
+    assert_eq!(response.status(), warp::http::StatusCode::ACCEPTED);
+    let queued: serde_json::Value = serde_json::from_str(&response.text().await.expect("Unable to get text from response"))
+        .expect("Unable to parse response as json");
+    let job_id = queued["job_id"].as_i64().expect("Missing job id");
+    for _ in 0..200 {
+        let response = reqwest::Client::new().get(format!("http://{}:{}/api/jobs/{}",
+                test_resources.addr.ip(), test_resources.addr.port(), job_id))
+            .header(reqwest::header::AUTHORIZATION, &format!("bearer {}", test_resources.jwt))
+            .send()
+            .await
+            .expect("Error sending request to server");
+        assert_eq!(response.status(), warp::http::StatusCode::OK);
+        let job: serde_json::Value = serde_json::from_str(&response.text().await.expect("Unable to get text from response"))
+            .expect("Unable to parse response as json");
+        if job["status"] == "succeeded" || job["status"] == "failed" {
+            return job;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+    panic!("Job {} didn't finish", job_id);
+}
+
+async fn post_fetch(test_resources: &TestResources, body: serde_json::Value) -> reqwest::Response {
+    reqwest::Client::new().post(format!("http://{}:{}/api/fetch",
             test_resources.addr.ip(), test_resources.addr.port()))
-        .body(serde_json::json!({"username": "new-user", "password": "Password123"}).to_string())
-        .header(reqwest::header::AUTHORIZATION, &format!("bearer {}", test_resources.admin_jwt))
+        .header(reqwest::header::AUTHORIZATION, &format!("bearer {}", test_resources.jwt))
+        .body(body.to_string())
         .send()
         .await
-        .expect("Error sending request to server");
-    assert_eq!(response.status().is_success(), true);
-    let users_post = sqlx::query_as::<_, (String, Vec<Role>)>("select username, roles from users where username = 'new-user'")
-        .fetch_one(&test_resources.pool).await.expect("Unable to get user count before registering");
-    assert_eq!(users_post.0, "new-user");
-    assert_eq!(users_post.1.len(), 1);
-    assert_eq!(users_post.1[0], Role::User);
+        .expect("Error sending request to server")
 }
 
-#[tokio::test]
-async fn test_register_user_not_allowed_for_non_admins() {
-    let test_resources = start_test_server().await;
-    let client = reqwest::Client::new();
-    let response = client.post(format!("http://{}:{}/api/register",
+async fn list_tags(test_resources: &TestResources) -> Vec<TagCount> {
+    let response = reqwest::Client::new().get(format!("http://{}:{}/api/tags",
             test_resources.addr.ip(), test_resources.addr.port()))
-        .body(serde_json::json!({"username": "new-user", "password": "Password123"}).to_string())
         .header(reqwest::header::AUTHORIZATION, &format!("bearer {}", test_resources.jwt))
         .send()
         .await
         .expect("Error sending request to server");
-    assert_eq!(response.status(), warp::http::StatusCode::UNAUTHORIZED);
-    let new_user = sqlx::query_as::<_, (String, Vec<Role>)>("select username, roles from users where username = 'new-user'")
-        .fetch_optional(&test_resources.pool).await.expect("Unable to query for new user");
-    assert!(new_user.is_none());
+    assert_eq!(response.status(), warp::http::StatusCode::OK);
+    let tags: ListTagsResponse = serde_json::from_str(&response.text().await.expect("Unable to get text from response"))
+        .expect("Unable to parse response as tags");
+    tags.tags
 }
 
-#[tokio::test]
-async fn test_associate_app_to_user() {
-    let test_resources = start_test_server().await;
-    let apps_pre = sqlx::query_as::<_, (i64,)>("select count(id) from connected_apps")
-        .fetch_one(&test_resources.pool).await.expect("Unable to get app count before registering");
-    assert_eq!(apps_pre.0, 1);
-    let client = reqwest::Client::new();
-    let now = chrono::offset::Utc::now().format("%Y-%m-%dT%H:%M:%S");
-    let response = client.post(format!("http://{}:{}/api/associate-app-to-user",
-            test_resources.addr.ip(), test_resources.addr.port()))
-        .body(serde_json::json!({"sub": "sub", "client_id": &format!("client_id_{}", now),
-            "app_host": &format!("app_host_{}", now)}).to_string())
+async fn list_webpage_titles(test_resources: &TestResources, query: &str) -> Vec<(String, Vec<String>)> {
+    let response = reqwest::Client::new().get(format!("http://{}:{}/api/list-stored-webpages?{}",
+            test_resources.addr.ip(), test_resources.addr.port(), query))
         .header(reqwest::header::AUTHORIZATION, &format!("bearer {}", test_resources.jwt))
         .send()
         .await
         .expect("Error sending request to server");
-    assert_eq!(response.status(), warp::http::StatusCode::CREATED);
-    let apps_post = sqlx::query_as::<_, (i64,String,String,String)>(
-            "select user_id, sub, client_id, app_host from connected_apps where user_id = 1 order by id desc limit 1")
-        .fetch_optional(&test_resources.pool).await.expect(
-            "Unable to get app count before registering");
-    assert_eq!(apps_post.is_some(), true);
-    if let Some(apps_post) = apps_post {
-        let (user_id, sub, client_id, app_host) = apps_post;
-        assert_eq!(user_id, 1);
-        assert_eq!(sub, "sub");
-        assert_eq!(client_id, format!("client_id_{}", now));
-        assert_eq!(app_host, format!("app_host_{}", now));
-    }
+    assert_eq!(response.status(), warp::http::StatusCode::OK);
+    let list: serde_json::Value = serde_json::from_str(&response.text().await.expect("Unable to get text from response"))
+        .expect("Unable to parse response as json");
+    let mut webpages: Vec<(String, Vec<String>)> = list["webpage_infos"].as_array().expect("Missing webpage_infos").iter()
+        .map(|info| (info["title"].as_str().expect("Missing title").to_string(),
+            serde_json::from_value(info["tags"].clone()).expect("Missing tags")))
+        .collect();
+    webpages.sort();
+    webpages
+}
+
+fn tag_count(tag: &str, count: i64) -> TagCount {
+    TagCount {tag: tag.into(), count}
 }
 
 #[tokio::test]
-async fn test_associate_app_to_user_existing_app() {
+async fn test_tags() {
     let test_resources = start_test_server().await;
     let client = reqwest::Client::new();
-
-    sqlx::query("insert into connected_apps(user_id, sub, client_id, app_host) values (1, 'sub', 'client_id', 'app_host')")
-        .execute(&test_resources.pool).await
-        .expect("Unable to insert app into database");
-
-    let response = client.post(format!("http://{}:{}/api/associate-app-to-user",
-            test_resources.addr.ip(), test_resources.addr.port()))
-        .body(serde_json::json!({"sub": "sub", "client_id": "client_id",
-            "app_host": "app_host"}).to_string())
+    let first = save_webpage(&test_resources, "https://example.com/first",
+        "<html><head><title>First</title></head><body><p>First page</p></body></html>", &["rust", " web "]).await;
+    let second = save_webpage(&test_resources, "https://example.com/second",
+        "<html><head><title>Second</title></head><body><p>Second page</p></body></html>", &["rust"]).await;
+    assert_eq!(list_tags(&test_resources).await, vec![tag_count("rust", 2), tag_count("web", 1)]);
+    let response = client.post(format!("http://{}:{}/api/webpage/{}/tags",
+            test_resources.addr.ip(), test_resources.addr.port(), second))
         .header(reqwest::header::AUTHORIZATION, &format!("bearer {}", test_resources.jwt))
+        .body(serde_json::json!({"tags": ["reading", "rust"]}).to_string())
         .send()
         .await
         .expect("Error sending request to server");
-    assert_eq!(response.status(), warp::http::StatusCode::CREATED);
+    assert_eq!(response.status(), warp::http::StatusCode::OK);
+    let tags: TagList = serde_json::from_str(&response.text().await.expect("Unable to get text from response"))
+        .expect("Unable to parse response as tags");
+    assert_eq!(tags.tags, vec!["reading".to_string(), "rust".to_string()]);
+    let response = client.get(format!("http://{}:{}/api/webpage/{}",
+            test_resources.addr.ip(), test_resources.addr.port(), first))
+        .header(reqwest::header::AUTHORIZATION, &format!("bearer {}", test_resources.jwt))
+        .send()
+        .await
+        .expect("Error sending request to server");
+    let webpage: serde_json::Value = serde_json::from_str(&response.text().await.expect("Unable to get text from response"))
+        .expect("Unable to parse response as json");
+    assert_eq!(webpage["tags"], serde_json::json!(["rust", "web"]));
+    assert_eq!(list_webpage_titles(&test_resources, "tags=rust,web").await,
+        vec![("First".to_string(), vec!["rust".to_string(), "web".to_string()])]);
+    assert_eq!(list_webpage_titles(&test_resources, "tags=rust").await.len(), 2);
+    // Renaming a tag to an existing tag merges them.
+    let response = client.post(format!("http://{}:{}/api/tags/rename",
+            test_resources.addr.ip(), test_resources.addr.port()))
+        .header(reqwest::header::AUTHORIZATION, &format!("bearer {}", test_resources.jwt))
+        .body(serde_json::json!({"from": "web", "to": "reading"}).to_string())
+        .send()
+        .await
+        .expect("Error sending request to server");
+    assert_eq!(response.status(), warp::http::StatusCode::NO_CONTENT);
+    assert_eq!(list_tags(&test_resources).await, vec![tag_count("reading", 2), tag_count("rust", 2)]);
+    let response = client.post(format!("http://{}:{}/api/tags/rename",
+            test_resources.addr.ip(), test_resources.addr.port()))
+        .header(reqwest::header::AUTHORIZATION, &format!("bearer {}", test_resources.jwt))
+        .body(serde_json::json!({"from": "rust", "to": "rustlang"}).to_string())
+        .send()
+        .await
+        .expect("Error sending request to server");
+    assert_eq!(response.status(), warp::http::StatusCode::NO_CONTENT);
+    let response = client.delete(format!("http://{}:{}/api/webpage/{}/tags",
+            test_resources.addr.ip(), test_resources.addr.port(), first))
+        .header(reqwest::header::AUTHORIZATION, &format!("bearer {}", test_resources.jwt))
+        .body(serde_json::json!({"tags": ["reading"]}).to_string())
+        .send()
+        .await
+        .expect("Error sending request to server");
+    assert_eq!(response.status(), warp::http::StatusCode::OK);
+    assert_eq!(list_tags(&test_resources).await, vec![tag_count("reading", 1), tag_count("rustlang", 2)]);
+    // Tagged webpages can still be deleted and tags no other webpage uses are removed with them.
+    let response = client.delete(format!("http://{}:{}/api/webpage/{}",
+            test_resources.addr.ip(), test_resources.addr.port(), second))
+        .header(reqwest::header::AUTHORIZATION, &format!("bearer {}", test_resources.jwt))
+        .send()
+        .await
+        .expect("Error sending request to server");
+    assert_eq!(response.status(), warp::http::StatusCode::NO_CONTENT);
+    assert_eq!(list_tags(&test_resources).await, vec![tag_count("rustlang", 1)]);
+}
+
+#[tokio::test]
+async fn test_tags_other_users_webpage() {
+    let test_resources = start_test_server().await;
+    let id = save_webpage(&test_resources, "https://example.com/page",
+        "<p>An html document</p>", &["rust"]).await;
+    let response = reqwest::Client::new().post(format!("http://{}:{}/api/webpage/{}/tags",
+            test_resources.addr.ip(), test_resources.addr.port(), id))
+        .header(reqwest::header::AUTHORIZATION, &format!("bearer {}", test_resources.admin_jwt))
+        .body(serde_json::json!({"tags": ["mine"]}).to_string())
+        .send()
+        .await
+        .expect("Error sending request to server");
+    assert_eq!(response.status(), warp::http::StatusCode::NOT_FOUND);
+    // Tags are per user so the same tag name can be used by several users.
+    let (count,) = sqlx::query_as::<_, (i64,)>("select count(*) from tags")
+        .fetch_one(&test_resources.pool).await.expect("Unable to count tags");
+    assert_eq!(count, 1);
+}
+
+async fn search(test_resources: &TestResources, jwt: &str, query: &str) -> (reqwest::StatusCode, serde_json::Value) {
+    let response = reqwest::Client::new().get(format!("http://{}:{}/api/search",
+            test_resources.addr.ip(), test_resources.addr.port()))
+        .query(&[("q", query)])
+        .header(reqwest::header::AUTHORIZATION, &format!("bearer {}", jwt))
+        .send()
+        .await
+        .expect("Error sending request to server");
+    let status = response.status();
+    let json = serde_json::from_str(&response.text().await.expect("Unable to get text from response"))
+        .expect("Unable to parse response as json");
+    (status, json)
+}
+
+fn search_titles(results: &serde_json::Value) -> Vec<&str> {
+    results["results"].as_array().expect("Missing results").iter()
+        .map(|result| result["title"].as_str().expect("Missing title"))
+        .collect()
+}
+
+#[tokio::test]
+async fn test_search() {
+    let test_resources = start_test_server().await;
+    save_webpage(&test_resources, "https://example.com/dogs",
+        "<html lang=\"en-GB\"><head><title>Running dogs</title></head><body><p>The dogs were running quickly through the park.</p></body></html>", &["animals"]).await;
+    save_webpage(&test_resources, "https://example.com/cats",
+        "<html lang=\"en\"><head><title>Sleeping cats</title></head><body><p>The cats sleep while the dogs <b>run</b> around in the garden.</p></body></html>", &[]).await;
+    save_webpage(&test_resources, "https://example.com/postgres",
+        "<html><head><title>Postgres</title></head><body><p>Full-text search in postgres uses a tsvector.</p></body></html>", &[]).await;
+    let jwt = &test_resources.jwt;
+    // Both pages are in English so running is stemmed to run. The title match ranks higher.
+    let (status, results) = search(&test_resources, jwt, "running").await;
+    assert_eq!(status, warp::http::StatusCode::OK);
+    assert_eq!(search_titles(&results), vec!["Running dogs", "Sleeping cats"]);
+    assert_eq!(results["results"][0]["tags"], serde_json::json!(["animals"]));
+    assert_eq!(results["results"][1]["snippet"],
+        "cats sleep while the dogs <mark>run</mark> around in the garden");
+    let (_, results) = search(&test_resources, jwt, "\"dogs were running\"").await;
+    assert_eq!(search_titles(&results), vec!["Running dogs"]);
+    let (_, results) = search(&test_resources, jwt, "dogs -garden").await;
+    assert_eq!(search_titles(&results), vec!["Running dogs"]);
+    let (_, results) = search(&test_resources, jwt, "tsvec* OR garden").await;
+    assert_eq!(search_titles(&results), vec!["Postgres", "Sleeping cats"]);
+    // The exclusion applies to both words joined by OR.
+    let (_, results) = search(&test_resources, jwt, "garden OR tsvector -cats").await;
+    assert_eq!(search_titles(&results), vec!["Postgres"]);
+    let (_, results) = search(&test_resources, jwt, "full-text").await;
+    assert_eq!(search_titles(&results), vec!["Postgres"]);
+    let (_, results) = search(&test_resources, &test_resources.admin_jwt, "dogs").await;
+    assert!(search_titles(&results).is_empty());
+    let (status, _) = search(&test_resources, jwt, " -* ").await;
+    assert_eq!(status, warp::http::StatusCode::BAD_REQUEST);
+}
+
+async fn list_webpages(test_resources: &TestResources, query: &[(&str, &str)]) -> (reqwest::StatusCode, serde_json::Value) {
+    let response = reqwest::Client::new().get(format!("http://{}:{}/api/list-stored-webpages",
+            test_resources.addr.ip(), test_resources.addr.port()))
+        .query(query)
+        .header(reqwest::header::AUTHORIZATION, &format!("bearer {}", test_resources.jwt))
+        .send()
+        .await
+        .expect("Error sending request to server");
+    let status = response.status();
+    let json = serde_json::from_str(&response.text().await.expect("Unable to get text from response"))
+        .expect("Unable to parse response as json");
+    (status, json)
+}
+
+fn listed_titles(list: &serde_json::Value) -> Vec<&str> {
+    list["webpage_infos"].as_array().expect("Missing webpage_infos").iter()
+        .map(|info| info["title"].as_str().expect("Missing title"))
+        .collect()
+}
+
+#[tokio::test]
+async fn test_list_webpages() {
+    let test_resources = start_test_server().await;
+    for (url, title, added) in [("https://www.example.com/a", "Banana", "2026-01-03T00:00:00Z"),
+            ("https://blog.example.com/b", "apple", "2026-01-02T00:00:00Z"),
+            ("https://other.org/c", "Cherry", "2026-01-01T00:00:00Z")] {
+        let id = save_webpage(&test_resources, url,
+            &format!("<html><head><title>{}</title></head><body><p>Some text</p></body></html>", title), &[]).await;
+        sqlx::query("update webpages set added = $1::timestamptz where id = $2")
+            .bind(added)
+            .bind(id)
+            .execute(&test_resources.pool).await.expect("Unable to update webpage");
+    }
+    sqlx::query("update webpages set read_at = now() where title = 'apple'")
+        .execute(&test_resources.pool).await.expect("Unable to update webpage");
+    let (status, list) = list_webpages(&test_resources, &[("view", "all")]).await;
+    assert_eq!(status, warp::http::StatusCode::OK);
+    assert_eq!(listed_titles(&list), vec!["Banana", "apple", "Cherry"]);
+    assert_eq!(list["total"], 3);
+    assert_eq!(list["next_cursor"], serde_json::Value::Null);
+    assert_eq!(list["webpage_infos"][0]["url"], "https://www.example.com/a");
+    assert_eq!(list["webpage_infos"][0]["added"], "2026-01-03T00:00:00Z");
+    let (_, list) = list_webpages(&test_resources, &[("view", "all"), ("sort", "title")]).await;
+    assert_eq!(listed_titles(&list), vec!["apple", "Banana", "Cherry"]);
+    let (_, list) = list_webpages(&test_resources, &[("view", "all"), ("sort", "domain"), ("order", "desc")]).await;
+    assert_eq!(listed_titles(&list), vec!["Cherry", "Banana", "apple"]);
+    let (_, list) = list_webpages(&test_resources, &[("view", "all"), ("sort", "added"), ("order", "asc"), ("limit", "2")]).await;
+    assert_eq!(listed_titles(&list), vec!["Cherry", "apple"]);
+    assert_eq!(list["total"], 3);
+    let cursor = list["next_cursor"].as_str().expect("Missing cursor").to_string();
+    let (_, list) = list_webpages(&test_resources, &[("view", "all"), ("limit", "2"), ("cursor", &cursor)]).await;
+    assert_eq!(listed_titles(&list), vec!["Banana"]);
+    assert_eq!(list["total"], 3);
+    assert_eq!(list["next_cursor"], serde_json::Value::Null);
+    let (_, list) = list_webpages(&test_resources, &[("view", "all"), ("domain", "example.com")]).await;
+    assert_eq!(listed_titles(&list), vec!["Banana", "apple"]);
+    assert_eq!(list["total"], 2);
+    let (_, list) = list_webpages(&test_resources, &[("view", "all"), ("domain", "blog.example.com")]).await;
+    assert_eq!(listed_titles(&list), vec!["apple"]);
+    let (_, list) = list_webpages(&test_resources, &[("view", "all"), ("read", "false")]).await;
+    assert_eq!(listed_titles(&list), vec!["Banana", "Cherry"]);
+    let (_, list) = list_webpages(&test_resources, &[("view", "all"), ("read", "true")]).await;
+    assert_eq!(listed_titles(&list), vec!["apple"]);
+    let (_, list) = list_webpages(&test_resources, &[("view", "all"), ("added_after", "2026-01-02T00:00:00Z")]).await;
+    assert_eq!(listed_titles(&list), vec!["Banana", "apple"]);
+    let (_, list) = list_webpages(&test_resources, &[("view", "all"), ("added_before", "2026-01-02T00:00:00Z")]).await;
+    assert_eq!(listed_titles(&list), vec!["Cherry"]);
+    // Read webpages aren't in the inbox which is shown by default.
+    let (_, list) = list_webpages(&test_resources, &[]).await;
+    assert_eq!(listed_titles(&list), vec!["Banana", "Cherry"]);
+    assert_eq!(list["total"], 2);
+    let (status, _) = list_webpages(&test_resources, &[("cursor", "invalid")]).await;
+    assert_eq!(status, warp::http::StatusCode::BAD_REQUEST);
+}
+
+async fn change_state(test_resources: &TestResources, jwt: &str, method: reqwest::Method, id: i64, flag: &str) ->
+        (reqwest::StatusCode, serde_json::Value) {
+    let response = reqwest::Client::new().request(method, format!("http://{}:{}/api/webpage/{}/{}",
+            test_resources.addr.ip(), test_resources.addr.port(), id, flag))
+        .header(reqwest::header::AUTHORIZATION, &format!("bearer {}", jwt))
+        .send()
+        .await
+        .expect("Error sending request to server");
+    let status = response.status();
+    let json = serde_json::from_str(&response.text().await.expect("Unable to get text from response"))
+        .unwrap_or(serde_json::Value::Null);
+    (status, json)
+}
+
+#[tokio::test]
+async fn test_reading_state() {
+    let test_resources = start_test_server().await;
+    let jwt = &test_resources.jwt;
+    let first = save_webpage(&test_resources, "https://example.com/first",
+        "<html><head><title>First</title></head><body><p>First page</p></body></html>", &[]).await;
+    let second = save_webpage(&test_resources, "https://example.com/second",
+        "<html><head><title>Second</title></head><body><p>Second page</p></body></html>", &[]).await;
+    let (status, state) = change_state(&test_resources, jwt, reqwest::Method::PUT, first, "starred").await;
+    assert_eq!(status, warp::http::StatusCode::OK);
+    assert_eq!(state, serde_json::json!({"read_at": null, "archived": false, "starred": true}));
+    change_state(&test_resources, jwt, reqwest::Method::PUT, first, "archived").await;
+    let (_, list) = list_webpages(&test_resources, &[("view", "archive")]).await;
+    assert_eq!(listed_titles(&list), vec!["First"]);
+    let (_, list) = list_webpages(&test_resources, &[("view", "starred")]).await;
+    assert_eq!(listed_titles(&list), vec!["First"]);
+    assert_eq!(list["webpage_infos"][0]["archived"], true);
+    let (_, list) = list_webpages(&test_resources, &[]).await;
+    assert_eq!(listed_titles(&list), vec!["Second"]);
+    let (_, state) = change_state(&test_resources, jwt, reqwest::Method::DELETE, first, "archived").await;
+    assert_eq!(state, serde_json::json!({"read_at": null, "archived": false, "starred": true}));
+    let (_, state) = change_state(&test_resources, jwt, reqwest::Method::PUT, first, "read").await;
+    let read_at = state["read_at"].as_str().expect("Missing read_at").to_string();
+    // Marking a webpage as read again keeps the original time.
+    let (_, state) = change_state(&test_resources, jwt, reqwest::Method::PUT, first, "read").await;
+    assert_eq!(state["read_at"], read_at.as_str());
+    let (_, list) = list_webpages(&test_resources, &[]).await;
+    assert_eq!(listed_titles(&list), vec!["Second"]);
+    let (_, state) = change_state(&test_resources, jwt, reqwest::Method::DELETE, first, "read").await;
+    assert_eq!(state["read_at"], serde_json::Value::Null);
+    let response = reqwest::Client::new().get(format!("http://{}:{}/api/webpage/{}?mark_read=true",
+            test_resources.addr.ip(), test_resources.addr.port(), second))
+        .header(reqwest::header::AUTHORIZATION, &format!("bearer {}", jwt))
+        .send()
+        .await
+        .expect("Error sending request to server");
+    let webpage: serde_json::Value = serde_json::from_str(&response.text().await.expect("Unable to get text from response"))
+        .expect("Unable to parse response as json");
+    assert!(webpage["read_at"].is_string());
+    let (_, list) = list_webpages(&test_resources, &[]).await;
+    assert_eq!(listed_titles(&list), vec!["First"]);
+    let (status, _) = change_state(&test_resources, &test_resources.admin_jwt, reqwest::Method::PUT, first, "starred").await;
+    assert_eq!(status, warp::http::StatusCode::NOT_FOUND);
+    let (status, _) = change_state(&test_resources, jwt, reqwest::Method::PUT, first, "unknown").await;
+    assert!(status.is_client_error());
+}
+
+async fn reading_progress(test_resources: &TestResources, jwt: &str, id: i64, progress: Option<serde_json::Value>) ->
+        (reqwest::StatusCode, serde_json::Value) {
+    let url = format!("http://{}:{}/api/webpage/{}/progress", test_resources.addr.ip(),
+        test_resources.addr.port(), id);
+    let client = reqwest::Client::new();
+    let request = match progress {
+        Some(progress) => client.put(url).body(progress.to_string()),
+        None => client.get(url),
+    };
+    let response = request
+        .header(reqwest::header::AUTHORIZATION, &format!("bearer {}", jwt))
+        .send()
+        .await
+        .expect("Error sending request to server");
+    let status = response.status();
+    let json = serde_json::from_str(&response.text().await.expect("Unable to get text from response"))
+        .expect("Unable to parse response as json");
+    (status, json)
+}
+
+#[tokio::test]
+async fn test_reading_progress() {
+    let test_resources = start_test_server().await;
+    let jwt = &test_resources.jwt;
+    let id = save_webpage(&test_resources, "https://example.com/page",
+        "<p>An html document</p>", &[]).await;
+    let (status, _) = reading_progress(&test_resources, jwt, id, None).await;
+    assert_eq!(status, warp::http::StatusCode::NOT_FOUND);
+    let progress = serde_json::json!({"percentage": 40.0, "anchor": null, "updated_at": "2026-01-02T10:00:00Z"});
+    let (status, saved) = reading_progress(&test_resources, jwt, id, Some(progress.clone())).await;
+    assert_eq!(status, warp::http::StatusCode::OK);
+    assert_eq!(saved, progress);
+    // Progress which was recorded earlier on another device doesn't overwrite newer progress.
+    let (status, saved) = reading_progress(&test_resources, jwt, id, Some(serde_json::json!(
+        {"percentage": 10.0, "updated_at": "2026-01-02T09:00:00Z"}))).await;
+    assert_eq!(status, warp::http::StatusCode::OK);
+    assert_eq!(saved, progress);
+    let newer = serde_json::json!({"percentage": 75.5, "anchor": "conclusion", "updated_at": "2026-01-02T11:00:00Z"});
+    let (_, saved) = reading_progress(&test_resources, jwt, id, Some(newer.clone())).await;
+    assert_eq!(saved, newer);
+    let (_, saved) = reading_progress(&test_resources, jwt, id, None).await;
+    assert_eq!(saved, newer);
+    let (status, _) = reading_progress(&test_resources, jwt, id, Some(serde_json::json!(
+        {"percentage": 120.0, "updated_at": "2026-01-02T12:00:00Z"}))).await;
+    assert_eq!(status, warp::http::StatusCode::BAD_REQUEST);
+    let (status, _) = reading_progress(&test_resources, &test_resources.admin_jwt, id, Some(serde_json::json!(
+        {"percentage": 1.0, "updated_at": "2026-01-02T12:00:00Z"}))).await;
+    assert_eq!(status, warp::http::StatusCode::NOT_FOUND);
+    let (status, _) = reading_progress(&test_resources, &test_resources.admin_jwt, id, None).await;
+    assert_eq!(status, warp::http::StatusCode::NOT_FOUND);
+}
+
+async fn send_highlight(test_resources: &TestResources, jwt: &str, method: reqwest::Method, path: &str,
+        body: Option<serde_json::Value>) -> (reqwest::StatusCode, String) {
+    let mut request = reqwest::Client::new().request(method, format!("http://{}:{}/api/webpage/{}",
+            test_resources.addr.ip(), test_resources.addr.port(), path))
+        .header(reqwest::header::AUTHORIZATION, &format!("bearer {}", jwt));
+    if let Some(body) = body {
+        request = request.body(body.to_string());
+    }
+    let response = request.send().await.expect("Error sending request to server");
+    (response.status(), response.text().await.expect("Unable to get text from response"))
+}
+
+#[tokio::test]
+async fn test_highlights() {
+    let test_resources = start_test_server().await;
+    let jwt = &test_resources.jwt;
+    let id = save_webpage(&test_resources, "https://example.com/page",
+        "<html><head><title>Page</title></head><body><p>One fish, two fish, red fish, blue fish.</p></body></html>", &[]).await;
+    let (status, body) = send_highlight(&test_resources, jwt, reqwest::Method::POST, &format!("{}/highlights", id),
+        Some(serde_json::json!({"selector": {"exact": "fish", "prefix": "red ", "suffix": ", blue"}, "note": "Colours"}))).await;
+    assert_eq!(status, warp::http::StatusCode::CREATED);
+    let created: Highlight = serde_json::from_str(&body).expect("Unable to parse response as highlight");
+    assert_eq!(created.selector, TextQuoteSelector {exact: "fish".into(), prefix: "red ".into(), suffix: ", blue".into()});
+    assert_eq!(created.note.as_deref(), Some("Colours"));
+    let (status, _) = send_highlight(&test_resources, jwt, reqwest::Method::POST, &format!("{}/highlights", id),
+        Some(serde_json::json!({"selector": {"exact": " "}}))).await;
+    assert_eq!(status, warp::http::StatusCode::BAD_REQUEST);
+    let (status, _) = send_highlight(&test_resources, &test_resources.admin_jwt, reqwest::Method::POST, &format!("{}/highlights", id),
+        Some(serde_json::json!({"selector": {"exact": "fish"}}))).await;
+    assert_eq!(status, warp::http::StatusCode::NOT_FOUND);
+    let (status, body) = send_highlight(&test_resources, jwt, reqwest::Method::PUT, &format!("{}/highlights/{}", id, created.id),
+        Some(serde_json::json!({"selector": {"exact": "fish", "prefix": "red ", "suffix": ", blue"}, "note": "Red"}))).await;
+    assert_eq!(status, warp::http::StatusCode::OK);
+    let updated: Highlight = serde_json::from_str(&body).expect("Unable to parse response as highlight");
+    assert_eq!(updated.note.as_deref(), Some("Red"));
+    let (_, body) = send_highlight(&test_resources, jwt, reqwest::Method::GET, &id.to_string(), None).await;
+    let webpage: serde_json::Value = serde_json::from_str(&body).expect("Unable to parse response as json");
+    assert_eq!(webpage["highlights"], serde_json::to_value(vec![&updated]).expect("Unable to serialize highlight"));
+    let (_, body) = send_highlight(&test_resources, jwt, reqwest::Method::GET, &format!("{}/export?format=singlefile", id), None).await;
+    assert!(body.contains("One fish, two fish, red <mark>fish</mark>, blue fish."));
+    assert!(body.contains("<blockquote><p>fish</p></blockquote><p>Red</p>"));
+    let (status, _) = send_highlight(&test_resources, &test_resources.admin_jwt, reqwest::Method::DELETE, &format!("{}/highlights/{}", id, created.id), None).await;
+    assert_eq!(status, warp::http::StatusCode::NOT_FOUND);
+    let (status, _) = send_highlight(&test_resources, jwt, reqwest::Method::DELETE, &format!("{}/highlights/{}", id, created.id), None).await;
+    assert_eq!(status, warp::http::StatusCode::NO_CONTENT);
+    let (status, body) = send_highlight(&test_resources, jwt, reqwest::Method::GET, &format!("{}/highlights", id), None).await;
+    assert_eq!(status, warp::http::StatusCode::OK);
+    assert_eq!(body, "{\"highlights\":[]}");
+}
+
+#[tokio::test]
+async fn test_delete_webpage() {
+    let test_resources = start_test_server().await;
+    execute_sql_from_file("tests/data/insert-webpage.sql", &test_resources.pool).await.expect("Unable to insert webpages");
+    let results = sqlx::query_as::<_, (String, String)>("SELECT url, title FROM webpages WHERE id = 1")
+        .fetch_optional(&test_resources.pool).await.expect("Unable to query for webpage prior to deleting");
+    assert!(results.is_some());
+    let client = reqwest::Client::new();
+    let response = client.delete(format!("http://{}:{}/api/webpage/1",
+            test_resources.addr.ip(), test_resources.addr.port()))
+        .header(reqwest::header::AUTHORIZATION, &format!("bearer {}", test_resources.jwt))
+        .send()
+        .await
+        .expect("Error sending request to server");
+    assert!(response.status().is_success());
+    let results = sqlx::query_as::<_, (String, String)>("SELECT url, title FROM webpages WHERE id = 1")
+        .fetch_optional(&test_resources.pool).await.expect("Unable to query for webpage after deleting");
+    assert!(results.is_none());
+}
+
+#[tokio::test]
+async fn test_register_user() {
+    let test_resources = start_test_server().await;
+    let users_pre = sqlx::query_as::<_, (i64,)>("select count(id) from users")
+        .fetch_one(&test_resources.pool).await.expect("Unable to get user count before registering");
+    assert_eq!(users_pre.0, 2);
+    let client = reqwest::Client::new();
+    let response = client.post(format!("http://{}:{}/api/register",
+            test_resources.addr.ip(), test_resources.addr.port()))
+        .body(serde_json::json!({"username": "new-user", "password": "Password123"}).to_string())
+        .header(reqwest::header::AUTHORIZATION, &format!("bearer {}", test_resources.admin_jwt))
+        .send()
+        .await
+        .expect("Error sending request to server");
+    assert!(response.status().is_success());
+    let users_post = sqlx::query_as::<_, (String, Vec<Role>)>("select username, roles from users where username = 'new-user'")
+        .fetch_one(&test_resources.pool).await.expect("Unable to get user count before registering");
+    assert_eq!(users_post.0, "new-user");
+    assert_eq!(users_post.1.len(), 1);
+    assert_eq!(users_post.1[0], Role::User);
+}
+
+#[tokio::test]
+async fn test_register_user_not_allowed_for_non_admins() {
+    let test_resources = start_test_server().await;
+    let client = reqwest::Client::new();
+    let response = client.post(format!("http://{}:{}/api/register",
+            test_resources.addr.ip(), test_resources.addr.port()))
+        .body(serde_json::json!({"username": "new-user", "password": "Password123"}).to_string())
+        .header(reqwest::header::AUTHORIZATION, &format!("bearer {}", test_resources.jwt))
+        .send()
+        .await
+        .expect("Error sending request to server");
+    assert_eq!(response.status(), warp::http::StatusCode::UNAUTHORIZED);
+    let new_user = sqlx::query_as::<_, (String, Vec<Role>)>("select username, roles from users where username = 'new-user'")
+        .fetch_optional(&test_resources.pool).await.expect("Unable to query for new user");
+    assert!(new_user.is_none());
+}
+
+#[tokio::test]
+async fn test_associate_app_to_user() {
+    let test_resources = start_test_server().await;
+    let apps_pre = sqlx::query_as::<_, (i64,)>("select count(id) from connected_apps")
+        .fetch_one(&test_resources.pool).await.expect("Unable to get app count before registering");
+    assert_eq!(apps_pre.0, 1);
+    let client = reqwest::Client::new();
+    let now = chrono::offset::Utc::now().format("%Y-%m-%dT%H:%M:%S");
+    let response = client.post(format!("http://{}:{}/api/associate-app-to-user",
+            test_resources.addr.ip(), test_resources.addr.port()))
+        .body(serde_json::json!({"sub": "sub", "client_id": &format!("client_id_{}", now),
+            "app_host": &format!("app_host_{}", now)}).to_string())
+        .header(reqwest::header::AUTHORIZATION, &format!("bearer {}", test_resources.jwt))
+        .send()
+        .await
+        .expect("Error sending request to server");
+    assert_eq!(response.status(), warp::http::StatusCode::CREATED);
+    let apps_post = sqlx::query_as::<_, (i64,String,String,String)>(
+            "select user_id, sub, client_id, app_host from connected_apps where user_id = 1 order by id desc limit 1")
+        .fetch_optional(&test_resources.pool).await.expect(
+            "Unable to get app count before registering");
+    assert!(apps_post.is_some());
+    if let Some(apps_post) = apps_post {
+        let (user_id, sub, client_id, app_host) = apps_post;
+        assert_eq!(user_id, 1);
+        assert_eq!(sub, "sub");
+        assert_eq!(client_id, format!("client_id_{}", now));
+        assert_eq!(app_host, format!("app_host_{}", now));
+    }
+}
+
+#[tokio::test]
+async fn test_associate_app_to_user_existing_app() {
+    let test_resources = start_test_server().await;
+    let client = reqwest::Client::new();
+
+    sqlx::query("insert into connected_apps(user_id, sub, client_id, app_host) values (1, 'sub', 'client_id', 'app_host')")
+        .execute(&test_resources.pool).await
+        .expect("Unable to insert app into database");
+
+    let response = client.post(format!("http://{}:{}/api/associate-app-to-user",
+            test_resources.addr.ip(), test_resources.addr.port()))
+        .body(serde_json::json!({"sub": "sub", "client_id": "client_id",
+            "app_host": "app_host"}).to_string())
+        .header(reqwest::header::AUTHORIZATION, &format!("bearer {}", test_resources.jwt))
+        .send()
+        .await
+        .expect("Error sending request to server");
+    assert_eq!(response.status(), warp::http::StatusCode::CREATED);
     let apps_post = sqlx::query_as::<_, (i64,String,String,String)>(
             "select user_id, sub, client_id, app_host from connected_apps where user_id = 1 order by id desc")
         .fetch_one(&test_resources.pool).await.expect(
@@ -477,6 +1355,30 @@ async fn test_associate_app_to_user_malformed_json() {
 }
 
 async fn start_test_server() -> TestResources {
+    start_test_server_with_http_config(test_http_config()).await
+}
+
+fn test_http_config() -> HttpClientConfig {
+    HttpClientConfig {
+        read_timeout: std::time::Duration::from_secs(1),
+        timeout: std::time::Duration::from_secs(5),
+        max_redirects: 3,
+        user_agent: "article-server-test".to_string(),
+        accept_language: "da".to_string(),
+        max_response_size: 64 * 1024,
+        // The mock servers run on localhost.
+        url_policy: UrlPolicy {allow_private_addresses: true, ..UrlPolicy::default()},
+        // All mock servers have the same host.
+        politeness: PolitenessConfig {
+            max_requests_per_host: 4,
+            request_delay: std::time::Duration::ZERO,
+            ..PolitenessConfig::default()
+        },
+        ..HttpClientConfig::default()
+    }
+}
+
+async fn start_test_server_with_http_config(http: HttpClientConfig) -> TestResources {
     init_logging();
     let addr = get_address();
     let pool = create_db().await;
@@ -487,7 +1389,7 @@ async fn start_test_server() -> TestResources {
     let jwt = create_jwt(&user, secret.as_ref(), 60 * 60 * 24).expect("Error creating jwt");
     let (admin_user, admin_secret) = sqlx::query_as::<_, (String, String)>("SELECT users.username, jwt_secrets.secret FROM jwt_secrets JOIN users on users.id = jwt_secrets.user_id where users.id = 2").fetch_one(&pool).await.expect("Unable to fetch admin jwt secret");
     let admin_jwt = create_jwt(&admin_user, admin_secret.as_ref(), 60 * 60 * 24).expect("Error creating admin jwt");
-    let _ = tokio::spawn(async move {
+    tokio::spawn(async move {
         // Starting a task with a server started by warp::Server::run is possibly impossible to do
         // at the moment. I get the error
         // 28 |         rt.spawn(setup_server(addr));
@@ -497,7 +1399,7 @@ async fn start_test_server() -> TestResources {
         //    = note: ...but `warp::reply::Reply` is actually implemented for the type `&'static str`
         // I could get it to work by using Server::bind instead but that then creates an
         // intermediate Future and that's why I have the double await here.
-        setup_server(addr, cloned_pool).await.await;
+        setup_server(addr, cloned_pool, http).await.await;
     });
     tokio::time::sleep(std::time::Duration::from_secs(1)).await;
     TestResources {
@@ -508,10 +1410,712 @@ async fn start_test_server() -> TestResources {
     }
 }
 
-async fn setup_server(addr: SocketAddr, pool: PgPool) -> impl core::future::Future {
+async fn setup_server(addr: SocketAddr, pool: PgPool, http: HttpClientConfig) -> impl core::future::Future {
     let server_args = ServerArgs {
         pool,
         addr,
+        // Failed jobs are retried right away so the tests don't have to wait.
+        jobs: JobQueueConfig {
+            workers: 2,
+            max_attempts: 3,
+            retry_delay: std::time::Duration::from_millis(10),
+            poll_interval: std::time::Duration::from_millis(50),
+        },
+        http,
+        site_config_dir: None,
     };
     start_server(server_args)
 }
+
+#[tokio::test]
+async fn test_fetch_duplicate_url() {
+    let test_resources = start_test_server().await;
+    let html = "<html><head><title>First</title><link rel=\"canonical\" href=\"https://example.com/article\"></head><body><p>First version</p></body></html>";
+    let job = wait_for_job(&test_resources, post_fetch(&test_resources,
+        serde_json::json!({"url": "https://example.com/article/?utm_source=feed#comments", "html": html})).await).await;
+    assert_eq!(job["result"], "created");
+    let id = job["webpage_id"].as_i64().expect("Missing webpage id");
+    let (url,) = sqlx::query_as::<_, (String,)>("select url from webpages where id = $1")
+        .bind(id)
+        .fetch_one(&test_resources.pool).await.expect("Unable to query for fetched webpage");
+    assert_eq!(url, "https://example.com/article");
+
+    // The same url with other tracking parameters is found before a job is queued.
+    for (on_duplicate, status) in [("existing", warp::http::StatusCode::OK), ("conflict", warp::http::StatusCode::CONFLICT)] {
+        let response = post_fetch(&test_resources, serde_json::json!({"url": "https://example.com:443/article?fbclid=abc",
+            "on_duplicate": on_duplicate})).await;
+        assert_eq!(response.status(), status);
+        let json: serde_json::Value = serde_json::from_str(&response.text().await.expect("Unable to get text from response"))
+            .expect("Unable to parse response as json");
+        assert_eq!(json, serde_json::json!({"id": id}));
+    }
+    // Another url pointing to the same canonical url is only found once it has been fetched.
+    let amp_html = "<html><head><title>AMP</title><link rel=\"canonical\" href=\"https://example.com/article/\"></head><body><p>AMP version</p></body></html>";
+    let job = wait_for_job(&test_resources, post_fetch(&test_resources,
+        serde_json::json!({"url": "https://example.com/amp/article", "html": amp_html, "on_duplicate": "conflict"})).await).await;
+    assert_eq!(job["result"], "duplicate");
+    assert_eq!(job["webpage_id"], id);
+    // A canonical url on another host isn't trusted, so the page can't overwrite the article.
+    let other_html = "<html><head><title>Other</title><link rel=\"canonical\" href=\"https://example.com/article\"></head><body><p>Another site</p></body></html>";
+    let job = wait_for_job(&test_resources, post_fetch(&test_resources,
+        serde_json::json!({"url": "https://other.example.org/page", "html": other_html, "on_duplicate": "update"})).await).await;
+    assert_eq!(job["result"], "created");
+    assert_ne!(job["webpage_id"], id);
+
+    let updated_html = "<html><head><title>Updated</title></head><body><p>Second version</p></body></html>";
+    let job = wait_for_job(&test_resources, post_fetch(&test_resources, serde_json::json!({"url": "https://example.com/article",
+        "html": updated_html, "tags": ["news"], "on_duplicate": "update"})).await).await;
+    assert_eq!(job["result"], "updated");
+    assert_eq!(job["webpage_id"], id);
+    let (title, count) = sqlx::query_as::<_, (String, i64)>("select title, (select count(*) from webpages) from webpages where id = $1")
+        .bind(id)
+        .fetch_one(&test_resources.pool).await.expect("Unable to query for updated webpage");
+    assert_eq!(title, "Updated");
+    assert_eq!(count, 2);
+    assert_eq!(list_tags(&test_resources).await, vec![tag_count("news", 1)]);
+}
+
+#[tokio::test]
+async fn test_backfill_url_keys() {
+    let test_resources = start_test_server().await;
+    // Webpages saved before url keys were added, two of them from the same page.
+    for url in ["https://example.com/article/?utm_source=feed#comments", "https://example.com/article", "https://example.com/other"] {
+        sqlx::query("insert into webpages(url, title, text, html, user_id) values ($1, 'Title', 'Text', 'Html', 1)")
+            .bind(url)
+            .execute(&test_resources.pool).await.expect("Unable to insert webpage");
+    }
+    migrate_db(&test_resources.pool).await.expect("Unable to migrate database");
+    let url_keys = sqlx::query_as::<_, (Option<String>,)>("select url_key from webpages order by id")
+        .fetch_all(&test_resources.pool).await.expect("Unable to query for url keys");
+    assert_eq!(url_keys, vec![(Some("https://example.com/article".to_string()),), (None,),
+        (Some("https://example.com/other".to_string()),)]);
+    let (id,) = sqlx::query_as::<_, (i64,)>("select min(id) from webpages")
+        .fetch_one(&test_resources.pool).await.expect("Unable to query for webpage id");
+    let response = post_fetch(&test_resources, serde_json::json!({"url": "https://example.com/article?fbclid=abc",
+        "on_duplicate": "conflict"})).await;
+    assert_eq!(response.status(), warp::http::StatusCode::CONFLICT);
+    let json: serde_json::Value = serde_json::from_str(&response.text().await.expect("Unable to get text from response"))
+        .expect("Unable to parse response as json");
+    assert_eq!(json, serde_json::json!({"id": id}));
+}
+
+#[tokio::test]
+async fn test_fetch_job_retried() {
+    let test_resources = start_test_server().await;
+    let mock_server = MockServer::start().await;
+    Mock::given(wiremock::matchers::method("GET"))
+        .and(wiremock::matchers::path("busy"))
+        .respond_with(ResponseTemplate::new(503))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+    Mock::given(wiremock::matchers::method("GET"))
+        .and(wiremock::matchers::path("busy"))
+        .respond_with(ResponseTemplate::new(200)
+            .set_body_raw("<html><head><title>Busy</title></head><body><p>Eventually fetched</p></body></html>", "text/html"))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+    Mock::given(wiremock::matchers::method("GET"))
+        .and(wiremock::matchers::path("missing"))
+        .respond_with(ResponseTemplate::new(404))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+    let response = post_fetch(&test_resources, serde_json::json!({"url": format!("{}/busy", mock_server.uri())})).await;
+    let location = response.headers().get(reqwest::header::LOCATION)
+        .and_then(|value| value.to_str().ok()).map(|value| value.to_string());
+    let job = wait_for_job(&test_resources, response).await;
+    assert_eq!(location, Some(format!("/api/jobs/{}", job["id"])));
+    assert_eq!(job["status"], "succeeded");
+    assert_eq!(job["attempts"], 2);
+    assert_eq!(job["error"], serde_json::Value::Null);
+    // Client errors aren't retried.
+    let job = wait_for_job(&test_resources, post_fetch(&test_resources,
+        serde_json::json!({"url": format!("{}/missing", mock_server.uri())})).await).await;
+    assert_eq!(job["status"], "failed");
+    assert_eq!(job["attempts"], 1);
+    // Users can only see their own jobs.
+    let response = reqwest::Client::new().get(format!("http://{}:{}/api/jobs/{}",
+            test_resources.addr.ip(), test_resources.addr.port(), job["id"]))
+        .header(reqwest::header::AUTHORIZATION, &format!("bearer {}", test_resources.admin_jwt))
+        .send()
+        .await
+        .expect("Error sending request to server");
+    assert_eq!(response.status(), warp::http::StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_refetch_snapshots() {
+    let test_resources = start_test_server().await;
+    let client = reqwest::Client::new();
+    let mock_server = MockServer::start().await;
+    Mock::given(wiremock::matchers::method("GET"))
+        .and(wiremock::matchers::path("article"))
+        .respond_with(ResponseTemplate::new(200)
+            .set_body_string("<html><head><title>Changed</title></head><body><p>The second version of the article</p></body></html>"))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+    Mock::given(wiremock::matchers::method("GET"))
+        .and(wiremock::matchers::path("gone"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&mock_server)
+        .await;
+    let id = save_webpage(&test_resources, &format!("{}/article", mock_server.uri()),
+        "<html><head><title>Original</title></head><body><p>The first version of the article</p></body></html>", &[]).await;
+    let base = format!("http://{}:{}/api/webpage/{}", test_resources.addr.ip(), test_resources.addr.port(), id);
+
+    let response = client.post(format!("{}/refetch", base))
+        .header(reqwest::header::AUTHORIZATION, &format!("bearer {}", test_resources.admin_jwt))
+        .send()
+        .await
+        .expect("Error sending request to server");
+    assert_eq!(response.status(), warp::http::StatusCode::NOT_FOUND);
+    let response = client.post(format!("{}/refetch", base))
+        .header(reqwest::header::AUTHORIZATION, &format!("bearer {}", test_resources.jwt))
+        .send()
+        .await
+        .expect("Error sending request to server");
+    assert_eq!(response.status(), warp::http::StatusCode::CREATED);
+    let snapshot: serde_json::Value = serde_json::from_str(&response.text().await.expect("Unable to get text from response"))
+        .expect("Unable to parse response as json");
+    assert_eq!(snapshot["title"], "Changed");
+    let response = client.get(format!("{}/snapshots", base))
+        .header(reqwest::header::AUTHORIZATION, &format!("bearer {}", test_resources.jwt))
+        .send()
+        .await
+        .expect("Error sending request to server");
+    let snapshots: serde_json::Value = serde_json::from_str(&response.text().await.expect("Unable to get text from response"))
+        .expect("Unable to parse response as json");
+    assert_eq!(snapshots["snapshots"], serde_json::json!([snapshot]));
+
+    let show = |query: Vec<(&'static str, String)>| {
+        let request = client.get(&base)
+            .query(&query)
+            .header(reqwest::header::AUTHORIZATION, &format!("bearer {}", test_resources.jwt));
+        async move {
+            let response = request.send().await.expect("Error sending request to server");
+            let status = response.status();
+            let json: serde_json::Value = serde_json::from_str(&response.text().await.expect("Unable to get text from response"))
+                .expect("Unable to parse response as json");
+            (status, json)
+        }
+    };
+    let (_, webpage) = show(vec![]).await;
+    assert_eq!(webpage["title"], "Original");
+    assert_eq!(webpage["snapshot"], serde_json::Value::Null);
+    let (_, webpage) = show(vec![("snapshot", snapshot["id"].to_string())]).await;
+    assert_eq!(webpage["title"], "Changed");
+    assert!(webpage["content"].as_str().expect("Missing content").contains("The second version"));
+    assert_eq!(webpage["snapshot"], snapshot);
+    let fetched = snapshot["fetched"].as_str().expect("Missing fetched").to_string();
+    let (_, webpage) = show(vec![("at", fetched)]).await;
+    assert_eq!(webpage["title"], "Changed");
+    let (_, webpage) = show(vec![("at", "2000-01-01T00:00:00Z".to_string())]).await;
+    assert_eq!(webpage["title"], "Original");
+    let (status, _) = show(vec![("snapshot", "0".to_string())]).await;
+    assert_eq!(status, warp::http::StatusCode::NOT_FOUND);
+
+    let response = client.get(format!("{}/diff", base))
+        .header(reqwest::header::AUTHORIZATION, &format!("bearer {}", test_resources.jwt))
+        .send()
+        .await
+        .expect("Error sending request to server");
+    assert_eq!(response.status(), warp::http::StatusCode::OK);
+    let diff: serde_json::Value = serde_json::from_str(&response.text().await.expect("Unable to get text from response"))
+        .expect("Unable to parse response as json");
+    assert_eq!(diff, serde_json::json!({"from": null, "to": snapshot["id"], "changes": [
+        {"change": "equal", "text": "The "},
+        {"change": "delete", "text": "first"},
+        {"change": "insert", "text": "second"},
+        {"change": "equal", "text": " version of the article"},
+    ]}));
+
+    let gone = save_webpage(&test_resources, &format!("{}/gone", mock_server.uri()),
+        "<html><head><title>Gone</title></head><body><p>Soon gone</p></body></html>", &[]).await;
+    let response = client.post(format!("http://{}:{}/api/webpage/{}/refetch",
+            test_resources.addr.ip(), test_resources.addr.port(), gone))
+        .header(reqwest::header::AUTHORIZATION, &format!("bearer {}", test_resources.jwt))
+        .send()
+        .await
+        .expect("Error sending request to server");
+    assert_eq!(response.status(), warp::http::StatusCode::BAD_GATEWAY);
+}
+
+#[tokio::test]
+async fn test_snapshot_assets_kept_when_webpage_updated() {
+    let test_resources = start_test_server().await;
+    let client = reqwest::Client::new();
+    let mock_server = MockServer::start().await;
+    Mock::given(wiremock::matchers::method("GET"))
+        .and(wiremock::matchers::path("article"))
+        .respond_with(ResponseTemplate::new(200)
+            .set_body_string("<html><head><title>Changed</title></head><body><p><img src=\"/images/new.png\">The second version of the article</p></body></html>"))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+    let image = b"\x89PNG new image data".to_vec();
+    Mock::given(wiremock::matchers::method("GET"))
+        .and(wiremock::matchers::path("images/new.png"))
+        .respond_with(ResponseTemplate::new(200)
+            .insert_header("content-type", "image/png")
+            .set_body_bytes(image.clone()))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+    let url = format!("{}/article", mock_server.uri());
+    let id = save_webpage(&test_resources, &url,
+        "<html><head><title>Original</title></head><body><p>The first version of the article</p></body></html>", &[]).await;
+    let response = client.post(format!("http://{}:{}/api/webpage/{}/refetch",
+            test_resources.addr.ip(), test_resources.addr.port(), id))
+        .header(reqwest::header::AUTHORIZATION, &format!("bearer {}", test_resources.jwt))
+        .send()
+        .await
+        .expect("Error sending request to server");
+    assert_eq!(response.status(), warp::http::StatusCode::CREATED);
+
+    let job = wait_for_job(&test_resources, post_fetch(&test_resources, serde_json::json!({"url": url,
+        "html": "<html><head><title>Updated</title></head><body><p>The third version of the article</p></body></html>",
+        "on_duplicate": "update"})).await).await;
+    assert_eq!(job["result"], "updated");
+    let response = client.get(format!("http://{}:{}/api/asset/{:x}",
+            test_resources.addr.ip(), test_resources.addr.port(), Sha256::digest(&image)))
+        .header(reqwest::header::AUTHORIZATION, &format!("bearer {}", test_resources.jwt))
+        .send()
+        .await
+        .expect("Error sending request to server");
+    assert_eq!(response.status(), warp::http::StatusCode::OK);
+    assert_eq!(response.bytes().await.expect("Unable to get body of response").to_vec(), image);
+}
+
+#[tokio::test]
+async fn test_fetch_limits() {
+    let test_resources = start_test_server().await;
+    let mock_server = MockServer::start().await;
+    let html = "<html><head><title>Title</title></head><body><p>An html document</p></body></html>";
+    Mock::given(wiremock::matchers::method("GET"))
+        .and(wiremock::matchers::path("slow"))
+        .respond_with(ResponseTemplate::new(200)
+            .set_body_raw(html, "text/html")
+            .set_delay(std::time::Duration::from_secs(2)))
+        .mount(&mock_server)
+        .await;
+    Mock::given(wiremock::matchers::method("GET"))
+        .and(wiremock::matchers::path("large"))
+        .respond_with(ResponseTemplate::new(200)
+            .set_body_raw(format!("<html><body><p>{}</p></body></html>", "text ".repeat(20000)), "text/html"))
+        .mount(&mock_server)
+        .await;
+    Mock::given(wiremock::matchers::method("GET"))
+        .and(wiremock::matchers::path("loop"))
+        .respond_with(ResponseTemplate::new(302)
+            .insert_header("location", "/loop"))
+        .mount(&mock_server)
+        .await;
+    Mock::given(wiremock::matchers::method("GET"))
+        .and(wiremock::matchers::path("headers"))
+        .and(wiremock::matchers::header("user-agent", "article-server-test"))
+        .and(wiremock::matchers::header("accept-language", "da"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(html, "text/html"))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let job = wait_for_job(&test_resources, post_fetch(&test_resources,
+        serde_json::json!({"url": format!("{}/headers", mock_server.uri())})).await).await;
+    assert_eq!(job["status"], "succeeded", "{}", job);
+    // Timeouts are retried.
+    let job = wait_for_job(&test_resources, post_fetch(&test_resources,
+        serde_json::json!({"url": format!("{}/slow", mock_server.uri())})).await).await;
+    assert_eq!(job["status"], "failed");
+    assert_eq!(job["attempts"], 3);
+    assert!(job["error"].as_str().is_some_and(|error| error.contains("No response received")), "{}", job);
+    let job = wait_for_job(&test_resources, post_fetch(&test_resources,
+        serde_json::json!({"url": format!("{}/large", mock_server.uri())})).await).await;
+    assert_eq!(job["status"], "failed");
+    assert_eq!(job["attempts"], 1);
+    assert!(job["error"].as_str().is_some_and(|error| error.contains("larger than 65536 bytes")), "{}", job);
+    let job = wait_for_job(&test_resources, post_fetch(&test_resources,
+        serde_json::json!({"url": format!("{}/loop", mock_server.uri())})).await).await;
+    assert_eq!(job["status"], "failed");
+    assert_eq!(job["attempts"], 1);
+}
+
+#[tokio::test]
+async fn test_fetch_webpage_charset() {
+    let test_resources = start_test_server().await;
+    let mock_server = MockServer::start().await;
+    // The encoding is only declared by a <meta> element.
+    Mock::given(wiremock::matchers::method("GET"))
+        .and(wiremock::matchers::path("shift-jis"))
+        .respond_with(ResponseTemplate::new(200)
+            .set_body_raw(include_bytes!("../src/test-data/charset-shift-jis.html").to_vec(), "text/html"))
+        .mount(&mock_server)
+        .await;
+    // The encoding isn't declared at all.
+    Mock::given(wiremock::matchers::method("GET"))
+        .and(wiremock::matchers::path("koi8-r"))
+        .respond_with(ResponseTemplate::new(200)
+            .set_body_raw(include_bytes!("../src/test-data/charset-koi8-r.html").to_vec(), "text/html"))
+        .mount(&mock_server)
+        .await;
+
+    for (path, title, text) in [
+            ("shift-jis", "日本語の記事", "これは日本語で書かれた記事です。"),
+            ("koi8-r", "Статья на русском языке", "Это статья, написанная на русском языке.")] {
+        let url = format!("{}/{}", mock_server.uri(), path);
+        let job = wait_for_job(&test_resources, post_fetch(&test_resources,
+            serde_json::json!({"url": url})).await).await;
+        assert_eq!(job["status"], "succeeded", "{}", job);
+        let (saved_title, saved_text) = sqlx::query_as::<_, (String, String)>("select title, text from webpages where id = $1")
+            .bind(job["webpage_id"].as_i64())
+            .fetch_one(&test_resources.pool).await.expect("Unable to query for fetched webpage");
+        assert_eq!(saved_title, title);
+        assert!(saved_text.contains(text), "{}", saved_text);
+    }
+}
+
+#[tokio::test]
+async fn test_fetch_url_policy() {
+    let test_resources = start_test_server_with_http_config(HttpClientConfig {
+        url_policy: UrlPolicy::default(),
+        ..test_http_config()
+    }).await;
+    let mock_server = MockServer::start().await;
+    let html = "<html><head><title>Internal</title></head><body><p>An internal document</p></body></html>";
+    Mock::given(wiremock::matchers::method("GET"))
+        .and(wiremock::matchers::path("internal"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(html, "text/html"))
+        .expect(0)
+        .mount(&mock_server)
+        .await;
+    let port = mock_server.address().port();
+
+    for url in [format!("http://127.0.0.1:{}/internal", port), format!("http://localhost:{}/internal", port),
+            "http://169.254.169.254/latest/meta-data/".to_string(), "file:///etc/passwd".to_string()] {
+        let job = wait_for_job(&test_resources, post_fetch(&test_resources,
+            serde_json::json!({"url": url})).await).await;
+        assert_eq!(job["status"], "failed", "{}", job);
+        // Forbidden urls aren't retried.
+        assert_eq!(job["attempts"], 1, "{}", job);
+    }
+
+    // Redirects from an allowed site to a forbidden address aren't followed.
+    let allowed_test_resources = start_test_server_with_http_config(HttpClientConfig {
+        url_policy: UrlPolicy {allow: vec!["localhost".parse().unwrap()], ..UrlPolicy::default()},
+        ..test_http_config()
+    }).await;
+    Mock::given(wiremock::matchers::method("GET"))
+        .and(wiremock::matchers::path("redirect"))
+        .respond_with(ResponseTemplate::new(302)
+            .insert_header("location", format!("http://127.0.0.1:{}/internal", port).as_str()))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+    let job = wait_for_job(&allowed_test_resources, post_fetch(&allowed_test_resources,
+        serde_json::json!({"url": format!("http://localhost:{}/redirect", port)})).await).await;
+    assert_eq!(job["status"], "failed", "{}", job);
+    assert!(job["error"].as_str().is_some_and(|error| error.contains("127.0.0.1 is not a public address")), "{}", job);
+}
+
+#[tokio::test]
+async fn test_fetch_url_policy_allow_list() {
+    let test_resources = start_test_server_with_http_config(HttpClientConfig {
+        url_policy: UrlPolicy {allow: vec!["127.0.0.0/8".parse().unwrap()], ..UrlPolicy::default()},
+        ..test_http_config()
+    }).await;
+    let mock_server = MockServer::start().await;
+    let html = "<html><head><title>Internal</title></head><body><p>An internal document</p></body></html>";
+    Mock::given(wiremock::matchers::method("GET"))
+        .and(wiremock::matchers::path("internal"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(html, "text/html"))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+    let job = wait_for_job(&test_resources, post_fetch(&test_resources,
+        serde_json::json!({"url": format!("{}/internal", mock_server.uri())})).await).await;
+    assert_eq!(job["status"], "succeeded", "{}", job);
+}
+
+#[tokio::test]
+async fn test_fetch_robots_txt() {
+    let test_resources = start_test_server_with_http_config(HttpClientConfig {
+        politeness: PolitenessConfig {respect_robots_txt: true, ..test_http_config().politeness},
+        ..test_http_config()
+    }).await;
+    let mock_server = MockServer::start().await;
+    let html = "<html><head><title>Title</title></head><body><p>An html document</p></body></html>";
+    Mock::given(wiremock::matchers::method("GET"))
+        .and(wiremock::matchers::path("robots.txt"))
+        .respond_with(ResponseTemplate::new(200)
+            .set_body_raw("User-agent: *\nDisallow: /\n\nUser-agent: article-server-test\nDisallow: /private/\n", "text/plain"))
+        // robots.txt is cached.
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+    Mock::given(wiremock::matchers::method("GET"))
+        .and(wiremock::matchers::path("public"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(html, "text/html"))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+    Mock::given(wiremock::matchers::method("GET"))
+        .and(wiremock::matchers::path("private/page"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(html, "text/html"))
+        .expect(0)
+        .mount(&mock_server)
+        .await;
+
+    let job = wait_for_job(&test_resources, post_fetch(&test_resources,
+        serde_json::json!({"url": format!("{}/public", mock_server.uri())})).await).await;
+    assert_eq!(job["status"], "succeeded", "{}", job);
+    let job = wait_for_job(&test_resources, post_fetch(&test_resources,
+        serde_json::json!({"url": format!("{}/private/page", mock_server.uri())})).await).await;
+    assert_eq!(job["status"], "failed", "{}", job);
+    assert_eq!(job["attempts"], 1);
+    assert!(job["error"].as_str().is_some_and(|error| error.contains("disallowed by robots.txt")), "{}", job);
+}
+
+#[tokio::test]
+async fn test_fetch_retry_after() {
+    let test_resources = start_test_server().await;
+    let mock_server = MockServer::start().await;
+    Mock::given(wiremock::matchers::method("GET"))
+        .and(wiremock::matchers::path("busy"))
+        .respond_with(ResponseTemplate::new(429).insert_header("retry-after", "1"))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+    Mock::given(wiremock::matchers::method("GET"))
+        .and(wiremock::matchers::path("busy"))
+        .respond_with(ResponseTemplate::new(200)
+            .set_body_raw("<html><head><title>Busy</title></head><body><p>Eventually fetched</p></body></html>", "text/html"))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let start = std::time::Instant::now();
+    let job = wait_for_job(&test_resources, post_fetch(&test_resources,
+        serde_json::json!({"url": format!("{}/busy", mock_server.uri())})).await).await;
+    assert_eq!(job["status"], "succeeded", "{}", job);
+    assert_eq!(job["attempts"], 2);
+    // The retry waits for Retry-After rather than the much shorter retry delay of the queue.
+    assert!(start.elapsed() >= std::time::Duration::from_secs(1));
+}
+
+#[tokio::test]
+async fn test_fetch_asset_retry_after() {
+    let test_resources = start_test_server().await;
+    let mock_server = MockServer::start().await;
+    Mock::given(wiremock::matchers::method("GET"))
+        .and(wiremock::matchers::path("article"))
+        .respond_with(ResponseTemplate::new(200)
+            .set_body_raw("<html><head><title>Article</title></head><body><p><img src=\"/images/busy.png\">An article</p></body></html>", "text/html"))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+    Mock::given(wiremock::matchers::method("GET"))
+        .and(wiremock::matchers::path("images/busy.png"))
+        .respond_with(ResponseTemplate::new(503).insert_header("retry-after", "2"))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+    Mock::given(wiremock::matchers::method("GET"))
+        .and(wiremock::matchers::path("another-article"))
+        .respond_with(ResponseTemplate::new(200)
+            .set_body_raw("<html><head><title>Another article</title></head><body><p>Another article</p></body></html>", "text/html"))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let start = std::time::Instant::now();
+    for path in ["article", "another-article"] {
+        let job = wait_for_job(&test_resources, post_fetch(&test_resources,
+            serde_json::json!({"url": format!("{}/{}", mock_server.uri(), path)})).await).await;
+        assert_eq!(job["status"], "succeeded", "{}", job);
+    }
+    // The host is left alone for the time the image download was asked to wait.
+    assert!(start.elapsed() >= std::time::Duration::from_secs(2));
+}
+
+#[tokio::test]
+async fn test_fetch_webpage_multiple_pages() {
+    let test_resources = start_test_server().await;
+    let mock_server = MockServer::start().await;
+    let page = |number: usize, head: &str, links: &str| format!("<html><head><title>Long article</title>{}</head><body><article><p>Part {} of the long article.</p></article><nav>{}</nav></body></html>",
+        head, number, links);
+    Mock::given(wiremock::matchers::method("GET"))
+        .and(wiremock::matchers::path("article"))
+        .and(wiremock::matchers::query_param("page", "2"))
+        .respond_with(ResponseTemplate::new(200)
+            .set_body_raw(page(2, "", "<a href=\"/article\">1</a> <a href=\"/article?page=3\">3</a>"), "text/html"))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+    Mock::given(wiremock::matchers::method("GET"))
+        .and(wiremock::matchers::path("article"))
+        .and(wiremock::matchers::query_param("page", "3"))
+        // The last page links back to the first page.
+        .respond_with(ResponseTemplate::new(200)
+            .set_body_raw(page(3, "<link rel=\"next\" href=\"/article\">", ""), "text/html"))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+    Mock::given(wiremock::matchers::method("GET"))
+        .and(wiremock::matchers::path("article"))
+        .respond_with(ResponseTemplate::new(200)
+            .set_body_raw(page(1, "<link rel=\"next\" href=\"/article?page=2\"><link rel=\"alternate\" href=\"https://other.example.com/\">", ""), "text/html"))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let job = wait_for_job(&test_resources, post_fetch(&test_resources,
+        serde_json::json!({"url": format!("{}/article", mock_server.uri())})).await).await;
+    assert_eq!(job["status"], "succeeded", "{}", job);
+    let (title, text) = sqlx::query_as::<_, (String, String)>("select title, text from webpages where id = $1")
+        .bind(job["webpage_id"].as_i64())
+        .fetch_one(&test_resources.pool).await.expect("Unable to query for fetched webpage");
+    assert_eq!(title, "Long article");
+    let positions: Vec<usize> = (1..=3)
+        .map(|part| text.find(&format!("Part {} of the long article.", part)).expect(&text))
+        .collect();
+    assert!(positions.windows(2).all(|pair| pair[0] < pair[1]), "{}", text);
+}
+
+#[tokio::test]
+async fn test_fetch_webpage_next_post_not_appended() {
+    let test_resources = start_test_server().await;
+    let mock_server = MockServer::start().await;
+    let post = |text: &str, head: &str, footer: &str| format!("<html><head><title>A blog</title>{}</head><body><article><p>{}</p><footer>{}</footer></article></body></html>",
+        head, text, footer);
+    Mock::given(wiremock::matchers::method("GET"))
+        .and(wiremock::matchers::path("a-post"))
+        .respond_with(ResponseTemplate::new(200)
+            .set_body_raw(post("The text of a post about gardening.", "",
+                "<a rel=\"next\" href=\"/another-post\">Next post</a>"), "text/html"))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+    Mock::given(wiremock::matchers::method("GET"))
+        .and(wiremock::matchers::path("another-post"))
+        .respond_with(ResponseTemplate::new(200)
+            .set_body_raw(post("The text of another post about cooking.", "", ""), "text/html"))
+        .expect(0)
+        .mount(&mock_server)
+        .await;
+    // A misconfigured blog with a <link rel="next"> to the next post, which has its own
+    // canonical url.
+    Mock::given(wiremock::matchers::method("GET"))
+        .and(wiremock::matchers::path("third-post"))
+        .respond_with(ResponseTemplate::new(200)
+            .set_body_raw(post("The text of a third post about birds.", "<link rel=\"next\" href=\"/?p=4\">", ""), "text/html"))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+    Mock::given(wiremock::matchers::method("GET"))
+        .and(wiremock::matchers::path("/"))
+        .and(wiremock::matchers::query_param("p", "4"))
+        .respond_with(ResponseTemplate::new(200)
+            .set_body_raw(post("The text of a fourth post about bees.", "<link rel=\"canonical\" href=\"/fourth-post\">", ""), "text/html"))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    for (path, text, other_text) in [("a-post", "gardening", "cooking"), ("third-post", "birds", "bees")] {
+        let job = wait_for_job(&test_resources, post_fetch(&test_resources,
+            serde_json::json!({"url": format!("{}/{}", mock_server.uri(), path)})).await).await;
+        assert_eq!(job["status"], "succeeded", "{}", job);
+        let (webpage_text,) = sqlx::query_as::<_, (String,)>("select text from webpages where id = $1")
+            .bind(job["webpage_id"].as_i64())
+            .fetch_one(&test_resources.pool).await.expect("Unable to query for fetched webpage");
+        assert!(webpage_text.contains(text), "{}", webpage_text);
+        assert!(!webpage_text.contains(other_text), "{}", webpage_text);
+    }
+}
+
+#[tokio::test]
+async fn test_show_webpage_text_and_markdown() {
+    let test_resources = start_test_server().await;
+    let id = save_webpage(&test_resources, "https://example.com/article",
+        "<html><head><title>An article</title></head><body><article><h2>A heading</h2><p>A paragraph with a <a href=\"/other\">link</a> and <strong>bold</strong> text which is long enough to be readable.</p><ul><li>One</li><li>Two</li></ul></article></body></html>",
+        &[]).await;
+    let show = |mode: &'static str| {
+        let test_resources = &test_resources;
+        async move {
+            let response = reqwest::Client::new().get(format!("http://{}:{}/api/webpage/{}?mode={}",
+                    test_resources.addr.ip(), test_resources.addr.port(), id, mode))
+                .header(reqwest::header::AUTHORIZATION, &format!("bearer {}", test_resources.jwt))
+                .send()
+                .await
+                .expect("Error sending request to server");
+            assert_eq!(response.status(), warp::http::StatusCode::OK);
+            let content_type = response.headers()[reqwest::header::CONTENT_TYPE].to_str().unwrap().to_string();
+            (content_type, response.text().await.expect("Unable to get text from response"))
+        }
+    };
+    let (content_type, text) = show("text").await;
+    assert_eq!(content_type, "text/plain; charset=utf-8");
+    assert_eq!(text, "An article\n\nA heading\n\nA paragraph with a link and bold text which is long enough to be readable.\n\n- One\n- Two\n");
+    let (content_type, markdown) = show("markdown").await;
+    assert_eq!(content_type, "text/markdown; charset=utf-8");
+    assert_eq!(markdown, "# An article\n\n## A heading\n\nA paragraph with a [link](https://example.com/other) and **bold** text which is long enough to be readable.\n\n- One\n- Two\n");
+    let (content_type, json) = show("readable").await;
+    assert_eq!(content_type, "application/json");
+    let webpage: serde_json::Value = serde_json::from_str(&json).expect("Unable to parse response as json");
+    assert!(webpage["content"].as_str().unwrap().starts_with("<h2>A heading</h2>"), "{}", webpage);
+}
+
+#[tokio::test]
+async fn test_list_webpages_text_stats() {
+    let test_resources = start_test_server().await;
+    let english = "The hedgehog is a small spiny mammal which is found in Europe, Asia and Africa. ";
+    let german = "Der Igel ist ein kleines Säugetier mit Stacheln, das in Europa, Asien und Afrika lebt. ";
+    for (title, lang, text) in [("Long", " lang=\"en-GB\"", english.repeat(40)), ("Short", "", english.to_string()),
+            ("German", "", german.repeat(5))] {
+        save_webpage(&test_resources, &format!("https://example.com/{}", title),
+            &format!("<html{}><head><title>{}</title></head><body><article><p>{}</p></article></body></html>", lang, title, text),
+            &[]).await;
+    }
+    let (status, list) = list_webpages(&test_resources, &[("sort", "reading_time")]).await;
+    assert_eq!(status, warp::http::StatusCode::OK);
+    assert_eq!(listed_titles(&list), vec!["Short", "German", "Long"]);
+    // The language is taken from the lang attribute or detected from the text.
+    let stats: Vec<_> = list["webpage_infos"].as_array().expect("Missing webpage_infos").iter()
+        .map(|info| (info["word_count"].clone(), info["reading_time"].clone(), info["language"].clone()))
+        .collect();
+    assert_eq!(stats, vec![(15.into(), 1.into(), "en".into()), (75.into(), 1.into(), "de".into()),
+        (600.into(), 3.into(), "en-GB".into())]);
+    let (_, list) = list_webpages(&test_resources, &[("sort", "word_count"), ("order", "desc")]).await;
+    assert_eq!(listed_titles(&list), vec!["Long", "German", "Short"]);
+    let (_, list) = list_webpages(&test_resources, &[("sort", "title"), ("language", "en")]).await;
+    assert_eq!(listed_titles(&list), vec!["Long", "Short"]);
+    let (_, list) = list_webpages(&test_resources, &[("language", "en_gb")]).await;
+    assert_eq!(listed_titles(&list), vec!["Long"]);
+    let (_, list) = list_webpages(&test_resources, &[("sort", "language"), ("language", "e")]).await;
+    assert_eq!(listed_titles(&list), Vec::<&str>::new());
+    let (_, list) = list_webpages(&test_resources, &[("sort", "language"), ("max_reading_time", "1")]).await;
+    assert_eq!(listed_titles(&list), vec!["German", "Short"]);
+    let (_, list) = list_webpages(&test_resources, &[("sort", "title"), ("min_reading_time", "2")]).await;
+    assert_eq!(listed_titles(&list), vec!["Long"]);
+    let (_, list) = list_webpages(&test_resources, &[("sort", "title"), ("min_word_count", "20"), ("max_word_count", "100")]).await;
+    assert_eq!(listed_titles(&list), vec!["German"]);
+}
+
+#[tokio::test]
+async fn test_webpage_excerpt() {
+    let test_resources = start_test_server().await;
+    save_webpage(&test_resources, "https://example.com/described",
+        "<html><head><title>Described</title><meta name=\"description\" content=\"What the hedgehog page is about.\"></head><body><article><p>The hedgehog page itself.</p></article></body></html>", &[]).await;
+    save_webpage(&test_resources, "https://example.com/article",
+        "<html><head><title>Article</title></head><body><article><h1>Hedgehogs in the garden</h1><p>By A. Writer</p><p>Hedgehogs visit gardens at night to look for insects, worms and snails to eat.</p><p>A small hole in the fence lets them move between the gardens of a street.</p><p>They sleep through the winter under leaves and hedges.</p></article></body></html>", &[]).await;
+    let (_, list) = list_webpages(&test_resources, &[("sort", "title")]).await;
+    assert_eq!(listed_titles(&list), vec!["Article", "Described"]);
+    assert_eq!(list["webpage_infos"][0]["excerpt"], "Hedgehogs visit gardens at night to look for insects, worms and snails to eat. A small hole in the fence lets them move between the gardens of a street.");
+    assert_eq!(list["webpage_infos"][1]["excerpt"], "What the hedgehog page is about.");
+    let (_, results) = search(&test_resources, &test_resources.jwt, "page").await;
+    assert_eq!(search_titles(&results), vec!["Described"]);
+    assert_eq!(results["results"][0]["excerpt"], "What the hedgehog page is about.");
+}
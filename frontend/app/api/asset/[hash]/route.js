@@ -0,0 +1,16 @@
+"use server"
+
+import { get_jwt } from "../../../../helpers/cookies.js";
+
+// Archived images and stylesheets are referenced from the saved html as /api/asset/{hash}. The
+// browser loads them without an authorization header so the jwt is taken from the cookie here.
+export async function GET(request, {params}) {
+    const {hash} = params;
+    const jwt = await get_jwt();
+    if(jwt === null) {
+        return new Response(JSON.stringify({message: "Missing authorization header"}), {"headers": {"content-type": "application/json"}, "status": 401});
+    }
+    const response = await fetch(`${process.env.BACKEND_URL}/api/asset/${encodeURIComponent(hash)}`,
+        {headers: {"authorization": `Bearer ${jwt}`}});
+    return new Response(response.body, {"status": response.status, "headers": response.headers});
+}
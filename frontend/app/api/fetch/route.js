@@ -15,6 +15,15 @@ export async function POST(request) {
     if(body.html !== undefined && body.html !== null) {
         post_data["html"] = body.html;
     }
+    if(body.archive_original !== undefined && body.archive_original !== null) {
+        post_data["archive_original"] = body.archive_original;
+    }
+    if(body.tags !== undefined && body.tags !== null) {
+        post_data["tags"] = body.tags;
+    }
+    if(body.on_duplicate !== undefined && body.on_duplicate !== null) {
+        post_data["on_duplicate"] = body.on_duplicate;
+    }
     const post_headers = {"Authorization": headers.get("authorization")};
     const auth_type = searchParams.get("auth-type");
     if(auth_type !== undefined && auth_type !== null) {
@@ -23,7 +32,7 @@ export async function POST(request) {
     return axios.post(`${process.env.BACKEND_URL}/api/fetch`,
         post_data, {"headers": post_headers})
         .then(data => {
-            return new Response("", {"status": 200});
+            return new Response(JSON.stringify(data.data), {"headers": {"content-type": "application/json"}, "status": data.status});
         })
         .catch(error => {
             const error_info = get_info_from_request_error(error);
@@ -32,6 +41,9 @@ export async function POST(request) {
             if(error.response !== undefined && error.response.status !== undefined) {
                 status = error.response.status
             }
+            if(status === 409) {
+                return new Response(JSON.stringify(error.response.data), {"headers": {"content-type": "application/json"}, "status": status});
+            }
             return new Response("", {"status": status});
         });
 }
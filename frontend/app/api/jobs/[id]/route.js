@@ -0,0 +1,19 @@
+"use server"
+
+import axios from "axios";
+
+import { get_headers_from_request } from "../../../../helpers/utils.js";
+
+export async function GET(request, {params}) {
+    const {id} = params;
+    const headers = get_headers_from_request(request);
+    return axios.get(`${process.env.BACKEND_URL}/api/jobs/${encodeURIComponent(id)}`, {headers})
+        .then(data => {
+            return new Response(JSON.stringify(data.data), {"headers": {"content-type": "application/json"}});
+        })
+        .catch(error => {
+            console.debug(`Cannot get job ${id}: ${error}`);
+            const status = error.response?.status ?? 500;
+            return new Response(JSON.stringify({"message": "Cannot get job"}), {"headers": {"content-type": "application/json"}, "status": status});
+        });
+}
@@ -6,7 +6,9 @@ import { get_headers_from_request } from "../../../helpers/utils.js";
 
 export async function GET(request) {
     const headers = get_headers_from_request(request);
-    return axios.get(`${process.env.BACKEND_URL}/api/list-stored-webpages`, {headers})
+    // Sorting, filtering and the cursor for the next page are passed on as they are.
+    const params = Object.fromEntries(request.nextUrl.searchParams);
+    return axios.get(`${process.env.BACKEND_URL}/api/list-stored-webpages`, {headers, params})
         .then(data => {
             return new Response(JSON.stringify(data.data), {"headers": {"content-type": "application/json"}});
         })
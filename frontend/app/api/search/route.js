@@ -0,0 +1,19 @@
+"use server"
+
+import axios from "axios";
+
+import { get_headers_from_request } from "../../../helpers/utils.js";
+
+export async function GET(request) {
+    const headers = get_headers_from_request(request);
+    const q = request.nextUrl.searchParams.get("q") ?? "";
+    return axios.get(`${process.env.BACKEND_URL}/api/search`, {headers, params: {q}})
+        .then(data => {
+            return new Response(JSON.stringify(data.data), {"headers": {"content-type": "application/json"}});
+        })
+        .catch(error => {
+            console.debug(`Cannot search webpages: ${error}`);
+            const status = error.response?.status ?? 500;
+            return new Response(JSON.stringify({"message": "Cannot search webpages"}), {"headers": {"content-type": "application/json"}, "status": status});
+        });
+}
@@ -11,7 +11,7 @@ import { get_jwt } from "../../../helpers/cookies.js";
 export default async function ShowStoredWebpage({params}) {
     const {id} = params;
     const jwt = await get_jwt();
-    const f = await fetch(`${process.env.BACKEND_URL}/api/webpage/${id}`, {headers: {"authorization": `Bearer ${jwt}`}});
+    const f = await fetch(`${process.env.BACKEND_URL}/api/webpage/${id}?mark_read=true`, {headers: {"authorization": `Bearer ${jwt}`}});
     if(f.status >= 400) {
         return <LoadingError/>;
     }
@@ -2,13 +2,36 @@
 
 import axios from "axios";
 
+const JOB_POLL_INTERVAL = 1000;
+
 export default function WebpageFetch({jwt, revalidateCallback}) {
+    // The webpage is fetched in the background so the job is polled until it is done.
+    function wait_for_job(url, job_id) {
+        axios.get(`/api/jobs/${job_id}`, {headers: {"Authorization": `bearer ${jwt}`}})
+            .then(data => {
+                if(data.data.status === "succeeded") {
+                    revalidateCallback();
+                } else if(data.data.status === "failed") {
+                    alert(`Error fetching page ${url}: ${data.data.error}`);
+                } else {
+                    setTimeout(() => wait_for_job(url, job_id), JOB_POLL_INTERVAL);
+                }
+            })
+            .catch(error => {
+                console.error(`Unable to get job ${job_id} fetching ${url}: ${error}`);
+            });
+    }
+
     function submit(e) {
         e.preventDefault();
         const url = e.target.elements["webpage-url-input"].value;
         axios.post("/api/fetch", {url}, {headers: {"Authorization": `bearer ${jwt}`}})
             .then(data => {
-                revalidateCallback();
+                if(data.status === 202) {
+                    wait_for_job(url, data.data.job_id);
+                } else {
+                    revalidateCallback();
+                }
             })
             .catch(error => {
                 console.error(`Unable to fetch ${url}: ${error}`)